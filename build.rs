@@ -0,0 +1,14 @@
+fn main() {
+    // only the gRPC daemon needs generated code
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/wrpl.proto");
+        // protox compiles the .proto in-process, so protoc isn't required
+        let descriptors = protox::compile(["proto/wrpl.proto"], ["proto"])
+            .expect("Failed to compile proto/wrpl.proto");
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)
+            .expect("Failed to generate gRPC code");
+    }
+}
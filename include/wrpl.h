@@ -0,0 +1,136 @@
+#ifndef WRPL_H
+#define WRPL_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An opaque parsed replay.
+ */
+typedef struct WrplReplay WrplReplay;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Parses a replay and returns a handle, or null on failure.
+ *
+ * # Safety
+ * `bytes` must point to `len` readable bytes.
+ */
+struct WrplReplay *wrpl_parse(const uint8_t *bytes, size_t len);
+
+/**
+ * Parses a replay and returns it as a JSON string, or null on failure.
+ *
+ * # Safety
+ * `bytes` must point to `len` readable bytes.
+ */
+char *wrpl_parse_json(const uint8_t *bytes, size_t len);
+
+/**
+ * Frees a handle returned by [`wrpl_parse`]. Null is ignored.
+ *
+ * # Safety
+ * `replay` must come from [`wrpl_parse`] and not have been freed already.
+ */
+void wrpl_free(struct WrplReplay *replay);
+
+/**
+ * Frees a string returned by this library. Null is ignored.
+ *
+ * # Safety
+ * `s` must come from this library and not have been freed already.
+ */
+void wrpl_string_free(char *s);
+
+/**
+ * Returns the last error on this thread, or null. The string is owned by
+ * the library and valid until the next failing call on this thread.
+ */
+const char *wrpl_last_error(void);
+
+/**
+ * Returns the whole replay as a JSON string.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+char *wrpl_to_json(const struct WrplReplay *replay);
+
+/**
+ * Returns the session ID from the header, or 0.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+uint64_t wrpl_session_id(const struct WrplReplay *replay);
+
+/**
+ * Returns the level path from the header, or null.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+char *wrpl_level(const struct WrplReplay *replay);
+
+/**
+ * Returns the number of packets in the stream.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+uint64_t wrpl_packet_count(const struct WrplReplay *replay);
+
+/**
+ * Returns the number of chat messages.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+size_t wrpl_chat_count(const struct WrplReplay *replay);
+
+/**
+ * Returns the sender of chat message `index`, or null if out of range.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+char *wrpl_chat_sender(const struct WrplReplay *replay, size_t index);
+
+/**
+ * Returns the text of chat message `index`, or null if out of range.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+char *wrpl_chat_message(const struct WrplReplay *replay, size_t index);
+
+/**
+ * Returns the time of chat message `index` in milliseconds (converted from
+ * the raw ticks, but not corrected, see [`crate::clock`]), or 0.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+uint32_t wrpl_chat_timestamp_ms(const struct WrplReplay *replay, size_t index);
+
+/**
+ * Returns the results as a JSON string, or null if the replay has none.
+ *
+ * # Safety
+ * `replay` must be a valid handle from [`wrpl_parse`].
+ */
+char *wrpl_results_json(const struct WrplReplay *replay);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WRPL_H */
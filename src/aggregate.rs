@@ -0,0 +1,295 @@
+//! Per-player totals over many replays.
+//!
+//! Feed results to an [`Aggregator`] one replay at a time. Each session is
+//! only counted once, so a client replay and its server copies can all be
+//! added without inflating the numbers (see also [`crate::dedup`]).
+//!
+//! [`CareerStats`] does the same for a single player, keeping a history of
+//! their battles for progress graphs.
+
+use crate::header::{self, ReplayHeader, SessionId};
+use crate::locate;
+use crate::parser::{self, ParseOptions, ParsedReplay, PlayerData, ReplayResults};
+use anyhow::{Context, Result};
+use log::warn;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// How a battle went for one player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BattleOutcome {
+    Won,
+    Lost,
+    /// The author left early, or the player's team isn't known.
+    Unknown,
+}
+
+/// How the battle went for `player`. Results only say whether the replay's
+/// author won, so this compares the player's team with the author's.
+pub fn player_outcome(results: &ReplayResults, player: &PlayerData) -> BattleOutcome {
+    let author_won = match results.outcome_label() {
+        "Victory" => true,
+        "Defeat" => false,
+        _ => return BattleOutcome::Unknown,
+    };
+    let author_team = results
+        .players
+        .iter()
+        .find(|p| p.player_info.user_id == results.author_user_id)
+        .map(|p| p.replay_data.team);
+    match author_team {
+        Some(team) if team > 0 && player.replay_data.team > 0 => {
+            if (player.replay_data.team == team) == author_won {
+                BattleOutcome::Won
+            } else {
+                BattleOutcome::Lost
+            }
+        }
+        _ => BattleOutcome::Unknown,
+    }
+}
+
+/// Totals over some set of battles.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Totals {
+    pub battles: u32,
+    pub won: u32,
+    pub lost: u32,
+    /// Air, ground and naval kills of players (AI kills not included).
+    pub kills: i64,
+    pub ai_kills: i64,
+    pub assists: i64,
+    pub deaths: i64,
+    pub score: i64,
+    /// Seconds, from the results' `time_played`.
+    pub time_played: f64,
+}
+
+impl Totals {
+    fn add(&mut self, results: &ReplayResults, player: &PlayerData) {
+        let data = &player.replay_data;
+        self.battles += 1;
+        match player_outcome(results, player) {
+            BattleOutcome::Won => self.won += 1,
+            BattleOutcome::Lost => self.lost += 1,
+            BattleOutcome::Unknown => {}
+        }
+        self.kills += (data.kills + data.ground_kills + data.naval_kills) as i64;
+        self.ai_kills += (data.ai_kills + data.ai_ground_kills + data.ai_naval_kills) as i64;
+        self.assists += data.assists as i64;
+        self.deaths += data.deaths as i64;
+        self.score += data.score as i64;
+        self.time_played += results.time_played;
+    }
+
+    /// Kills per death (kills if never died).
+    pub fn kd(&self) -> f64 {
+        self.kills as f64 / self.deaths.max(1) as f64
+    }
+
+    /// Share of battles won, out of those with a known outcome.
+    pub fn winrate(&self) -> Option<f64> {
+        let decided = self.won + self.lost;
+        (decided > 0).then(|| self.won as f64 / decided as f64)
+    }
+}
+
+/// Everything known about one player.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerAggregate {
+    pub user_id: String,
+    /// Most recently seen name.
+    pub username: String,
+    pub totals: Totals,
+    /// Keyed by the header's `level`.
+    pub by_map: BTreeMap<String, Totals>,
+    /// Keyed by the header's `battle_class`.
+    pub by_mode: BTreeMap<String, Totals>,
+}
+
+/// Collects [`PlayerAggregate`]s from many replays.
+#[derive(Debug, Clone, Default)]
+pub struct Aggregator {
+    players: BTreeMap<String, PlayerAggregate>,
+    sessions: BTreeSet<SessionId>,
+}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the results of one battle. Returns false (and adds nothing) if
+    /// this session was already added.
+    pub fn add(&mut self, header: &ReplayHeader, results: &ReplayResults) -> bool {
+        if !self.sessions.insert(header.session_id) {
+            return false;
+        }
+        for player in &results.players {
+            let user_id = &player.player_info.user_id;
+            let aggregate =
+                self.players
+                    .entry(user_id.clone())
+                    .or_insert_with(|| PlayerAggregate {
+                        user_id: user_id.clone(),
+                        username: String::new(),
+                        totals: Totals::default(),
+                        by_map: BTreeMap::new(),
+                        by_mode: BTreeMap::new(),
+                    });
+            aggregate.username = player.player_info.username.clone();
+            aggregate.totals.add(results, player);
+            aggregate
+                .by_map
+                .entry(header.level.clone())
+                .or_default()
+                .add(results, player);
+            aggregate
+                .by_mode
+                .entry(header.battle_class.clone())
+                .or_default()
+                .add(results, player);
+        }
+        true
+    }
+
+    /// Same as [`Aggregator::add`], for a parse with results. Returns false
+    /// if it has none.
+    pub fn add_replay(&mut self, header: &ReplayHeader, replay: &ParsedReplay) -> bool {
+        match &replay.replay_results {
+            Some(results) => self.add(header, results),
+            None => false,
+        }
+    }
+
+    /// Number of distinct sessions added.
+    pub fn sessions(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn player(&self, user_id: &str) -> Option<&PlayerAggregate> {
+        self.players.get(user_id)
+    }
+
+    /// All players, by user ID.
+    pub fn players(&self) -> impl Iterator<Item = &PlayerAggregate> {
+        self.players.values()
+    }
+}
+
+/// One battle in a [`CareerStats`] history.
+#[derive(Debug, Clone, Serialize)]
+pub struct CareerBattle {
+    /// From the header, if the results came with one.
+    pub session_id: Option<SessionId>,
+    pub start_time: Option<u32>,
+    pub level: Option<String>,
+    pub outcome: BattleOutcome,
+    pub kills: i32,
+    pub deaths: i32,
+    pub assists: i32,
+    pub score: i32,
+}
+
+/// One player's performance across many replays.
+#[derive(Debug, Clone, Serialize)]
+pub struct CareerStats {
+    pub user_id: String,
+    /// Most recently seen name, empty until the player shows up.
+    pub username: String,
+    pub totals: Totals,
+    /// In the order they were ingested.
+    pub history: Vec<CareerBattle>,
+    #[serde(skip)]
+    sessions: BTreeSet<SessionId>,
+}
+
+impl CareerStats {
+    pub fn new(user_id: impl Into<String>) -> Self {
+        CareerStats {
+            user_id: user_id.into(),
+            username: String::new(),
+            totals: Totals::default(),
+            history: Vec::new(),
+            sessions: BTreeSet::new(),
+        }
+    }
+
+    /// Adds a battle, if the player was in it. Without a header there's no
+    /// telling sessions apart, so the same battle can be counted twice; use
+    /// [`CareerStats::ingest_replay`] where possible.
+    pub fn ingest(&mut self, results: &ReplayResults) -> bool {
+        self.add(None, results)
+    }
+
+    /// Adds a battle, if the player was in it and its session wasn't
+    /// added before.
+    pub fn ingest_replay(&mut self, header: &ReplayHeader, results: &ReplayResults) -> bool {
+        if self.sessions.contains(&header.session_id) {
+            return false;
+        }
+        self.add(Some(header), results)
+    }
+
+    fn add(&mut self, header: Option<&ReplayHeader>, results: &ReplayResults) -> bool {
+        let Some(player) = results
+            .players
+            .iter()
+            .find(|p| p.player_info.user_id == self.user_id)
+        else {
+            return false;
+        };
+        if let Some(header) = header {
+            self.sessions.insert(header.session_id);
+        }
+        let data = &player.replay_data;
+        self.username = player.player_info.username.clone();
+        self.totals.add(results, player);
+        self.history.push(CareerBattle {
+            session_id: header.map(|h| h.session_id),
+            start_time: header.map(|h| h.start_time),
+            level: header.map(|h| h.level.clone()),
+            outcome: player_outcome(results, player),
+            kills: data.kills + data.ground_kills + data.naval_kills,
+            deaths: data.deaths,
+            assists: data.assists,
+            score: data.score,
+        });
+        true
+    }
+
+    /// Ingests every replay in `dir` (not recursively), oldest first.
+    /// Returns how many battles were added. Unreadable files are skipped.
+    pub fn ingest_dir(&mut self, dir: &Path) -> Result<usize> {
+        let mut summaries = locate::scan_replays(dir)?;
+        summaries.retain(|s| s.header.is_some());
+        summaries.sort_by_key(|s| s.header.as_ref().map(|h| h.start_time));
+
+        let mut added = 0;
+        for summary in summaries {
+            let data = match fs::read(&summary.path) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Couldn't read {:?}: {}", summary.path, e);
+                    continue;
+                }
+            };
+            let Ok(header) = header::parse_header(&data) else {
+                continue;
+            };
+            let results = parser::results_for(&data, &header, &ParseOptions::default());
+            if let Some(results) = results {
+                if self.ingest_replay(&header, &results) {
+                    added += 1;
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize career stats")
+    }
+}
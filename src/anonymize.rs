@@ -0,0 +1,256 @@
+//! Replacing player nicknames, squadron tags and user IDs in a client replay.
+//!
+//! Every replacement has the same byte length as the original, so all length
+//! prefixes and BLK string offsets stay valid and the replay remains playable.
+
+use crate::header::HEADER_SIZE;
+use crate::parser::{self, ReplayResults};
+use crate::raw;
+use crate::rewrite;
+use anyhow::{bail, Context, Result};
+use flate2::Compression;
+use log::info;
+use memchr::memmem;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+/// Names shorter than this are only replaced where their position is known
+/// (chat senders), as short byte patterns match random binary data too often.
+const MIN_RAW_MATCH_LEN: usize = 4;
+
+/// File type byte of an uncompressed BLK with its names inline.
+const FAT_BLK: u8 = 0x01;
+
+/// The result of [`anonymize`].
+#[derive(Debug, Clone)]
+pub struct AnonymizedReplay {
+    /// The rewritten replay file.
+    pub data: Vec<u8>,
+    /// Original string -> pseudonym, for names, tags and user IDs.
+    pub mapping: BTreeMap<String, String>,
+}
+
+/// What pseudonyms too short for a prefix are spelled with.
+const PSEUDONYM_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Builds a pseudonym of exactly `len` bytes, e.g. `Player0007`. Names too
+/// short for the prefix get `index` in base 62 instead, which wraps around
+/// (see [`Pseudonyms::unique_pseudonym`]).
+fn pseudonym(prefix: &str, index: usize, len: usize) -> String {
+    let digits = index.to_string();
+    if digits.len() >= len {
+        let base = PSEUDONYM_ALPHABET.len();
+        let mut rest = index;
+        let mut out = vec![0u8; len];
+        for byte in out.iter_mut().rev() {
+            *byte = PSEUDONYM_ALPHABET[rest % base];
+            rest /= base;
+        }
+        return String::from_utf8(out).expect("the alphabet is ASCII");
+    }
+    let prefix = &prefix[..prefix.len().min(len - digits.len())];
+    format!("{}{:0>width$}", prefix, digits, width = len - prefix.len())
+}
+
+/// Collects everything that identifies a player, mapped to pseudonyms.
+struct Pseudonyms {
+    strings: BTreeMap<String, String>,
+    user_ids: BTreeMap<u64, u64>,
+}
+
+impl Pseudonyms {
+    fn new() -> Self {
+        Pseudonyms {
+            strings: BTreeMap::new(),
+            user_ids: BTreeMap::new(),
+        }
+    }
+
+    /// A pseudonym of `len` bytes that isn't given out yet (nor a known
+    /// name), so two players never end up as one.
+    fn unique_pseudonym(&self, prefix: &str, len: usize) -> Result<String> {
+        let taken = |fake: &String| {
+            self.strings.contains_key(fake) || self.strings.values().any(|v| v == fake)
+        };
+        // enough to get past everything given out so far, and round the
+        // alphabet once for the shortest names
+        let tries = self.strings.len() + PSEUDONYM_ALPHABET.len() + 1;
+        (self.strings.len() + 1..)
+            .take(tries)
+            .map(|index| pseudonym(prefix, index, len))
+            .find(|fake| !taken(fake))
+            .with_context(|| format!("Ran out of {}-byte pseudonyms", len))
+    }
+
+    fn add_name(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() || self.strings.contains_key(name) {
+            return Ok(());
+        }
+        let fake = self.unique_pseudonym("Player", name.len())?;
+        self.strings.insert(name.to_string(), fake);
+        Ok(())
+    }
+
+    fn add_tag(&mut self, tag: &str) -> Result<()> {
+        if tag.is_empty() || self.strings.contains_key(tag) {
+            return Ok(());
+        }
+        let fake = self.unique_pseudonym("Tag", tag.len())?;
+        self.strings.insert(tag.to_string(), fake);
+        Ok(())
+    }
+
+    fn add_user_id(&mut self, user_id: &str) {
+        let Ok(id) = user_id.parse::<u64>() else {
+            return;
+        };
+        if self.user_ids.contains_key(&id) {
+            return;
+        }
+        // keep the same number of digits, so the string form keeps its length
+        let fake = 10u64.pow(user_id.len() as u32 - 1) + self.user_ids.len() as u64 + 1;
+        self.user_ids.insert(id, fake);
+        self.strings.insert(user_id.to_string(), fake.to_string());
+    }
+
+    fn add_results(&mut self, results: &ReplayResults) -> Result<()> {
+        for player in &results.players {
+            self.add_name(&player.player_info.username)?;
+            self.add_tag(&player.player_info.squadron_tag)?;
+            self.add_user_id(&player.player_info.user_id);
+        }
+        self.add_user_id(&results.author_user_id);
+        Ok(())
+    }
+
+    /// Replaces every occurrence of a known string, longest first.
+    fn replace_strings(&self, buffer: &mut [u8]) -> usize {
+        let mut pairs: Vec<_> = self
+            .strings
+            .iter()
+            .filter(|(from, _)| from.len() >= MIN_RAW_MATCH_LEN)
+            .collect();
+        pairs.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+
+        let mut replaced = 0;
+        for (from, to) in pairs {
+            replaced += replace_all(buffer, from.as_bytes(), to.as_bytes());
+        }
+        replaced
+    }
+}
+
+fn replace_all(buffer: &mut [u8], from: &[u8], to: &[u8]) -> usize {
+    debug_assert_eq!(from.len(), to.len());
+    let positions: Vec<usize> = memmem::find_iter(buffer, from).collect();
+    for &pos in &positions {
+        buffer[pos..pos + to.len()].copy_from_slice(to);
+    }
+    positions.len()
+}
+
+/// Walks the decompressed packet stream, collecting chat senders and
+/// rewriting them in place (which also covers names too short for raw matching).
+fn rewrite_chat_senders(stream: &mut [u8], names: &mut Pseudonyms, apply: bool) -> Result<()> {
+    let mut pos = 0usize;
+    let mut last_timestamp_ms = 0;
+
+    while pos < stream.len() {
+        let mut cursor = Cursor::new(&stream[pos..]);
+        let Some((size, prefix_len)) = raw::read_variable_length_size(&mut cursor)? else {
+            break;
+        };
+        let packet_start = pos + prefix_len;
+        let packet_end = (packet_start + size as usize).min(stream.len());
+        pos = packet_end;
+
+        let packet = &stream[packet_start..packet_end];
+        let mut packet_cursor = Cursor::new(packet);
+        let Some((packet_type, timestamp_ms, header_len)) =
+            raw::read_packet_header_from_stream(&mut packet_cursor, last_timestamp_ms)?
+        else {
+            continue;
+        };
+        last_timestamp_ms = timestamp_ms;
+
+        if packet_type != 3 {
+            continue;
+        }
+        // [flag][sender_len][sender][message_len][message]...
+        let payload_start = packet_start + header_len;
+        if payload_start + 2 > packet_end {
+            continue;
+        }
+        let sender_len = stream[payload_start + 1] as usize;
+        let sender_start = payload_start + 2;
+        if sender_start + sender_len > packet_end {
+            continue;
+        }
+        let sender =
+            String::from_utf8_lossy(&stream[sender_start..sender_start + sender_len]).into_owned();
+
+        if apply {
+            if let Some(fake) = names.strings.get(&sender) {
+                stream[sender_start..sender_start + sender_len].copy_from_slice(fake.as_bytes());
+            }
+        } else {
+            names.add_name(&sender)?;
+        }
+    }
+    Ok(())
+}
+
+/// Anonymizes a client replay.
+///
+/// Players are taken from the results blob and chat senders. Names are replaced
+/// in the packet stream, the mission settings and the results; user IDs are
+/// replaced in the results only, where they're strings. Fails if the results
+/// aren't a FAT BLK, as anything compressed can't be rewritten in place.
+pub fn anonymize(data: &[u8]) -> Result<AnonymizedReplay> {
+    let split = rewrite::split_replay(data)?;
+    let layout = split.layout;
+
+    let mut names = Pseudonyms::new();
+    if layout.rez_offset > 0 {
+        // names are only plain bytes in a FAT BLK, compressed ones can't be
+        // rewritten in place
+        let kind = data.get(layout.rez_offset).copied();
+        if kind != Some(FAT_BLK) {
+            bail!(
+                "The results BLK isn't a plain FAT BLK (type {:#04x?}), can't rewrite it",
+                kind
+            );
+        }
+        if let Some(results) = parser::parse_replay_results(data, layout.rez_offset) {
+            names.add_results(&results)?;
+        }
+    }
+
+    let mut stream = split.stream;
+    rewrite_chat_senders(&mut stream, &mut names, false)?;
+    rewrite_chat_senders(&mut stream, &mut names, true)?;
+    let stream_hits = names.replace_strings(&mut stream);
+
+    let mut prefix = split.prefix.to_vec();
+    let prefix_hits = names.replace_strings(&mut prefix[HEADER_SIZE..]);
+
+    let mut tail = split.tail.to_vec();
+    let tail_hits = names.replace_strings(&mut tail);
+
+    info!(
+        "Anonymized {} identifiers ({} in stream, {} in settings, {} in results)",
+        names.strings.len(),
+        stream_hits,
+        prefix_hits,
+        tail_hits
+    );
+
+    let compressed = rewrite::compress_stream(&stream, Compression::default())
+        .context("Failed to recompress packet stream")?;
+    let data = rewrite::assemble(&prefix, &compressed, &tail, &layout)?;
+
+    Ok(AnonymizedReplay {
+        data,
+        mapping: names.strings,
+    })
+}
@@ -0,0 +1,78 @@
+use clap::Parser;
+use log::{error, info};
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+use wrpl::anonymize;
+
+#[derive(Parser, Debug)]
+#[command(
+    author = "llama-for3ver",
+    version = "0.1",
+    about = "Rewrites a client replay with player names, squadron tags and user IDs replaced by pseudonyms.",
+    help_template = "\
+{name} {version} ({author})
+{about}
+
+USAGE:
+    {usage}
+
+EXAMPLES:
+    ./anonymize -r ./#2025.05.05.wrpl -o ./shared.wrpl
+
+OPTIONS:
+    {options}
+"
+)]
+struct Args {
+    /// Path to the replay file to anonymize.
+    #[arg(short, long)]
+    replay_file: PathBuf,
+
+    /// Where to write the anonymized replay.
+    #[arg(short, long)]
+    output_file: PathBuf,
+
+    /// Print the original -> pseudonym mapping (keep it private!).
+    #[arg(long, default_value_t = false)]
+    show_mapping: bool,
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+
+    let file_data = match fs::read(&args.replay_file) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Error reading replay file {:?}: {}", args.replay_file, e);
+            exit(1);
+        }
+    };
+
+    let anonymized = match anonymize::anonymize(&file_data) {
+        Ok(anonymized) => anonymized,
+        Err(e) => {
+            error!("Failed to anonymize replay: {:?}", e);
+            exit(1);
+        }
+    };
+
+    if let Err(e) = fs::write(&args.output_file, &anonymized.data) {
+        error!("Error writing {:?}: {}", args.output_file, e);
+        exit(1);
+    }
+
+    if args.show_mapping {
+        for (original, fake) in &anonymized.mapping {
+            println!("{} -> {}", original, fake);
+        }
+    }
+
+    info!(
+        "Wrote anonymized replay ({} identifiers replaced) to {:?}",
+        anonymized.mapping.len(),
+        args.output_file
+    );
+}
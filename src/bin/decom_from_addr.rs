@@ -1,27 +1,152 @@
-use flate2::read::ZlibDecoder;
-use std::env;
-use std::fs::File;
-use std::io::{self, BufReader, Seek, SeekFrom};
-
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    // FIXME: use clap
-    if args.len() != 4 {
-        eprintln!("Usage: {} <input_file> <offset> <output_file>", args[0]);
-        std::process::exit(1);
+use clap::Parser;
+use log::{error, info, warn};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use wrpl::utils::{self, FileOffset};
+
+#[derive(Parser, Debug)]
+#[command(
+    author = "llama-for3ver",
+    version = "0.1",
+    about = "Decompresses the zlib stream at an offset in a file, e.g. to look at it in a hex editor.",
+    help_template = "\
+{name} {version} ({author})
+{about}
+
+USAGE:
+    {usage}
+
+{all-args}
+"
+)]
+struct Args {
+    /// File the stream is in.
+    input_file: PathBuf,
+
+    /// Where the stream starts (hex, decimal, 4k...). Negative counts from
+    /// the end.
+    #[arg(value_parser = utils::parse_file_offset, allow_hyphen_values = true)]
+    offset: FileOffset,
+
+    /// Where to write the decompressed stream, "-" for stdout.
+    #[arg(default_value = "-")]
+    output_file: PathBuf,
+
+    /// Stop reading the input here, as if the file ended. Negative counts
+    /// from the end.
+    #[arg(long, value_parser = utils::parse_file_offset, allow_hyphen_values = true)]
+    end: Option<FileOffset>,
+
+    /// Write at most this many decompressed bytes.
+    #[arg(long, value_parser = utils::parse_offset)]
+    limit: Option<u64>,
+
+    /// Print how far it's got to stderr.
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+}
+
+/// Print progress every this many bytes written.
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Counts what's written, drops anything past the limit and reports
+/// progress.
+struct Output<W: Write> {
+    inner: W,
+    written: u64,
+    limit: u64,
+    progress: bool,
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.limit.saturating_sub(self.written);
+        let keep = &buf[..buf.len().min(room as usize)];
+        self.inner.write_all(keep)?;
+        let before = self.written;
+        self.written += keep.len() as u64;
+        if self.progress && before / PROGRESS_STEP != self.written / PROGRESS_STEP {
+            eprint!("\r{} MiB written", self.written / PROGRESS_STEP);
+        }
+        // pretend the rest was written too, so decompression carries on
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
-    let input_path = &args[1];
-    let offset: u64 = args[2].parse().expect("Invalid offset");
-    let output_path = &args[3];
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let file = File::open(input_path)?;
-    let mut reader = BufReader::new(file);
-    reader.seek(SeekFrom::Start(offset))?;
+    let args = Args::parse();
 
-    let mut decoder = ZlibDecoder::new(reader);
+    let data = match fs::read(&args.input_file) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Couldn't read {}: {}", args.input_file.display(), e);
+            exit(1);
+        }
+    };
+    let len = data.len() as u64;
+    let Some(offset) = args.offset.resolve(len) else {
+        error!("Offset {:?} is before the start of the file", args.offset);
+        exit(1);
+    };
+    let end = match args.end.map(|end| end.resolve(len)) {
+        None => len,
+        Some(Some(end)) if end >= offset => end.min(len),
+        Some(_) => {
+            error!("--end is before the offset");
+            exit(1);
+        }
+    };
 
-    let mut output = File::create(output_path)?;
-    io::copy(&mut decoder, &mut output)?;
+    let writer: Box<dyn Write> = if args.output_file.as_os_str() == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        match File::create(&args.output_file) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                error!("Couldn't create {}: {}", args.output_file.display(), e);
+                exit(1);
+            }
+        }
+    };
+    let mut output = Output {
+        inner: BufWriter::new(writer),
+        written: 0,
+        limit: args.limit.unwrap_or(u64::MAX),
+        progress: args.progress,
+    };
 
-    Ok(())
+    let result = utils::decompress_stream(&data[..end as usize], offset, &mut output);
+    if args.progress && output.written >= PROGRESS_STEP {
+        eprintln!();
+    }
+    if let Err(e) = output.flush() {
+        error!("Couldn't write the output: {}", e);
+        exit(1);
+    }
+    match result {
+        Ok(stream) => {
+            info!(
+                "Decompressed {} bytes into {}, wrote {} (stream ends at {:#x})",
+                stream.bytes_in,
+                stream.bytes_out,
+                output.written,
+                offset + stream.bytes_in
+            );
+            if !stream.complete {
+                warn!("The stream was cut off before its end");
+            }
+        }
+        Err(e) => {
+            error!("{:#}", e);
+            exit(1);
+        }
+    }
 }
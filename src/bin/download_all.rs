@@ -1,13 +1,16 @@
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
+use wrpl::download::{self, DownloadOptions};
+use wrpl::header::SessionId;
 
 #[derive(Parser, Debug)]
 #[clap(
     author = "llama-for3ver",
-    about = "A CLI util to download all associated replays for a given game ID.",
-    version = "1.0",
+    about = "A CLI util to download all associated replays for one or more game IDs.",
+    version = "1.1",
     help_template = "\
 {name} {version} ({author})
 
@@ -16,63 +19,121 @@ use clap::Parser;
 USAGE:
     {usage}
 
+EXAMPLES:
+    ./download_all -i 4a9c2f1e0b2b2c7 -i 4a9c2f1e0b2b2c8
+    ./download_all --id-file ids.txt
+    cat ids.txt | ./download_all --id-file -
+
 OPTIONS:
 {options}
 "
 )]
 struct Args {
-    /// The replay ID in hex format.
-    #[clap(short, long)]
-    id: String,
+    /// The replay ID(s) in hex format. Can be given multiple times.
+    #[clap(short, long = "id", num_args = 1..)]
+    ids: Vec<String>,
+
+    /// File with one replay ID per line, or "-" for stdin.
+    /// Blank lines and lines starting with '#' are ignored.
+    #[clap(long)]
+    id_file: Option<PathBuf>,
 
     /// The directory to save the replays to. Default "replays".
+    /// Each session is saved in its own subdirectory named after the ID.
     #[clap(short, long, default_value = "replays")]
     output_dir: String,
+
+    /// Base URL of the replay CDN, without a trailing slash.
+    #[clap(long, default_value = download::DEFAULT_BASE_URL)]
+    base_url: String,
+
+    /// Proxy to route requests through (http://, https:// or socks5://).
+    #[clap(long)]
+    proxy: Option<String>,
+
+    /// User-Agent header sent with each request.
+    #[clap(long, default_value = concat!("wrpl/", env!("CARGO_PKG_VERSION")))]
+    user_agent: String,
+}
+
+/// Reads IDs from a file (or stdin if the path is "-"), one per line.
+fn read_id_list(path: &Path) -> io::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(fs::File::open(path)?))
+    };
+
+    let mut ids = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let id = line.trim();
+        if id.is_empty() || id.starts_with('#') {
+            continue;
+        }
+        ids.push(id.to_string());
+    }
+    Ok(ids)
 }
 
 #[tokio::main]
-/// Downloads all replays associated with a given ID.
+/// Downloads all replays associated with the given IDs.
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     let args = Args::parse();
 
+    let mut ids = args.ids.clone();
+    if let Some(id_file) = &args.id_file {
+        ids.extend(read_id_list(id_file)?);
+    }
+    if ids.is_empty() {
+        eprintln!("No replay IDs given, use --id or --id-file.");
+        std::process::exit(1);
+    }
+
     let output_dir = PathBuf::from(&args.output_dir);
 
     // create the output directory if it doesn't exist...
     fs::create_dir_all(&output_dir)?;
 
-    println!("Downloading replays for ID: {}", args.id);
-    println!("Saving replays to: {}", output_dir.display());
+    let options = DownloadOptions {
+        base_url: args.base_url.clone(),
+        proxy: args.proxy.clone(),
+        user_agent: args.user_agent.clone(),
+    };
+    let client = options.build_client()?;
 
-    let mut i = 0;
-    loop {
-        let url = format!(
-            // seems to need a 0 prepended
-            // also this specific domain is optimal -
-            // the something.warthunder.com one has rate limiting
-            // but still redirects to this one
-            "https://wt-replays-cdnnow.cdn.gaijin.net/0{}/{:04}.wrpl",
-            args.id, i
-        );
-
-        let response = reqwest::get(&url).await?;
-
-        if response.status() == reqwest::StatusCode::NOT_FOUND {
-            println!("Replay {} not found, stopping.", i);
-            break;
-        }
+    println!("Saving replays to: {}", output_dir.display());
 
-        if !response.status().is_success() {
-            eprintln!("Error downloading replay {}: {}", i, response.status());
-            break;
+    let mut failed = 0;
+    for raw_id in &ids {
+        // it ends up in a path, so only take real session IDs
+        let Ok(session_id) = raw_id.parse::<SessionId>() else {
+            eprintln!("Not a session ID (hex, e.g. 5650b4a0022daeb): {:?}", raw_id);
+            failed += 1;
+            continue;
+        };
+        let id = session_id.url_id();
+        println!("Downloading replays for ID: {}", id);
+        match download::fetch_session_with_client(&client, &id, &output_dir.join(&id), &options)
+            .await
+        {
+            Ok(paths) if paths.is_empty() => {
+                eprintln!("No replays found for ID: {}", id);
+                failed += 1;
+            }
+            Ok(paths) => println!("Downloaded {} replay(s) for ID: {}", paths.len(), id),
+            Err(e) => {
+                eprintln!("Failed to download ID {}: {}", id, e);
+                failed += 1;
+            }
         }
+    }
 
-        let file_path = output_dir.join(format!("{:04}.wrpl", i));
-        let mut file = fs::File::create(&file_path)?;
-        let content = response.bytes().await?;
-        std::io::copy(&mut content.as_ref(), &mut file)?;
-
-        println!("Downloaded replay: {:04}.wrpl", i);
-        i += 1;
+    if failed > 0 {
+        eprintln!("{} of {} session(s) failed.", failed, ids.len());
+        std::process::exit(1);
     }
     Ok(())
 }
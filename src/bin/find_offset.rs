@@ -4,7 +4,8 @@ use log::{error, info, warn};
 use std::fs;
 use std::io::{Cursor, Read};
 use std::path::PathBuf;
-use wrpl::utils;
+use wrpl::parser::DEFAULT_MAX_PACKET_SIZE;
+use wrpl::utils::{self, FileOffset};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -31,13 +32,14 @@ struct Args {
     #[arg(long, default_value_t = false)]
     skip_zlib: bool,
 
-    /// Range to search start, default 0.
-    #[arg(long, default_value = "0", value_parser = utils::parse_offset)]
-    search_start: u64,
+    /// Range to search start, default 0. Negative counts from the end
+    /// (e.g. -64k).
+    #[arg(long, default_value = "0", value_parser = utils::parse_file_offset, allow_hyphen_values = true)]
+    search_start: FileOffset,
 
     /// Range to search stop (not inclusive), default file end.
-    #[arg(long, default_value = "0", value_parser = utils::parse_offset)]
-    search_end: u64,
+    #[arg(long, default_value = "-0", value_parser = utils::parse_file_offset, allow_hyphen_values = true)]
+    search_end: FileOffset,
 }
 
 // Structure to hold both the chat info and the raw packet as hex
@@ -62,11 +64,12 @@ fn main() {
     };
 
     let file_len = data.len() as u64;
-    let search_start = args.search_start;
-    let search_end = if args.search_end == 0 {
-        file_len
-    } else {
-        args.search_end
+    let (Some(search_start), Some(search_end)) = (
+        args.search_start.resolve(file_len),
+        args.search_end.resolve(file_len),
+    ) else {
+        error!("Search range starts before the file");
+        std::process::exit(1);
     };
 
     if search_start >= search_end || search_end > file_len {
@@ -140,15 +143,19 @@ fn try_parse_chat_at_offset(
 
     let mut last_timestamp = 0u32;
     for _ in 0..6 {
-        let size_prefix = match wrpl::parser::read_variable_length_size(&mut reader) {
-            Ok(Some((packet_size, _))) if packet_size > 0 && packet_size < 0x2000 => packet_size,
+        let size_prefix = match wrpl::raw::read_variable_length_size(&mut reader) {
+            Ok(Some((packet_size, _)))
+                if packet_size > 0 && packet_size <= DEFAULT_MAX_PACKET_SIZE =>
+            {
+                packet_size
+            }
             _ => return None,
         };
         let mut packet_buf = vec![0u8; size_prefix as usize];
-        if let Err(_) = reader.read_exact(&mut packet_buf) {
+        if reader.read_exact(&mut packet_buf).is_err() {
             return None;
         }
-        let parsed_header = wrpl::parser::read_packet_header_from_stream(
+        let parsed_header = wrpl::raw::read_packet_header_from_stream(
             &mut Cursor::new(&packet_buf),
             last_timestamp,
         );
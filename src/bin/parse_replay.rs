@@ -1,9 +1,10 @@
-use clap::{crate_authors, Parser};
+use clap::{crate_authors, Parser, ValueEnum};
 use log::{debug, error, info, warn};
 use std::fs::{self};
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use wrpl::{header, parser, utils};
+use wrpl::{cache, capture, export, format, header, parser, utils};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -21,13 +22,22 @@ USAGE:
 EXAMPLES:
     ./parse_replay -r ./#2025.05.05.wrpl
     ./parse_replay -r ./#2025.05.05.wrpl --skip-zlib --offset 0x000004D1
+    curl -s https://example.com/replay.wrpl | ./parse_replay -r -
 
 OPTIONS:
     {options}
+
+EXIT CODES:
+    0  success
+    1  other errors (reading the file, bad arguments, writing --csv...)
+    2  the replay header couldn't be parsed
+    3  the packet stream couldn't be found
+    4  the packet stream couldn't be parsed
+    5  --parse-results was given but there are no results
 "
 )]
 struct Args {
-    /// Path to the replay file to decode.
+    /// Path to the replay file to decode, or "-" to read it from stdin.
     #[arg(short, long)]
     replay_file: PathBuf,
 
@@ -36,22 +46,134 @@ struct Args {
     #[arg(short, long, value_parser = utils::parse_offset)] // Using utils
     offset: Option<u64>,
 
-    /// Skip zlib decompression and parse raw packet data [directly from the offset].
-    #[arg(long, default_value_t = false)]
+    /// How the packet stream is compressed. "auto" works it out from the
+    /// bytes at the offset.
+    #[arg(long, value_enum, default_value_t = CompressionArg::Auto)]
+    compression: CompressionArg,
+
+    /// Parse raw packet data directly from the offset. Same as
+    /// --compression none.
+    #[arg(long, default_value_t = false, conflicts_with = "compression")]
     skip_zlib: bool,
 
     /// Parse replay results data (requires header parsing).
     #[arg(long, default_value_t = false)]
     parse_results: bool,
+
+    /// Fail on unknown packet types, empty packets and truncated data
+    /// instead of skipping over them.
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Only decode packets of this raw type ID (e.g. 3 for chat). Repeatable.
+    #[arg(long)]
+    packet_type: Vec<u8>,
+
+    /// Show chat messages a player repeated within this many seconds once,
+    /// with a count.
+    #[arg(long, value_name = "SECONDS")]
+    collapse_chat: Option<u32>,
+
+    /// Treat size prefixes over this (e.g. 64k) as corruption. Defaults to
+    /// 1M.
+    #[arg(long, value_parser = parse_packet_size)]
+    max_packet_size: Option<u32>,
+
+    /// What to do about empty packets: "continue", "end" (of the stream),
+    /// or a number of them to allow before failing.
+    #[arg(long, value_parser = parse_zero_size_policy, default_value = "continue")]
+    zero_size: parser::ZeroSizePolicy,
+
+    /// Keep decoded results in this directory and reuse them next time.
+    #[arg(long, requires = "parse_results")]
+    results_cache: Option<PathBuf>,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Write chat.csv (and players.csv with --parse-results) into this directory.
+    #[arg(long)]
+    csv: Option<PathBuf>,
+
+    /// Dump payloads of unknown packet types (or those given with
+    /// --capture-type) into this directory, for reverse-engineering.
+    #[arg(long)]
+    capture_dir: Option<PathBuf>,
+
+    /// Raw packet type ID to capture instead of unknown types. Repeatable.
+    #[arg(long, requires = "capture_dir")]
+    capture_type: Vec<u8>,
+}
+
+/// Exit codes, see the help text.
+const EXIT_ERROR: i32 = 1;
+const EXIT_BAD_HEADER: i32 = 2;
+const EXIT_NO_OFFSET: i32 = 3;
+const EXIT_STREAM_FAILED: i32 = 4;
+const EXIT_RESULTS_FAILED: i32 = 5;
+
+/// How the parsed replay is presented.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Log header, chat and results as text.
+    Text,
+    /// Print a Markdown battle summary to stdout.
+    Markdown,
+    /// Print a canonical JSON snapshot of the parse, for diffing across
+    /// versions.
+    Snapshot,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum CompressionArg {
+    Auto,
+    None,
+    Zlib,
+    RawDeflate,
+    Gzip,
+    /// Needs the zstd feature.
+    Zstd,
 }
 
-fn humanize_victory_or_loss(input: &str) -> String {
-    match input {
-        "fail" => "Victory".to_string(),
-        "success" => "Defeat".to_string(),
-        // not sure what this one means
-        "left" => "Draw".to_string(),
-        _ => "Unknown".to_string(),
+impl From<CompressionArg> for parser::Compression {
+    fn from(arg: CompressionArg) -> Self {
+        match arg {
+            CompressionArg::Auto => parser::Compression::Auto,
+            CompressionArg::None => parser::Compression::None,
+            CompressionArg::Zlib => parser::Compression::Zlib,
+            CompressionArg::RawDeflate => parser::Compression::RawDeflate,
+            CompressionArg::Gzip => parser::Compression::Gzip,
+            CompressionArg::Zstd => parser::Compression::Zstd,
+        }
+    }
+}
+
+/// A [`utils::parse_offset`] that fits a packet size.
+fn parse_packet_size(s: &str) -> Result<u32, String> {
+    let size = utils::parse_offset(s)?;
+    u32::try_from(size).map_err(|_| format!("{} is too big for a packet size", size))
+}
+
+fn parse_zero_size_policy(s: &str) -> Result<parser::ZeroSizePolicy, String> {
+    match s {
+        "continue" => Ok(parser::ZeroSizePolicy::Continue),
+        "end" => Ok(parser::ZeroSizePolicy::EndOfStream),
+        _ => s
+            .parse()
+            .map(parser::ZeroSizePolicy::AbortAfter)
+            .map_err(|_| format!("expected continue, end or a number, got {:?}", s)),
+    }
+}
+
+/// Reads the whole replay from a file, or from stdin if the path is "-".
+fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fs::read(path)
     }
 }
 
@@ -60,33 +182,30 @@ fn main() {
 
     let args = Args::parse();
 
-    // read the file into memory first
-    let file_data = match fs::read(&args.replay_file) {
+    // read the file (or stdin) into memory first
+    let file_data = match read_input(&args.replay_file) {
         Ok(data) => data,
         Err(e) => {
             error!("Error reading replay file {:?}: {}", args.replay_file, e);
-            exit(1);
+            exit(EXIT_ERROR);
         }
     };
 
     let start_offset: u64;
-    let mut has_wrpl_header = false;
-
-    // check for magic bytes
-    // FIXME: magic bytes are actually longer than 2 bytes
-    if file_data.len() >= 2 {
-        if file_data[0..2] == *b"\xE5\xAC" {
-            has_wrpl_header = true;
-            debug!("File starts with magic bytes, assuming normal .wrpl.");
-        } else {
-            warn!(
-                "File does not start with magic bytes.
-                Assuming it contains only stream data"
-            );
+
+    let kind = format::inspect(&file_data);
+    debug!("Detected {:?}", kind);
+    let has_wrpl_header = kind.has_header();
+    if !has_wrpl_header && kind != format::ReplayKind::RawStream {
+        if let Err(e) = format::check_magic(&file_data) {
+            warn!("{:#}. Assuming it contains only stream data", e);
         }
-    } else {
-        error!("File is too short!");
     }
+    let compression = if args.skip_zlib {
+        parser::Compression::None
+    } else {
+        args.compression.into()
+    };
 
     let header_info = if has_wrpl_header {
         // parse the header (still needs path for now)
@@ -94,12 +213,14 @@ fn main() {
         match header::parse_header(&file_data) {
             Ok(header) => {
                 info!("Successfully parsed replay header:");
-                println!("{}", header);
+                if args.format == OutputFormat::Text {
+                    println!("{}", header);
+                }
                 Some(header)
             }
             Err(e) => {
                 error!("Failed to parse replay header: {}", e);
-                None
+                exit(EXIT_BAD_HEADER);
             }
         }
     } else {
@@ -125,8 +246,8 @@ fn main() {
         }
         start_offset = user_offset;
     } else if has_wrpl_header && header_info.is_some() {
-        if args.skip_zlib {
-            warn!("--skip-zlib provided, but file appears to be a standard .wrpl (starts with E5 AC).");
+        if compression == parser::Compression::None {
+            warn!("Compression none given, but file appears to be a standard .wrpl (has a replay header).");
             info!(
                 "Assuming raw stream starts at offset 0 (header will be skipped).
             Consider using --offset if data is after header."
@@ -134,60 +255,91 @@ fn main() {
             start_offset = 0; // default to 0 if skipping zlib
                               // even with wrpl header, user might want to skip header
         } else {
-            info!("Attempting to auto-detect zlib stream start offset (searching after 0xE5AC)...");
-            // Use utils::find_zlib_header_offset, but it needs a Path. Pass the path.
-            // If it were refactored to take &[u8], we'd pass &file_data.
-            // also we should NOT search after 0xE5AC, but after the real header
-            match utils::find_zlib_header_offset(&args.replay_file, 2, None) {
-                Ok(Some(detected_offset)) => {
-                    start_offset = detected_offset;
+            match kind.stream_offset() {
+                Some(detected_offset) => {
+                    info!(
+                        "Packet stream starts at {:#0x} ({:?})",
+                        detected_offset, kind
+                    );
+                    start_offset = detected_offset as u64;
                 }
-                Ok(None) => {
+                None => {
                     error!("Failed to automatically find zlib stream start.");
                     eprintln!("You may need to specify the offset manually using --offset.");
-                    exit(1);
-                }
-                Err(e) => {
-                    error!("Error during zlib header search: {:?}", e);
-                    exit(1);
+                    exit(EXIT_NO_OFFSET);
                 }
             }
         }
     } else {
         // no WRPL header detected and no offset provided.
         info!("Assuming stream starts at offset 0.");
-        if !args.skip_zlib {
-            warn!(
-                "File does not look like a .wrpl and --skip-zlib not specified.
-                Will attempt zlib decompression from offset 0, but this may not work."
-            );
-        }
         start_offset = 0;
     }
 
-    let replay_result = if args.parse_results && header_info.is_some() {
-        parser::process_replay_stream(
-            &file_data,
-            start_offset,
-            args.skip_zlib,
-            Some(header_info.as_ref().unwrap()),
-        )
-    } else {
-        parser::process_replay_stream(&file_data, start_offset, args.skip_zlib, None)
+    let results_cache = match args.results_cache.as_ref().map(cache::ResultsCache::new) {
+        Some(Ok(cache)) => Some(cache),
+        Some(Err(e)) => {
+            error!("{:#}", e);
+            exit(EXIT_ERROR);
+        }
+        None => None,
+    };
+    let options = parser::ParseOptions {
+        strict: args.strict,
+        results_cache,
+        packet_types: args.packet_type.clone(),
+        collapse_chat_ms: args.collapse_chat.map(|s| s.saturating_mul(1000)),
+        max_packet_size: args.max_packet_size,
+        zero_size: args.zero_size,
+        ..Default::default()
     };
+    let header = header_info.as_ref().filter(|_| args.parse_results);
+    let replay_result = parser::process_replay_stream_with_options(
+        &file_data,
+        start_offset,
+        compression,
+        header,
+        &options,
+    );
 
-    match replay_result {
-        Ok(stats) => {
+    let stats = match replay_result {
+        Ok(stats) => stats,
+        Err(e) => {
+            error!("Error during replay stream processing: {:?}", e);
+            exit(EXIT_STREAM_FAILED);
+        }
+    };
+
+    match args.format {
+        OutputFormat::Snapshot => {
+            print!(
+                "{}",
+                export::snapshot::snapshot_string(header_info.as_ref(), &stats)
+            );
+        }
+        OutputFormat::Markdown => {
+            println!(
+                "{}",
+                export::markdown::battle_summary(header_info.as_ref(), &stats)
+            );
+        }
+        OutputFormat::Text => {
             // display chat messages here...
             // omit channel_type and is_enemy for now
             if !stats.chat_messages.is_empty() {
                 info!("Found {} chat messages:", stats.chat_messages.len());
                 for (i, chat) in stats.chat_messages.iter().enumerate() {
+                    let repeats = if chat.repeats > 0 {
+                        format!(" (x{})", chat.repeats + 1)
+                    } else {
+                        String::new()
+                    };
                     info!(
-                        "{}: {} says '{}' ", // (channel: {:?}, enemy: {:?})",
+                        "{}: {} says '{}'{}", // (channel: {:?}, enemy: {:?})",
                         i + 1,
                         chat.sender,
                         chat.message,
+                        repeats,
                         // chat.channel_type,
                         // chat.is_enemy
                     );
@@ -197,31 +349,91 @@ fn main() {
             // try and display replay results
             if let Some(ref results) = stats.replay_results {
                 info!("Found {} players", results.players.len());
-                info!("Status: {}", humanize_victory_or_loss(&results.status));
+                info!("Status: {}", results.outcome_label());
                 info!("Time Played: {:.1} seconds", results.time_played);
                 info!("Author: {} [{}]", results.author, results.author_user_id);
-            } else if args.parse_results {
-                warn!("Replay results parsing was requested but no results found");
             }
 
-            debug!("Processing Stats:");
-            debug!("  Packets Processed: {}", stats.packet_count);
-            debug!(
-                "  Total Decompressed Bytes: {}",
-                stats.total_decompressed_bytes
+            let duration = stats.check_duration(header_info.as_ref());
+            info!(
+                "Duration: {:.1} seconds",
+                duration.end_time_ms as f64 / 1000.0
             );
+            for issue in &duration.issues {
+                warn!("Duration mismatch: {}", issue);
+            }
+        }
+    }
 
-            // if stats.final_offset > 0 {
-            //     info!("  Final Offset: {:#0x}", stats.final_offset);
-            // } else if !args.skip_zlib {
-            //     info!("  Final Offset: Unknown (due to zlib stream)");
-            // }
+    if let Some(dir) = &args.csv {
+        match export::csv::write_replay_csv(
+            &stats.chat_messages,
+            stats.replay_results.as_ref(),
+            dir,
+        ) {
+            Ok(()) => info!("Wrote CSV files to {:?}", dir),
+            Err(e) => {
+                error!("Failed to write CSV files: {:?}", e);
+                exit(EXIT_ERROR);
+            }
         }
-        Err(e) => {
-            error!("Error during replay stream processing: {:?}", e);
-            exit(1);
+    }
+
+    if let Some(dir) = &args.capture_dir {
+        let options = capture::CaptureOptions {
+            types: args.capture_type.clone(),
+        };
+        match capture::capture_packets(&stats.packets, &options, dir) {
+            Ok(summary) => info!(
+                "Captured {} packets into {:?}",
+                summary.captured_packets(),
+                dir
+            ),
+            Err(e) => {
+                error!("Failed to capture packets: {:?}", e);
+                exit(EXIT_ERROR);
+            }
         }
     }
 
+    debug!("Processing Stats:");
+    debug!("  Packets Processed: {}", stats.packet_count);
+    debug!(
+        "  Total Decompressed Bytes: {}",
+        stats.total_decompressed_bytes
+    );
+    for (raw_type, bytes) in &stats.bytes_by_type {
+        debug!(
+            "  {:?} ({}): {} packets, {} bytes ({:.1}%)",
+            parser::ReplayPacketType::from(*raw_type),
+            raw_type,
+            bytes.packets,
+            bytes.bytes,
+            bytes.share(stats.total_decompressed_bytes) * 100.0
+        );
+    }
+    debug!(
+        "  Memory Kept: {} bytes ({} payloads, {} chat, {} decoded)",
+        stats.memory.total(),
+        stats.memory.payloads,
+        stats.memory.chat,
+        stats.memory.decoded
+    );
+    let timings = stats.timings;
+    debug!(
+        "  Timings: {:?} decompressing, {:?} in the packet loop, {:?} chat, {:?} results",
+        timings.decompression, timings.packet_loop, timings.chat, timings.results
+    );
+
+    // if stats.final_offset > 0 {
+    //     info!("  Final Offset: {:#0x}", stats.final_offset);
+    // } else if compression != parser::Compression::None {
+    //     info!("  Final Offset: Unknown (due to zlib stream)");
+    // }
+
+    if args.parse_results && stats.replay_results.is_none() {
+        error!("Replay results parsing was requested but no results found");
+        exit(EXIT_RESULTS_FAILED);
+    }
     info!("Successfully finished processing!");
 }
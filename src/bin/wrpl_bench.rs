@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use flate2::read::ZlibDecoder;
+use std::fs;
+use std::hint::black_box;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{Duration, Instant};
+use wrpl::format;
+use wrpl::parser::{self, Compression, ParseOptions};
+
+#[derive(Parser, Debug)]
+#[command(
+    author = "llama-for3ver",
+    version = "0.1",
+    about = "Measures how fast replays parse, to catch slowdowns in the packet loop.",
+    help_template = "\
+{name} {version} ({author})
+{about}
+
+USAGE:
+    {usage}
+
+EXAMPLES:
+    ./wrpl-bench ./client.wrpl
+    ./wrpl-bench -n 50 --no-zlib ./client.wrpl
+
+OPTIONS:
+    {options}
+"
+)]
+struct Args {
+    /// The replay to parse.
+    replay_file: PathBuf,
+
+    /// How many times to parse it per mode.
+    #[arg(short = 'n', long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// Only run the modes that parse the already decompressed stream.
+    #[arg(long, default_value_t = false, conflicts_with = "only_zlib")]
+    no_zlib: bool,
+
+    /// Only run the modes that decompress the stream while parsing.
+    #[arg(long, default_value_t = false)]
+    only_zlib: bool,
+}
+
+/// One row of the report.
+struct Mode {
+    name: &'static str,
+    compression: Compression,
+    discard_payloads: bool,
+}
+
+const MODES: [Mode; 4] = [
+    Mode {
+        name: "zlib, payloads",
+        compression: Compression::Zlib,
+        discard_payloads: false,
+    },
+    Mode {
+        name: "zlib, no payloads",
+        compression: Compression::Zlib,
+        discard_payloads: true,
+    },
+    Mode {
+        name: "raw, payloads",
+        compression: Compression::None,
+        discard_payloads: false,
+    },
+    Mode {
+        name: "raw, no payloads",
+        compression: Compression::None,
+        discard_payloads: true,
+    },
+];
+
+fn run(args: &Args) -> Result<()> {
+    let file = fs::read(&args.replay_file)
+        .with_context(|| format!("Failed to read {:?}", args.replay_file))?;
+    let offset = format::detect_format(&file)
+        .stream_offset()
+        .context("Couldn't find a zlib packet stream")?;
+
+    // decompress once up front for the raw modes
+    let mut raw = Vec::new();
+    ZlibDecoder::new(&file[offset..])
+        .read_to_end(&mut raw)
+        .context("Failed to decompress the packet stream")?;
+    let first = parser::process_replay_data(&raw, 0, Compression::None)?;
+
+    println!(
+        "{:?}: {} packets, {:.2} MB compressed, {:.2} MB decompressed, {} iterations",
+        args.replay_file,
+        first.packet_count,
+        (file.len() - offset) as f64 / 1e6,
+        raw.len() as f64 / 1e6,
+        args.iterations
+    );
+    println!(
+        "{:<20} {:>12} {:>12} {:>14}",
+        "mode", "ms/parse", "MB/s", "packets/s"
+    );
+
+    for mode in &MODES {
+        let uncompressed = mode.compression == Compression::None;
+        if (args.no_zlib && !uncompressed) || (args.only_zlib && uncompressed) {
+            continue;
+        }
+        let options = ParseOptions {
+            discard_payloads: mode.discard_payloads,
+            ..Default::default()
+        };
+        let (data, start) = if uncompressed {
+            (&raw[..], 0)
+        } else {
+            (&file[..], offset as u64)
+        };
+
+        let mut total = Duration::ZERO;
+        for _ in 0..args.iterations {
+            let started = Instant::now();
+            let replay =
+                parser::process_replay_data_with_options(data, start, mode.compression, &options)?;
+            total += started.elapsed();
+            black_box(replay);
+        }
+
+        let seconds = total.as_secs_f64() / args.iterations as f64;
+        // throughput of the decompressed stream, so the modes compare
+        println!(
+            "{:<20} {:>12.2} {:>12.1} {:>14.0}",
+            mode.name,
+            seconds * 1000.0,
+            raw.len() as f64 / 1e6 / seconds,
+            first.packet_count as f64 / seconds
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args = Args::parse();
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {:#}", e);
+        exit(1);
+    }
+}
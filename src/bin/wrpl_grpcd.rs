@@ -0,0 +1,223 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use clap::Parser;
+use log::{debug, info};
+use tokio_stream::Stream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use wrpl::header::{self, ReplayHeader};
+use wrpl::parser::{self, ChatInfo, PacketRef, PacketVisitor, ReplayResults};
+use wrpl::{clock, format};
+
+mod proto {
+    tonic::include_proto!("wrpl.v1");
+}
+
+use proto::replay_decoder_server::{ReplayDecoder, ReplayDecoderServer};
+use proto::replay_event::Event;
+
+#[derive(Parser, Debug)]
+#[clap(
+    author = "llama-for3ver",
+    about = "A gRPC service that decodes replays sent to it (see proto/wrpl.proto).",
+    version = "0.1",
+    help_template = "\
+{name} {version} ({author})
+
+{about}
+
+USAGE:
+    {usage}
+
+EXAMPLES:
+    ./wrpl-grpcd
+    ./wrpl-grpcd --listen 0.0.0.0:50051 --max-message-size 134217728
+
+OPTIONS:
+{options}
+"
+)]
+struct Args {
+    /// Address to listen on.
+    #[clap(short, long, default_value = "127.0.0.1:50051")]
+    listen: SocketAddr,
+
+    /// Largest accepted request in bytes. Replays easily exceed gRPC's 4 MiB default.
+    #[clap(long, default_value_t = 64 * 1024 * 1024)]
+    max_message_size: usize,
+}
+
+fn invalid(e: anyhow::Error) -> Status {
+    Status::invalid_argument(format!("{:#}", e))
+}
+
+fn parse_header(data: &[u8]) -> Result<ReplayHeader, Status> {
+    format::check_magic(data).map_err(invalid)?;
+    header::parse_header(data).map_err(invalid)
+}
+
+/// Collects packets and chat as events, in stream order: each chat message
+/// right after the packet that carried it.
+#[derive(Default)]
+struct Events(Vec<Result<proto::ReplayEvent, Status>>);
+
+impl Events {
+    fn push(&mut self, event: Event) {
+        self.0.push(Ok(proto::ReplayEvent { event: Some(event) }));
+    }
+}
+
+impl PacketVisitor for Events {
+    fn packet(&mut self, p: PacketRef<'_>) {
+        self.push(Event::Packet(proto::Packet {
+            packet_type: p.raw_type as u32,
+            timestamp_ms: clock::ticks_to_ms(p.timestamp_ms),
+            payload: p.payload.to_vec(),
+        }));
+    }
+
+    fn chat(&mut self, c: &ChatInfo) {
+        self.push(Event::Chat(proto::ChatMessage {
+            timestamp_ms: clock::ticks_to_ms(c.timestamp_ms),
+            sender: c.sender.clone(),
+            message: c.message.clone(),
+            channel_type: c.channel_type.map(u32::from),
+            is_enemy: c.is_enemy.map(u32::from),
+        }));
+    }
+}
+
+/// Parses the header and the packet stream into events.
+fn replay_events(data: &[u8]) -> Result<Vec<Result<proto::ReplayEvent, Status>>, Status> {
+    parse_header(data)?;
+    if format::detect_format(data).stream_offset().is_none() {
+        return Err(Status::invalid_argument(
+            "Couldn't find the packet stream, is the replay truncated?",
+        ));
+    }
+    let events = parser::parse_with(data, Events::default()).map_err(invalid)?;
+    Ok(events.0)
+}
+
+impl From<ReplayHeader> for proto::Header {
+    fn from(h: ReplayHeader) -> Self {
+        proto::Header {
+            version: h.version,
+            level: h.level,
+            level_settings: h.level_settings,
+            battle_type: h.battle_type,
+            environment: h.environment,
+            visibility: h.visibility,
+            rez_offset: h.rez_offset,
+            difficulty: h.difficulty.difficulty_value as u32,
+            session_type: h.session_type,
+            session_id: h.session_id.0,
+            m_set_size: h.m_set_size,
+            loc_name: h.loc_name,
+            start_time: h.start_time,
+            time_limit: h.time_limit,
+            score_limit: h.score_limit,
+            battle_class: h.battle_class,
+            battle_kill_streak: h.battle_kill_streak,
+        }
+    }
+}
+
+impl From<ReplayResults> for proto::Results {
+    fn from(r: ReplayResults) -> Self {
+        proto::Results {
+            outcome: r.outcome_label().to_string(),
+            status: r.status,
+            time_played: r.time_played,
+            author: r.author,
+            author_user_id: r.author_user_id,
+            players: r
+                .players
+                .into_iter()
+                .map(|p| proto::Player {
+                    user_id: p.player_info.user_id,
+                    username: p.player_info.username,
+                    squadron_tag: p.player_info.squadron_tag,
+                    platform: p.player_info.platform,
+                    team: p.replay_data.team,
+                    squad: p.replay_data.squad,
+                    kills: p.replay_data.kills,
+                    ground_kills: p.replay_data.ground_kills,
+                    naval_kills: p.replay_data.naval_kills,
+                    assists: p.replay_data.assists,
+                    deaths: p.replay_data.deaths,
+                    score: p.replay_data.score,
+                    lineup: p.replay_data.lineup,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Decoder;
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::ReplayEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ReplayDecoder for Decoder {
+    async fn parse_header(
+        &self,
+        request: Request<proto::ReplayBytes>,
+    ) -> Result<Response<proto::Header>, Status> {
+        let data = request.into_inner().data;
+        debug!("ParseHeader: {} bytes", data.len());
+        Ok(Response::new(parse_header(&data)?.into()))
+    }
+
+    type ParseReplayStream = EventStream;
+
+    async fn parse_replay(
+        &self,
+        request: Request<proto::ReplayBytes>,
+    ) -> Result<Response<Self::ParseReplayStream>, Status> {
+        let data = request.into_inner().data;
+        debug!("ParseReplay: {} bytes", data.len());
+        // decompression and parsing are CPU bound, keep them off the runtime
+        let events = tokio::task::spawn_blocking(move || replay_events(&data))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(Box::pin(tokio_stream::iter(events))))
+    }
+
+    async fn parse_results(
+        &self,
+        request: Request<proto::ReplayBytes>,
+    ) -> Result<Response<proto::Results>, Status> {
+        let data = request.into_inner().data;
+        debug!("ParseResults: {} bytes", data.len());
+        let results = tokio::task::spawn_blocking(move || {
+            let header = parse_header(&data)?;
+            parser::results_for(&data, &header, &parser::ParseOptions::default())
+                .ok_or_else(|| Status::not_found("No results found in the replay"))
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(results.into()))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+
+    let service = ReplayDecoderServer::new(Decoder)
+        .max_decoding_message_size(args.max_message_size)
+        .max_encoding_message_size(args.max_message_size);
+
+    info!("Listening on {}", args.listen);
+    Server::builder()
+        .add_service(service)
+        .serve(args.listen)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::net::SocketAddr;
+
+use axum::extract::{DefaultBodyLimit, Multipart};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::Parser;
+use log::{debug, info};
+use serde_json::{json, Value};
+use tower_http::cors::{Any, CorsLayer};
+use wrpl::header;
+use wrpl::{format, logging, parser};
+
+#[derive(Parser, Debug)]
+#[clap(
+    author = "llama-for3ver",
+    about = "An HTTP service that decodes uploaded replays into JSON.",
+    version = "0.1",
+    help_template = "\
+{name} {version} ({author})
+
+{about}
+
+USAGE:
+    {usage}
+
+EXAMPLES:
+    ./wrpl-server
+    ./wrpl-server --listen 0.0.0.0:8080 --allow-origin https://example.com
+    curl -F replay=@#2025.05.05.wrpl http://127.0.0.1:8080/parse
+
+OPTIONS:
+{options}
+"
+)]
+struct Args {
+    /// Address to listen on.
+    #[clap(short, long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Largest accepted upload in bytes.
+    #[clap(long, default_value_t = 64 * 1024 * 1024)]
+    max_upload_size: usize,
+
+    /// Allow cross-origin requests from this origin ("*" for any).
+    #[clap(long)]
+    allow_origin: Option<String>,
+}
+
+/// An error returned to the client as `{"error": "..."}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError(StatusCode::BAD_REQUEST, message.into())
+}
+
+/// Parses a whole replay into the JSON returned by `/parse`.
+fn parse_replay(data: &[u8]) -> Result<Value, ApiError> {
+    format::check_magic(data).map_err(|e| bad_request(format!("{:#}", e)))?;
+    let (header, mut log) = logging::capture(|| header::parse_header(data));
+    let header = header.map_err(|e| bad_request(format!("{:#}", e)))?;
+    let offset = format::detect_format(data)
+        .stream_offset()
+        .ok_or_else(|| bad_request("Couldn't find the packet stream, is the replay truncated?"))?;
+    let options = parser::ParseOptions {
+        capture_log: true,
+        ..Default::default()
+    };
+    let mut replay = parser::process_replay_stream_with_options(
+        data,
+        offset as u64,
+        parser::Compression::Zlib,
+        Some(&header),
+        &options,
+    )
+    .map_err(|e| ApiError(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+    log.append(&mut replay.log);
+
+    Ok(json!({
+        "header": header,
+        "chat": replay.chat_messages,
+        "results": replay.replay_results,
+        "outcome": replay.replay_results.as_ref().map(|r| r.outcome_label()),
+        "packet_count": replay.packet_count,
+        "log": log,
+    }))
+}
+
+/// `POST /parse` with a multipart form; the first file field is parsed.
+async fn parse(mut multipart: Multipart) -> Result<Json<Value>, ApiError> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| bad_request(e.body_text()))?
+    {
+        if field.file_name().is_none() && field.name() != Some("replay") {
+            continue;
+        }
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| bad_request(e.body_text()))?;
+        debug!("Parsing upload of {} bytes", data.len());
+
+        // decompression and parsing are CPU bound, keep them off the runtime
+        let value = tokio::task::spawn_blocking(move || parse_replay(&data))
+            .await
+            .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+        return Ok(Json(value));
+    }
+    Err(bad_request(
+        "No replay file in the form (use a field named \"replay\")",
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+
+    let mut app = Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/parse", post(parse))
+        .layer(DefaultBodyLimit::max(args.max_upload_size));
+
+    if let Some(origin) = &args.allow_origin {
+        let cors = CorsLayer::new().allow_methods(Any).allow_headers(Any);
+        let cors = if origin == "*" {
+            cors.allow_origin(Any)
+        } else {
+            cors.allow_origin(origin.parse::<HeaderValue>()?)
+        };
+        app = app.layer(cors);
+    }
+
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Listening on http://{}", args.listen);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
@@ -0,0 +1,428 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+use wrpl::diff::{self, DiffOptions};
+use wrpl::header::{self, HeaderOptions, ReplayHeader};
+use wrpl::parser::{self, ChatInfo, PacketRef, PacketVisitor, ParsedReplay, ReplayPacketType};
+use wrpl::search::{self, GrepOptions};
+use wrpl::tail::{TailOptions, Tailer};
+use wrpl::utils::hex;
+use wrpl::writer::{self, PacketRange};
+use wrpl::{clock, dissect, format, validate};
+
+#[derive(Parser, Debug)]
+#[command(
+    author = "llama-for3ver",
+    version = "0.1",
+    about = "Assorted tools for inspecting and comparing replays.",
+    help_template = "\
+{name} {version} ({author})
+{about}
+
+USAGE:
+    {usage}
+
+EXAMPLES:
+    ./wrpl_tool diff ./client.wrpl ./other_client.wrpl
+    ./wrpl_tool validate ./replays/*.wrpl
+    ./wrpl_tool header-diff ./old.wrpl ./new.wrpl
+    ./wrpl_tool grep -i gg ./replays
+    ./wrpl_tool dissect ./client.wrpl 120 121
+    ./wrpl_tool extract ./client.wrpl ./fight.bin --start-ms 60000 --end-ms 90000
+    ./wrpl_tool tail ./replays/current.wrpl
+
+COMMANDS:
+{subcommands}
+
+OPTIONS:
+    {options}
+"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare the packets of two replays of the same session.
+    Diff {
+        /// The first replay.
+        a: PathBuf,
+        /// The second replay.
+        b: PathBuf,
+        /// How far apart (in ms, after alignment) matching packets may be.
+        #[arg(long, default_value_t = 1000)]
+        tolerance_ms: u64,
+        /// Don't estimate the clock offset between the replays.
+        #[arg(long, default_value_t = false)]
+        no_align: bool,
+        /// How many unmatched packets to list per side.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Check replays for corruption.
+    Validate {
+        /// The replays to check.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Compare the headers of two replays, including the unknown regions.
+    HeaderDiff {
+        /// The first replay.
+        a: PathBuf,
+        /// The second replay.
+        b: PathBuf,
+    },
+    /// Search the chat of every replay in a folder.
+    Grep {
+        /// Text to look for in messages.
+        pattern: String,
+        /// The folder of replays.
+        dir: PathBuf,
+        /// Match regardless of case.
+        #[arg(short, long, default_value_t = false)]
+        ignore_case: bool,
+        /// Also match sender names.
+        #[arg(long, default_value_t = false)]
+        sender: bool,
+    },
+    /// Show which bytes of some packets are which field.
+    Dissect {
+        /// The replay.
+        file: PathBuf,
+        /// Indices of the packets to show.
+        #[arg(required = true)]
+        packets: Vec<usize>,
+    },
+    /// Write some packets of a replay as an uncompressed packet stream.
+    Extract {
+        /// The replay.
+        file: PathBuf,
+        /// Where to write the packets.
+        output: PathBuf,
+        /// Index of the first packet to write.
+        #[arg(long, conflicts_with_all = ["start_ms", "end_ms"])]
+        start: Option<usize>,
+        /// Index of the packet to stop before, default the end.
+        #[arg(long, conflicts_with_all = ["start_ms", "end_ms"])]
+        end: Option<usize>,
+        /// Time (ms into the battle) of the first packet to write.
+        #[arg(long)]
+        start_ms: Option<u64>,
+        /// Time to stop before, default the end.
+        #[arg(long)]
+        end_ms: Option<u64>,
+    },
+    /// Print the chat of a replay the game is still writing, as it comes.
+    Tail {
+        /// The replay being written.
+        file: PathBuf,
+        /// How often to look for more, in ms.
+        #[arg(long, default_value_t = 500)]
+        poll_ms: u64,
+        /// Give up once the file hasn't grown for this many seconds, 0 to
+        /// wait forever.
+        #[arg(long, default_value_t = 60)]
+        idle_secs: u64,
+    },
+}
+
+/// Parses a whole replay file, finding the packet stream after the header.
+fn load(path: &Path) -> Result<(Option<ReplayHeader>, ParsedReplay)> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let kind = format::inspect(&data);
+    let header = if kind.has_header() {
+        Some(header::parse_header(&data)?)
+    } else {
+        None
+    };
+    let offset = kind
+        .stream_offset()
+        .with_context(|| format!("Couldn't find the packet stream in {:?}", path))?;
+    let replay = parser::process_replay_stream(
+        &data,
+        offset as u64,
+        parser::Compression::Auto,
+        header.as_ref(),
+    )?;
+    Ok((header, replay))
+}
+
+fn describe(replay: &ParsedReplay, index: usize) -> String {
+    let packet = &replay.packets[index];
+    format!(
+        "#{:<7} t={:>9.3}s  {:<16} {:>6} bytes",
+        index,
+        clock::ticks_to_ms(packet.timestamp_ms) as f64 / 1000.0,
+        match packet.packet_type {
+            ReplayPacketType::Unknown => format!("Unknown({})", packet.raw_type),
+            known => format!("{:?}", known),
+        },
+        packet.size
+    )
+}
+
+fn run_diff(a: &Path, b: &Path, options: DiffOptions, limit: usize) -> Result<bool> {
+    let (_, replay_a) = load(a)?;
+    let (_, replay_b) = load(b)?;
+    let diff = diff::diff_packets(&replay_a.packets, &replay_b.packets, &options);
+
+    println!("A: {:?} ({} packets)", a, replay_a.packets.len());
+    println!("B: {:?} ({} packets)", b, replay_b.packets.len());
+    println!("Clock offset A -> B: {} ms", diff.offset_ms);
+    println!();
+    println!(
+        "{:<20} {:>10} {:>10} {:>10}",
+        "type", "matched", "only A", "only B"
+    );
+    for (raw_type, counts) in &diff.by_type {
+        println!(
+            "{:<20} {:>10} {:>10} {:>10}",
+            format!("{:?} ({})", ReplayPacketType::from(*raw_type), raw_type),
+            counts.matched,
+            counts.only_in_a,
+            counts.only_in_b
+        );
+    }
+
+    for (label, replay, indices) in [
+        ("A", &replay_a, &diff.only_in_a),
+        ("B", &replay_b, &diff.only_in_b),
+    ] {
+        if indices.is_empty() {
+            continue;
+        }
+        println!();
+        println!("Only in {} ({} packets):", label, indices.len());
+        for &i in indices.iter().take(limit) {
+            println!("  {}", describe(replay, i));
+        }
+        if indices.len() > limit {
+            println!("  ... {} more", indices.len() - limit);
+        }
+    }
+
+    Ok(diff.is_identical())
+}
+
+fn run_validate(files: &[PathBuf]) -> Result<bool> {
+    let mut all_ok = true;
+    for path in files {
+        let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let report = validate::validate(&data);
+        println!("{:?}", path);
+        print!("{}", report);
+        all_ok &= report.is_ok();
+    }
+    Ok(all_ok)
+}
+
+fn run_header_diff(a: &Path, b: &Path) -> Result<bool> {
+    let options = HeaderOptions {
+        keep_unknown: true,
+        mission_settings: true,
+    };
+    let read = |path: &Path| -> Result<ReplayHeader> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        header::parse_header_with_options(&data, &options)
+            .with_context(|| format!("Failed to parse the header of {:?}", path))
+    };
+    let diffs = diff::diff_headers(&read(a)?, &read(b)?);
+
+    println!("A: {:?}", a);
+    println!("B: {:?}", b);
+    if diffs.is_empty() {
+        println!("Headers are identical");
+    }
+    for field in &diffs {
+        println!();
+        if field.offsets.is_empty() {
+            println!("{}", field.field);
+            println!("  A: {}", field.a);
+            println!("  B: {}", field.b);
+            continue;
+        }
+        // raw bytes, with the ones that differ marked
+        let (hex_a, hex_b) = (field.a.as_bytes(), field.b.as_bytes());
+        let marks: String = (0..hex_a.len().max(hex_b.len()) / 2)
+            .map(|i| {
+                let byte = |hex: &[u8]| hex.get(i * 2..i * 2 + 2).map(<[u8]>::to_vec);
+                if byte(hex_a) == byte(hex_b) {
+                    "  "
+                } else {
+                    "^^"
+                }
+            })
+            .collect();
+        let offsets: Vec<String> = field.offsets.iter().map(|o| format!("{:#x}", o)).collect();
+        println!("{} (differs at {})", field.field, offsets.join(", "));
+        println!("  A: {}", field.a);
+        println!("  B: {}", field.b);
+        println!("     {}", marks.trim_end());
+    }
+    Ok(diffs.is_empty())
+}
+
+fn run_grep(pattern: &str, dir: &Path, options: &GrepOptions) -> Result<bool> {
+    let matches = search::grep_dir(dir, pattern, options)?;
+    for m in &matches {
+        println!(
+            "{}\t{}\tt={:.3}s\t{}: {}",
+            m.path.display(),
+            m.session_id
+                .map_or_else(|| "-".to_string(), |id| format!("{:x}", id)),
+            m.time_ms() as f64 / 1000.0,
+            m.chat.sender,
+            m.chat.message
+        );
+    }
+    Ok(!matches.is_empty())
+}
+
+fn run_dissect(path: &Path, indices: &[usize]) -> Result<()> {
+    let (_, replay) = load(path)?;
+    for &i in indices {
+        let packet = replay
+            .packets
+            .get(i)
+            .with_context(|| format!("No packet {} ({} packets)", i, replay.packets.len()))?;
+        println!("{}", describe(&replay, i));
+        let fields = dissect::dissect(packet);
+        if fields.is_empty() {
+            println!("  (not understood) {}", hex::encode(&packet.payload));
+        }
+        for field in fields {
+            let bytes = &packet.payload[field.range.clone()];
+            let shown = hex::encode(&bytes[..bytes.len().min(16)]);
+            println!(
+                "  {:>5}..{:<5} {:<14} {:<32} {}{}",
+                field.range.start,
+                field.range.end,
+                field.name,
+                field.value,
+                shown,
+                if bytes.len() > 16 { "..." } else { "" }
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn run_extract(path: &Path, output: &Path, range: PacketRange) -> Result<()> {
+    let (_, replay) = load(path)?;
+    let stream = writer::extract_packets(&replay, range)?;
+    fs::write(output, &stream).with_context(|| format!("Failed to write {:?}", output))?;
+    println!("Wrote {} bytes to {:?}", stream.len(), output);
+    Ok(())
+}
+
+/// Prints chat and the start and end of the battle as [`Tailer`] finds them.
+struct PrintLive;
+
+impl PacketVisitor for PrintLive {
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        match packet.packet_type {
+            ReplayPacketType::StartMarker => println!("-- battle started"),
+            ReplayPacketType::EndMarker => println!("-- battle ended"),
+            _ => {}
+        }
+    }
+
+    fn chat(&mut self, chat: &ChatInfo) {
+        println!("{}: {}", chat.sender, chat.message);
+    }
+}
+
+fn run_tail(path: &Path, options: TailOptions) -> Result<()> {
+    let mut tailer = Tailer::new(path, options);
+    tailer.follow(PrintLive)?;
+    if !tailer.finished() {
+        println!("-- stopped waiting after {} packets", tailer.packet_count());
+    }
+    Ok(())
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = Args::parse();
+
+    let result = match args.command {
+        Command::Diff {
+            a,
+            b,
+            tolerance_ms,
+            no_align,
+            limit,
+        } => {
+            let options = DiffOptions {
+                tolerance_ms,
+                align: !no_align,
+            };
+            // like diff(1), exit with 1 if the replays differ
+            run_diff(&a, &b, options, limit).map(|same| if same { 0 } else { 1 })
+        }
+        Command::Validate { files } => run_validate(&files).map(|ok| if ok { 0 } else { 1 }),
+        Command::HeaderDiff { a, b } => {
+            run_header_diff(&a, &b).map(|same| if same { 0 } else { 1 })
+        }
+        Command::Grep {
+            pattern,
+            dir,
+            ignore_case,
+            sender,
+        } => {
+            let options = GrepOptions {
+                ignore_case,
+                match_sender: sender,
+            };
+            // like grep(1), exit with 1 if nothing matched
+            run_grep(&pattern, &dir, &options).map(|found| if found { 0 } else { 1 })
+        }
+        Command::Dissect { file, packets } => run_dissect(&file, &packets).map(|()| 0),
+        Command::Extract {
+            file,
+            output,
+            start,
+            end,
+            start_ms,
+            end_ms,
+        } => {
+            let range = if start_ms.is_some() || end_ms.is_some() {
+                PacketRange::Time(start_ms.unwrap_or(0)..end_ms.unwrap_or(u64::MAX))
+            } else {
+                PacketRange::Indices(start.unwrap_or(0)..end.unwrap_or(usize::MAX))
+            };
+            run_extract(&file, &output, range).map(|()| 0)
+        }
+        Command::Tail {
+            file,
+            poll_ms,
+            idle_secs,
+        } => {
+            let options = TailOptions {
+                poll_interval: Duration::from_millis(poll_ms),
+                idle_timeout: (idle_secs > 0).then(|| Duration::from_secs(idle_secs)),
+                // only chat and the markers are shown
+                parse: parser::ParseOptions {
+                    packet_types: vec![0, 1, 3],
+                    ..Default::default()
+                },
+            };
+            run_tail(&file, options).map(|()| 0)
+        }
+    };
+
+    match result {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            exit(2);
+        }
+    }
+}
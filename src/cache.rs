@@ -0,0 +1,107 @@
+//! On-disk cache of decoded results BLKs.
+//!
+//! Decoding the results BLK is the slowest part of parsing a replay, and
+//! batch jobs tend to parse the same replays over and over. Entries are keyed
+//! by session ID, `rez_offset` and a hash of the blob, so a replay that was
+//! rewritten or anonymized gets a new entry instead of stale results.
+
+use crate::header::{ReplayHeader, SessionId};
+use crate::logging::warn;
+use crate::parser::{self, ReplayResults};
+use crate::utils::fnv1a;
+use anyhow::{Context, Result};
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory of decoded results, one JSON file per blob.
+#[derive(Debug, Clone)]
+pub struct ResultsCache {
+    dir: PathBuf,
+}
+
+impl ResultsCache {
+    /// Uses `dir` as the cache, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Couldn't create cache directory {}", dir.display()))?;
+        Ok(ResultsCache { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the entry for a blob lives.
+    pub fn path(&self, session_id: SessionId, rez_offset: u32, blob: &[u8]) -> PathBuf {
+        self.dir.join(format!(
+            "{}_{:x}_{:016x}.json",
+            session_id,
+            rez_offset,
+            fnv1a(blob)
+        ))
+    }
+
+    /// The cached JSON for a blob, if there is any.
+    pub fn get(&self, session_id: SessionId, rez_offset: u32, blob: &[u8]) -> Option<String> {
+        fs::read_to_string(self.path(session_id, rez_offset, blob)).ok()
+    }
+
+    /// Stores the decoded JSON for a blob.
+    pub fn put(
+        &self,
+        session_id: SessionId,
+        rez_offset: u32,
+        blob: &[u8],
+        json: &str,
+    ) -> Result<()> {
+        let path = self.path(session_id, rez_offset, blob);
+        // write then rename, so a crashed run can't leave half an entry
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json).with_context(|| format!("Couldn't write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    /// Same as [`parser::parse_replay_results`] at `header.rez_offset`, but
+    /// decodes the blob only if it isn't cached yet.
+    pub fn parse_replay_results(
+        &self,
+        data: &[u8],
+        header: &ReplayHeader,
+    ) -> Option<ReplayResults> {
+        let blob = data.get(header.rez_offset as usize..)?;
+        self.parse_results_blob(blob, header)
+    }
+
+    /// Same as [`ResultsCache::parse_replay_results`], given the blob at
+    /// `header.rez_offset`.
+    pub(crate) fn parse_results_blob(
+        &self,
+        blob: &[u8],
+        header: &ReplayHeader,
+    ) -> Option<ReplayResults> {
+        if blob.is_empty() {
+            return None;
+        }
+        let rez_offset = header.rez_offset;
+        let json = match self.get(header.session_id, rez_offset, blob) {
+            Some(json) => {
+                debug!("Results of {} found in cache", header.session_id);
+                json
+            }
+            None => {
+                let json = parser::decompress_blk(blob)
+                    .map_err(|e| warn!("Failed to decompress replay results data: {}", e))
+                    .ok()?;
+                if let Err(e) = self.put(header.session_id, rez_offset, blob, &json) {
+                    warn!("Couldn't cache results: {:#}", e);
+                }
+                json
+            }
+        };
+        parser::parse_replay_results_json(&json)
+            .map_err(|e| warn!("Failed to parse replay results JSON: {}", e))
+            .ok()
+    }
+}
@@ -0,0 +1,131 @@
+//! Dumping raw packet payloads for reverse-engineering.
+//!
+//! For every captured type `N`, `type_N.bin` holds the payloads back to back
+//! and `type_N.csv` says where each one came from. `summary.csv` counts all
+//! packet types in the replay, captured or not.
+
+use crate::parser::{PacketInfo, ReplayPacketType};
+use anyhow::{Context, Result};
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Which packets [`capture_packets`] dumps.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureOptions {
+    /// Raw type IDs to capture. Empty means every type the parser doesn't know.
+    pub types: Vec<u8>,
+}
+
+impl CaptureOptions {
+    fn wants(&self, packet: &PacketInfo) -> bool {
+        if self.types.is_empty() {
+            packet.packet_type == ReplayPacketType::Unknown
+        } else {
+            self.types.contains(&packet.raw_type)
+        }
+    }
+}
+
+/// How often one raw packet type occurs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TypeFrequency {
+    pub count: u64,
+    pub total_bytes: u64,
+    /// Whether its payloads were written out.
+    pub captured: bool,
+}
+
+/// The result of [`capture_packets`], keyed by raw type ID.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureSummary {
+    pub frequencies: BTreeMap<u8, TypeFrequency>,
+}
+
+impl CaptureSummary {
+    /// Number of packets written out.
+    pub fn captured_packets(&self) -> u64 {
+        self.frequencies
+            .values()
+            .filter(|f| f.captured)
+            .map(|f| f.count)
+            .sum()
+    }
+}
+
+/// Writes the payloads of the selected packets into `dir` (created if needed).
+pub fn capture_packets(
+    packets: &[PacketInfo],
+    options: &CaptureOptions,
+    dir: &Path,
+) -> Result<CaptureSummary> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+    // (payloads, index, bytes written to payloads so far)
+    let mut outputs: BTreeMap<u8, (BufWriter<File>, BufWriter<File>, u64)> = BTreeMap::new();
+    let mut summary = CaptureSummary::default();
+
+    for (i, packet) in packets.iter().enumerate() {
+        let frequency = summary.frequencies.entry(packet.raw_type).or_default();
+        frequency.count += 1;
+        frequency.total_bytes += packet.size as u64;
+        if !options.wants(packet) {
+            continue;
+        }
+        frequency.captured = true;
+
+        let (payloads, index, written) = match outputs.entry(packet.raw_type) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let create = |name: String| -> Result<BufWriter<File>> {
+                    let path = dir.join(name);
+                    let file = File::create(&path)
+                        .with_context(|| format!("Failed to create {:?}", path))?;
+                    Ok(BufWriter::new(file))
+                };
+                let payloads = create(format!("type_{}.bin", packet.raw_type))?;
+                let mut index = create(format!("type_{}.csv", packet.raw_type))?;
+                writeln!(
+                    index,
+                    "packet_index,stream_offset,timestamp,payload_size,bin_offset"
+                )?;
+                e.insert((payloads, index, 0))
+            }
+        };
+
+        payloads.write_all(&packet.payload)?;
+        writeln!(
+            index,
+            "{},{},{},{},{}",
+            i,
+            packet.stream_offset,
+            packet.timestamp_ms,
+            packet.payload.len(),
+            written
+        )?;
+        *written += packet.payload.len() as u64;
+    }
+
+    for (payloads, index, _) in outputs.values_mut() {
+        payloads.flush()?;
+        index.flush()?;
+    }
+
+    let mut writer = BufWriter::new(File::create(dir.join("summary.csv"))?);
+    writeln!(writer, "raw_type,packet_type,count,total_bytes,captured")?;
+    for (raw_type, frequency) in &summary.frequencies {
+        writeln!(
+            writer,
+            "{},{:?},{},{},{}",
+            raw_type,
+            ReplayPacketType::from(*raw_type),
+            frequency.count,
+            frequency.total_bytes,
+            frequency.captured
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(summary)
+}
@@ -0,0 +1,226 @@
+//! Turning raw packet timestamps into a monotonic timeline.
+//!
+//! Despite the name, [`PacketInfo::timestamp_ms`] counts 1/256 ms ticks: the
+//! last packet's value divided by 256 matches the results' `timePlayed`.
+//! The raw clock also steps backwards now and then (usually by a few ms), and
+//! a long enough session would wrap the u32 (after ~4.6 hours).
+//! [`ClockModel`] smooths all of that into `relative_time_ms`.
+
+use crate::header::ReplayHeader;
+use crate::parser::{PacketInfo, ReplayPacketType, ReplayResults};
+use serde::Serialize;
+use std::fmt;
+
+/// Raw timestamp ticks per millisecond.
+pub const TICKS_PER_MS: u32 = 256;
+
+/// Backwards steps up to this long are treated as jitter and flattened,
+/// longer ones as the clock being reset.
+pub const DEFAULT_JITTER_TOLERANCE_MS: u64 = 1000;
+
+/// Converts a raw timestamp to milliseconds (without any correction).
+pub fn ticks_to_ms(raw: u32) -> u32 {
+    raw / TICKS_PER_MS
+}
+
+/// The corrected time of one packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct PacketTime {
+    /// The timestamp as stored in the packet.
+    pub raw: u32,
+    /// Milliseconds since the session clock started, never decreasing.
+    pub relative_time_ms: u64,
+    /// How many NextSegment packets came before this one.
+    pub segment: u32,
+}
+
+/// What the clock model had to correct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ClockStats {
+    /// Small backwards steps that were flattened.
+    pub jitter: u32,
+    /// Large backwards jumps, treated as the clock restarting.
+    pub resets: u32,
+    /// u32 overflows of the raw clock.
+    pub wraps: u32,
+    /// NextSegment packets seen.
+    pub segments: u32,
+}
+
+/// Feeds on packets in stream order and assigns each a corrected time.
+#[derive(Debug, Clone)]
+pub struct ClockModel {
+    jitter_tolerance_ms: u64,
+    /// Ticks added to raw timestamps to account for resets and wraps.
+    offset_ticks: u64,
+    last_raw: Option<u32>,
+    last_ms: u64,
+    segment: u32,
+    stats: ClockStats,
+}
+
+impl Default for ClockModel {
+    fn default() -> Self {
+        ClockModel::with_jitter_tolerance(DEFAULT_JITTER_TOLERANCE_MS)
+    }
+}
+
+impl ClockModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_jitter_tolerance(jitter_tolerance_ms: u64) -> Self {
+        ClockModel {
+            jitter_tolerance_ms,
+            offset_ticks: 0,
+            last_raw: None,
+            last_ms: 0,
+            segment: 0,
+            stats: ClockStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ClockStats {
+        self.stats
+    }
+
+    /// Corrects the timestamp of the next packet.
+    pub fn observe(&mut self, packet_type: ReplayPacketType, raw: u32) -> PacketTime {
+        if let Some(last) = self.last_raw.filter(|&last| raw < last) {
+            let back = last - raw;
+            if back > u32::MAX / 2 {
+                self.offset_ticks += 1 << 32;
+                self.stats.wraps += 1;
+            } else if back as u64 > self.jitter_tolerance_ms * TICKS_PER_MS as u64 {
+                // carry on from where the old clock stopped
+                self.offset_ticks += back as u64;
+                self.stats.resets += 1;
+            } else {
+                self.stats.jitter += 1;
+            }
+        }
+        self.last_raw = Some(raw);
+
+        let ms = (self.offset_ticks + raw as u64) / TICKS_PER_MS as u64;
+        self.last_ms = self.last_ms.max(ms);
+
+        let time = PacketTime {
+            raw,
+            relative_time_ms: self.last_ms,
+            segment: self.segment,
+        };
+        // the NextSegment packet itself still belongs to the old segment
+        if packet_type == ReplayPacketType::NextSegment {
+            self.segment += 1;
+            self.stats.segments += 1;
+        }
+        time
+    }
+}
+
+/// Corrects the timestamps of all packets with the default model.
+pub fn packet_times(packets: &[PacketInfo]) -> (Vec<PacketTime>, ClockStats) {
+    let mut clock = ClockModel::new();
+    let times = packets
+        .iter()
+        .map(|p| clock.observe(p.packet_type, p.timestamp_ms))
+        .collect();
+    (times, clock.stats())
+}
+
+/// Differences under this are not reported by [`check_duration`].
+pub const DURATION_TOLERANCE_MS: u64 = 2000;
+
+/// Something off about a replay's duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DurationIssue {
+    /// Packets stop before the time played in the results.
+    Truncated { missing_ms: u64 },
+    /// Packets go on after the time played in the results.
+    LongerThanResults { extra_ms: u64 },
+    /// Packets go on past the header's time limit.
+    ExceedsTimeLimit { over_ms: u64 },
+}
+
+impl fmt::Display for DurationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DurationIssue::Truncated { missing_ms } => write!(
+                f,
+                "packets end {:.1}s before the time played in the results, replay is probably truncated",
+                *missing_ms as f64 / 1000.0
+            ),
+            DurationIssue::LongerThanResults { extra_ms } => write!(
+                f,
+                "packets continue {:.1}s after the time played in the results",
+                *extra_ms as f64 / 1000.0
+            ),
+            DurationIssue::ExceedsTimeLimit { over_ms } => write!(
+                f,
+                "packets continue {:.1}s past the time limit",
+                *over_ms as f64 / 1000.0
+            ),
+        }
+    }
+}
+
+/// A replay's duration, measured from its packets and compared against
+/// the results and header.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct DurationReport {
+    /// Corrected time of the last packet (the session clock starts at 0).
+    pub end_time_ms: u64,
+    /// From the first packet to the last.
+    pub packet_span_ms: u64,
+    /// `timePlayed` from the results.
+    pub time_played_ms: Option<u64>,
+    /// The header's time limit.
+    pub time_limit_ms: Option<u64>,
+    pub issues: Vec<DurationIssue>,
+}
+
+/// Measures the replay's duration and cross-checks it.
+pub fn check_duration(
+    packets: &[PacketInfo],
+    results: Option<&ReplayResults>,
+    header: Option<&ReplayHeader>,
+) -> DurationReport {
+    let (times, _) = packet_times(packets);
+    let end_time_ms = times.last().map_or(0, |t| t.relative_time_ms);
+    let start_time_ms = times.first().map_or(0, |t| t.relative_time_ms);
+
+    let time_played_ms = results.map(|r| (r.time_played * 1000.0).round() as u64);
+    // time_limit is in minutes, 0 means none
+    let time_limit_ms = header
+        .filter(|h| h.time_limit > 0)
+        .map(|h| h.time_limit as u64 * 60_000);
+
+    let mut issues = Vec::new();
+    if let Some(played) = time_played_ms {
+        if end_time_ms + DURATION_TOLERANCE_MS < played {
+            issues.push(DurationIssue::Truncated {
+                missing_ms: played - end_time_ms,
+            });
+        } else if end_time_ms > played + DURATION_TOLERANCE_MS {
+            issues.push(DurationIssue::LongerThanResults {
+                extra_ms: end_time_ms - played,
+            });
+        }
+    }
+    if let Some(limit) = time_limit_ms {
+        if end_time_ms > limit + DURATION_TOLERANCE_MS {
+            issues.push(DurationIssue::ExceedsTimeLimit {
+                over_ms: end_time_ms - limit,
+            });
+        }
+    }
+
+    DurationReport {
+        end_time_ms,
+        packet_span_ms: end_time_ms - start_time_ms,
+        time_played_ms,
+        time_limit_ms,
+        issues,
+    }
+}
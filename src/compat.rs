@@ -0,0 +1,93 @@
+//! Which replay versions this crate can read, and how much of them.
+//!
+//! Only a handful of versions have been seen (see [`TESTED_VERSIONS`]).
+//! The header and packet framing haven't changed between them, so other
+//! believable versions (see [`format::VERSION_RANGE`]) are expected to
+//! mostly work, but BLKs like the results may have changed.
+
+use crate::format;
+use crate::header::ReplayHeader;
+use serde::Serialize;
+
+/// Versions seen in sample replays, which everything works on. Versions in
+/// between haven't been seen, so they're only [`SupportLevel::Partial`].
+pub const TESTED_VERSIONS: &[u32] = &[101_286, 101_301];
+
+/// How well a replay version is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SupportLevel {
+    /// A tested version.
+    Full,
+    /// Not tested, but close enough that the basics should work.
+    Partial,
+    /// Not a version any replay has had, probably not a replay.
+    Unknown,
+}
+
+/// Parts of the crate that depend on the replay format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Subsystem {
+    Header,
+    /// Decompressing and splitting the stream into packets.
+    PacketStream,
+    Chat,
+    /// The mission settings BLK after the header.
+    MissionSettings,
+    /// The results BLK at `rez_offset`.
+    Results,
+}
+
+const ALL_SUBSYSTEMS: [Subsystem; 5] = [
+    Subsystem::Header,
+    Subsystem::PacketStream,
+    Subsystem::Chat,
+    Subsystem::MissionSettings,
+    Subsystem::Results,
+];
+
+/// What can be expected of a replay version, see [`check`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Compatibility {
+    pub version: u32,
+    pub level: SupportLevel,
+    /// The subsystems expected to work on it.
+    pub working: Vec<Subsystem>,
+}
+
+impl Compatibility {
+    pub fn expects(&self, subsystem: Subsystem) -> bool {
+        self.working.contains(&subsystem)
+    }
+}
+
+/// Classifies a replay version.
+pub fn check(version: u32) -> Compatibility {
+    let (level, working) = if TESTED_VERSIONS.contains(&version) {
+        (SupportLevel::Full, ALL_SUBSYSTEMS.to_vec())
+    } else if format::VERSION_RANGE.contains(&version) {
+        (
+            SupportLevel::Partial,
+            vec![Subsystem::Header, Subsystem::PacketStream, Subsystem::Chat],
+        )
+    } else {
+        (SupportLevel::Unknown, Vec::new())
+    };
+    Compatibility {
+        version,
+        level,
+        working,
+    }
+}
+
+/// Classifies the version at the start of `data` (a replay file). `None`
+/// if it's too short to have one.
+pub fn check_data(data: &[u8]) -> Option<Compatibility> {
+    let version = u32::from_le_bytes(data.get(4..8)?.try_into().unwrap());
+    Some(check(version))
+}
+
+impl ReplayHeader {
+    pub fn compatibility(&self) -> Compatibility {
+        check(self.version)
+    }
+}
@@ -0,0 +1,108 @@
+//! Converting world positions to minimap and map grid coordinates.
+//!
+//! The map is the world's x/z plane (`y` is up), with north towards `+z`.
+//! Its extent isn't in the replay header, so it has to come from the level's
+//! metadata (or be measured by hand).
+
+use crate::telemetry::Vec3;
+use std::fmt;
+
+/// The area of the world covered by the minimap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapBounds {
+    pub min_x: f32,
+    pub min_z: f32,
+    pub max_x: f32,
+    pub max_z: f32,
+}
+
+impl MapBounds {
+    /// A square map of `size` metres centred on `(center_x, center_z)`.
+    pub fn square(center_x: f32, center_z: f32, size: f32) -> Self {
+        let half = size / 2.0;
+        MapBounds {
+            min_x: center_x - half,
+            min_z: center_z - half,
+            max_x: center_x + half,
+            max_z: center_z + half,
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max_z - self.min_z
+    }
+
+    pub fn contains(&self, position: Vec3) -> bool {
+        (self.min_x..=self.max_x).contains(&position.x)
+            && (self.min_z..=self.max_z).contains(&position.z)
+    }
+
+    /// Minimap coordinates `(u, v)` in `0..=1`, from the top left corner
+    /// (north-west). Positions outside the map give values outside that range.
+    pub fn to_minimap(&self, position: Vec3) -> (f32, f32) {
+        (
+            (position.x - self.min_x) / self.width(),
+            (self.max_z - position.z) / self.height(),
+        )
+    }
+
+    /// The inverse of [`to_minimap`](Self::to_minimap), at height 0.
+    pub fn from_minimap(&self, u: f32, v: f32) -> Vec3 {
+        Vec3::new(
+            self.min_x + u * self.width(),
+            0.0,
+            self.max_z - v * self.height(),
+        )
+    }
+}
+
+/// A map grid square, e.g. `C7`: rows are letters from the top,
+/// columns numbers from the left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GridRef {
+    /// 0-based row, `0` is `A`.
+    pub row: u8,
+    /// 0-based column, `0` is `1`.
+    pub column: u8,
+}
+
+impl fmt::Display for GridRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", (b'A' + self.row) as char, self.column + 1)
+    }
+}
+
+/// The in-game map grid laid over [`MapBounds`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapGrid {
+    pub bounds: MapBounds,
+    pub rows: u8,
+    pub columns: u8,
+}
+
+impl MapGrid {
+    /// The usual 10x10 (A1-J10) grid.
+    pub fn new(bounds: MapBounds) -> Self {
+        MapGrid {
+            bounds,
+            rows: 10,
+            columns: 10,
+        }
+    }
+
+    /// The grid square containing `position`, or `None` if it's off the map.
+    pub fn cell(&self, position: Vec3) -> Option<GridRef> {
+        if !self.bounds.contains(position) {
+            return None;
+        }
+        let (u, v) = self.bounds.to_minimap(position);
+        // the far edges belong to the last row/column
+        let column = ((u * self.columns as f32) as u8).min(self.columns - 1);
+        let row = ((v * self.rows as f32) as u8).min(self.rows - 1);
+        Some(GridRef { row, column })
+    }
+}
@@ -0,0 +1,192 @@
+//! Decoders for packets this crate doesn't understand, supplied by other
+//! crates.
+//!
+//! Implement [`PacketDecoder`], register it in a [`DecoderRegistry`] and put
+//! that in [`ParseOptions::decoders`](crate::parser::ParseOptions::decoders).
+//! Whatever it decodes ends up in
+//! [`ParsedReplay::decoded`](crate::parser::ParsedReplay::decoded) (or
+//! [`PacketVisitor::decoded`]).
+//!
+//! For quick experiments there's also [`MpiHandlers`], which hands MPI
+//! messages to closures by message ID:
+//!
+//! ```no_run
+//! # let data = Vec::new();
+//! let mut sizes = Vec::new();
+//! let mut handlers = wrpl::decode::MpiHandlers::new();
+//! handlers.on(0x582d, |_packet, frame| sizes.push(frame.payload.len()));
+//! wrpl::parser::parse_with(&data, handlers)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::logging::warn;
+use crate::parser::{self, MpiFrame, PacketRef, PacketVisitor, ReplayPacketType};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Which packets a [`PacketDecoder`] is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum DecoderKey {
+    /// Every packet of a raw packet type.
+    Packet(u8),
+    /// MPI packets with this message ID. Takes precedence over a
+    /// `Packet(4)` decoder.
+    Mpi(u16),
+}
+
+/// Decodes the payloads of some kind of packet.
+pub trait PacketDecoder: Send + Sync {
+    fn key(&self) -> DecoderKey;
+    /// Short name for what it decodes, e.g. `"unit_position"`. Saved with
+    /// everything it decodes.
+    fn name(&self) -> &str;
+    /// Decodes a packet's payload. For MPI decoders that's the whole
+    /// payload, IDs included (see [`parser::parse_mpi_packet`]).
+    fn decode(&self, payload: &[u8]) -> Result<serde_json::Value>;
+}
+
+/// A packet decoded by a [`PacketDecoder`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedPacket {
+    /// [`PacketDecoder::name`] of the decoder.
+    pub decoder: String,
+    pub raw_type: u8,
+    /// Raw timestamp, in 1/256 ms ticks (see [`crate::clock`]).
+    pub timestamp_ms: u32,
+    /// Same as the packet's [`PacketInfo::stream_offset`](crate::parser::PacketInfo::stream_offset).
+    pub stream_offset: u64,
+    pub value: serde_json::Value,
+}
+
+/// The decoders to run while parsing, by [`DecoderKey`]. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    decoders: HashMap<DecoderKey, Arc<dyn PacketDecoder>>,
+}
+
+impl fmt::Debug for DecoderRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys: Vec<_> = self.decoders.keys().collect();
+        keys.sort();
+        f.debug_struct("DecoderRegistry")
+            .field("keys", &keys)
+            .finish()
+    }
+}
+
+impl DecoderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a decoder, replacing any other with the same key.
+    pub fn register<D: PacketDecoder + 'static>(&mut self, decoder: D) {
+        self.decoders.insert(decoder.key(), Arc::new(decoder));
+    }
+
+    pub fn len(&self) -> usize {
+        self.decoders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    /// The decoder for a packet, if any.
+    pub fn get(&self, raw_type: u8, payload: &[u8]) -> Option<&dyn PacketDecoder> {
+        let mpi = match ReplayPacketType::from(raw_type) {
+            ReplayPacketType::MPI => parser::parse_mpi_packet(payload)
+                .and_then(|frame| self.decoders.get(&DecoderKey::Mpi(frame.message_id))),
+            _ => None,
+        };
+        mpi.or_else(|| self.decoders.get(&DecoderKey::Packet(raw_type)))
+            .map(|d| d.as_ref())
+    }
+
+    /// Runs the decoder for `packet`, if there is one. Decoders that fail
+    /// are logged and give nothing.
+    pub fn decode(&self, packet: &PacketRef<'_>) -> Option<DecodedPacket> {
+        let decoder = self.get(packet.raw_type, packet.payload)?;
+        match decoder.decode(packet.payload) {
+            Ok(value) => Some(DecodedPacket {
+                decoder: decoder.name().to_string(),
+                raw_type: packet.raw_type,
+                timestamp_ms: packet.timestamp_ms,
+                stream_offset: packet.stream_offset,
+                value,
+            }),
+            Err(e) => {
+                warn!(
+                    "Decoder {} failed on the packet at stream offset {:#x}: {:#}",
+                    decoder.name(),
+                    packet.stream_offset,
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+type MpiHandler<'a> = Box<dyn FnMut(&PacketRef<'_>, MpiFrame<'_>) + 'a>;
+
+/// Closures to call with MPI messages, by message ID. A [`PacketVisitor`],
+/// so use it with [`parser::parse_with`].
+#[derive(Default)]
+pub struct MpiHandlers<'a> {
+    handlers: HashMap<u16, MpiHandler<'a>>,
+    other: Option<MpiHandler<'a>>,
+}
+
+impl fmt::Debug for MpiHandlers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<_> = self.handlers.keys().collect();
+        ids.sort();
+        f.debug_struct("MpiHandlers")
+            .field("message_ids", &ids)
+            .field("other", &self.other.is_some())
+            .finish()
+    }
+}
+
+impl<'a> MpiHandlers<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `handler` with every MPI message with ID `message_id`,
+    /// replacing any handler it had.
+    pub fn on<F>(&mut self, message_id: u16, handler: F)
+    where
+        F: FnMut(&PacketRef<'_>, MpiFrame<'_>) + 'a,
+    {
+        self.handlers.insert(message_id, Box::new(handler));
+    }
+
+    /// Calls `handler` with the MPI messages no other handler is for.
+    pub fn on_other<F>(&mut self, handler: F)
+    where
+        F: FnMut(&PacketRef<'_>, MpiFrame<'_>) + 'a,
+    {
+        self.other = Some(Box::new(handler));
+    }
+}
+
+impl PacketVisitor for MpiHandlers<'_> {
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        if packet.packet_type != ReplayPacketType::MPI {
+            return;
+        }
+        let Some(frame) = parser::parse_mpi_packet(packet.payload) else {
+            return;
+        };
+        if let Some(handler) = self.handlers.get_mut(&frame.message_id) {
+            handler(&packet, frame);
+        } else if let Some(handler) = &mut self.other {
+            handler(&packet, frame);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Grouping replay files by session, to avoid counting the same battle twice.
+//!
+//! The same battle can end up on disk several times: the client replay, server
+//! replays downloaded from the CDN, or just the same file downloaded twice.
+//! All of them share the header's session ID.
+//!
+//! A server replay is split into segments (see [`crate::download`]), which
+//! also share the session ID. The segments together are one copy of the
+//! battle; they're told apart by [`ReplayFileSummary::segment_hash`], so only
+//! byte-identical segments count as copies of each other.
+
+use crate::header::SessionId;
+use crate::locate::ReplayFileSummary;
+use std::collections::{BTreeMap, HashSet};
+
+/// One copy of a battle: a client replay, or the segments of a server
+/// replay (in path order).
+#[derive(Debug, Clone)]
+pub struct ReplayCopy {
+    pub files: Vec<ReplayFileSummary>,
+}
+
+impl ReplayCopy {
+    /// Ranks how complete the copy is, like [`completeness`] over all its
+    /// files: whether any of them has results, then the total size.
+    pub fn completeness(&self) -> (bool, u64) {
+        self.files
+            .iter()
+            .map(completeness)
+            .fold((false, 0), |(results, size), (has, len)| {
+                (results || has, size + len)
+            })
+    }
+
+    /// Whether this is a server replay's segments.
+    pub fn is_segmented(&self) -> bool {
+        self.files.iter().any(|f| f.segment_hash.is_some())
+    }
+}
+
+/// All copies of one session, most complete first.
+#[derive(Debug, Clone)]
+pub struct SessionGroup {
+    /// The shared session ID.
+    pub session_id: SessionId,
+    /// The copies of this session, sorted by [`ReplayCopy::completeness`]
+    /// (best first).
+    pub copies: Vec<ReplayCopy>,
+}
+
+impl SessionGroup {
+    /// The most complete copy of the battle.
+    pub fn best(&self) -> &ReplayCopy {
+        // groups are never empty
+        &self.copies[0]
+    }
+
+    /// Every copy except [`SessionGroup::best`].
+    pub fn duplicates(&self) -> &[ReplayCopy] {
+        &self.copies[1..]
+    }
+
+    /// Whether more than one copy was found for this session.
+    pub fn has_duplicates(&self) -> bool {
+        self.copies.len() > 1
+    }
+}
+
+/// Ranks how complete a replay file is, higher is better.
+///
+/// Files whose results blob lies inside the file beat those without results,
+/// then larger files beat smaller (possibly truncated) ones.
+pub fn completeness(summary: &ReplayFileSummary) -> (bool, u64) {
+    let has_results = summary
+        .header
+        .as_ref()
+        .is_some_and(|h| h.rez_offset > 0 && (h.rez_offset as u64) < summary.size);
+    (has_results, summary.size)
+}
+
+/// Splits the files of one session into copies. Each client replay is a
+/// copy; the server segments make up one more, with each repeated segment
+/// a (partial) copy of its own.
+fn copies(files: Vec<ReplayFileSummary>) -> Vec<ReplayCopy> {
+    let mut copies = Vec::new();
+    let mut segments = Vec::new();
+    let mut seen = HashSet::new();
+    for file in files {
+        match file.segment_hash {
+            Some(hash) if seen.insert(hash) => segments.push(file),
+            _ => copies.push(ReplayCopy { files: vec![file] }),
+        }
+    }
+    if !segments.is_empty() {
+        segments.sort_by(|a, b| a.path.cmp(&b.path));
+        copies.push(ReplayCopy { files: segments });
+    }
+    copies.sort_by_key(|c| std::cmp::Reverse(c.completeness()));
+    copies
+}
+
+/// Groups replay files by session ID.
+/// Files whose header couldn't be parsed are left out.
+pub fn group_by_session(summaries: &[ReplayFileSummary]) -> Vec<SessionGroup> {
+    let mut sessions: BTreeMap<SessionId, Vec<ReplayFileSummary>> = BTreeMap::new();
+
+    for summary in summaries {
+        if let Some(header) = &summary.header {
+            sessions
+                .entry(header.session_id)
+                .or_default()
+                .push(summary.clone());
+        }
+    }
+
+    sessions
+        .into_iter()
+        .map(|(session_id, files)| SessionGroup {
+            session_id,
+            copies: copies(files),
+        })
+        .collect()
+}
+
+/// Returns only the files of the most complete copy of each session: one
+/// client replay, or all segments of a server replay.
+pub fn unique_replays(summaries: &[ReplayFileSummary]) -> Vec<ReplayFileSummary> {
+    group_by_session(summaries)
+        .into_iter()
+        .flat_map(|mut group| group.copies.swap_remove(0).files)
+        .collect()
+}
@@ -0,0 +1,303 @@
+//! Comparing the packets of two replays of the same session, and the
+//! headers of any two replays.
+//!
+//! Packets are matched when they have the same type and payload and their
+//! corrected times (see [`crate::clock`]) are close. Two recordings don't
+//! start their clocks at the same moment, so by default the offset between
+//! them is estimated first from payloads that occur exactly once in each.
+
+use crate::clock;
+use crate::header::ReplayHeader;
+use crate::parser::PacketInfo;
+use crate::utils::hex;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+/// How [`diff_packets`] matches packets.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOptions {
+    /// How far apart (after alignment) two matching packets may be.
+    pub tolerance_ms: u64,
+    /// Estimate the clock offset between the replays before matching.
+    pub align: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            tolerance_ms: 1000,
+            align: true,
+        }
+    }
+}
+
+/// Match counts for one raw packet type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TypeDiff {
+    pub matched: usize,
+    pub only_in_a: usize,
+    pub only_in_b: usize,
+}
+
+/// The result of [`diff_packets`]. Packets are referred to by index.
+#[derive(Debug, Clone, Default)]
+pub struct PacketDiff {
+    /// Milliseconds added to `a`'s times to line them up with `b`'s.
+    pub offset_ms: i64,
+    /// Matched `(a, b)` index pairs.
+    pub matched: Vec<(usize, usize)>,
+    pub only_in_a: Vec<usize>,
+    pub only_in_b: Vec<usize>,
+    pub by_type: BTreeMap<u8, TypeDiff>,
+}
+
+impl PacketDiff {
+    /// Whether every packet found a match.
+    pub fn is_identical(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty()
+    }
+}
+
+type PacketKey<'a> = (u8, &'a [u8]);
+
+fn key(packet: &PacketInfo) -> PacketKey<'_> {
+    (packet.raw_type, &packet.payload)
+}
+
+fn corrected_times(packets: &[PacketInfo]) -> Vec<i64> {
+    let (times, _) = clock::packet_times(packets);
+    times.iter().map(|t| t.relative_time_ms as i64).collect()
+}
+
+/// Index of each payload that occurs exactly once (`None` for repeats).
+fn unique_positions(packets: &[PacketInfo]) -> HashMap<PacketKey<'_>, Option<usize>> {
+    let mut positions = HashMap::new();
+    for (i, packet) in packets.iter().enumerate() {
+        positions
+            .entry(key(packet))
+            .and_modify(|e| *e = None)
+            .or_insert(Some(i));
+    }
+    positions
+}
+
+/// Median time difference between packets whose payload is unique in both.
+fn estimate_offset(a: &[PacketInfo], a_times: &[i64], b: &[PacketInfo], b_times: &[i64]) -> i64 {
+    let b_unique = unique_positions(b);
+    let mut deltas: Vec<i64> = unique_positions(a)
+        .iter()
+        .filter_map(|(k, &i)| {
+            let j = (*b_unique.get(k)?)?;
+            Some(b_times[j] - a_times[i?])
+        })
+        .collect();
+    if deltas.is_empty() {
+        return 0;
+    }
+    deltas.sort_unstable();
+    deltas[deltas.len() / 2]
+}
+
+/// Matches the packets of `a` against those of `b`.
+pub fn diff_packets(a: &[PacketInfo], b: &[PacketInfo], options: &DiffOptions) -> PacketDiff {
+    let a_times = corrected_times(a);
+    let b_times = corrected_times(b);
+    let offset_ms = if options.align {
+        estimate_offset(a, &a_times, b, &b_times)
+    } else {
+        0
+    };
+    let tolerance = options.tolerance_ms as i64;
+
+    // unmatched b packets by key, in stream (so time) order
+    let mut candidates: HashMap<PacketKey, VecDeque<usize>> = HashMap::new();
+    for (j, packet) in b.iter().enumerate() {
+        candidates.entry(key(packet)).or_default().push_back(j);
+    }
+    let mut b_matched = vec![false; b.len()];
+
+    let mut diff = PacketDiff {
+        offset_ms,
+        ..Default::default()
+    };
+    for (i, packet) in a.iter().enumerate() {
+        let time = a_times[i] + offset_ms;
+        let found = candidates.get_mut(&key(packet)).and_then(|list| {
+            // a's times only go up, so candidates too early now stay too early
+            while list.front().is_some_and(|&j| b_times[j] < time - tolerance) {
+                list.pop_front();
+            }
+            // b's times only go up too, so only the first one can be in range
+            list.front()
+                .is_some_and(|&j| b_times[j] <= time + tolerance)
+                .then(|| list.pop_front())
+                .flatten()
+        });
+        let entry = diff.by_type.entry(packet.raw_type).or_default();
+        match found {
+            Some(j) => {
+                b_matched[j] = true;
+                entry.matched += 1;
+                diff.matched.push((i, j));
+            }
+            None => {
+                entry.only_in_a += 1;
+                diff.only_in_a.push(i);
+            }
+        }
+    }
+    for (j, packet) in b.iter().enumerate() {
+        if !b_matched[j] {
+            diff.by_type.entry(packet.raw_type).or_default().only_in_b += 1;
+            diff.only_in_b.push(j);
+        }
+    }
+
+    diff
+}
+
+/// A header field that differs, see [`diff_headers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderFieldDiff {
+    /// The field's name; `mission_settings.<key>` for mission settings, and
+    /// the region's name for [`crate::header::UnknownRegions`].
+    pub field: String,
+    /// The value in `a` (hex for raw regions), empty if it has none.
+    pub a: String,
+    pub b: String,
+    /// For raw regions, the file offsets of the bytes that differ.
+    pub offsets: Vec<usize>,
+}
+
+/// Compares two headers field by field. Mission settings are compared by
+/// top-level key, and unknown regions byte by byte if both headers kept
+/// them (see [`crate::header::HeaderOptions::keep_unknown`]).
+pub fn diff_headers(a: &ReplayHeader, b: &ReplayHeader) -> Vec<HeaderFieldDiff> {
+    let mut diffs = Vec::new();
+    let mut compare = |field: &str, a: String, b: String| {
+        if a != b {
+            diffs.push(HeaderFieldDiff {
+                field: field.to_string(),
+                a,
+                b,
+                offsets: Vec::new(),
+            });
+        }
+    };
+    compare(
+        "magic",
+        format!("{:#x}", a.magic),
+        format!("{:#x}", b.magic),
+    );
+    compare("version", a.version.to_string(), b.version.to_string());
+    compare("level", a.level.clone(), b.level.clone());
+    compare(
+        "level_settings",
+        a.level_settings.clone(),
+        b.level_settings.clone(),
+    );
+    compare("battle_type", a.battle_type.clone(), b.battle_type.clone());
+    compare("environment", a.environment.clone(), b.environment.clone());
+    compare("visibility", a.visibility.clone(), b.visibility.clone());
+    compare(
+        "rez_offset",
+        a.rez_offset.to_string(),
+        b.rez_offset.to_string(),
+    );
+    compare(
+        "difficulty",
+        a.difficulty.difficulty_value.to_string(),
+        b.difficulty.difficulty_value.to_string(),
+    );
+    compare(
+        "difficulty_unknown_nibble",
+        a.difficulty.unknown_nibble.to_string(),
+        b.difficulty.unknown_nibble.to_string(),
+    );
+    compare(
+        "session_type",
+        a.session_type.to_string(),
+        b.session_type.to_string(),
+    );
+    compare(
+        "session_id",
+        format!("{:x}", a.session_id),
+        format!("{:x}", b.session_id),
+    );
+    compare(
+        "m_set_size",
+        a.m_set_size.to_string(),
+        b.m_set_size.to_string(),
+    );
+    compare("loc_name", a.loc_name.clone(), b.loc_name.clone());
+    compare(
+        "start_time",
+        a.start_time.to_string(),
+        b.start_time.to_string(),
+    );
+    compare(
+        "time_limit",
+        a.time_limit.to_string(),
+        b.time_limit.to_string(),
+    );
+    compare(
+        "score_limit",
+        a.score_limit.to_string(),
+        b.score_limit.to_string(),
+    );
+    compare(
+        "battle_class",
+        a.battle_class.clone(),
+        b.battle_class.clone(),
+    );
+    compare(
+        "battle_kill_streak",
+        a.battle_kill_streak.clone(),
+        b.battle_kill_streak.clone(),
+    );
+
+    match (&a.mission_settings, &b.mission_settings) {
+        (
+            Some(serde_json::Value::Object(settings_a)),
+            Some(serde_json::Value::Object(settings_b)),
+        ) => {
+            let keys: BTreeSet<_> = settings_a.keys().chain(settings_b.keys()).collect();
+            for key in keys {
+                let value = |settings: &serde_json::Map<String, serde_json::Value>| {
+                    settings.get(key).map(|v| v.to_string()).unwrap_or_default()
+                };
+                compare(
+                    &format!("mission_settings.{}", key),
+                    value(settings_a),
+                    value(settings_b),
+                );
+            }
+        }
+        // server replays have none, no point listing every key
+        (settings_a, settings_b) => {
+            let present = |settings: &Option<serde_json::Value>| {
+                if settings.is_some() { "present" } else { "" }.to_string()
+            };
+            compare("mission_settings", present(settings_a), present(settings_b));
+        }
+    }
+
+    if let (Some(unknown_a), Some(unknown_b)) = (&a.unknown, &b.unknown) {
+        for ((field, offset, bytes_a), (_, _, bytes_b)) in
+            unknown_a.regions().into_iter().zip(unknown_b.regions())
+        {
+            let offsets: Vec<usize> = (0..bytes_a.len().max(bytes_b.len()))
+                .filter(|&i| bytes_a.get(i) != bytes_b.get(i))
+                .map(|i| offset + i)
+                .collect();
+            if !offsets.is_empty() {
+                diffs.push(HeaderFieldDiff {
+                    field: field.to_string(),
+                    a: hex::encode(bytes_a),
+                    b: hex::encode(bytes_b),
+                    offsets,
+                });
+            }
+        }
+    }
+    diffs
+}
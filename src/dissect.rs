@@ -0,0 +1,113 @@
+//! Which bytes of a packet are which field, for inspector UIs that show a
+//! payload next to what it decodes to (like Wireshark).
+//!
+//! Only the packet types with known layouts are broken down: chat and the
+//! MPI header. Anything else gets no annotations.
+
+use crate::parser::{self, PacketInfo, ReplayPacketType};
+use serde::Serialize;
+use std::ops::Range;
+
+/// A decoded field of a packet payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldAnnotation {
+    /// Where the field is in the payload.
+    pub range: Range<usize>,
+    pub name: &'static str,
+    /// The decoded value, for display.
+    pub value: String,
+}
+
+impl FieldAnnotation {
+    fn new(range: Range<usize>, name: &'static str, value: impl ToString) -> Self {
+        FieldAnnotation {
+            range,
+            name,
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Describes the fields of `packet`'s payload, in order. Empty if its type
+/// isn't understood or the payload was discarded.
+pub fn dissect(packet: &PacketInfo) -> Vec<FieldAnnotation> {
+    dissect_payload(packet.packet_type, &packet.payload)
+}
+
+/// [`dissect`] for just a payload and its type.
+pub fn dissect_payload(packet_type: ReplayPacketType, payload: &[u8]) -> Vec<FieldAnnotation> {
+    let mut fields = match packet_type {
+        ReplayPacketType::Chat => dissect_chat(payload),
+        ReplayPacketType::MPI => dissect_mpi(payload),
+        _ => return Vec::new(),
+    };
+    // whatever's left after the known fields
+    let end = fields.last().map_or(0, |f| f.range.end);
+    if !fields.is_empty() && end < payload.len() {
+        fields.push(FieldAnnotation::new(
+            end..payload.len(),
+            "unknown",
+            format!("{} bytes", payload.len() - end),
+        ));
+    }
+    fields
+}
+
+fn dissect_mpi(payload: &[u8]) -> Vec<FieldAnnotation> {
+    let Some(frame) = parser::parse_mpi_packet(payload) else {
+        return Vec::new();
+    };
+    let mut fields = vec![
+        FieldAnnotation::new(0..2, "object_id", frame.object_id),
+        FieldAnnotation::new(2..4, "message_id", format!("{:#06x}", frame.message_id)),
+    ];
+    if !frame.payload.is_empty() {
+        fields.push(FieldAnnotation::new(
+            4..payload.len(),
+            "message",
+            format!("{} bytes", frame.payload.len()),
+        ));
+    }
+    fields
+}
+
+/// Follows [`parser::parse_chat_packet`], stopping at the first field that
+/// doesn't fit.
+fn dissect_chat(payload: &[u8]) -> Vec<FieldAnnotation> {
+    let mut fields = Vec::new();
+    let Some(&flags) = payload.first() else {
+        return fields;
+    };
+    fields.push(FieldAnnotation::new(
+        0..1,
+        "flags",
+        format!("{:#04x}", flags),
+    ));
+
+    let mut pos = 1;
+    for (len_name, name) in [("sender_len", "sender"), ("message_len", "message")] {
+        let Some(&len) = payload.get(pos) else {
+            return fields;
+        };
+        fields.push(FieldAnnotation::new(pos..pos + 1, len_name, len));
+        pos += 1;
+        let Some(text) = payload.get(pos..pos + len as usize) else {
+            return fields;
+        };
+        fields.push(FieldAnnotation::new(
+            pos..pos + text.len(),
+            name,
+            String::from_utf8_lossy(text),
+        ));
+        pos += text.len();
+    }
+
+    for name in ["channel_type", "is_enemy"] {
+        let Some(&value) = payload.get(pos) else {
+            return fields;
+        };
+        fields.push(FieldAnnotation::new(pos..pos + 1, name, value));
+        pos += 1;
+    }
+    fields
+}
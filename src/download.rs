@@ -0,0 +1,122 @@
+//! Downloading of server replays from Gaijin's CDN.
+//!
+//! Server replays are split into numbered segments (`0000.wrpl`, `0001.wrpl`, ...)
+//! which are fetched one by one until the CDN returns a 404.
+
+use crate::header::SessionId;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The default CDN to download replays from.
+// this specific domain is optimal -
+// the something.warthunder.com one has rate limiting
+// but still redirects to this one
+pub const DEFAULT_BASE_URL: &str = "https://wt-replays-cdnnow.cdn.gaijin.net";
+
+/// Options controlling how replays are downloaded.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Base URL of the replay CDN, without a trailing slash.
+    pub base_url: String,
+    /// Proxy to route requests through (http://, https:// or socks5://).
+    pub proxy: Option<String>,
+    /// User-Agent header sent with each request.
+    pub user_agent: String,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            proxy: None,
+            user_agent: concat!("wrpl/", env!("CARGO_PKG_VERSION")).to_string(),
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Builds a `reqwest::Client` honouring the proxy and User-Agent.
+    pub fn build_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().user_agent(&self.user_agent);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy {}", proxy))?,
+            );
+        }
+        builder.build().context("Failed to build HTTP client")
+    }
+}
+
+/// Returns the URL of segment `index` of the session `id` (hex).
+pub fn segment_url(base_url: &str, id: &str, index: u32) -> String {
+    // the path wants all 16 hex digits
+    let id = id
+        .parse::<SessionId>()
+        .map_or_else(|_| id.to_string(), SessionId::url_id);
+    format!(
+        "{}/{}/{:04}.wrpl",
+        base_url.trim_end_matches('/'),
+        id,
+        index
+    )
+}
+
+/// Downloads every segment of the session `id` (hex) into `dest`.
+/// Returns the paths of the downloaded segments, in order.
+pub async fn fetch_session(
+    id: &str,
+    dest: &Path,
+    options: &DownloadOptions,
+) -> Result<Vec<PathBuf>> {
+    let client = options.build_client()?;
+    fetch_session_with_client(&client, id, dest, options).await
+}
+
+/// Same as [`fetch_session`], but reuses an existing client.
+/// Useful when downloading many sessions in a row.
+pub async fn fetch_session_with_client(
+    client: &reqwest::Client,
+    id: &str,
+    dest: &Path,
+    options: &DownloadOptions,
+) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dest)
+        .with_context(|| format!("Failed to create output directory {:?}", dest))?;
+
+    let mut paths = Vec::new();
+    let mut i = 0;
+    loop {
+        let url = segment_url(&options.base_url, id, i);
+
+        let response = client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("Request for {} failed", url))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            info!("Replay {} not found, stopping.", i);
+            break;
+        }
+
+        if !response.status().is_success() {
+            if paths.is_empty() {
+                bail!("Error downloading replay {}: {}", i, response.status());
+            }
+            warn!("Error downloading replay {}: {}", i, response.status());
+            break;
+        }
+
+        let file_path = dest.join(format!("{:04}.wrpl", i));
+        let content = response.bytes().await?;
+        fs::write(&file_path, &content)
+            .with_context(|| format!("Failed to write {:?}", file_path))?;
+
+        info!("Downloaded replay: {}/{:04}.wrpl", id, i);
+        paths.push(file_path);
+        i += 1;
+    }
+    Ok(paths)
+}
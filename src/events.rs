@@ -0,0 +1,240 @@
+//! Publishing what a parse finds to many consumers at once (a UI, a logger,
+//! stats...), so they don't each parse the replay.
+//!
+//! The broadcasters are [`PacketVisitor`]s, so they're used with
+//! [`parser::parse_with`](crate::parser::parse_with):
+//!
+//! ```no_run
+//! # let data = Vec::new();
+//! let mut broadcaster = wrpl::events::Broadcaster::new();
+//! let events = broadcaster.subscribe();
+//! std::thread::spawn(move || {
+//!     for event in events {
+//!         println!("{:?}", event);
+//!     }
+//! });
+//! // dropping the broadcaster afterwards ends the subscribers' loops
+//! wrpl::parser::parse_with(&data, broadcaster)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! For a replay that's already parsed, [`ParsedReplay::lifecycle_events`]
+//! finds when the battle started and ended.
+
+use crate::clock;
+use crate::decode::DecodedPacket;
+use crate::header::ReplayHeader;
+use crate::parser::{
+    ChatInfo, PacketInfo, PacketRef, PacketVisitor, ParsedReplay, ReplayPacketType, ReplayResults,
+};
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+/// Something found while parsing. Sent as `Arc<GameEvent>`, so subscribers
+/// share one copy.
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    Header(Box<ReplayHeader>),
+    Packet(PacketInfo),
+    /// Comes right after its chat packet.
+    Chat(ChatInfo),
+    /// Comes right after its packet, see [`crate::decode`].
+    Decoded(DecodedPacket),
+    /// The start marker packet, once loading is over. The packet's
+    /// timestamp in ms, without the corrections of [`crate::clock`].
+    BattleStart {
+        timestamp_ms: u32,
+    },
+    /// The end marker packet, which replays that were cut short don't have.
+    BattleEnd {
+        timestamp_ms: u32,
+    },
+    /// After all packets, if the replay has results.
+    Results(ReplayResults),
+}
+
+/// The [`GameEvent::BattleStart`] or [`GameEvent::BattleEnd`] for a
+/// marker packet.
+fn lifecycle_event(packet: &PacketRef<'_>) -> Option<GameEvent> {
+    let timestamp_ms = clock::ticks_to_ms(packet.timestamp_ms);
+    match packet.packet_type {
+        ReplayPacketType::StartMarker => Some(GameEvent::BattleStart { timestamp_ms }),
+        ReplayPacketType::EndMarker => Some(GameEvent::BattleEnd { timestamp_ms }),
+        _ => None,
+    }
+}
+
+/// Sends every [`GameEvent`] to any number of std channels.
+#[derive(Debug, Default)]
+pub struct Broadcaster {
+    senders: Vec<Sender<Arc<GameEvent>>>,
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new channel that gets every event from now on. It ends when the
+    /// broadcaster is dropped.
+    pub fn subscribe(&mut self) -> Receiver<Arc<GameEvent>> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Number of subscribers still listening.
+    pub fn subscribers(&self) -> usize {
+        self.senders.len()
+    }
+
+    pub fn send(&mut self, event: GameEvent) {
+        let event = Arc::new(event);
+        // forget subscribers that hung up
+        self.senders
+            .retain(|sender| sender.send(Arc::clone(&event)).is_ok());
+    }
+}
+
+impl PacketVisitor for Broadcaster {
+    fn header(&mut self, header: &ReplayHeader) {
+        self.send(GameEvent::Header(Box::new(header.clone())));
+    }
+
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        if !self.senders.is_empty() {
+            self.send(GameEvent::Packet(packet.to_owned()));
+        }
+        if let Some(event) = lifecycle_event(&packet) {
+            self.send(event);
+        }
+    }
+
+    fn chat(&mut self, chat: &ChatInfo) {
+        self.send(GameEvent::Chat(chat.clone()));
+    }
+
+    fn decoded(&mut self, decoded: &DecodedPacket) {
+        self.send(GameEvent::Decoded(decoded.clone()));
+    }
+
+    fn results(&mut self, results: &ReplayResults) {
+        self.send(GameEvent::Results(results.clone()));
+    }
+}
+
+/// Sends every [`GameEvent`] to a tokio broadcast channel. Subscribers that
+/// fall more than `capacity` events behind miss some (see
+/// [`tokio::sync::broadcast`]).
+#[cfg(feature = "broadcast")]
+#[derive(Debug)]
+pub struct TokioBroadcaster {
+    sender: tokio::sync::broadcast::Sender<Arc<GameEvent>>,
+}
+
+#[cfg(feature = "broadcast")]
+impl TokioBroadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = tokio::sync::broadcast::channel(capacity);
+        TokioBroadcaster { sender }
+    }
+
+    /// A receiver for every event from now on. It ends when the
+    /// broadcaster is dropped.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Arc<GameEvent>> {
+        self.sender.subscribe()
+    }
+
+    pub fn send(&self, event: GameEvent) {
+        // only fails if no one's listening, which is fine
+        let _ = self.sender.send(Arc::new(event));
+    }
+}
+
+#[cfg(feature = "broadcast")]
+impl PacketVisitor for TokioBroadcaster {
+    fn header(&mut self, header: &ReplayHeader) {
+        self.send(GameEvent::Header(Box::new(header.clone())));
+    }
+
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        if self.sender.receiver_count() > 0 {
+            self.send(GameEvent::Packet(packet.to_owned()));
+        }
+        if let Some(event) = lifecycle_event(&packet) {
+            self.send(event);
+        }
+    }
+
+    fn chat(&mut self, chat: &ChatInfo) {
+        self.send(GameEvent::Chat(chat.clone()));
+    }
+
+    fn decoded(&mut self, decoded: &DecodedPacket) {
+        self.send(GameEvent::Decoded(decoded.clone()));
+    }
+
+    fn results(&mut self, results: &ReplayResults) {
+        self.send(GameEvent::Results(results.clone()));
+    }
+}
+
+/// Whether a [`LifecycleEvent`] is the battle starting or ending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Lifecycle {
+    BattleStart,
+    BattleEnd,
+}
+
+/// A marker packet on the replay's timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LifecycleEvent {
+    pub kind: Lifecycle,
+    /// Index in [`ParsedReplay::packets`].
+    pub packet_index: usize,
+    /// Corrected time in ms, see [`crate::clock`].
+    pub time_ms: u64,
+}
+
+impl ParsedReplay {
+    /// The battle start and end markers, in order. Needs the marker packets
+    /// (types 0 and 1) to have been kept.
+    pub fn lifecycle_events(&self) -> Vec<LifecycleEvent> {
+        let (times, _) = self.packet_times();
+        self.packets
+            .iter()
+            .zip(times)
+            .enumerate()
+            .filter_map(|(packet_index, (packet, time))| {
+                let kind = match packet.packet_type {
+                    ReplayPacketType::StartMarker => Lifecycle::BattleStart,
+                    ReplayPacketType::EndMarker => Lifecycle::BattleEnd,
+                    _ => return None,
+                };
+                Some(LifecycleEvent {
+                    kind,
+                    packet_index,
+                    time_ms: time.relative_time_ms,
+                })
+            })
+            .collect()
+    }
+
+    /// When the battle started, i.e. how long the loading before it took.
+    /// `None` without a start marker.
+    pub fn battle_start_ms(&self) -> Option<u64> {
+        self.lifecycle_events()
+            .into_iter()
+            .find(|e| e.kind == Lifecycle::BattleStart)
+            .map(|e| e.time_ms)
+    }
+
+    /// Whether the replay got to an end marker. Replays of crashed clients
+    /// and server segments other than the last don't.
+    pub fn reached_end(&self) -> bool {
+        self.packets
+            .iter()
+            .any(|p| p.packet_type == ReplayPacketType::EndMarker)
+    }
+}
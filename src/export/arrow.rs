@@ -0,0 +1,144 @@
+//! Arrow IPC export of packets, chat and telemetry, for data-science tools.
+//!
+//! The resulting `.arrow` files load directly with `pyarrow.ipc`,
+//! `polars.read_ipc` or `pandas.read_feather`.
+
+use crate::clock;
+use crate::parser::ParsedReplay;
+use crate::telemetry::Track;
+use anyhow::{Context, Result};
+use arrow_array::{
+    ArrayRef, BinaryArray, Float32Array, Int32Array, RecordBatch, StringArray, UInt32Array,
+    UInt64Array, UInt8Array,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// One row per packet: index, type, timestamp (in ms, see
+/// [`clock::ticks_to_ms`]), payload size and payload.
+pub fn packets_batch(replay: &ParsedReplay) -> Result<RecordBatch> {
+    let packets = &replay.packets;
+    let schema = Schema::new(vec![
+        Field::new("index", DataType::UInt64, false),
+        Field::new("packet_type", DataType::Utf8, false),
+        Field::new("timestamp_ms", DataType::UInt32, false),
+        Field::new("payload_size", DataType::UInt32, false),
+        Field::new("payload", DataType::Binary, false),
+    ]);
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(0..packets.len() as u64)),
+        Arc::new(StringArray::from_iter_values(
+            packets.iter().map(|p| format!("{:?}", p.packet_type)),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            packets.iter().map(|p| clock::ticks_to_ms(p.timestamp_ms)),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            packets.iter().map(|p| p.size),
+        )),
+        Arc::new(BinaryArray::from_iter_values(
+            packets.iter().map(|p| p.payload.as_slice()),
+        )),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema), columns).context("Failed to build packets table")
+}
+
+/// One row per chat message, timestamped in ms.
+pub fn chat_batch(replay: &ParsedReplay) -> Result<RecordBatch> {
+    let chat = &replay.chat_messages;
+    let schema = Schema::new(vec![
+        Field::new("timestamp_ms", DataType::UInt32, false),
+        Field::new("sender", DataType::Utf8, false),
+        Field::new("message", DataType::Utf8, false),
+        Field::new("channel_type", DataType::UInt8, true),
+        Field::new("is_enemy", DataType::UInt8, true),
+        Field::new("team", DataType::Int32, true),
+        Field::new("squad", DataType::Int32, true),
+    ]);
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(
+            chat.iter().map(|c| clock::ticks_to_ms(c.timestamp_ms)),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            chat.iter().map(|c| c.sender.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            chat.iter().map(|c| c.message.as_str()),
+        )),
+        Arc::new(chat.iter().map(|c| c.channel_type).collect::<UInt8Array>()),
+        Arc::new(chat.iter().map(|c| c.is_enemy).collect::<UInt8Array>()),
+        Arc::new(chat.iter().map(|c| c.team()).collect::<Int32Array>()),
+        Arc::new(chat.iter().map(|c| c.squad()).collect::<Int32Array>()),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema), columns).context("Failed to build chat table")
+}
+
+/// One row per telemetry sample, across all tracks.
+pub fn tracks_batch(tracks: &[Track]) -> Result<RecordBatch> {
+    let samples = || {
+        tracks
+            .iter()
+            .flat_map(|t| t.samples.iter().map(move |s| (&t.unit, s)))
+    };
+    let schema = Schema::new(vec![
+        Field::new("unit_id", DataType::UInt32, false),
+        Field::new("name", DataType::Utf8, true),
+        Field::new("time_ms", DataType::UInt32, false),
+        Field::new("x", DataType::Float32, false),
+        Field::new("y", DataType::Float32, false),
+        Field::new("z", DataType::Float32, false),
+    ]);
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(samples().map(|(u, _)| u.id))),
+        Arc::new(
+            samples()
+                .map(|(u, _)| u.name.as_deref())
+                .collect::<StringArray>(),
+        ),
+        Arc::new(UInt32Array::from_iter_values(
+            samples().map(|(_, s)| s.time_ms),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            samples().map(|(_, s)| s.position.x),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            samples().map(|(_, s)| s.position.y),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            samples().map(|(_, s)| s.position.z),
+        )),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema), columns).context("Failed to build telemetry table")
+}
+
+/// Writes a single table as an Arrow IPC file.
+pub fn write_ipc<W: Write>(batch: &RecordBatch, writer: W) -> Result<()> {
+    let mut ipc = FileWriter::try_new(writer, &batch.schema())?;
+    ipc.write(batch)?;
+    ipc.finish()?;
+    Ok(())
+}
+
+/// Writes `packets.arrow` and `chat.arrow` into `dir`.
+pub fn write_replay_ipc(replay: &ParsedReplay, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    for (name, batch) in [
+        ("packets.arrow", packets_batch(replay)?),
+        ("chat.arrow", chat_batch(replay)?),
+    ] {
+        let path = dir.join(name);
+        let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        write_ipc(&batch, file)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! CSV export of chat, per-player results and telemetry samples.
+//!
+//! Kill events aren't decoded from the packet stream yet, so kills are
+//! exported per player from the results (one row per player).
+
+use crate::clock;
+use crate::parser::{ChatInfo, ReplayResults};
+use crate::telemetry::Track;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Quotes a field if it contains a separator, quote or newline.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// `timestamp_ms,sender,message,channel_type,is_enemy,team,squad`, the team
+/// and squad empty for senders that aren't in the results.
+pub fn write_chat_csv<W: Write>(chat: &[ChatInfo], mut writer: W) -> Result<()> {
+    writeln!(
+        writer,
+        "timestamp_ms,sender,message,channel_type,is_enemy,team,squad"
+    )?;
+    for c in chat {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{}",
+            clock::ticks_to_ms(c.timestamp_ms),
+            field(&c.sender),
+            field(&c.message),
+            opt(c.channel_type),
+            opt(c.is_enemy),
+            opt(c.team()),
+            opt(c.squad())
+        )?;
+    }
+    // a BufWriter would swallow errors from its last write when dropped
+    writer.flush()?;
+    Ok(())
+}
+
+/// One row per player with their end-of-battle stats.
+pub fn write_players_csv<W: Write>(results: &ReplayResults, mut writer: W) -> Result<()> {
+    writeln!(
+        writer,
+        "user_id,username,squadron_tag,platform,team,squad,auto_squad,kills,ground_kills,\
+naval_kills,team_kills,ai_kills,ai_ground_kills,ai_naval_kills,assists,deaths,capture_zone,\
+damage_zone,score,award_damage,missile_evades,lineup"
+    )?;
+    for p in &results.players {
+        let (info, d) = (&p.player_info, &p.replay_data);
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            field(&info.user_id),
+            field(&info.username),
+            field(&info.squadron_tag),
+            field(&info.platform),
+            d.team,
+            d.squad,
+            d.auto_squad,
+            d.kills,
+            d.ground_kills,
+            d.naval_kills,
+            d.team_kills,
+            d.ai_kills,
+            d.ai_ground_kills,
+            d.ai_naval_kills,
+            d.assists,
+            d.deaths,
+            d.capture_zone,
+            d.damage_zone,
+            d.score,
+            d.award_damage,
+            d.missile_evades,
+            field(&d.lineup.join(";"))
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// `unit_id,name,time_ms,x,y,z`, one row per sample.
+pub fn write_telemetry_csv<W: Write>(tracks: &[Track], mut writer: W) -> Result<()> {
+    writeln!(writer, "unit_id,name,time_ms,x,y,z")?;
+    for track in tracks {
+        let name = field(track.unit.name.as_deref().unwrap_or(""));
+        for s in &track.samples {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                track.unit.id, name, s.time_ms, s.position.x, s.position.y, s.position.z
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `chat.csv` and (if there are results) `players.csv` into `dir`.
+pub fn write_replay_csv(
+    chat: &[ChatInfo],
+    results: Option<&ReplayResults>,
+    dir: &Path,
+) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+    let create = |name: &str| -> Result<BufWriter<File>> {
+        let path = dir.join(name);
+        let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        Ok(BufWriter::new(file))
+    };
+
+    write_chat_csv(chat, create("chat.csv")?)?;
+    if let Some(results) = results {
+        write_players_csv(results, create("players.csv")?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! GeoJSON and KML export of unit tracks.
+//!
+//! Coordinates are map-local metres, not WGS84: horizontal `x`/`z` become
+//! easting/northing and `y` (up) becomes the altitude.
+
+use crate::telemetry::Track;
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+/// Builds a GeoJSON `FeatureCollection` with one `LineString` per track.
+/// Each feature carries the unit and the per-point timestamps as properties.
+pub fn tracks_to_geojson(tracks: &[Track]) -> Value {
+    let features: Vec<Value> = tracks
+        .iter()
+        .filter(|track| !track.samples.is_empty())
+        .map(|track| {
+            let coordinates: Vec<[f32; 3]> = track
+                .samples
+                .iter()
+                .map(|s| [s.position.x, s.position.z, s.position.y])
+                .collect();
+            let times: Vec<u32> = track.samples.iter().map(|s| s.time_ms).collect();
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                },
+                "properties": {
+                    "unit_id": track.unit.id,
+                    "name": track.unit.name,
+                    "times_ms": times,
+                },
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// Same as [`tracks_to_geojson`], serialized to a string.
+pub fn tracks_to_geojson_string(tracks: &[Track]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&tracks_to_geojson(tracks))?)
+}
+
+/// Builds a KML document with one `gx:Track` per unit.
+///
+/// `start_time` (seconds since epoch, e.g. the header's `start_time`) is added
+/// to each sample's timestamp for the `<when>` elements.
+pub fn tracks_to_kml(tracks: &[Track], start_time: u32) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n",
+    );
+    out.push_str("<Document>\n");
+
+    for track in tracks.iter().filter(|t| !t.samples.is_empty()) {
+        let name = match &track.unit.name {
+            Some(name) => format!("{} ({})", name, track.unit.id),
+            None => format!("Unit {}", track.unit.id),
+        };
+        // writing to a String can't fail
+        let _ = writeln!(out, "<Placemark>\n<name>{}</name>", escape_xml(&name));
+        out.push_str("<gx:Track>\n<altitudeMode>absolute</altitudeMode>\n");
+        for sample in &track.samples {
+            let millis = start_time as u64 * 1000 + sample.time_ms as u64;
+            let _ = writeln!(out, "<when>{}</when>", iso8601(millis));
+        }
+        for sample in &track.samples {
+            let p = sample.position;
+            let _ = writeln!(out, "<gx:coord>{} {} {}</gx:coord>", p.x, p.z, p.y);
+        }
+        out.push_str("</gx:Track>\n</Placemark>\n");
+    }
+
+    out.push_str("</Document>\n</kml>\n");
+    out
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats milliseconds since epoch as `YYYY-MM-DDTHH:MM:SS.sssZ`.
+fn iso8601(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60,
+        millis % 1000
+    )
+}
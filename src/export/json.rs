@@ -0,0 +1,178 @@
+//! Writing a parse out as a JSON array while it's still going, e.g. for a
+//! web backend that wants to start its response before the whole replay
+//! is parsed.
+//!
+//! [`JsonStream`] is a [`PacketVisitor`], so it's used with
+//! [`parser::parse_with`], or with [`write_json`] which does that for you:
+//!
+//! ```no_run
+//! # let data = Vec::new();
+//! use wrpl::export::json::{self, JsonStreamOptions};
+//! use wrpl::parser::ParseOptions;
+//!
+//! let out = std::io::BufWriter::new(std::io::stdout().lock());
+//! json::write_json(&data, out, &ParseOptions::default(), JsonStreamOptions::default())?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Each element is an object with an `"event"` tag: `header`, `packet`,
+//! `chat`, `decoded`, `battle_start`, `battle_end` or `results`, in the
+//! order the parse finds them. Times are in ms (see [`clock::ticks_to_ms`]).
+
+use crate::clock;
+use crate::decode::DecodedPacket;
+use crate::header::ReplayHeader;
+use crate::parser::{
+    self, ChatInfo, PacketRef, PacketVisitor, ParseOptions, ReplayPacketType, ReplayResults,
+};
+use crate::utils::hex;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// What goes into the array besides the header, chat, decoded packets,
+/// battle start/end and results, which are always there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonStreamOptions {
+    /// Leave out `packet` events, which are most of the output.
+    pub skip_packets: bool,
+    /// Put each packet's payload in, as hex. Off by default as it more
+    /// than doubles the size of the stream.
+    pub payloads: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Header(&'a ReplayHeader),
+    Packet {
+        packet_type: ReplayPacketType,
+        raw_type: u8,
+        timestamp_ms: u32,
+        stream_offset: u64,
+        size: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        payload: Option<String>,
+    },
+    Chat(ChatInfo),
+    Decoded(&'a DecodedPacket),
+    BattleStart {
+        timestamp_ms: u32,
+    },
+    BattleEnd {
+        timestamp_ms: u32,
+    },
+    Results(&'a ReplayResults),
+}
+
+/// Writes everything it's given to `out` as elements of a JSON array. The
+/// array is only closed by [`JsonStream::finish`].
+///
+/// Each element is written straight to `out`, so give it a buffered writer.
+/// Write errors can't stop the parse, so the first one is kept, nothing
+/// more is written, and `finish` returns it.
+pub struct JsonStream<W: Write> {
+    out: W,
+    options: JsonStreamOptions,
+    elements: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonStream<W> {
+    pub fn new(out: W, options: JsonStreamOptions) -> Self {
+        JsonStream {
+            out,
+            options,
+            elements: 0,
+            error: None,
+        }
+    }
+
+    /// Number of elements written so far.
+    pub fn elements(&self) -> u64 {
+        self.elements
+    }
+
+    fn write(&mut self, event: &Event<'_>) {
+        if self.error.is_some() {
+            return;
+        }
+        let separator: &[u8] = if self.elements == 0 { b"[\n" } else { b",\n" };
+        let written = self
+            .out
+            .write_all(separator)
+            .and_then(|_| serde_json::to_writer(&mut self.out, event).map_err(io::Error::from));
+        match written {
+            Ok(()) => self.elements += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Closes the array (writing `[]` if nothing was written) and flushes,
+    /// returning the writer, or the first error writing to it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let end: &[u8] = if self.elements == 0 {
+            b"[]\n"
+        } else {
+            b"\n]\n"
+        };
+        self.out.write_all(end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> PacketVisitor for JsonStream<W> {
+    fn header(&mut self, header: &ReplayHeader) {
+        self.write(&Event::Header(header));
+    }
+
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        let timestamp_ms = clock::ticks_to_ms(packet.timestamp_ms);
+        if !self.options.skip_packets {
+            self.write(&Event::Packet {
+                packet_type: packet.packet_type,
+                raw_type: packet.raw_type,
+                timestamp_ms,
+                stream_offset: packet.stream_offset,
+                size: packet.payload.len(),
+                payload: self.options.payloads.then(|| hex::encode(packet.payload)),
+            });
+        }
+        match packet.packet_type {
+            ReplayPacketType::StartMarker => self.write(&Event::BattleStart { timestamp_ms }),
+            ReplayPacketType::EndMarker => self.write(&Event::BattleEnd { timestamp_ms }),
+            _ => {}
+        }
+    }
+
+    fn chat(&mut self, chat: &ChatInfo) {
+        self.write(&Event::Chat(ChatInfo {
+            timestamp_ms: clock::ticks_to_ms(chat.timestamp_ms),
+            ..chat.clone()
+        }));
+    }
+
+    fn decoded(&mut self, decoded: &DecodedPacket) {
+        self.write(&Event::Decoded(decoded));
+    }
+
+    fn results(&mut self, results: &ReplayResults) {
+        self.write(&Event::Results(results));
+    }
+}
+
+/// Parses a replay (see [`parser::parse_with_options`]), writing it to
+/// `out` as it goes. Returns the writer once the array is closed.
+pub fn write_json<W: Write>(
+    data: &[u8],
+    out: W,
+    options: &ParseOptions,
+    json_options: JsonStreamOptions,
+) -> Result<W> {
+    let stream = parser::parse_with_options(data, JsonStream::new(out, json_options), options)?;
+    stream.finish().context("Failed to write the JSON")
+}
@@ -0,0 +1,102 @@
+//! Markdown battle summaries, for pasting into Discord or forums.
+
+use crate::header::ReplayHeader;
+use crate::parser::ParsedReplay;
+use std::fmt::Write;
+
+/// How many players the summary's table lists.
+const TOP_PLAYERS: usize = 10;
+/// How many chat messages the summary quotes.
+const CHAT_EXCERPT: usize = 10;
+
+/// Escapes characters that would break a Markdown table cell or formatting.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '|' | '*' | '_' | '`' | '~' | '\\' | '[' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Builds a Markdown summary: map and mode, outcome, a table of the top
+/// players by score and an excerpt of the chat.
+pub fn battle_summary(header: Option<&ReplayHeader>, replay: &ParsedReplay) -> String {
+    let mut out = String::new();
+
+    // writing to a String can't fail, so the results are ignored
+    match header {
+        Some(header) => {
+            let _ = writeln!(out, "## {}\n", escape(&header.battle_type));
+            let _ = writeln!(out, "- **Map:** {}", escape(&header.map_name()));
+            let _ = writeln!(out, "- **Mode:** {}", escape(&header.battle_class));
+            let _ = writeln!(out, "- **Session:** `{:x}`", header.session_id);
+        }
+        None => {
+            let _ = writeln!(out, "## Replay\n");
+        }
+    }
+
+    if let Some(results) = &replay.replay_results {
+        let _ = writeln!(out, "- **Outcome:** {}", results.outcome_label());
+        let _ = writeln!(
+            out,
+            "- **Duration:** {}:{:02}",
+            results.time_played as u64 / 60,
+            results.time_played as u64 % 60
+        );
+        let _ = writeln!(out, "- **Recorded by:** {}", escape(&results.author));
+
+        let mut players: Vec<_> = results.players.iter().collect();
+        players.sort_by_key(|p| std::cmp::Reverse(p.replay_data.score));
+
+        if !players.is_empty() {
+            let _ = writeln!(out, "\n### Top players\n");
+            let _ = writeln!(
+                out,
+                "| # | Player | Team | Score | Kills | Ground kills | Assists | Deaths |"
+            );
+            let _ = writeln!(out, "|---|---|---|---|---|---|---|---|");
+            for (i, p) in players.iter().take(TOP_PLAYERS).enumerate() {
+                let name = if p.player_info.squadron_tag.is_empty() {
+                    p.player_info.username.clone()
+                } else {
+                    format!("{} {}", p.player_info.squadron_tag, p.player_info.username)
+                };
+                let d = &p.replay_data;
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} | {} | {} | {} |",
+                    i + 1,
+                    escape(&name),
+                    d.team,
+                    d.score,
+                    d.kills,
+                    d.ground_kills,
+                    d.assists,
+                    d.deaths
+                );
+            }
+        }
+    }
+
+    if !replay.chat_messages.is_empty() {
+        let _ = writeln!(out, "\n### Chat\n");
+        for chat in replay.chat_messages.iter().take(CHAT_EXCERPT) {
+            let _ = writeln!(
+                out,
+                "- **{}:** {}",
+                escape(&chat.sender),
+                escape(&chat.message)
+            );
+        }
+        let remaining = replay.chat_messages.len().saturating_sub(CHAT_EXCERPT);
+        if remaining > 0 {
+            let _ = writeln!(out, "\n_...and {} more messages_", remaining);
+        }
+    }
+
+    out
+}
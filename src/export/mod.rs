@@ -0,0 +1,11 @@
+//! Exporting parsed replay data to other formats.
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+pub mod geo;
+pub mod json;
+pub mod markdown;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
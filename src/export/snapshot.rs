@@ -0,0 +1,118 @@
+//! Canonical JSON snapshots of a parse, for regression testing.
+//!
+//! A snapshot is the header, a summary of the packet stream (counts, sizes
+//! and a hash of the payloads per type), the chat and the results. It's
+//! written with sorted keys, two-space indents and floats rounded to
+//! [`FLOAT_DECIMALS`] places, so the same parse always gives the same bytes
+//! and a diff of two snapshots only shows what changed.
+
+use crate::header::ReplayHeader;
+use crate::parser::ParsedReplay;
+use crate::utils::fnv1a;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Bumped whenever the snapshot layout changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Decimal places floats are written with.
+pub const FLOAT_DECIMALS: usize = 6;
+
+/// Builds the snapshot as a JSON value.
+pub fn snapshot(header: Option<&ReplayHeader>, replay: &ParsedReplay) -> Value {
+    // keyed by raw type, so unknown types stay apart
+    let mut types: BTreeMap<u8, (u64, u64, Vec<u8>)> = BTreeMap::new();
+    for packet in &replay.packets {
+        let (count, bytes, payloads) = types.entry(packet.raw_type).or_default();
+        *count += 1;
+        *bytes += packet.size as u64;
+        payloads.extend_from_slice(&packet.payload);
+    }
+    let types: Map<String, Value> = types
+        .into_iter()
+        .map(|(raw_type, (count, bytes, payloads))| {
+            let value = json!({
+                "count": count,
+                "bytes": bytes,
+                "digest": format!("{:016x}", fnv1a(&payloads)),
+            });
+            (raw_type.to_string(), value)
+        })
+        .collect();
+
+    json!({
+        "snapshot_version": SNAPSHOT_VERSION,
+        "header": header,
+        "stream": {
+            "packets": replay.packet_count,
+            "decompressed_bytes": replay.total_decompressed_bytes,
+            "complete": replay.complete,
+            "trailing_bytes": replay.trailing_bytes,
+        },
+        "packet_types": types,
+        "chat": replay.chat_messages,
+        "results": replay.replay_results,
+    })
+}
+
+/// Builds the snapshot as canonical JSON text.
+pub fn snapshot_string(header: Option<&ReplayHeader>, replay: &ParsedReplay) -> String {
+    let mut out = String::new();
+    write_canonical(&mut out, &snapshot(header, replay), 0);
+    out.push('\n');
+    out
+}
+
+/// Writes `value` with sorted keys and fixed float formatting.
+pub fn write_canonical(out: &mut String, value: &Value, indent: usize) {
+    let pad = |out: &mut String, indent: usize| {
+        out.push('\n');
+        out.extend(std::iter::repeat_n("  ", indent));
+    };
+    match value {
+        Value::Number(n) if n.is_f64() => {
+            let f = n.as_f64().unwrap();
+            let mut s = format!("{:.*}", FLOAT_DECIMALS, f);
+            // 1.500000 -> 1.5, 2.000000 -> 2.0
+            while s.ends_with('0') && !s.ends_with(".0") {
+                s.pop();
+            }
+            if s == "-0.0" {
+                s = "0.0".to_string();
+            }
+            out.push_str(&s);
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                pad(out, indent + 1);
+                write_canonical(out, item, indent + 1);
+            }
+            pad(out, indent);
+            out.push(']');
+        }
+        Value::Object(map) if !map.is_empty() => {
+            let mut keys: Vec<_> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                pad(out, indent + 1);
+                let _ = write!(out, "{}: ", Value::String(key.clone()));
+                write_canonical(out, &map[key], indent + 1);
+            }
+            pad(out, indent);
+            out.push('}');
+        }
+        // strings, ints, bools, null and empty containers
+        other => {
+            let _ = write!(out, "{}", other);
+        }
+    }
+}
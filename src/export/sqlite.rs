@@ -0,0 +1,179 @@
+//! SQLite export of a single parsed replay, for exploring a battle with SQL.
+
+use crate::clock;
+use crate::parser::{ParsedReplay, ReplayPacketType};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+DROP TABLE IF EXISTS packets;
+DROP TABLE IF EXISTS chat;
+DROP TABLE IF EXISTS events;
+DROP TABLE IF EXISTS players;
+DROP TABLE IF EXISTS results;
+CREATE TABLE packets (
+    idx INTEGER PRIMARY KEY,
+    packet_type TEXT NOT NULL,
+    timestamp_ms INTEGER NOT NULL,
+    payload_size INTEGER NOT NULL,
+    payload BLOB NOT NULL
+);
+CREATE TABLE chat (
+    timestamp_ms INTEGER NOT NULL,
+    sender TEXT NOT NULL,
+    message TEXT NOT NULL,
+    channel_type INTEGER,
+    is_enemy INTEGER,
+    team INTEGER,
+    squad INTEGER
+);
+CREATE TABLE events (
+    packet_idx INTEGER NOT NULL REFERENCES packets(idx),
+    timestamp_ms INTEGER NOT NULL,
+    kind TEXT NOT NULL
+);
+CREATE TABLE results (
+    status TEXT NOT NULL,
+    time_played REAL NOT NULL,
+    author TEXT NOT NULL,
+    author_user_id TEXT NOT NULL
+);
+CREATE TABLE players (
+    user_id TEXT NOT NULL,
+    username TEXT NOT NULL,
+    squadron_id TEXT NOT NULL,
+    squadron_tag TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    team INTEGER NOT NULL,
+    squad INTEGER NOT NULL,
+    auto_squad INTEGER NOT NULL,
+    kills INTEGER NOT NULL,
+    ground_kills INTEGER NOT NULL,
+    naval_kills INTEGER NOT NULL,
+    team_kills INTEGER NOT NULL,
+    ai_kills INTEGER NOT NULL,
+    ai_ground_kills INTEGER NOT NULL,
+    ai_naval_kills INTEGER NOT NULL,
+    assists INTEGER NOT NULL,
+    deaths INTEGER NOT NULL,
+    capture_zone INTEGER NOT NULL,
+    damage_zone INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    award_damage INTEGER NOT NULL,
+    missile_evades INTEGER NOT NULL,
+    lineup TEXT NOT NULL
+);
+";
+
+/// Writes a parsed replay into the SQLite database at `path`.
+/// Existing tables of the same name are replaced.
+///
+/// `events` holds the lifecycle markers (start, end, next segment) found in the stream.
+/// Times are in milliseconds (see [`clock::ticks_to_ms`]).
+pub fn to_sqlite(parsed: &ParsedReplay, path: &Path) -> Result<()> {
+    let mut conn =
+        Connection::open(path).with_context(|| format!("Failed to open database {:?}", path))?;
+    write_to_connection(parsed, &mut conn)
+}
+
+/// Same as [`to_sqlite`], but into an existing connection.
+pub fn write_to_connection(parsed: &ParsedReplay, conn: &mut Connection) -> Result<()> {
+    // the schema drops the old tables, so a failed export leaves them be
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)
+        .context("Failed to create export schema")?;
+
+    {
+        let mut packet_stmt = tx.prepare(
+            "INSERT INTO packets (idx, packet_type, timestamp_ms, payload_size, payload)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut event_stmt =
+            tx.prepare("INSERT INTO events (packet_idx, timestamp_ms, kind) VALUES (?1, ?2, ?3)")?;
+        for (i, packet) in parsed.packets.iter().enumerate() {
+            let packet_type = format!("{:?}", packet.packet_type);
+            packet_stmt.execute(params![
+                i as i64,
+                packet_type,
+                clock::ticks_to_ms(packet.timestamp_ms),
+                packet.size as i64,
+                packet.payload,
+            ])?;
+            if matches!(
+                packet.packet_type,
+                ReplayPacketType::StartMarker
+                    | ReplayPacketType::EndMarker
+                    | ReplayPacketType::NextSegment
+            ) {
+                let timestamp_ms = clock::ticks_to_ms(packet.timestamp_ms);
+                event_stmt.execute(params![i as i64, timestamp_ms, packet_type])?;
+            }
+        }
+
+        let mut chat_stmt = tx.prepare(
+            "INSERT INTO chat (timestamp_ms, sender, message, channel_type, is_enemy, team, squad)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for c in &parsed.chat_messages {
+            chat_stmt.execute(params![
+                clock::ticks_to_ms(c.timestamp_ms),
+                c.sender,
+                c.message,
+                c.channel_type,
+                c.is_enemy,
+                c.team(),
+                c.squad()
+            ])?;
+        }
+
+        if let Some(results) = &parsed.replay_results {
+            tx.execute(
+                "INSERT INTO results (status, time_played, author, author_user_id)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    results.status,
+                    results.time_played,
+                    results.author,
+                    results.author_user_id
+                ],
+            )?;
+
+            let mut player_stmt = tx.prepare(
+                "INSERT INTO players VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+                    ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23)",
+            )?;
+            for p in &results.players {
+                let (info, d) = (&p.player_info, &p.replay_data);
+                player_stmt.execute(params![
+                    info.user_id,
+                    info.username,
+                    info.squadron_id,
+                    info.squadron_tag,
+                    info.platform,
+                    d.team,
+                    d.squad,
+                    d.auto_squad,
+                    d.kills,
+                    d.ground_kills,
+                    d.naval_kills,
+                    d.team_kills,
+                    d.ai_kills,
+                    d.ai_ground_kills,
+                    d.ai_naval_kills,
+                    d.assists,
+                    d.deaths,
+                    d.capture_zone,
+                    d.damage_zone,
+                    d.score,
+                    d.award_damage,
+                    d.missile_evades,
+                    d.lineup.join(";"),
+                ])?;
+            }
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,319 @@
+//! C ABI for embedding the decoder in non-Rust tools.
+//!
+//! Build a shared library with
+//! `cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib`
+//! and include `include/wrpl.h` (regenerate it with `cbindgen -o include/wrpl.h`).
+//!
+//! Every string returned by these functions is owned by the caller and must be
+//! released with [`wrpl_string_free`]; handles with [`wrpl_free`].
+//! On failure, functions return null and [`wrpl_last_error`] describes why.
+//! That includes panics, which are caught rather than unwinding into C.
+
+use crate::clock;
+use crate::format;
+use crate::header::{self, ReplayHeader};
+use crate::parser::{self, ChatInfo, ParsedReplay};
+use anyhow::{bail, Result};
+use serde_json::json;
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Runs the body of an exported function, turning a panic into `fallback`
+/// (and the last error), since unwinding into C aborts the host.
+fn guard<T>(fallback: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        set_last_error(format!("Panicked: {}", panic_message(payload.as_ref())));
+        fallback
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+fn to_c_string(s: &str) -> *mut c_char {
+    // interior NULs can't be represented, replace them
+    CString::new(s.replace('\0', " "))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// An opaque parsed replay.
+pub struct WrplReplay {
+    header: Option<ReplayHeader>,
+    replay: ParsedReplay,
+}
+
+impl WrplReplay {
+    /// Chat times are in ms, like [`wrpl_chat_timestamp_ms`].
+    fn to_json(&self) -> serde_json::Value {
+        let chat: Vec<ChatInfo> = self
+            .replay
+            .chat_messages
+            .iter()
+            .map(|chat| ChatInfo {
+                timestamp_ms: clock::ticks_to_ms(chat.timestamp_ms),
+                ..chat.clone()
+            })
+            .collect();
+        json!({
+            "header": self.header,
+            "chat": chat,
+            "results": self.replay.replay_results,
+            "packet_count": self.replay.packet_count,
+        })
+    }
+}
+
+/// Parses a whole replay file: header, packet stream and results.
+fn parse_bytes(data: &[u8]) -> Result<WrplReplay> {
+    format::check_magic(data)?;
+    let header = header::parse_header(data)?;
+    let Some(offset) = format::detect_format(data).stream_offset() else {
+        bail!("Couldn't find the packet stream, is the replay truncated?");
+    };
+    let replay = parser::process_replay_stream(
+        data,
+        offset as u64,
+        parser::Compression::Zlib,
+        Some(&header),
+    )?;
+    Ok(WrplReplay {
+        header: Some(header),
+        replay,
+    })
+}
+
+/// Builds a slice from a C pointer, treating null as an error.
+unsafe fn input_slice<'a>(bytes: *const u8, len: usize) -> Option<&'a [u8]> {
+    if bytes.is_null() {
+        set_last_error("Null data pointer".to_string());
+        return None;
+    }
+    Some(std::slice::from_raw_parts(bytes, len))
+}
+
+/// Parses a replay and returns a handle, or null on failure.
+///
+/// # Safety
+/// `bytes` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_parse(bytes: *const u8, len: usize) -> *mut WrplReplay {
+    guard(ptr::null_mut(), || {
+        let Some(data) = input_slice(bytes, len) else {
+            return ptr::null_mut();
+        };
+        match parse_bytes(data) {
+            Ok(replay) => Box::into_raw(Box::new(replay)),
+            Err(e) => {
+                set_last_error(format!("{:#}", e));
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Parses a replay and returns it as a JSON string, or null on failure.
+///
+/// # Safety
+/// `bytes` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_parse_json(bytes: *const u8, len: usize) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let Some(data) = input_slice(bytes, len) else {
+            return ptr::null_mut();
+        };
+        match parse_bytes(data) {
+            Ok(replay) => to_c_string(&replay.to_json().to_string()),
+            Err(e) => {
+                set_last_error(format!("{:#}", e));
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Frees a handle returned by [`wrpl_parse`]. Null is ignored.
+///
+/// # Safety
+/// `replay` must come from [`wrpl_parse`] and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_free(replay: *mut WrplReplay) {
+    guard((), || {
+        if !replay.is_null() {
+            drop(Box::from_raw(replay));
+        }
+    })
+}
+
+/// Frees a string returned by this library. Null is ignored.
+///
+/// # Safety
+/// `s` must come from this library and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_string_free(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+/// Returns the last error on this thread, or null. The string is owned by
+/// the library and valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn wrpl_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+    })
+}
+
+/// Returns the whole replay as a JSON string.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_to_json(replay: *const WrplReplay) -> *mut c_char {
+    guard(ptr::null_mut(), || match replay.as_ref() {
+        Some(replay) => to_c_string(&replay.to_json().to_string()),
+        None => ptr::null_mut(),
+    })
+}
+
+/// Returns the session ID from the header, or 0.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_session_id(replay: *const WrplReplay) -> u64 {
+    guard(0, || {
+        replay
+            .as_ref()
+            .and_then(|r| r.header.as_ref())
+            .map_or(0, |h| h.session_id.0)
+    })
+}
+
+/// Returns the level path from the header, or null.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_level(replay: *const WrplReplay) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        match replay.as_ref().and_then(|r| r.header.as_ref()) {
+            Some(header) => to_c_string(&header.level),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Returns the number of packets in the stream.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_packet_count(replay: *const WrplReplay) -> u64 {
+    guard(0, || replay.as_ref().map_or(0, |r| r.replay.packet_count))
+}
+
+/// Returns the number of chat messages.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_chat_count(replay: *const WrplReplay) -> usize {
+    guard(0, || {
+        replay.as_ref().map_or(0, |r| r.replay.chat_messages.len())
+    })
+}
+
+/// Returns the sender of chat message `index`, or null if out of range.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_chat_sender(replay: *const WrplReplay, index: usize) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        match replay
+            .as_ref()
+            .and_then(|r| r.replay.chat_messages.get(index))
+        {
+            Some(chat) => to_c_string(&chat.sender),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Returns the text of chat message `index`, or null if out of range.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_chat_message(replay: *const WrplReplay, index: usize) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        match replay
+            .as_ref()
+            .and_then(|r| r.replay.chat_messages.get(index))
+        {
+            Some(chat) => to_c_string(&chat.message),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Returns the time of chat message `index` in milliseconds (converted from
+/// the raw ticks, but not corrected, see [`crate::clock`]), or 0.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_chat_timestamp_ms(replay: *const WrplReplay, index: usize) -> u32 {
+    guard(0, || {
+        replay
+            .as_ref()
+            .and_then(|r| r.replay.chat_messages.get(index))
+            .map_or(0, |chat| clock::ticks_to_ms(chat.timestamp_ms))
+    })
+}
+
+/// Returns the results as a JSON string, or null if the replay has none.
+///
+/// # Safety
+/// `replay` must be a valid handle from [`wrpl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn wrpl_results_json(replay: *const WrplReplay) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        match replay
+            .as_ref()
+            .and_then(|r| r.replay.replay_results.as_ref())
+        {
+            Some(results) => match serde_json::to_string(results) {
+                Ok(json) => to_c_string(&json),
+                Err(e) => {
+                    set_last_error(e.to_string());
+                    ptr::null_mut()
+                }
+            },
+            None => ptr::null_mut(),
+        }
+    })
+}
@@ -0,0 +1,129 @@
+//! Flight metrics worked out from a unit's path, for looking at dogfights in
+//! numbers.
+//!
+//! The replay only gives positions, so everything here is derived from a
+//! [`Trajectory`]: speeds from its velocity, G-load and turn rate from how
+//! that velocity changes. Airspeeds assume still air and the standard
+//! atmosphere, so they're estimates. Only makes sense for aircraft.
+
+use crate::telemetry::Vec3;
+use crate::trajectory::Trajectory;
+use serde::Serialize;
+
+const GRAVITY: f32 = 9.80665;
+/// Air density at sea level (kg/m³) in the standard atmosphere.
+const SEA_LEVEL_DENSITY: f32 = 1.225;
+
+/// How [`flight_metrics`] samples a trajectory.
+#[derive(Debug, Clone, Copy)]
+pub struct FlightOptions {
+    /// Time between samples.
+    pub interval_ms: u32,
+    /// World `y` of sea level, as maps aren't all at 0.
+    pub sea_level: f32,
+}
+
+impl Default for FlightOptions {
+    fn default() -> Self {
+        FlightOptions {
+            interval_ms: 250,
+            sea_level: 0.0,
+        }
+    }
+}
+
+/// Flight metrics at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FlightSample {
+    pub time_ms: u32,
+    /// Metres above sea level.
+    pub altitude: f32,
+    /// True airspeed in m/s.
+    pub tas: f32,
+    /// Indicated airspeed in m/s, estimated from the TAS and the air density
+    /// at this altitude.
+    pub ias: f32,
+    /// Vertical speed in m/s, negative when descending.
+    pub climb_rate: f32,
+    /// Load factor in G, 1 in level flight.
+    pub g_load: f32,
+    /// How fast the heading changes, in degrees per second either way.
+    pub turn_rate: f32,
+}
+
+/// Air density (kg/m³) at `altitude` metres in the standard atmosphere.
+pub fn air_density(altitude: f32) -> f32 {
+    let altitude = altitude.max(0.0);
+    if altitude <= 11_000.0 {
+        SEA_LEVEL_DENSITY * (1.0 - 2.255_77e-5 * altitude).powf(4.255_9)
+    } else {
+        // above the tropopause the temperature is constant
+        0.363_9 * (-(altitude - 11_000.0) / 6_341.6).exp()
+    }
+}
+
+/// Estimates the indicated airspeed from the true airspeed (both m/s), i.e.
+/// the equivalent airspeed, ignoring compressibility.
+pub fn ias_from_tas(tas: f32, altitude: f32) -> f32 {
+    tas * (air_density(altitude) / SEA_LEVEL_DENSITY).sqrt()
+}
+
+/// Velocity and acceleration (m/s²) at `time_ms`, the acceleration from the
+/// velocity a bit either side.
+fn motion_at(trajectory: &Trajectory, time_ms: f64) -> Option<(Vec3, Vec3)> {
+    let velocity = trajectory.velocity_at(time_ms)?;
+    let start = trajectory.start_ms()? as f64;
+    let end = trajectory.end_ms()? as f64;
+    let (before, after) = ((time_ms - 50.0).max(start), (time_ms + 50.0).min(end));
+    let dt = (after - before) as f32 / 1000.0;
+    let acceleration = if dt > 0.0 {
+        (trajectory.velocity_at(after)? - trajectory.velocity_at(before)?) * (1.0 / dt)
+    } else {
+        Vec3::default()
+    };
+    Some((velocity, acceleration))
+}
+
+/// The metrics at `time_ms`, `None` outside the trajectory.
+pub fn flight_sample_at(
+    trajectory: &Trajectory,
+    time_ms: u32,
+    options: &FlightOptions,
+) -> Option<FlightSample> {
+    let position = trajectory.position_at(time_ms as f64)?;
+    let (velocity, acceleration) = motion_at(trajectory, time_ms as f64)?;
+    let altitude = position.y - options.sea_level;
+    let tas = velocity.length();
+
+    // what the pilot feels: the acceleration plus holding up against gravity
+    let felt = acceleration + Vec3::new(0.0, GRAVITY, 0.0);
+    let horizontal = velocity.x * velocity.x + velocity.z * velocity.z;
+    let turn_rate = if horizontal > 1.0 {
+        ((velocity.x * acceleration.z - velocity.z * acceleration.x) / horizontal)
+            .abs()
+            .to_degrees()
+    } else {
+        0.0
+    };
+
+    Some(FlightSample {
+        time_ms,
+        altitude,
+        tas,
+        ias: ias_from_tas(tas, altitude),
+        climb_rate: velocity.y,
+        g_load: felt.length() / GRAVITY,
+        turn_rate,
+    })
+}
+
+/// The metrics every [`FlightOptions::interval_ms`] along `trajectory`.
+pub fn flight_metrics(trajectory: &Trajectory, options: &FlightOptions) -> Vec<FlightSample> {
+    let (Some(start), Some(end)) = (trajectory.start_ms(), trajectory.end_ms()) else {
+        return Vec::new();
+    };
+    (start..=end)
+        .step_by(options.interval_ms.max(1) as usize)
+        .filter_map(|time_ms| flight_sample_at(trajectory, time_ms, options))
+        .collect()
+}
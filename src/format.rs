@@ -0,0 +1,229 @@
+//! Telling what kind of file some bytes are.
+//!
+//! Every replay starts with the same u32 magic and a version. After the
+//! header, client replays have the mission settings BLK and then the zlib
+//! packet stream, while server replays start the stream right away.
+
+use crate::header::{HEADER_SIZE, M_SET_OFFSET, REZ_OFFSET_POS};
+use crate::parser::ReplayPacketType;
+use crate::raw;
+use crate::utils;
+use anyhow::{bail, Result};
+use std::io::Cursor;
+use std::ops::RangeInclusive;
+
+/// The magic at the start of every replay (`E5 AC 00 10` on disk).
+pub const MAGIC: u32 = 0x1000_ACE5;
+
+/// Replay versions that are believable. For the ones seen so far, see
+/// [`crate::compat::TESTED_VERSIONS`].
+pub const VERSION_RANGE: RangeInclusive<u32> = 100_000..=199_999;
+
+/// What [`detect_format`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// A client replay: header, mission settings, then the packet stream.
+    ClientReplay { stream_offset: usize },
+    /// A server replay (segment): header, then the packet stream.
+    ServerReplay { stream_offset: usize },
+    /// A replay header, but no packet stream after it (truncated?).
+    HeaderOnly,
+    /// A bare zlib stream, e.g. the packet stream cut out of a replay.
+    ZlibStream,
+    /// Anything else. Could be a decompressed packet stream.
+    Unknown,
+}
+
+impl FileKind {
+    /// Whether it starts with a replay header.
+    pub fn has_header(self) -> bool {
+        matches!(
+            self,
+            FileKind::ClientReplay { .. } | FileKind::ServerReplay { .. } | FileKind::HeaderOnly
+        )
+    }
+
+    /// Where the compressed packet stream starts, if there is one.
+    pub fn stream_offset(self) -> Option<usize> {
+        match self {
+            FileKind::ClientReplay { stream_offset } | FileKind::ServerReplay { stream_offset } => {
+                Some(stream_offset)
+            }
+            FileKind::ZlibStream => Some(0),
+            FileKind::HeaderOnly | FileKind::Unknown => None,
+        }
+    }
+}
+
+/// Whether two bytes are a valid zlib header (deflate, any level).
+pub fn is_zlib_header(bytes: &[u8]) -> bool {
+    match bytes {
+        [cmf, flg, ..] => {
+            cmf & 0x0F == 8
+                && cmf >> 4 <= 7
+                && flg & 0x20 == 0 // no preset dictionary
+                && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+/// Checks the magic and version at the start of `data`, saying what's
+/// wrong if they don't look like a replay's.
+pub fn check_magic(data: &[u8]) -> Result<()> {
+    if data.len() < HEADER_SIZE {
+        bail!(
+            "Data is too short for a replay header ({} < {} bytes)",
+            data.len(),
+            HEADER_SIZE
+        );
+    }
+    let magic = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    if magic != MAGIC {
+        bail!(
+            "Bad magic {:#010x}, expected {:#010x}: not a .wrpl file",
+            magic,
+            MAGIC
+        );
+    }
+    let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    if !VERSION_RANGE.contains(&version) {
+        bail!("Unexpected replay version {}", version);
+    }
+    Ok(())
+}
+
+/// Works out what kind of file `data` is, and where its packet stream is.
+pub fn detect_format(data: &[u8]) -> FileKind {
+    if check_magic(data).is_err() {
+        return if is_zlib_header(data) {
+            FileKind::ZlibStream
+        } else {
+            FileKind::Unknown
+        };
+    }
+    let after_header = data.get(M_SET_OFFSET..).unwrap_or_default();
+    // servers use level 0 (78 01), which find_zlib_header doesn't look for
+    if is_zlib_header(after_header) {
+        return FileKind::ServerReplay {
+            stream_offset: M_SET_OFFSET,
+        };
+    }
+    match utils::find_zlib_header(data, HEADER_SIZE) {
+        Some(stream_offset) => FileKind::ClientReplay { stream_offset },
+        None => FileKind::HeaderOnly,
+    }
+}
+
+/// What kind of replay data something is, as told by [`inspect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayKind {
+    /// A client replay, the one file the game saves.
+    Client {
+        stream_offset: usize,
+    },
+    /// One segment of a server replay. Only the last segment of a session
+    /// has results (and a `rez_offset`), the others end with a
+    /// [`ReplayPacketType::NextSegment`] packet.
+    ServerSegment {
+        stream_offset: usize,
+        last: bool,
+    },
+    /// A replay header with no packet stream after it.
+    HeaderOnly,
+    /// A compressed packet stream without a header.
+    ZlibStream,
+    /// A decompressed packet stream, e.g. from `decom_from_addr`.
+    RawStream,
+    Unknown,
+}
+
+impl ReplayKind {
+    pub fn has_header(self) -> bool {
+        matches!(
+            self,
+            ReplayKind::Client { .. } | ReplayKind::ServerSegment { .. } | ReplayKind::HeaderOnly
+        )
+    }
+
+    /// Where the packet stream starts, if there is one.
+    pub fn stream_offset(self) -> Option<usize> {
+        match self {
+            ReplayKind::Client { stream_offset }
+            | ReplayKind::ServerSegment { stream_offset, .. } => Some(stream_offset),
+            ReplayKind::ZlibStream | ReplayKind::RawStream => Some(0),
+            ReplayKind::HeaderOnly | ReplayKind::Unknown => None,
+        }
+    }
+
+    /// Whether the packet stream needs decompressing.
+    pub fn is_compressed(self) -> bool {
+        !matches!(self, ReplayKind::RawStream)
+    }
+}
+
+/// Whether `data` starts like a decompressed packet stream: the first
+/// packet is the header info one, and the next few have known types and
+/// fit in the data.
+pub fn is_packet_stream(data: &[u8]) -> bool {
+    check_packets(data, false)
+}
+
+/// [`is_packet_stream`] for the first bytes of a longer stream, where the
+/// last packet may be cut off.
+pub fn is_packet_stream_prefix(data: &[u8]) -> bool {
+    check_packets(data, true)
+}
+
+fn check_packets(data: &[u8], partial: bool) -> bool {
+    let mut cursor = Cursor::new(data);
+    let mut timestamp = 0;
+    for i in 0..8 {
+        let Ok(Some((size, _))) = raw::read_variable_length_size(&mut cursor) else {
+            // running out early is fine for tiny streams
+            return i > 0 && (partial || cursor.position() as usize == data.len());
+        };
+        let start = cursor.position();
+        let Ok(Some((raw_type, time, _))) =
+            raw::read_packet_header_from_stream(&mut cursor, timestamp)
+        else {
+            return partial && i > 0;
+        };
+        timestamp = time;
+        let packet_type = ReplayPacketType::from(raw_type);
+        if packet_type == ReplayPacketType::Unknown
+            || (i == 0 && packet_type != ReplayPacketType::ReplayHeaderInfo)
+        {
+            return false;
+        }
+        let end = start + u64::from(size);
+        if size == 0 {
+            return false;
+        }
+        if end > data.len() as u64 {
+            return partial;
+        }
+        cursor.set_position(end);
+    }
+    true
+}
+
+/// Works out what kind of replay data `data` is from its magic, header and
+/// the start of its stream. Cheap: nothing is decompressed.
+pub fn inspect(data: &[u8]) -> ReplayKind {
+    match detect_format(data) {
+        FileKind::ClientReplay { stream_offset } => ReplayKind::Client { stream_offset },
+        FileKind::ServerReplay { stream_offset } => {
+            let rez_offset =
+                u32::from_le_bytes(data[REZ_OFFSET_POS..REZ_OFFSET_POS + 4].try_into().unwrap());
+            ReplayKind::ServerSegment {
+                stream_offset,
+                last: rez_offset != 0,
+            }
+        }
+        FileKind::HeaderOnly => ReplayKind::HeaderOnly,
+        FileKind::ZlibStream => ReplayKind::ZlibStream,
+        FileKind::Unknown if is_packet_stream(data) => ReplayKind::RawStream,
+        FileKind::Unknown => ReplayKind::Unknown,
+    }
+}
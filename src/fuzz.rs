@@ -0,0 +1,214 @@
+//! Entry points for fuzzing, and `Arbitrary` inputs for them.
+//!
+//! Each entry point takes raw fuzzer bytes and only panics on a bug: either
+//! a parser panicked on bad input, or something encoded (e.g. with
+//! [`raw::write_variable_length_size`]) didn't read back the same.
+//! A `cargo fuzz` target (with this crate's `arbitrary` feature on) is a
+//! single call:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| wrpl::fuzz::packet_stream(data));
+//! ```
+
+use crate::format;
+use crate::header;
+use crate::parser::{self, PacketVisitor, ParseOptions};
+use crate::raw;
+use crate::validate;
+use arbitrary::{Arbitrary, Result, Unstructured};
+use smallvec::SmallVec;
+use std::io::Cursor;
+
+/// A packet size, spread evenly over the five prefix lengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizePrefix(pub u32);
+
+/// The sizes each prefix length can hold (shortest form).
+const SIZE_PREFIX_RANGES: [(u32, u32); 5] = [
+    (0, 0x3F),
+    (0x40, 0x3FFF),
+    (0x4000, 0x1F_FFFF),
+    (0x20_0000, 0x0FFF_FFFF),
+    (0x1000_0000, u32::MAX),
+];
+
+impl SizePrefix {
+    pub fn encode(&self) -> SmallVec<[u8; 5]> {
+        raw::write_variable_length_size(self.0)
+    }
+}
+
+impl<'a> Arbitrary<'a> for SizePrefix {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let (lo, hi) = *u.choose(&SIZE_PREFIX_RANGES)?;
+        Ok(SizePrefix(u.int_in_range(lo..=hi)?))
+    }
+}
+
+/// A packet's type byte and timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    /// Never has the 0x10 bit set, that's the "same timestamp" flag.
+    pub raw_type: u8,
+    pub timestamp_ms: u32,
+    /// Timestamp of the packet before, decides whether it's written.
+    pub last_timestamp_ms: u32,
+}
+
+impl PacketHeader {
+    pub fn encode(&self) -> SmallVec<[u8; 5]> {
+        raw::write_packet_header(self.raw_type, self.timestamp_ms, self.last_timestamp_ms)
+    }
+}
+
+impl<'a> Arbitrary<'a> for PacketHeader {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let raw_type = u8::arbitrary(u)? & !0x10;
+        let last_timestamp_ms = u32::arbitrary(u)?;
+        // unchanged timestamps are common, make sure they come up
+        let timestamp_ms = if bool::arbitrary(u)? {
+            last_timestamp_ms
+        } else {
+            u32::arbitrary(u)?
+        };
+        Ok(PacketHeader {
+            raw_type,
+            timestamp_ms,
+            last_timestamp_ms,
+        })
+    }
+}
+
+/// The payload of a chat packet (type 3).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatPayload {
+    pub flag: u8,
+    /// At most 255 bytes, its length is a single byte.
+    pub sender: String,
+    /// At most 255 bytes, as is `sender`.
+    pub message: String,
+    pub channel_type: Option<u8>,
+    /// Only written if `channel_type` is.
+    pub is_enemy: Option<u8>,
+}
+
+/// Cuts `s` down to 255 bytes, on a char boundary.
+fn short_string(mut s: String) -> String {
+    let mut len = s.len().min(255);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    s.truncate(len);
+    s
+}
+
+impl ChatPayload {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![self.flag, self.sender.len() as u8];
+        out.extend_from_slice(self.sender.as_bytes());
+        out.push(self.message.len() as u8);
+        out.extend_from_slice(self.message.as_bytes());
+        if let Some(channel_type) = self.channel_type {
+            out.push(channel_type);
+            out.extend(self.is_enemy);
+        }
+        out
+    }
+}
+
+impl<'a> Arbitrary<'a> for ChatPayload {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let flag = u8::arbitrary(u)?;
+        let sender = short_string(String::arbitrary(u)?);
+        let message = short_string(String::arbitrary(u)?);
+        let channel_type = Option::<u8>::arbitrary(u)?;
+        let is_enemy = match channel_type {
+            Some(_) => Option::<u8>::arbitrary(u)?,
+            None => None,
+        };
+        Ok(ChatPayload {
+            flag,
+            sender,
+            message,
+            channel_type,
+            is_enemy,
+        })
+    }
+}
+
+/// Decodes `data` as a size prefix, then round-trips an arbitrary one.
+pub fn size_prefix(data: &[u8]) {
+    if let Ok(Some((_, len))) = raw::read_variable_length_size(&mut Cursor::new(data)) {
+        assert!(len <= data.len());
+    }
+
+    let Ok(prefix) = Unstructured::new(data).arbitrary::<SizePrefix>() else {
+        return;
+    };
+    let encoded = prefix.encode();
+    let decoded = raw::read_variable_length_size(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(decoded, Some((prefix.0, encoded.len())), "{:?}", prefix);
+}
+
+/// Decodes `data` as a packet header, then round-trips an arbitrary one.
+pub fn packet_header(data: &[u8]) {
+    if let Ok(Some((_, _, len))) = raw::read_packet_header_from_stream(&mut Cursor::new(data), 0) {
+        assert!(len <= data.len());
+    }
+
+    let Ok(header) = Unstructured::new(data).arbitrary::<PacketHeader>() else {
+        return;
+    };
+    let encoded = header.encode();
+    let decoded =
+        raw::read_packet_header_from_stream(&mut Cursor::new(&encoded), header.last_timestamp_ms)
+            .unwrap();
+    assert_eq!(
+        decoded,
+        Some((header.raw_type, header.timestamp_ms, encoded.len())),
+        "{:?}",
+        header
+    );
+}
+
+/// Decodes `data` as a chat payload, then round-trips an arbitrary one.
+pub fn chat(data: &[u8]) {
+    let _ = parser::parse_chat_packet(data, 0);
+
+    let Ok(chat) = Unstructured::new(data).arbitrary::<ChatPayload>() else {
+        return;
+    };
+    let decoded = parser::parse_chat_packet(&chat.encode(), 0).unwrap();
+    assert_eq!(decoded.sender, chat.sender);
+    assert_eq!(decoded.message, chat.message);
+    assert_eq!(decoded.channel_type, chat.channel_type);
+    assert_eq!(decoded.is_enemy, chat.is_enemy);
+}
+
+/// Parses `data` as a decompressed packet stream, leniently and strictly.
+pub fn packet_stream(data: &[u8]) {
+    let lenient = parser::process_replay_data(data, 0, parser::Compression::None);
+    let strict = ParseOptions {
+        strict: true,
+        ..Default::default()
+    };
+    // whatever strict mode accepts, lenient mode reads the same
+    if let Ok(strict) =
+        parser::process_replay_data_with_options(data, 0, parser::Compression::None, &strict)
+    {
+        let lenient = lenient.unwrap();
+        assert!(strict.complete);
+        assert_eq!(strict.packets.len(), lenient.packets.len());
+    }
+}
+
+/// Everything that reads whole files, on `data` as a replay file.
+pub fn replay_file(data: &[u8]) {
+    struct Ignore;
+    impl PacketVisitor for Ignore {}
+
+    let _ = format::detect_format(data);
+    let _ = header::parse_header(data);
+    let _ = validate::validate(data);
+    let _ = parser::parse_with(data, Ignore);
+}
@@ -1,8 +1,90 @@
-use anyhow::Result;
+use crate::compat::{self, SupportLevel};
+use crate::logging::warn;
+use crate::{parser, utils};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
 use std::fmt;
 use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::panic;
+use std::str::FromStr;
+
+/// Size in bytes of the fixed replay header.
+pub const HEADER_SIZE: usize = 0x4C8;
+/// Position of the `rez_offset` field within the header.
+pub const REZ_OFFSET_POS: usize = 0x2AC;
+/// Where the mission settings BLK starts in client replays. The two bytes
+/// before it (after the header) are unknown, `0x3c` in clients, 0 in servers.
+pub const M_SET_OFFSET: usize = HEADER_SIZE + 2;
+
+/// Roughly when the seconds counter in [`SessionId`] started (late 2022),
+/// estimated from sample replays.
+pub const SESSION_ID_EPOCH: u64 = 1_667_996_400;
+
+/// A session (battle) ID.
+///
+/// The high 32 bits count seconds since [`SESSION_ID_EPOCH`] and track when
+/// the session was created. The low 32 bits tell apart sessions created in
+/// the same second (presumably server and counter, not decoded further).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize)]
+#[serde(transparent)]
+pub struct SessionId(pub u64);
+
+impl SessionId {
+    /// The seconds counter (high 32 bits).
+    pub fn time_part(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    /// Everything below the seconds counter (low 32 bits).
+    pub fn low_part(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Approximate creation time (unix seconds), usually a few minutes
+    /// before the header's `start_time`.
+    pub fn approx_unix_time(self) -> u64 {
+        SESSION_ID_EPOCH + self.time_part() as u64
+    }
+
+    /// The zero-padded hex form used in replay URLs.
+    pub fn url_id(self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+
+impl fmt::LowerHex for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
 
-#[derive(Debug, Clone)]
+impl FromStr for SessionId {
+    type Err = std::num::ParseIntError;
+
+    /// Parses the hex form, with or without `0x` and leading zeroes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hex = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        u64::from_str_radix(hex, 16).map(SessionId)
+    }
+}
+
+impl From<u64> for SessionId {
+    fn from(id: u64) -> Self {
+        SessionId(id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Difficulty {
     pub unknown_nibble: u8,
     pub difficulty_value: u8,
@@ -24,9 +106,157 @@ impl Difficulty {
     }
 }
 
+/// What a mission is won by, from [`BattleClass`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ObjectiveKind {
+    /// `Dom`: one zone (or airfield, in air battles) to hold.
+    Domination,
+    /// `Conq`: a single zone, taken from a starting position.
+    Conquest,
+    /// `Bttl`: zones taken one after another.
+    Battle,
+    /// `GS`
+    GroundStrike,
+    /// `FL`
+    Frontline,
+    /// No known objective, the first part of `battle_class` that isn't
+    /// known either, as it appears there.
+    Other(String),
+    /// No objective part in `battle_class`.
+    Unknown,
+}
+
+/// `battle_class` taken apart, e.g. `air_ground_Conq` is a ground battle
+/// with aircraft and a conquest objective.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BattleClass {
+    pub air: bool,
+    pub ground: bool,
+    pub naval: bool,
+    pub objective: ObjectiveKind,
+    /// Parts that aren't known, in order.
+    pub unknown: Vec<String>,
+}
+
+impl BattleClass {
+    /// Never fails, parts that aren't known end up in `unknown` (and the
+    /// first of them in [`ObjectiveKind::Other`], if there's no known
+    /// objective).
+    pub fn parse(battle_class: &str) -> Self {
+        let mut class = BattleClass {
+            air: false,
+            ground: false,
+            naval: false,
+            objective: ObjectiveKind::Unknown,
+            unknown: Vec::new(),
+        };
+        for part in battle_class.split('_').filter(|p| !p.is_empty()) {
+            match part.to_lowercase().as_str() {
+                "air" => class.air = true,
+                // air battles over airfields, as in `base_dom`
+                "base" => class.air = true,
+                "ground" | "tank" | "tanks" => class.ground = true,
+                "naval" | "ship" | "ships" => class.naval = true,
+                "dom" => class.objective = ObjectiveKind::Domination,
+                "conq" => class.objective = ObjectiveKind::Conquest,
+                "bttl" | "battle" => class.objective = ObjectiveKind::Battle,
+                "gs" => class.objective = ObjectiveKind::GroundStrike,
+                "fl" => class.objective = ObjectiveKind::Frontline,
+                _ => class.unknown.push(part.to_string()),
+            }
+        }
+        if class.objective == ObjectiveKind::Unknown {
+            if let Some(part) = class.unknown.first() {
+                class.objective = ObjectiveKind::Other(part.clone());
+            }
+        }
+        class
+    }
+}
+
+/// Which vehicles kill streak rewards are flown in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum KillStreakVehicles {
+    AircraftOrHelicopter,
+    Aircraft,
+    Helicopter,
+    /// Anything else, as it appears in `battle_kill_streak`.
+    Other(String),
+}
+
+/// `battle_kill_streak` parsed, e.g. `killStreaksAircraftOrHelicopter_1`.
+/// Only set in ground battles with kill streak rewards (including nukes).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KillStreakConfig {
+    pub vehicles: KillStreakVehicles,
+    /// The number after the last `_`. Not known what it picks, presumably
+    /// a set of rewards.
+    pub preset: Option<u32>,
+}
+
+impl KillStreakConfig {
+    /// `None` if kill streaks are off (the field is empty).
+    pub fn parse(battle_kill_streak: &str) -> Option<Self> {
+        if battle_kill_streak.is_empty() {
+            return None;
+        }
+        let (name, preset) = battle_kill_streak
+            .rsplit_once('_')
+            .and_then(|(name, n)| Some((name, Some(n.parse::<u32>().ok()?))))
+            .unwrap_or((battle_kill_streak, None));
+        let vehicles = match name.strip_prefix("killStreaks").unwrap_or(name) {
+            "AircraftOrHelicopter" => KillStreakVehicles::AircraftOrHelicopter,
+            "Aircraft" => KillStreakVehicles::Aircraft,
+            "Helicopter" => KillStreakVehicles::Helicopter,
+            _ => KillStreakVehicles::Other(name.to_string()),
+        };
+        Some(KillStreakConfig { vehicles, preset })
+    }
+}
+
+/// How to parse a header.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderOptions {
+    /// Keep the regions between known fields, see [`UnknownRegions`].
+    pub keep_unknown: bool,
+    /// Decode the mission settings BLK after the header, see
+    /// [`parse_mission_settings`].
+    pub mission_settings: bool,
+}
+
+/// The parts of the header between known fields, which are skipped as
+/// padding but likely hold something. Named after the field before them.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct UnknownRegions {
+    /// 35 bytes at 0x2B1.
+    pub after_difficulty: Vec<u8>,
+    /// 4 bytes at 0x2D8.
+    pub after_session_type: Vec<u8>,
+    /// 4 bytes at 0x2E4.
+    pub after_session_id: Vec<u8>,
+    /// 32 bytes at 0x2EC.
+    pub after_m_set_size: Vec<u8>,
+    /// 48 bytes at 0x398.
+    pub after_score_limit: Vec<u8>,
+}
+
+impl UnknownRegions {
+    /// Every region with its name and offset in the file.
+    pub fn regions(&self) -> [(&'static str, usize, &[u8]); 5] {
+        [
+            ("after_difficulty", 0x2B1, &self.after_difficulty),
+            ("after_session_type", 0x2D8, &self.after_session_type),
+            ("after_session_id", 0x2E4, &self.after_session_id),
+            ("after_m_set_size", 0x2EC, &self.after_m_set_size),
+            ("after_score_limit", 0x398, &self.after_score_limit),
+        ]
+    }
+}
+
 /// The header of a replay file.
 /// Should be agnostic towards server or client.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
 pub struct ReplayHeader {
     /// The magic bytes used for .wrpl.
     pub magic: u32,
@@ -55,8 +285,9 @@ pub struct ReplayHeader {
     pub session_type: u32,
     /// the session id of the replay.
     /// seen in both decimal and hex.
-    pub session_id_hex: u64,
+    pub session_id: SessionId,
     /// ???
+    // not the size of the mission settings BLK, that's much smaller
     pub m_set_size: u32,
     /// ???
     pub loc_name: String,
@@ -68,8 +299,30 @@ pub struct ReplayHeader {
     pub score_limit: u32,
     /// vehicles usable?
     pub battle_class: String,
-    /// `killStreaksAircraftOrHelicopter_1` if nukes are available
+    /// `killStreaksAircraftOrHelicopter_1` if nukes are available, see
+    /// [`ReplayHeader::kill_streak`].
     pub battle_kill_streak: String,
+    /// The mission settings BLK that follows the header, as JSON.
+    /// Only client replays have one, and it's only read with
+    /// [`HeaderOptions::mission_settings`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mission_settings: Option<serde_json::Value>,
+    /// Only kept with [`HeaderOptions::keep_unknown`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unknown: Option<UnknownRegions>,
+}
+
+impl ReplayHeader {
+    /// [`ReplayHeader::battle_class`] parsed, see [`BattleClass::parse`].
+    pub fn parsed_battle_class(&self) -> BattleClass {
+        BattleClass::parse(&self.battle_class)
+    }
+
+    /// [`ReplayHeader::battle_kill_streak`] parsed, `None` if kill streaks
+    /// are off.
+    pub fn kill_streak(&self) -> Option<KillStreakConfig> {
+        KillStreakConfig::parse(&self.battle_kill_streak)
+    }
 }
 
 impl fmt::Display for ReplayHeader {
@@ -91,7 +344,7 @@ impl fmt::Display for ReplayHeader {
         writeln!(
             f,
             "Session ID: {:#x} ({})",
-            self.session_id_hex, self.session_id_hex
+            self.session_id.0, self.session_id.0
         )?;
         writeln!(f, "MSet Size: {}", self.m_set_size)?;
         writeln!(f, "Location Name: {}", self.loc_name)?;
@@ -104,10 +357,37 @@ impl fmt::Display for ReplayHeader {
     }
 }
 
+/// Reads and parses the header from the start of a reader,
+/// leaving it positioned right after the header.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<ReplayHeader> {
+    let mut data = [0u8; HEADER_SIZE];
+    reader
+        .read_exact(&mut data)
+        .context("Input is too short to contain a replay header")?;
+    parse_header(&data)
+}
+
 /// Parses the header of a replay file from a byte slice.
 pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
+    parse_header_with_options(data, &HeaderOptions::default())
+}
+
+/// Same as [`parse_header`], with [`HeaderOptions`].
+pub fn parse_header_with_options(data: &[u8], options: &HeaderOptions) -> Result<ReplayHeader> {
     let mut cursor = Cursor::new(data);
     let mut buffer = [0u8; 4];
+    let mut unknown = UnknownRegions::default();
+    // skips `len` bytes, keeping them if asked to
+    let skip = |cursor: &mut Cursor<&[u8]>, len: usize| -> Result<Vec<u8>> {
+        if options.keep_unknown {
+            let mut region = vec![0u8; len];
+            cursor.read_exact(&mut region)?;
+            Ok(region)
+        } else {
+            cursor.seek(SeekFrom::Current(len as i64))?;
+            Ok(Vec::new())
+        }
+    };
 
     // Read magic
     cursor.read_exact(&mut buffer)?;
@@ -116,6 +396,13 @@ pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
     // Read version
     cursor.read_exact(&mut buffer)?;
     let version = u32::from_le_bytes(buffer);
+    let compatibility = compat::check(version);
+    if compatibility.level != SupportLevel::Full {
+        warn!(
+            "Replay version {} isn't a tested one ({:?} support), only {:?} expected to work",
+            version, compatibility.level, compatibility.working
+        );
+    }
 
     // Read level (128 bytes)
     let level = read_string(&mut cursor, 128)?;
@@ -141,30 +428,30 @@ pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
     cursor.read_exact(&mut diff_byte)?;
     let difficulty = Difficulty::from_byte(diff_byte[0]);
 
-    // Skip padding (35 bytes)
-    cursor.seek(SeekFrom::Current(35))?;
+    // Unknown (35 bytes)
+    unknown.after_difficulty = skip(&mut cursor, 35)?;
 
     // Read session type
     cursor.read_exact(&mut buffer)?;
     let session_type = u32::from_le_bytes(buffer);
 
-    // Skip padding (4 bytes)
-    cursor.seek(SeekFrom::Current(4))?;
+    // Unknown (4 bytes)
+    unknown.after_session_type = skip(&mut cursor, 4)?;
 
     // Read session id (8 bytes)
     let mut session_buffer = [0u8; 8];
     cursor.read_exact(&mut session_buffer)?;
-    let session_id_hex = u64::from_le_bytes(session_buffer);
+    let session_id = SessionId(u64::from_le_bytes(session_buffer));
 
-    // Skip padding (4 bytes)
-    cursor.seek(SeekFrom::Current(4))?;
+    // Unknown (4 bytes)
+    unknown.after_session_id = skip(&mut cursor, 4)?;
 
     // Read m_set_size
     cursor.read_exact(&mut buffer)?;
     let m_set_size = u32::from_le_bytes(buffer);
 
-    // Skip padding (32 bytes)
-    cursor.seek(SeekFrom::Current(32))?;
+    // Unknown (32 bytes)
+    unknown.after_m_set_size = skip(&mut cursor, 32)?;
 
     // Read loc_name (128 bytes)
     let loc_name = read_string(&mut cursor, 128)?;
@@ -181,8 +468,8 @@ pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
     cursor.read_exact(&mut buffer)?;
     let score_limit = u32::from_le_bytes(buffer);
 
-    // Skip padding (48 bytes)
-    cursor.seek(SeekFrom::Current(48))?;
+    // Unknown (48 bytes)
+    unknown.after_score_limit = skip(&mut cursor, 48)?;
 
     // Read battle_class (128 bytes)
     let battle_class = read_string(&mut cursor, 128)?;
@@ -190,6 +477,15 @@ pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
     // Read battle_kill_streak (128 bytes)
     let battle_kill_streak = read_string(&mut cursor, 128)?;
 
+    let mission_settings = if options.mission_settings {
+        parse_mission_settings(data).unwrap_or_else(|e| {
+            warn!("{:#}", e);
+            None
+        })
+    } else {
+        None
+    };
+
     Ok(ReplayHeader {
         magic,
         version,
@@ -201,7 +497,7 @@ pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
         rez_offset,
         difficulty,
         session_type,
-        session_id_hex,
+        session_id,
         m_set_size,
         loc_name,
         start_time,
@@ -209,9 +505,34 @@ pub fn parse_header(data: &[u8]) -> Result<ReplayHeader> {
         score_limit,
         battle_class,
         battle_kill_streak,
+        mission_settings,
+        unknown: options.keep_unknown.then_some(unknown),
     })
 }
 
+/// Parses the mission settings BLK after the header (see [`M_SET_OFFSET`]).
+/// Returns `None` if there isn't one, as in server replays where the packet
+/// stream starts right there.
+///
+/// The BLK parser panics on some truncated or corrupt BLKs, which is caught
+/// and returned as an error (the panic message is still printed).
+pub fn parse_mission_settings(data: &[u8]) -> Result<Option<serde_json::Value>> {
+    // only FAT BLKs have been seen here, anything else is more likely garbage
+    if !matches!(data.get(M_SET_OFFSET), Some(0x01 | 0x02)) {
+        return Ok(None);
+    }
+    // it runs up to the packet stream, without which it may be cut off
+    let end = utils::find_zlib_header(data, M_SET_OFFSET)
+        .context("No packet stream after the mission settings BLK, it may be cut off")?;
+    let blk = &data[M_SET_OFFSET..end];
+    let json = panic::catch_unwind(|| parser::decompress_blk(blk))
+        .map_err(|_| anyhow!("BLK parser panicked"))
+        .and_then(|json| json)
+        .context("Failed to decode mission settings BLK")?;
+    let value = serde_json::from_str(&json).context("Failed to parse mission settings JSON")?;
+    Ok(Some(value))
+}
+
 fn read_string<R: Read + Seek>(reader: &mut R, max_len: usize) -> Result<String> {
     let mut buffer = vec![0u8; max_len];
     reader.read_exact(&mut buffer)?;
@@ -0,0 +1,413 @@
+//! SQLite-backed index of a replay folder.
+//!
+//! Headers and results of every ingested replay are stored in a small database,
+//! so large collections can be queried without re-parsing each file.
+//! Files are only re-parsed when their size or modification time changes.
+
+use crate::header::{self, SessionId};
+use crate::parser::{self, ParseOptions, ReplayResults};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use rusqlite::types::ToSql;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS replays (
+    path TEXT PRIMARY KEY,
+    file_size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    session_id INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    level TEXT NOT NULL,
+    battle_type TEXT NOT NULL,
+    battle_class TEXT NOT NULL,
+    start_time INTEGER NOT NULL,
+    status TEXT,
+    outcome TEXT,
+    time_played REAL,
+    author TEXT,
+    author_user_id TEXT
+);
+CREATE TABLE IF NOT EXISTS players (
+    replay_path TEXT NOT NULL REFERENCES replays(path) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    username TEXT NOT NULL,
+    squadron_tag TEXT NOT NULL,
+    team INTEGER NOT NULL,
+    squad INTEGER NOT NULL,
+    kills INTEGER NOT NULL,
+    ground_kills INTEGER NOT NULL,
+    naval_kills INTEGER NOT NULL,
+    ai_kills INTEGER NOT NULL,
+    ai_ground_kills INTEGER NOT NULL,
+    ai_naval_kills INTEGER NOT NULL,
+    assists INTEGER NOT NULL,
+    deaths INTEGER NOT NULL,
+    score INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS replays_session_id ON replays(session_id);
+CREATE INDEX IF NOT EXISTS replays_start_time ON replays(start_time);
+CREATE INDEX IF NOT EXISTS replays_outcome ON replays(outcome);
+CREATE INDEX IF NOT EXISTS players_replay_path ON players(replay_path);
+CREATE INDEX IF NOT EXISTS players_user_id ON players(user_id);
+CREATE INDEX IF NOT EXISTS players_username ON players(username);
+";
+
+/// What happened to a file passed to [`ReplayLibrary::ingest_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestOutcome {
+    /// The replay wasn't indexed before.
+    Added,
+    /// The replay changed on disk and was re-parsed.
+    Updated,
+    /// The replay is already indexed and hasn't changed.
+    Unchanged,
+}
+
+/// Counts from [`ReplayLibrary::ingest_dir`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IngestSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Files that couldn't be parsed at all.
+    pub failed: usize,
+}
+
+/// Filters for [`ReplayLibrary::query`]. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayQuery {
+    /// Substring of the level path, e.g. `"sinai"`.
+    pub map: Option<String>,
+    /// Substring of the battle type or battle class, e.g. `"Conq"`.
+    pub mode: Option<String>,
+    /// Username or user ID of a player that took part.
+    pub player: Option<String>,
+    /// Only battles starting at or after this time (seconds since epoch).
+    pub started_after: Option<u32>,
+    /// Only battles starting before this time (seconds since epoch).
+    pub started_before: Option<u32>,
+    /// Outcome as given by [`ReplayResults::outcome_label`], e.g.
+    /// `"Victory"` or `"Defeat"`.
+    pub outcome: Option<String>,
+    /// Maximum number of rows to return.
+    pub limit: Option<usize>,
+}
+
+/// A replay row returned by [`ReplayLibrary::query`].
+#[derive(Debug, Clone)]
+pub struct IndexedReplay {
+    pub path: PathBuf,
+    pub session_id: SessionId,
+    pub version: u32,
+    pub level: String,
+    pub battle_type: String,
+    pub battle_class: String,
+    /// Seconds since epoch.
+    pub start_time: u32,
+    /// `None` if the replay had no results.
+    pub status: Option<String>,
+    /// See [`ReplayResults::outcome_label`], `None` if the replay had no
+    /// results.
+    pub outcome: Option<String>,
+    pub time_played: Option<f64>,
+    pub author: Option<String>,
+    pub author_user_id: Option<String>,
+}
+
+/// An index of replays backed by a SQLite database.
+pub struct ReplayLibrary {
+    conn: Connection,
+}
+
+impl ReplayLibrary {
+    /// Opens (or creates) the index database at `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open index database {:?}", path))?;
+        Self::from_connection(conn)
+    }
+
+    /// Creates a throwaway index that lives only in memory.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        add_outcome_column(&conn)?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create index schema")?;
+        Ok(ReplayLibrary { conn })
+    }
+
+    /// The underlying connection, for running custom SQL.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Number of indexed replays.
+    pub fn len(&self) -> Result<usize> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM replays", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Whether the index contains no replays.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Adds a replay to the index, re-parsing it only if it changed on disk.
+    pub fn ingest_file(&mut self, path: &Path) -> Result<IngestOutcome> {
+        let metadata =
+            fs::metadata(path).with_context(|| format!("Failed to stat replay {:?}", path))?;
+        let file_size = metadata.len() as i64;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as i64);
+        let path_str = path.to_string_lossy();
+
+        let existing: Option<(i64, i64)> = self
+            .conn
+            .query_row(
+                "SELECT file_size, modified FROM replays WHERE path = ?1",
+                [&path_str],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if existing == Some((file_size, modified)) {
+            debug!("{:?} is unchanged, skipping", path);
+            return Ok(IngestOutcome::Unchanged);
+        }
+
+        let data = fs::read(path).with_context(|| format!("Failed to read replay {:?}", path))?;
+        let header = header::parse_header(&data)
+            .with_context(|| format!("Failed to parse header of {:?}", path))?;
+        let results = parser::results_for(&data, &header, &ParseOptions::default());
+
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM replays WHERE path = ?1", [&path_str])?;
+        tx.execute(
+            "INSERT INTO replays (path, file_size, modified, session_id, version, level,
+                battle_type, battle_class, start_time, status, outcome, time_played, author,
+                author_user_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                path_str,
+                file_size,
+                modified,
+                // SQLite has no u64, store the bits as-is
+                header.session_id.0 as i64,
+                header.version,
+                header.level,
+                header.battle_type,
+                header.battle_class,
+                header.start_time,
+                results.as_ref().map(|r| &r.status),
+                results.as_ref().map(|r| r.outcome_label()),
+                results.as_ref().map(|r| r.time_played),
+                results.as_ref().map(|r| &r.author),
+                results.as_ref().map(|r| &r.author_user_id),
+            ],
+        )?;
+        if let Some(results) = &results {
+            insert_players(&tx, &path_str, results)?;
+        }
+        tx.commit()?;
+
+        Ok(if existing.is_some() {
+            IngestOutcome::Updated
+        } else {
+            IngestOutcome::Added
+        })
+    }
+
+    /// Returns the indexed replays matching `query`, newest first.
+    pub fn query(&self, query: &ReplayQuery) -> Result<Vec<IndexedReplay>> {
+        let mut sql = String::from(
+            "SELECT path, session_id, version, level, battle_type, battle_class, start_time,
+                status, outcome, time_played, author, author_user_id
+             FROM replays r WHERE 1 = 1",
+        );
+        let mut args: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(map) = &query.map {
+            sql.push_str(" AND r.level LIKE ? ESCAPE '\\'");
+            args.push(Box::new(contains_pattern(map)));
+        }
+        if let Some(mode) = &query.mode {
+            sql.push_str(
+                " AND (r.battle_type LIKE ? ESCAPE '\\' OR r.battle_class LIKE ? ESCAPE '\\')",
+            );
+            args.push(Box::new(contains_pattern(mode)));
+            args.push(Box::new(contains_pattern(mode)));
+        }
+        if let Some(player) = &query.player {
+            sql.push_str(
+                " AND EXISTS (SELECT 1 FROM players p WHERE p.replay_path = r.path
+                    AND (p.username = ? OR p.user_id = ?))",
+            );
+            args.push(Box::new(player.clone()));
+            args.push(Box::new(player.clone()));
+        }
+        if let Some(after) = query.started_after {
+            sql.push_str(" AND r.start_time >= ?");
+            args.push(Box::new(after));
+        }
+        if let Some(before) = query.started_before {
+            sql.push_str(" AND r.start_time < ?");
+            args.push(Box::new(before));
+        }
+        if let Some(outcome) = &query.outcome {
+            sql.push_str(" AND r.outcome = ?");
+            args.push(Box::new(outcome.clone()));
+        }
+        sql.push_str(" ORDER BY r.start_time DESC");
+        if let Some(limit) = query.limit {
+            sql.push_str(" LIMIT ?");
+            args.push(Box::new(limit as i64));
+        }
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args.iter()), |row| {
+            Ok(IndexedReplay {
+                path: PathBuf::from(row.get::<_, String>(0)?),
+                session_id: SessionId(row.get::<_, i64>(1)? as u64),
+                version: row.get(2)?,
+                level: row.get(3)?,
+                battle_type: row.get(4)?,
+                battle_class: row.get(5)?,
+                start_time: row.get(6)?,
+                status: row.get(7)?,
+                outcome: row.get(8)?,
+                time_played: row.get(9)?,
+                author: row.get(10)?,
+                author_user_id: row.get(11)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Ingests every `.wrpl` file in `dir` (non-recursively).
+    pub fn ingest_dir(&mut self, dir: &Path) -> Result<IngestSummary> {
+        let mut summary = IngestSummary::default();
+
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+            let path = entry?.path();
+            let is_wrpl = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("wrpl"));
+            if !is_wrpl || !path.is_file() {
+                continue;
+            }
+
+            match self.ingest_file(&path) {
+                Ok(IngestOutcome::Added) => summary.added += 1,
+                Ok(IngestOutcome::Updated) => summary.updated += 1,
+                Ok(IngestOutcome::Unchanged) => summary.unchanged += 1,
+                Err(e) => {
+                    warn!("Failed to index {:?}: {:?}", path, e);
+                    summary.failed += 1;
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Removes replays whose files no longer exist. Returns how many were removed.
+    pub fn prune_missing(&mut self) -> Result<usize> {
+        let paths: Vec<String> = {
+            let mut stmt = self.conn.prepare("SELECT path FROM replays")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let tx = self.conn.transaction()?;
+        let mut removed = 0;
+        for path in paths.iter().filter(|p| !Path::new(p).exists()) {
+            removed += tx.execute("DELETE FROM replays WHERE path = ?1", [path])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+}
+
+/// Adds the `outcome` column to indexes made before it existed, filling it
+/// in from `status` with [`parser::outcome_label_for`].
+fn add_outcome_column(conn: &Connection) -> Result<()> {
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('replays')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if columns.is_empty() || columns.iter().any(|c| c == "outcome") {
+        return Ok(());
+    }
+    debug!("Adding the outcome column to the index");
+    conn.execute_batch("ALTER TABLE replays ADD COLUMN outcome TEXT")
+        .context("Failed to add the outcome column to the index")?;
+    let statuses: Vec<String> = conn
+        .prepare("SELECT DISTINCT status FROM replays WHERE status IS NOT NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for status in statuses {
+        conn.execute(
+            "UPDATE replays SET outcome = ?1 WHERE status = ?2",
+            params![parser::outcome_label_for(&status), status],
+        )
+        .context("Failed to fill in the outcome column")?;
+    }
+    Ok(())
+}
+
+/// A LIKE pattern matching `text` anywhere, with its wildcards escaped
+/// (for use with `ESCAPE '\'`).
+fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+fn insert_players(conn: &Connection, path: &str, results: &ReplayResults) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO players (replay_path, user_id, username, squadron_tag, team, squad,
+            kills, ground_kills, naval_kills, ai_kills, ai_ground_kills, ai_naval_kills,
+            assists, deaths, score)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+    )?;
+    for player in &results.players {
+        let info = &player.player_info;
+        let data = &player.replay_data;
+        stmt.execute(params![
+            path,
+            info.user_id,
+            info.username,
+            info.squadron_tag,
+            data.team,
+            data.squad,
+            data.kills,
+            data.ground_kills,
+            data.naval_kills,
+            data.ai_kills,
+            data.ai_ground_kills,
+            data.ai_naval_kills,
+            data.assists,
+            data.deaths,
+            data.score,
+        ])?;
+    }
+    Ok(())
+}
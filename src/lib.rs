@@ -1,12 +1,75 @@
+//! Decoding War Thunder replays (`.wrpl`).
+//!
+//! Start with the [`prelude`]: [`Replay::open`](replay::Replay::open) parses
+//! a file into its header, chat, results and packets. Those types are the
+//! stable part of the crate. The structs it returns are `#[non_exhaustive]`,
+//! so fields can be added as more of the format is worked out without
+//! breaking anyone.
+//!
+//! Tools that work on the stream's bytes can use [`raw`], which follows the
+//! format as it's understood at the time and changes with it.
+
+pub mod aggregate;
+pub mod anonymize;
+pub mod cache;
+pub mod capture;
+pub mod clock;
+pub mod compat;
+pub mod coords;
+pub mod decode;
+pub mod dedup;
+pub mod diff;
+pub mod dissect;
+#[cfg(feature = "download")]
+pub mod download;
+pub mod events;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flight;
+pub mod format;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
 pub mod header;
+#[cfg(feature = "index")]
+pub mod index;
+pub mod localize;
+pub mod locate;
+pub mod logging;
+pub mod maps;
+pub mod memory;
+pub mod merge;
+#[cfg(feature = "metadata")]
+pub mod metadata;
 pub mod parser;
+pub mod playback;
+pub mod prelude;
+// experimental until checked against real packets, see its docs
+#[doc(hidden)]
+pub mod quantize;
+pub mod raw;
+pub mod replay;
+pub mod rewrite;
+pub mod search;
+pub mod seek;
+pub mod stats;
+pub mod tail;
+pub mod teams;
+pub mod telemetry;
+pub mod trajectory;
+pub mod transcode;
+pub mod transcript;
 pub mod utils;
+pub mod validate;
+pub mod writer;
 
 #[cfg(test)]
 mod tests {
     use std::fs::read;
+    use std::io::Cursor;
 
     use super::*;
+    use parser::Compression;
 
     // we already know the offset of zlib is 0x828 for client_1
     #[test]
@@ -15,7 +78,7 @@ mod tests {
     fn test_parse_client_1() {
         let file = std::fs::read("tests/replays/client_1.wrpl").unwrap();
 
-        let replay = parser::process_replay_stream(&file, 2088, false, None).unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
         // 19 messages
         assert_eq!(replay.chat_messages.len(), 19);
         // first one is "TEST" from "kiTmalZ"
@@ -57,7 +120,7 @@ mod tests {
         assert_eq!(header.rez_offset, 3662909);
         // skip difficulty for now
         assert_eq!(header.session_type, 0);
-        assert_eq!(header.session_id_hex, 335055458235795646);
+        assert_eq!(header.session_id.0, 335055458235795646);
         assert_eq!(header.m_set_size, 8062);
         assert_eq!(header.loc_name, "missions/_Conq1;sinai_02/name");
         assert_eq!(header.start_time, 1746008224);
@@ -88,7 +151,7 @@ mod tests {
         assert_eq!(header.visibility, "cloudy");
         assert_eq!(header.rez_offset, 0);
         assert_eq!(header.session_type, 0);
-        assert_eq!(header.session_id_hex, 336062142732521316);
+        assert_eq!(header.session_id.0, 336062142732521316);
         assert_eq!(header.m_set_size, 30709);
         assert_eq!(
             header.loc_name,
@@ -120,4 +183,2798 @@ mod tests {
         assert_eq!(results.players.len(), 18);
         assert_eq!(results.players[0].player_info.platform, "win64")
     }
+
+    #[test]
+    /// Scan /tests/replays/ and make sure the header-only read matches a full parse.
+    fn test_scan_replays() {
+        let summaries = locate::scan_replays(std::path::Path::new("tests/replays")).unwrap();
+        assert_eq!(summaries.len(), 6);
+
+        let client_1 = summaries
+            .iter()
+            .find(|s| s.path.ends_with("client_1.wrpl"))
+            .unwrap();
+        let header = client_1.header.as_ref().unwrap();
+        assert_eq!(header.session_id.0, 335055458235795646);
+        assert_eq!(header.battle_kill_streak, "");
+
+        // client_2 and server_2 are the same battle
+        let groups = dedup::group_by_session(&summaries);
+        let shared = groups
+            .iter()
+            .find(|g| g.session_id.0 == 0x5650b4a0022daeb)
+            .unwrap();
+        assert_eq!(shared.copies.len(), 2);
+        assert!(shared.best().files[0].path.ends_with("server_2.wrpl"));
+        assert!(shared.duplicates()[0].files[0]
+            .path
+            .ends_with("client_2.wrpl"));
+    }
+
+    #[test]
+    /// Server segments of one session are one copy, not copies of each other.
+    fn test_dedup_segments() {
+        let dir = std::env::temp_dir().join(format!("wrpl_dedup_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server_2 = read("tests/replays/server_2.wrpl").unwrap();
+        // server_3 as an earlier segment of server_2's battle
+        let mut first = read("tests/replays/server_3.wrpl").unwrap();
+        first[0x2DC..0x2E4].copy_from_slice(&server_2[0x2DC..0x2E4]);
+        std::fs::write(dir.join("0000.wrpl"), &first).unwrap();
+        std::fs::write(dir.join("0001.wrpl"), &server_2).unwrap();
+        // downloaded twice
+        std::fs::write(dir.join("0000 (1).wrpl"), &first).unwrap();
+        std::fs::copy("tests/replays/client_2.wrpl", dir.join("client_2.wrpl")).unwrap();
+
+        let summaries = locate::scan_replays(&dir).unwrap();
+        let groups = dedup::group_by_session(&summaries);
+        let unique = dedup::unique_replays(&summaries);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        let best = groups[0].best();
+        assert!(best.is_segmented());
+        // either download of the first segment, then the last
+        assert_eq!(best.files.len(), 2);
+        assert!(best.files[1].path.ends_with("0001.wrpl"));
+        assert_eq!(groups[0].duplicates().len(), 2);
+        assert_eq!(unique.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "index")]
+    /// Index /tests/replays/client_2.wrpl twice, the second time should be a no-op.
+    fn test_index_client_2() {
+        let mut library = index::ReplayLibrary::open_in_memory().unwrap();
+        let path = std::path::Path::new("tests/replays/client_2.wrpl");
+
+        assert_eq!(
+            library.ingest_file(path).unwrap(),
+            index::IngestOutcome::Added
+        );
+        assert_eq!(
+            library.ingest_file(path).unwrap(),
+            index::IngestOutcome::Unchanged
+        );
+        assert_eq!(library.len().unwrap(), 1);
+
+        let players: i64 = library
+            .connection()
+            .query_row("SELECT COUNT(*) FROM players", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(players, 18);
+
+        let by_author = library
+            .query(&index::ReplayQuery {
+                player: Some("176625161".to_string()),
+                outcome: Some("Victory".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(by_author.len(), 1);
+        assert_eq!(by_author[0].status.as_deref(), Some("fail"));
+        assert_eq!(by_author[0].author.as_deref(), Some("[WTPU3] kiTmalZ"));
+
+        let wrong_map = library
+            .query(&index::ReplayQuery {
+                map: Some("not_a_map".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(wrong_map.is_empty());
+        // LIKE wildcards are matched literally
+        let wildcard = library
+            .query(&index::ReplayQuery {
+                map: Some("_".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(wildcard.is_empty());
+
+        // an index from before the outcome column gets it filled in
+        let db = std::env::temp_dir().join(format!("wrpl_index_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db);
+        let mut library = index::ReplayLibrary::open(&db).unwrap();
+        library.ingest_file(path).unwrap();
+        library
+            .connection()
+            .execute_batch(
+                "DROP INDEX replays_outcome;
+                 ALTER TABLE replays DROP COLUMN outcome;",
+            )
+            .unwrap();
+        drop(library);
+        let library = index::ReplayLibrary::open(&db).unwrap();
+        let outcome: String = library
+            .connection()
+            .query_row("SELECT outcome FROM replays", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(outcome, "Victory");
+        drop(library);
+        std::fs::remove_file(&db).unwrap();
+    }
+
+    #[test]
+    /// Anonymize /tests/replays/client_1.wrpl and make sure it still parses,
+    /// with every name replaced.
+    fn test_anonymize_client_1() {
+        let file = std::fs::read("tests/replays/client_1.wrpl").unwrap();
+        let anonymized = anonymize::anonymize(&file).unwrap();
+        let fake_sender = &anonymized.mapping["kiTmalZ"];
+        assert_eq!(fake_sender.len(), "kiTmalZ".len());
+
+        let header = header::parse_header(&anonymized.data).unwrap();
+        let offset = utils::find_zlib_header(&anonymized.data, header::HEADER_SIZE).unwrap();
+        let replay = parser::process_replay_stream(
+            &anonymized.data,
+            offset as u64,
+            Compression::Zlib,
+            Some(&header),
+        )
+        .unwrap();
+
+        assert_eq!(replay.chat_messages.len(), 19);
+        assert_eq!(&replay.chat_messages[0].sender, fake_sender);
+        assert_eq!(replay.chat_messages[0].message, "TEST");
+
+        let results = replay.replay_results.unwrap();
+        for player in &results.players {
+            assert!(!anonymized
+                .mapping
+                .contains_key(&player.player_info.username));
+        }
+        assert!(!results.author.contains("kiTmalZ"));
+
+        // compressed results can't be rewritten in place
+        let mut zstd = file.clone();
+        let rez_offset = header.rez_offset as usize;
+        zstd[rez_offset] = 0x02;
+        assert!(anonymize::anonymize(&zstd).is_err());
+
+        // one-letter senders (more of them than there are digits) each get
+        // their own pseudonym
+        let header = header::parse_header(&file).unwrap();
+        let mut replay =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+        let mut senders = (b'a'..=b'z').cycle();
+        for packet in &mut replay.packets {
+            if packet.packet_type == parser::ReplayPacketType::Chat {
+                let sender_len = packet.payload[1] as usize;
+                let sender = senders.next().unwrap();
+                packet.payload.splice(1..2 + sender_len, [1, sender]);
+                packet.size = packet.payload.len() as u32;
+            }
+        }
+        let rez_offset = header.rez_offset as usize;
+        let renamed = writer::write_replay(
+            &header,
+            &file[header::HEADER_SIZE..2088],
+            &replay.packets,
+            Some(&file[rez_offset..]),
+            transcode::StreamEncoding::Zlib { level: 6 },
+        )
+        .unwrap();
+        let anonymized = anonymize::anonymize(&renamed).unwrap();
+        let fakes: Vec<&String> = (b'a'..=b's')
+            .map(|sender| &anonymized.mapping[&(sender as char).to_string()])
+            .collect();
+        let unique: std::collections::HashSet<_> = fakes.iter().collect();
+        assert_eq!(unique.len(), 19);
+        assert!(fakes.iter().all(|fake| fake.len() == 1));
+    }
+
+    #[test]
+    /// Transcode /tests/replays/client_1.wrpl to an uncompressed stream and parse it raw.
+    fn test_transcode_client_1_uncompressed() {
+        let file = std::fs::read("tests/replays/client_1.wrpl").unwrap();
+        let transcoded =
+            transcode::transcode(&file, transcode::StreamEncoding::Uncompressed).unwrap();
+        assert_eq!(transcoded.stream_offset, 2088);
+
+        let header = header::parse_header(&transcoded.data).unwrap();
+        let replay = parser::process_replay_stream(
+            &transcoded.data[..header.rez_offset as usize],
+            transcoded.stream_offset,
+            Compression::None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(replay.chat_messages.len(), 19);
+        assert_eq!(replay.chat_messages[18].sender, "AceLavrinenko");
+        assert!(
+            parser::parse_replay_results(&transcoded.data, header.rez_offset as usize).is_some()
+        );
+    }
+
+    #[test]
+    /// Re-write /tests/replays/client_1.wrpl from its parsed parts.
+    fn test_write_client_1() {
+        let file = std::fs::read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+
+        let rez_offset = header.rez_offset as usize;
+        let written = writer::write_replay(
+            &header,
+            &file[header::HEADER_SIZE..2088],
+            &replay.packets,
+            Some(&file[rez_offset..]),
+            transcode::StreamEncoding::Zlib { level: 1 },
+        )
+        .unwrap();
+
+        let new_header = header::parse_header(&written).unwrap();
+        assert_eq!(new_header.session_id, header.session_id);
+        let reparsed =
+            parser::process_replay_stream(&written, 2088, Compression::Zlib, Some(&new_header))
+                .unwrap();
+        assert_eq!(reparsed.packet_count, replay.packet_count);
+        for (a, b) in reparsed.packets.iter().zip(&replay.packets) {
+            assert_eq!(a.packet_type, b.packet_type);
+            assert_eq!(a.timestamp_ms, b.timestamp_ms);
+            assert!(a.payload == b.payload);
+        }
+        assert_eq!(reparsed.chat_messages.len(), 19);
+        assert!(reparsed.replay_results.is_some());
+
+        // the unknown header regions survive a round trip if they're kept
+        let options = header::HeaderOptions {
+            keep_unknown: true,
+            ..Default::default()
+        };
+        let header = header::parse_header_with_options(&file, &options).unwrap();
+        let written = writer::write_header(&header).unwrap();
+        assert_eq!(written, file[..header::HEADER_SIZE]);
+        assert!(header
+            .unknown
+            .unwrap()
+            .after_difficulty
+            .iter()
+            .any(|&b| b != 0));
+    }
+
+    #[test]
+    /// Export a hand-made track to GeoJSON and KML.
+    fn test_export_tracks() {
+        use telemetry::{TelemetrySample, Track, UnitRef, Vec3};
+
+        let tracks = vec![Track {
+            unit: UnitRef {
+                id: 7,
+                name: Some("kiTmalZ".to_string()),
+            },
+            samples: vec![
+                TelemetrySample {
+                    time_ms: 0,
+                    position: Vec3::new(1.0, 2.0, 3.0),
+                },
+                TelemetrySample {
+                    time_ms: 1500,
+                    position: Vec3::new(4.0, 5.0, 6.0),
+                },
+            ],
+        }];
+
+        let geojson = export::geo::tracks_to_geojson(&tracks);
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["coordinates"][1][0], 4.0);
+        assert_eq!(feature["geometry"]["coordinates"][1][1], 6.0);
+        assert_eq!(feature["properties"]["times_ms"][1], 1500);
+
+        let kml = export::geo::tracks_to_kml(&tracks, 1746008224);
+        assert!(kml.contains("<when>2025-04-30T10:17:05.500Z</when>"));
+        assert!(kml.contains("<gx:coord>1 3 2</gx:coord>"));
+    }
+
+    #[test]
+    #[cfg(feature = "arrow")]
+    /// Export the chat of /tests/replays/client_1.wrpl as an Arrow IPC table.
+    fn test_export_arrow_chat() {
+        let file = std::fs::read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+
+        let chat = export::arrow::chat_batch(&replay).unwrap();
+        assert_eq!(chat.num_rows(), 19);
+
+        let mut out = Vec::new();
+        export::arrow::write_ipc(&chat, &mut out).unwrap();
+        assert!(out.starts_with(b"ARROW1"));
+    }
+
+    #[test]
+    /// Export chat and players of /tests/replays/client_2.wrpl as CSV.
+    fn test_export_csv() {
+        let file = std::fs::read("tests/replays/client_2.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let results = parser::parse_replay_results(&file, header.rez_offset as usize).unwrap();
+
+        let mut out = Vec::new();
+        export::csv::write_players_csv(&results, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        // header + 18 players
+        assert_eq!(csv.lines().count(), 19);
+        assert!(csv.starts_with("user_id,username,"));
+
+        let chat = vec![parser::ChatInfo {
+            timestamp_ms: 5 * 256,
+            sender: "a".to_string(),
+            message: "hello, \"world\"".to_string(),
+            channel_type: Some(1),
+            is_enemy: None,
+            player_ref: None,
+            repeats: 0,
+        }];
+        let mut out = Vec::new();
+        export::csv::write_chat_csv(&chat, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap().lines().nth(1).unwrap(),
+            "5,a,\"hello, \"\"world\"\"\",1,,,"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    /// Export /tests/replays/client_2.wrpl into an in-memory SQLite database.
+    fn test_export_sqlite() {
+        let file = std::fs::read("tests/replays/client_2.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let offset = utils::find_zlib_header(&file, header::HEADER_SIZE).unwrap();
+        let replay =
+            parser::process_replay_stream(&file, offset as u64, Compression::Zlib, Some(&header))
+                .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        export::sqlite::write_to_connection(&replay, &mut conn).unwrap();
+
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("packets"), replay.packets.len() as i64);
+        assert_eq!(count("chat"), replay.chat_messages.len() as i64);
+        assert_eq!(count("players"), 18);
+        assert_eq!(count("results"), 1);
+        let first_chat: u32 = conn
+            .query_row("SELECT timestamp_ms FROM chat LIMIT 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(
+            first_chat,
+            clock::ticks_to_ms(replay.chat_messages[0].timestamp_ms)
+        );
+    }
+
+    #[test]
+    /// Build a Markdown summary of /tests/replays/client_2.wrpl.
+    fn test_markdown_summary() {
+        let file = std::fs::read("tests/replays/client_2.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let replay = parser::ParsedReplay {
+            replay_results: parser::parse_replay_results(&file, header.rez_offset as usize),
+            ..Default::default()
+        };
+
+        let summary = export::markdown::battle_summary(Some(&header), &replay);
+        assert!(summary.contains("- **Outcome:** Victory"));
+        assert!(summary.contains("| 1 |"));
+        assert!(summary.contains("| 10 |"));
+        assert!(!summary.contains("| 11 |"));
+    }
+
+    #[test]
+    /// Parse /tests/replays/client_2.wrpl from a file handle and compare
+    /// with the in-memory parse.
+    fn test_parse_reader_client_2() {
+        let mut file = std::fs::File::open("tests/replays/client_2.wrpl").unwrap();
+        let header = header::read_header(&mut file).unwrap();
+        let offset = utils::find_zlib_header_in_reader(&mut file, header::HEADER_SIZE as u64, None)
+            .unwrap()
+            .unwrap();
+        let streamed = parser::process_replay_stream_reader(
+            &mut file,
+            offset,
+            Compression::Zlib,
+            Some(&header),
+        )
+        .unwrap();
+
+        let data = read("tests/replays/client_2.wrpl").unwrap();
+        let in_memory =
+            parser::process_replay_stream(&data, offset, Compression::Zlib, Some(&header)).unwrap();
+
+        assert_eq!(streamed.packet_count, in_memory.packet_count);
+        assert_eq!(
+            streamed.total_decompressed_bytes,
+            in_memory.total_decompressed_bytes
+        );
+        assert_eq!(streamed.chat_messages.len(), in_memory.chat_messages.len());
+        assert_eq!(streamed.replay_results.unwrap().players.len(), 18);
+
+        // without rez_offset, the results are looked for at the end
+        let mut server_2 = read("tests/replays/server_2.wrpl").unwrap();
+        server_2[header::REZ_OFFSET_POS..header::REZ_OFFSET_POS + 4].fill(0);
+        let header = header::parse_header(&server_2).unwrap();
+        let offset = format::detect_format(&server_2).stream_offset().unwrap();
+        let streamed = parser::process_replay_stream_reader_with_options(
+            std::io::Cursor::new(&server_2),
+            offset as u64,
+            Compression::Zlib,
+            Some(&header),
+            &parser::ParseOptions::default(),
+        )
+        .unwrap();
+        let results = streamed.replay_results.unwrap();
+        assert!((results.time_played - 582.2).abs() < 0.1);
+    }
+
+    #[test]
+    /// Read bit-packed values MSB first, across byte boundaries.
+    fn test_bitreader() {
+        use utils::bitreader::BitReader;
+
+        let data = [0b1011_0010, 0xFF, 0x34, 0x12];
+        let mut reader = BitReader::new(&data);
+        assert!(reader.read_bit().unwrap());
+        assert_eq!(reader.read_bits(3).unwrap(), 0b011);
+        assert_eq!(reader.read_bits(8).unwrap(), 0b0010_1111);
+        reader.align_to_byte();
+        assert_eq!(reader.read_u16().unwrap(), 0x1234);
+        assert_eq!(reader.remaining_bits(), 0);
+        assert!(reader.read_bit().is_err());
+
+        let mut reader = BitReader::new(&[0xFF, 0x00]);
+        assert_eq!(reader.read_quantized_float(8, -10.0, 10.0).unwrap(), 10.0);
+        assert_eq!(reader.read_quantized_float(8, -10.0, 10.0).unwrap(), -10.0);
+
+        // largest component w, the other three (close to) zero
+        let mut reader = BitReader::new(&[0xDF, 0xBF, 0x7E]);
+        let quat = reader.read_compressed_quaternion(7).unwrap();
+        assert_eq!(reader.position(), 23);
+        for component in &quat.to_array()[..3] {
+            assert!(component.abs() < 0.01);
+        }
+        assert!((quat.w - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    /// Decode hand-packed positions and quaternions, and round-trip them.
+    fn test_quantize() {
+        use quantize::*;
+        use telemetry::{Quat, Vec3};
+
+        // 0..=1023 over [0, 1023] is exact: x=1, y=2, z=1023
+        let pos = PositionQuantization {
+            bits: [10, 10, 10],
+            min: Vec3::new(0.0, 0.0, 0.0),
+            max: Vec3::new(1023.0, 1023.0, 1023.0),
+        };
+        let packed = 1 | (2 << 10) | (1023 << 20);
+        assert_eq!(pos.decode(packed), Vec3::new(1.0, 2.0, 1023.0));
+        assert_eq!(pos.encode(Vec3::new(1.0, 2.0, 1023.0)), packed);
+
+        // a 16km map with 20 bits per horizontal axis is ~1.5cm precise
+        let map = PositionQuantization {
+            bits: [20, 12, 20],
+            min: Vec3::new(-8192.0, -100.0, -8192.0),
+            max: Vec3::new(8192.0, 4000.0, 8192.0),
+        };
+        let position = Vec3::new(1234.5, 250.25, -4321.75);
+        let decoded = map.decode(map.encode(position));
+        assert!((decoded.x - position.x).abs() < 0.02);
+        assert!((decoded.y - position.y).abs() < 1.0);
+        assert!((decoded.z - position.z).abs() < 0.02);
+
+        // identity: w is dropped (index 3), the rest sit in the middle
+        let identity = encode_quaternion(Quat::IDENTITY, 9);
+        assert_eq!(identity >> 27, 3);
+        let decoded = decode_quaternion(identity, 9);
+        assert!((decoded.w - 1.0).abs() < 1e-4);
+
+        // 90 degrees around y, also given as its negation
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        for quat in [
+            Quat::new(0.0, half, 0.0, half),
+            Quat::new(0.0, -half, 0.0, -half),
+        ] {
+            let decoded = decode_quaternion(encode_quaternion(quat, 12), 12);
+            let dot: f32 = quat
+                .to_array()
+                .iter()
+                .zip(decoded.to_array())
+                .map(|(a, b)| a * b)
+                .sum();
+            assert!(dot.abs() > 0.9999, "{:?} decoded as {:?}", quat, decoded);
+        }
+
+        // the widest layouts that fit, and the first ones that don't
+        assert_eq!(quantize(1.0, MAX_FLOAT_BITS, 0.0, 1.0), u32::MAX as u64);
+        assert_eq!(dequantize(u32::MAX as u64, MAX_FLOAT_BITS, 0.0, 1.0), 1.0);
+        let widest = PositionQuantization::new(
+            [22, 21, 21],
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        )
+        .unwrap();
+        assert_eq!(widest.encode(Vec3::new(1.0, 1.0, 1.0)), u64::MAX);
+        assert_eq!(widest.decode(u64::MAX), Vec3::new(1.0, 1.0, 1.0));
+        for bits in [[22, 22, 21], [33, 1, 1], [0, 10, 10]] {
+            assert!(PositionQuantization::new(bits, widest.min, widest.max).is_err());
+        }
+        let quat = Quat::new(0.0, 0.8, 0.0, 0.6);
+        let packed = encode_quaternion(quat, MAX_QUATERNION_BITS);
+        assert_eq!(packed >> (3 * MAX_QUATERNION_BITS), 1);
+        let decoded = decode_quaternion(packed, MAX_QUATERNION_BITS);
+        assert!((decoded.y - 0.8).abs() < 1e-5 && (decoded.w - 0.6).abs() < 1e-5);
+        let too_wide = MAX_QUATERNION_BITS + 1;
+        assert!(std::panic::catch_unwind(|| decode_quaternion(packed, too_wide)).is_err());
+        assert!(std::panic::catch_unwind(|| quantize(1.0, 33, 0.0, 1.0)).is_err());
+    }
+
+    #[test]
+    /// Convert world positions to minimap and grid coordinates.
+    fn test_coords() {
+        use coords::{MapBounds, MapGrid};
+        use telemetry::Vec3;
+
+        let bounds = MapBounds::square(0.0, 0.0, 4096.0);
+        let grid = MapGrid::new(bounds);
+
+        // north-west corner is A1, south-east J10
+        let north_west = Vec3::new(-2048.0, 0.0, 2048.0);
+        assert_eq!(bounds.to_minimap(north_west), (0.0, 0.0));
+        assert_eq!(grid.cell(north_west).unwrap().to_string(), "A1");
+        assert_eq!(
+            grid.cell(Vec3::new(2048.0, 0.0, -2048.0))
+                .unwrap()
+                .to_string(),
+            "J10"
+        );
+
+        let center = Vec3::new(1.0, 50.0, -1.0);
+        assert_eq!(grid.cell(center).unwrap().to_string(), "F6");
+        let (u, v) = bounds.to_minimap(center);
+        assert!((bounds.from_minimap(u, v).x - center.x).abs() < 1e-3);
+
+        assert!(grid.cell(Vec3::new(3000.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    /// Correct jitter, resets, wraps and segments, then check
+    /// /tests/replays/client_1.wrpl against its results.
+    fn test_clock() {
+        use clock::{ClockModel, TICKS_PER_MS};
+        use parser::ReplayPacketType::{NextSegment, MPI};
+
+        let ms = |ms: u32| ms * TICKS_PER_MS;
+        let mut clock = ClockModel::new();
+        let times: Vec<u64> = [
+            (MPI, ms(100)),
+            (MPI, ms(90)), // jitter, flattened
+            (MPI, ms(200)),
+            (NextSegment, ms(3000)),
+            (MPI, ms(5)), // reset, continues from 3000
+            (MPI, u32::MAX - 255),
+            (MPI, ms(1)), // wrap
+        ]
+        .into_iter()
+        .map(|(packet_type, raw)| clock.observe(packet_type, raw).relative_time_ms)
+        .collect();
+        assert_eq!(times[..5], [100, 100, 200, 3000, 3000]);
+        assert_eq!(times[6], (1u64 << 32) / 256 + 2995 + 1);
+        let stats = clock.stats();
+        assert_eq!(
+            (stats.jitter, stats.resets, stats.wraps, stats.segments),
+            (1, 1, 1, 1)
+        );
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let replay =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, Some(&header)).unwrap();
+        let (times, stats) = replay.packet_times();
+        assert!(times
+            .windows(2)
+            .all(|w| w[0].relative_time_ms <= w[1].relative_time_ms));
+        assert_eq!(stats.resets, 0);
+        let time_played_ms = replay.replay_results.unwrap().time_played * 1000.0;
+        let last_ms = times.last().unwrap().relative_time_ms as f64;
+        assert!((last_ms - time_played_ms).abs() < 100.0);
+    }
+
+    #[test]
+    /// Check the duration of /tests/replays/client_2.wrpl, and of a
+    /// truncated copy of its packets.
+    fn test_duration_client_2() {
+        let file = read("tests/replays/client_2.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let offset = utils::find_zlib_header(&file, header::HEADER_SIZE).unwrap();
+        let mut replay =
+            parser::process_replay_stream(&file, offset as u64, Compression::Zlib, Some(&header))
+                .unwrap();
+
+        assert_eq!(replay.duration().as_secs(), 578);
+        let report = replay.check_duration(Some(&header));
+        assert_eq!(report.time_limit_ms, Some(25 * 60_000));
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+
+        replay.packets.truncate(replay.packets.len() / 2);
+        let report = replay.check_duration(Some(&header));
+        assert!(matches!(
+            report.issues[..],
+            [clock::DurationIssue::Truncated { .. }]
+        ));
+    }
+
+    #[test]
+    /// Packet statistics of /tests/replays/client_1.wrpl.
+    fn test_packet_stats_client_1() {
+        use parser::ReplayPacketType;
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+        let stats = replay.stats();
+
+        assert_eq!(stats.total.count, replay.packets.len() as u64);
+        assert_eq!(
+            stats.by_type.values().map(|s| s.count).sum::<u64>(),
+            stats.total.count
+        );
+        let chat = stats.by_type[&ReplayPacketType::Chat];
+        assert_eq!(chat.count, 19);
+        assert!(chat.min_size <= chat.max_size);
+        assert!(chat.mean_size() >= chat.min_size as f64);
+        assert_eq!(stats.span_ms(), stats.last_time_ms - stats.first_time_ms);
+        assert_eq!(stats.last_time_ms, replay.duration().as_millis() as u64);
+    }
+
+    #[test]
+    /// Capture the chat packets of /tests/replays/client_1.wrpl.
+    fn test_capture_chat_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("wrpl_capture_{}", std::process::id()));
+        let options = capture::CaptureOptions { types: vec![3] };
+        let summary = capture::capture_packets(&replay.packets, &options, &dir).unwrap();
+        assert_eq!(summary.captured_packets(), 19);
+
+        let chat: Vec<_> = replay.packets.iter().filter(|p| p.raw_type == 3).collect();
+        let payloads = std::fs::read(dir.join("type_3.bin")).unwrap();
+        assert_eq!(
+            payloads.len(),
+            chat.iter().map(|p| p.payload.len()).sum::<usize>()
+        );
+        let index = std::fs::read_to_string(dir.join("type_3.csv")).unwrap();
+        assert_eq!(index.lines().count(), 20);
+        assert!(index
+            .lines()
+            .nth(1)
+            .unwrap()
+            .contains(&chat[0].stream_offset.to_string()));
+        assert!(dir.join("summary.csv").exists());
+        assert!(!dir.join("type_4.bin").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    /// Diff /tests/replays/client_1.wrpl against a copy with some packets
+    /// dropped and the clock shifted.
+    fn test_diff_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+
+        let options = diff::DiffOptions::default();
+        let same = diff::diff_packets(&replay.packets, &replay.packets, &options);
+        assert!(same.is_identical());
+        assert_eq!(same.offset_ms, 0);
+
+        let mut other = replay.packets.clone();
+        let chat_index = other.iter().position(|p| p.raw_type == 3).unwrap();
+        other.remove(chat_index);
+        for packet in &mut other {
+            packet.timestamp_ms += 5000 * clock::TICKS_PER_MS;
+        }
+        let diff = diff::diff_packets(&replay.packets, &other, &options);
+        assert_eq!(diff.offset_ms, 5000);
+        assert_eq!(diff.only_in_a, vec![chat_index]);
+        assert!(diff.only_in_b.is_empty());
+        assert_eq!(diff.by_type[&3].only_in_a, 1);
+    }
+
+    #[test]
+    /// Merge /tests/replays/client_1.wrpl with a fake "server" copy that
+    /// has no chat and a shifted clock.
+    fn test_merge_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let client =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, Some(&header)).unwrap();
+
+        let mut packets: Vec<_> = client
+            .packets
+            .iter()
+            .filter(|p| p.raw_type != 3)
+            .cloned()
+            .collect();
+        for packet in &mut packets {
+            packet.timestamp_ms += 2000 * clock::TICKS_PER_MS;
+        }
+        let half = packets.len() / 2;
+        let segments = vec![
+            parser::ParsedReplay {
+                packets: packets[..half].to_vec(),
+                ..Default::default()
+            },
+            parser::ParsedReplay {
+                packets: packets[half..].to_vec(),
+                ..Default::default()
+            },
+        ];
+
+        let client_packets = client.packets.len();
+        let (merged, report) = merge::merge(client, segments);
+        assert_eq!(report.offset_ms, 2000);
+        assert_eq!(report.client_only_packets, 19);
+        assert_eq!(merged.packets.len(), client_packets);
+        assert_eq!(merged.chat_messages.len(), 19);
+        assert!(merged.replay_results.is_some());
+        // chat packets land back where they were
+        let first_chat = merged.packets.iter().position(|p| p.raw_type == 3).unwrap();
+        assert!(
+            merged.packets[first_chat - 1].timestamp_ms <= merged.packets[first_chat].timestamp_ms
+        );
+    }
+
+    #[test]
+    /// Check the session ID of /tests/replays/client_1.wrpl.
+    fn test_session_id_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let id = header.session_id;
+        assert_eq!(id, header::SessionId(0x4a65b2a003348be));
+        assert_eq!(id.to_string(), "4a65b2a003348be");
+        assert_eq!(id.url_id(), "04a65b2a003348be");
+        assert_eq!("0x04a65b2a003348be".parse::<header::SessionId>(), Ok(id));
+        assert_eq!(id.time_part(), 0x4a65b2a);
+        assert_eq!(id.low_part(), 0x003348be);
+        // created shortly before the battle started
+        let start = header.start_time as u64;
+        assert!(id.approx_unix_time() <= start + 60);
+        assert!(id.approx_unix_time() + 3600 > start);
+    }
+
+    #[test]
+    /// Read the mission settings BLK of /tests/replays/client_1.wrpl.
+    fn test_mission_settings_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let options = header::HeaderOptions {
+            mission_settings: true,
+            ..Default::default()
+        };
+        let header = header::parse_header_with_options(&file, &options).unwrap();
+        let settings = header.mission_settings.unwrap();
+        assert_eq!(settings["level"], "levels/avg_egypt_sinai.bin");
+        assert_eq!(settings["locDesc"], "sinai_02/desc;missions/_Conq/desc");
+        assert_eq!(settings["allowedUnitTypes"]["isTanksAllowed"], true);
+        assert_eq!(settings["stars"]["year"], 1973);
+
+        // not read from the header alone
+        let header = header::read_header(&mut &file[..]).unwrap();
+        assert!(header.mission_settings.is_none());
+        // nor unless asked for
+        assert!(header::parse_header(&file)
+            .unwrap()
+            .mission_settings
+            .is_none());
+
+        // cut off mid-BLK, which the BLK parser panics on
+        for len in 2072..2088 {
+            let cut = &file[..len];
+            assert!(header::parse_mission_settings(cut).is_err());
+            let header = header::parse_header_with_options(cut, &options).unwrap();
+            assert!(header.mission_settings.is_none());
+            // even with the packet stream right after it
+            let mut spliced = cut.to_vec();
+            spliced.extend_from_slice(&file[2088..]);
+            assert!(header::parse_mission_settings(&spliced).is_err());
+        }
+
+        // server replays don't have one
+        let file = read("tests/replays/server_2.wrpl").unwrap();
+        assert!(header::parse_mission_settings(&file).unwrap().is_none());
+    }
+
+    #[test]
+    /// Find the results of /tests/replays/server_2.wrpl without rez_offset.
+    fn test_find_results_server_2() {
+        let mut server_2 = read("tests/replays/server_2.wrpl").unwrap();
+        let rez_offset = header::parse_header(&server_2).unwrap().rez_offset as usize;
+        server_2[header::REZ_OFFSET_POS..header::REZ_OFFSET_POS + 4].fill(0);
+
+        let (offset, results) = parser::find_replay_results(&server_2).unwrap();
+        assert_eq!(offset, rez_offset);
+        assert!((results.time_played - 582.2).abs() < 0.1);
+        let header = header::parse_header(&server_2).unwrap();
+        let options = parser::ParseOptions::default();
+        assert!(parser::results_for(&server_2, &header, &options).is_some());
+
+        let client_1 = read("tests/replays/client_1.wrpl").unwrap();
+        let (offset, _) = parser::find_replay_results(&client_1).unwrap();
+        assert_eq!(offset, 3662909);
+
+        // server_3 isn't the last segment of its session
+        let server_3 = read("tests/replays/server_3.wrpl").unwrap();
+        assert!(parser::find_replay_results(&server_3).is_none());
+        let (segment, _) = parser::find_segment_results(&[&server_2, &server_3]).unwrap();
+        assert_eq!(segment, 0);
+    }
+
+    #[test]
+    /// Detect the kind of every file in /tests/replays, and parse a server replay.
+    fn test_detect_format() {
+        use format::FileKind;
+
+        let client_1 = read("tests/replays/client_1.wrpl").unwrap();
+        assert_eq!(
+            format::detect_format(&client_1),
+            FileKind::ClientReplay {
+                stream_offset: 2088
+            }
+        );
+        assert_eq!(
+            format::detect_format(&client_1[..header::HEADER_SIZE]),
+            FileKind::HeaderOnly
+        );
+        assert_eq!(
+            format::detect_format(&client_1[2088..]),
+            FileKind::ZlibStream
+        );
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        assert_eq!(format::detect_format(&decom), FileKind::Unknown);
+
+        let mut bad_version = client_1[..header::HEADER_SIZE].to_vec();
+        bad_version[4..8].copy_from_slice(&7u32.to_le_bytes());
+        assert!(format::check_magic(&bad_version).is_err());
+        assert_eq!(format::detect_format(&bad_version), FileKind::Unknown);
+
+        let server_2 = read("tests/replays/server_2.wrpl").unwrap();
+        let kind = format::detect_format(&server_2);
+        assert_eq!(
+            kind,
+            FileKind::ServerReplay {
+                stream_offset: header::M_SET_OFFSET
+            }
+        );
+        let offset = kind.stream_offset().unwrap() as u64;
+        let replay =
+            parser::process_replay_stream(&server_2, offset, Compression::Zlib, None).unwrap();
+        assert_eq!(replay.packets.len(), 28090);
+    }
+
+    #[test]
+    /// Validate /tests/replays/client_1.wrpl, whole and damaged.
+    fn test_validate_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let report = validate::validate(&file);
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.checks.len(), 5);
+        assert_eq!(
+            report.check("packets").unwrap().detail,
+            "51952 packets, 0 empty"
+        );
+
+        // cut off in the middle of the packet stream
+        let report = validate::validate(&file[..1_000_000]);
+        let failed: Vec<_> = report.failures().map(|c| c.name).collect();
+        assert_eq!(failed, ["stream", "results"]);
+        assert_eq!(
+            report.check("packets").unwrap().outcome,
+            validate::Outcome::Skipped
+        );
+
+        let report = validate::validate(b"not a replay");
+        assert_eq!(report.failures().count(), 1);
+    }
+
+    #[test]
+    /// Parse /tests/replays/client_1.wrpl cut off in the middle of the stream.
+    fn test_truncated_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+        assert!(replay.complete);
+        assert_eq!(replay.trailing_bytes, 0);
+
+        let truncated =
+            parser::process_replay_stream(&file[..1_000_000], 2088, Compression::Zlib, None)
+                .unwrap();
+        assert!(!truncated.complete);
+        assert!(truncated.trailing_bytes > 0);
+        assert!(truncated.packets.len() < replay.packets.len());
+        // everything that was parsed matches the full parse
+        let last = truncated.packets.last().unwrap();
+        assert_eq!(
+            last.payload,
+            replay.packets[truncated.packets.len() - 1].payload
+        );
+
+        // the decompressed stream, cut 3 bytes into packet 100
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let cut = replay.packets[100].stream_offset as usize + 3;
+        let truncated = parser::process_replay_data(&decom[..cut], 0, Compression::None).unwrap();
+        assert!(!truncated.complete);
+        assert_eq!(truncated.packets.len(), 100);
+        assert_eq!(truncated.trailing_bytes, 3);
+    }
+
+    #[test]
+    /// Strict parsing of /tests/replays/client_1.wrpl
+    fn test_strict_client_1() {
+        let strict = parser::ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream_with_options(
+            &file,
+            2088,
+            Compression::Zlib,
+            None,
+            &strict,
+        )
+        .unwrap();
+        assert!(replay.complete);
+
+        let err = parser::process_replay_stream_with_options(
+            &file[..1_000_000],
+            2088,
+            Compression::Zlib,
+            None,
+            &strict,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("stream offset 0x"));
+
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let cut = replay.packets[100].stream_offset as usize + 3;
+        let err =
+            parser::process_replay_data_with_options(&decom[..cut], 0, Compression::None, &strict)
+                .unwrap_err();
+        assert!(format!("{:#}", err).contains(&format!("{:#x}", replay.packets[100].stream_offset)));
+    }
+
+    #[test]
+    /// Results of /tests/replays/client_1.wrpl through a ResultsCache
+    fn test_results_cache_client_1() {
+        let dir = std::env::temp_dir().join(format!("wrpl_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = cache::ResultsCache::new(&dir).unwrap();
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let blob = &file[header.rez_offset as usize..];
+
+        let fresh = cache.parse_replay_results(&file, &header).unwrap();
+        assert!(cache
+            .get(header.session_id, header.rez_offset, blob)
+            .is_some());
+        let cached = cache.parse_replay_results(&file, &header).unwrap();
+        assert_eq!(cached.players.len(), fresh.players.len());
+        assert_eq!(cached.time_played, fresh.time_played);
+
+        // a different blob is a different entry
+        let mut other = blob.to_vec();
+        *other.last_mut().unwrap() ^= 1;
+        assert!(cache
+            .get(header.session_id, header.rez_offset, &other)
+            .is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    /// Counting the packets of /tests/replays/client_1.wrpl with a visitor
+    fn test_parse_with_client_1() {
+        #[derive(Default)]
+        struct Counter {
+            headers: usize,
+            packets: usize,
+            payload_bytes: usize,
+            chat: usize,
+            players: Option<usize>,
+            stop_after: Option<usize>,
+        }
+        impl parser::PacketVisitor for Counter {
+            fn header(&mut self, _: &header::ReplayHeader) {
+                self.headers += 1;
+            }
+            fn packet(&mut self, packet: parser::PacketRef<'_>) {
+                self.packets += 1;
+                self.payload_bytes += packet.payload.len();
+            }
+            fn chat(&mut self, _: &parser::ChatInfo) {
+                self.chat += 1;
+            }
+            fn results(&mut self, results: &parser::ReplayResults) {
+                self.players = Some(results.players.len());
+            }
+            fn stop(&self) -> bool {
+                self.stop_after == Some(self.packets)
+            }
+        }
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let replay =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, Some(&header)).unwrap();
+        let counter = parser::parse_with(&file, Counter::default()).unwrap();
+        assert_eq!(counter.headers, 1);
+        assert_eq!(counter.packets, replay.packets.len());
+        assert_eq!(
+            counter.payload_bytes,
+            replay
+                .packets
+                .iter()
+                .map(|p| p.payload.len())
+                .sum::<usize>()
+        );
+        assert_eq!(counter.chat, replay.chat_messages.len());
+        assert_eq!(
+            counter.players,
+            replay.replay_results.map(|r| r.players.len())
+        );
+
+        let stopped = Counter {
+            stop_after: Some(10),
+            ..Default::default()
+        };
+        let stopped = parser::parse_with(&file, stopped).unwrap();
+        assert_eq!(stopped.packets, 10);
+    }
+
+    #[test]
+    /// Only the chat packets of /tests/replays/client_1.wrpl
+    fn test_packet_filter_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+        let options = parser::ParseOptions {
+            packet_types: vec![3],
+            ..Default::default()
+        };
+        let chat = parser::process_replay_stream_with_options(
+            &file,
+            2088,
+            Compression::Zlib,
+            None,
+            &options,
+        )
+        .unwrap();
+        assert!(chat.complete);
+        assert_eq!(chat.packet_count, replay.packet_count);
+        assert_eq!(chat.chat_messages.len(), replay.chat_messages.len());
+        let expected: Vec<_> = replay.packets.iter().filter(|p| p.raw_type == 3).collect();
+        assert_eq!(chat.packets.len(), expected.len());
+        for (a, b) in chat.packets.iter().zip(expected) {
+            // timestamps carry over the skipped packets
+            assert_eq!(a.timestamp_ms, b.timestamp_ms);
+            assert_eq!(a.stream_offset, b.stream_offset);
+            assert_eq!(a.payload, b.payload);
+        }
+    }
+
+    #[test]
+    /// /tests/replays/client_1.wrpl without the payloads
+    fn test_discard_payloads_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+        let options = parser::ParseOptions {
+            discard_payloads: true,
+            ..Default::default()
+        };
+        let light = parser::process_replay_stream_with_options(
+            &file,
+            2088,
+            Compression::Zlib,
+            None,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(light.packets.len(), replay.packets.len());
+        assert!(light.packets.iter().all(|p| p.payload.is_empty()));
+        for (a, b) in light.packets.iter().zip(&replay.packets) {
+            assert_eq!(a.size as usize, b.payload.len());
+            assert_eq!(a.size, b.size);
+        }
+        assert_eq!(light.chat_messages.len(), replay.chat_messages.len());
+        assert_eq!(light.stats(), replay.stats());
+    }
+
+    #[test]
+    /// Snapshot of /tests/replays/client_2.wrpl
+    fn test_snapshot_client_2() {
+        let file = read("tests/replays/client_2.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let parse = || {
+            parser::process_replay_stream(&file, 2063, Compression::Zlib, Some(&header)).unwrap()
+        };
+        let snapshot = export::snapshot::snapshot_string(Some(&header), &parse());
+        assert_eq!(
+            snapshot,
+            export::snapshot::snapshot_string(Some(&header), &parse())
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&snapshot).unwrap();
+        assert_eq!(value["snapshot_version"], 1);
+        assert_eq!(value["stream"]["complete"], true);
+        let keys: Vec<_> = snapshot
+            .lines()
+            .filter(|l| l.starts_with("  \"") && !l.starts_with("   "))
+            .collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        assert!(snapshot.contains("\"time_played\": "));
+    }
+
+    #[test]
+    #[cfg(feature = "arbitrary")]
+    /// The fuzz entry points, on random bytes and on mangled bits of
+    /// /tests/replays/client_1.wrpl
+    fn test_fuzz_entry_points() {
+        // xorshift, so failures can be reproduced
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+
+        for _ in 0..2000 {
+            let len = (next() % 64) as usize;
+            let data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            fuzz::size_prefix(&data);
+            fuzz::packet_header(&data);
+            fuzz::chat(&data);
+            fuzz::packet_stream(&data);
+        }
+        for _ in 0..50 {
+            let start = (next() as usize) % (decom.len() - 4096);
+            let mut data = decom[start..start + 4096].to_vec();
+            for _ in 0..4 {
+                let i = (next() as usize) % data.len();
+                data[i] = next() as u8;
+            }
+            fuzz::packet_stream(&data);
+        }
+        for _ in 0..10 {
+            let mut data = file[..0x2000].to_vec();
+            let i = (next() as usize) % data.len();
+            data[i] = next() as u8;
+            fuzz::replay_file(&data);
+        }
+    }
+
+    /// Sizes in every prefix form, with extra weight on the edges of each.
+    fn size_prefix_sizes() -> impl proptest::strategy::Strategy<Value = u32> {
+        use proptest::prelude::*;
+        let edges = [
+            0,
+            0x3F,
+            0x40,
+            0x3FFF,
+            0x4000,
+            0x1F_FFFF,
+            0x20_0000,
+            0x0FFF_FFFF,
+            0x1000_0000,
+            u32::MAX,
+        ];
+        prop_oneof![
+            proptest::sample::select(edges.to_vec()),
+            0..0x40u32,
+            0x40..0x4000u32,
+            0x4000..0x20_0000u32,
+            0x20_0000..0x1000_0000u32,
+            0x1000_0000..=u32::MAX,
+        ]
+    }
+
+    proptest::proptest! {
+        #[test]
+        /// Encoding then decoding a size prefix gives the size back, in
+        /// the shortest form
+        fn test_size_prefix_round_trip(size in size_prefix_sizes()) {
+            let encoded = raw::write_variable_length_size(size);
+            let expected_len = match size {
+                0..=0x3F => 1,
+                0x40..=0x3FFF => 2,
+                0x4000..=0x1F_FFFF => 3,
+                0x20_0000..=0x0FFF_FFFF => 4,
+                _ => 5,
+            };
+            proptest::prop_assert_eq!(encoded.len(), expected_len);
+            let decoded =
+                raw::read_variable_length_size(&mut Cursor::new(&encoded)).unwrap();
+            proptest::prop_assert_eq!(decoded, Some((size, expected_len)));
+        }
+
+        #[test]
+        /// Whatever decodes re-encodes to the same size, no longer than it was
+        fn test_size_prefix_decode_encode(
+            bytes in proptest::collection::vec(proptest::num::u8::ANY, 1..6)
+        ) {
+            let decoded = raw::read_variable_length_size(&mut Cursor::new(&bytes));
+            if let Ok(Some((size, len))) = decoded {
+                let encoded = raw::write_variable_length_size(size);
+                proptest::prop_assert!(encoded.len() <= len);
+                let decoded =
+                    raw::read_variable_length_size(&mut Cursor::new(&encoded)).unwrap();
+                proptest::prop_assert_eq!(decoded, Some((size, encoded.len())));
+            }
+        }
+
+        #[test]
+        /// Packet headers read back as written, with or without a timestamp
+        fn test_packet_header_round_trip(
+            packet_type in 0u8..0x10,
+            timestamp_ms: u32,
+            last_timestamp_ms: u32,
+            unchanged: bool,
+        ) {
+            let last_timestamp_ms = if unchanged { timestamp_ms } else { last_timestamp_ms };
+            let encoded =
+                raw::write_packet_header(packet_type, timestamp_ms, last_timestamp_ms);
+            let expected_len = if timestamp_ms == last_timestamp_ms { 1 } else { 5 };
+            proptest::prop_assert_eq!(encoded.len(), expected_len);
+            let decoded = raw::read_packet_header_from_stream(
+                &mut Cursor::new(&encoded),
+                last_timestamp_ms,
+            )
+            .unwrap();
+            proptest::prop_assert_eq!(
+                decoded,
+                Some((packet_type, timestamp_ms, expected_len))
+            );
+        }
+    }
+
+    #[test]
+    /// Aggregating /tests/replays/client_1.wrpl and client_2.wrpl (twice,
+    /// the second time as server_2.wrpl)
+    fn test_aggregate() {
+        let load = |path: &str| {
+            let file = read(path).unwrap();
+            let header = header::parse_header(&file).unwrap();
+            let results = parser::parse_replay_results(&file, header.rez_offset as usize);
+            (header, results)
+        };
+        let (header_1, results_1) = load("tests/replays/client_1.wrpl");
+        let (header_2, results_2) = load("tests/replays/client_2.wrpl");
+        let (server_header, _) = load("tests/replays/server_2.wrpl");
+        let (results_1, results_2) = (results_1.unwrap(), results_2.unwrap());
+
+        let mut aggregator = aggregate::Aggregator::new();
+        assert!(aggregator.add(&header_1, &results_1));
+        assert!(!aggregator.add(&header_1, &results_1));
+        assert!(aggregator.add(&header_2, &results_2));
+        assert!(!aggregator.add(&server_header, &results_2));
+        assert_eq!(aggregator.sessions(), 2);
+
+        let author = aggregator.player(&results_1.author_user_id).unwrap();
+        assert_eq!(author.totals.battles, 2);
+        // client_1 was lost, client_2 won
+        assert_eq!((author.totals.won, author.totals.lost), (1, 1));
+        assert_eq!(author.by_map.len(), 2);
+
+        let battles: u32 = aggregator.players().map(|p| p.totals.battles).sum();
+        assert_eq!(
+            battles as usize,
+            results_1.players.len() + results_2.players.len()
+        );
+        // everyone on the author's team shares the outcome
+        for player in &results_1.players {
+            let outcome = aggregate::player_outcome(&results_1, player);
+            assert_ne!(outcome, aggregate::BattleOutcome::Unknown);
+        }
+    }
+
+    #[test]
+    /// Career of the author of the replays in /tests/replays
+    fn test_career_stats() {
+        let mut career = aggregate::CareerStats::new("176625161");
+        // client_2 and server_2 are the same battle
+        assert_eq!(
+            career
+                .ingest_dir(std::path::Path::new("tests/replays"))
+                .unwrap(),
+            2
+        );
+        assert_eq!(career.username, "kiTmalZ");
+        assert_eq!(career.totals.battles, 2);
+        assert_eq!(career.history.len(), 2);
+        // oldest first
+        assert!(career.history[0].start_time <= career.history[1].start_time);
+
+        let json: serde_json::Value = serde_json::from_str(&career.to_json().unwrap()).unwrap();
+        assert_eq!(json["totals"]["battles"], 2);
+        assert!(json.get("sessions").is_none());
+
+        let mut stranger = aggregate::CareerStats::new("1");
+        assert_eq!(
+            stranger
+                .ingest_dir(std::path::Path::new("tests/replays"))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    /// Squads in the results of /tests/replays/client_1.wrpl
+    fn test_squads_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let results = parser::parse_replay_results(&file, header.rez_offset as usize).unwrap();
+
+        let all = results.squads(true);
+        assert_eq!(
+            all.iter().map(|s| s.size()).sum::<usize>(),
+            results.players.len()
+        );
+        let score: i32 = results.players.iter().map(|p| p.replay_data.score).sum();
+        assert_eq!(all.iter().map(|s| s.score).sum::<i32>(), score);
+
+        // only one squad the players made themselves
+        let squads = results.squads(false);
+        assert_eq!(squads.len(), 1);
+        assert_eq!(
+            (squads[0].team, squads[0].id, squads[0].size()),
+            (1, 4096, 2)
+        );
+        assert!(!squads[0].auto);
+    }
+
+    #[test]
+    /// Team make-up in /tests/replays/client_1.wrpl
+    fn test_team_composition_client_1() {
+        use teams::{VehicleClass, VehicleInfo};
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let results = parser::parse_replay_results(&file, header.rez_offset as usize).unwrap();
+
+        assert_eq!(
+            teams::guess_vehicle("germ_flakpanzer_zerstorer_45").class,
+            VehicleClass::Spaa
+        );
+        assert_eq!(
+            teams::guess_vehicle("bf-109k-4").class,
+            VehicleClass::Aircraft
+        );
+        assert_eq!(
+            teams::guess_vehicle("us_m18_hellcat").nation.as_deref(),
+            Some("us")
+        );
+
+        let mut table = teams::VehicleTable::new();
+        let composition = results.team_composition(&table);
+        assert_eq!(composition.len(), 2);
+        let lineups: usize = results
+            .players
+            .iter()
+            .map(|p| p.replay_data.lineup.len())
+            .sum();
+        assert_eq!(
+            composition.iter().map(|t| t.vehicles).sum::<usize>(),
+            lineups
+        );
+        let team_2 = &composition[1];
+        assert_eq!(team_2.team, 2);
+        assert!(team_2.by_nation["germ"] > team_2.by_nation["ussr"]);
+        assert!(team_2.by_class[&VehicleClass::Spaa] > 0);
+
+        // the table wins over the guess
+        table.insert(
+            "bf-109k-4".to_string(),
+            VehicleInfo {
+                class: VehicleClass::Aircraft,
+                nation: Some("germ".to_string()),
+                battle_rating: None,
+            },
+        );
+        let with_table = results.team_composition(&table);
+        assert_eq!(
+            with_table[1].by_nation["germ"],
+            team_2.by_nation["germ"] + 1
+        );
+    }
+
+    #[test]
+    /// Link the chat of /tests/replays/client_1.wrpl to the results
+    fn test_chat_player_ref_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let mut replay =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, Some(&header)).unwrap();
+        assert!(replay.chat_messages.iter().all(|c| c.player_ref.is_some()));
+        let author = replay.chat_messages[0].player_ref.as_ref().unwrap();
+        assert_eq!(author.user_id, "176625161");
+        let data = &replay.replay_results.as_ref().unwrap().players;
+        let data = &data
+            .iter()
+            .find(|p| p.player_info.user_id == author.user_id);
+        let data = &data.unwrap().replay_data;
+        assert_eq!(replay.chat_messages[0].team(), Some(data.team));
+        assert_eq!(replay.chat_messages[0].squad(), Some(data.squad));
+
+        // only by name, without results there's nothing to link to
+        let results = replay.replay_results.take().unwrap();
+        assert_eq!(replay.resolve_chat_senders(), 0);
+        assert_eq!(results.player_ref("KITMALZ").unwrap().user_id, "176625161");
+        assert!(results.player_ref("nobody").is_none());
+    }
+
+    #[test]
+    /// Split the chat of /tests/replays/client_1.wrpl by channel
+    fn test_transcripts_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let replay =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, Some(&header)).unwrap();
+        let transcripts = replay.transcripts();
+        assert_eq!(transcripts.all.len(), 2);
+        assert_eq!(transcripts.all[1].sender, "Gyaru-Destroyer");
+        // a client only gets its own team's team chat
+        assert_eq!(transcripts.team.len(), 17);
+        assert!(transcripts.enemy_team.is_empty());
+        assert!(transcripts.squad.is_empty() && transcripts.other.is_empty());
+
+        // without results, all team chat is the author's
+        let split = transcript::transcripts(&replay.chat_messages, None);
+        assert_eq!(split.team.len(), 17);
+        let author_team = transcripts.team[0].player_ref.as_ref().unwrap().team;
+        let split = transcript::transcripts(&replay.chat_messages, Some(3 - author_team));
+        assert_eq!(split.enemy_team.len(), 17);
+        assert_eq!(split.named()[2].1.len(), 17);
+    }
+
+    #[test]
+    /// Map names of the levels in /tests/replays
+    fn test_map_name() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        assert_eq!(header.level, "levels/avg_egypt_sinai.bin");
+        assert_eq!(header.map_name(), "Sinai");
+
+        let mut names = maps::MapNames::builtin();
+        names.insert("avg_egypt_sinai", "Sinai Peninsula");
+        assert_eq!(header.map_name_with(&names), "Sinai Peninsula");
+        let names = maps::MapNames::empty();
+        assert_eq!(header.map_name_with(&names), "Egypt Sinai");
+        assert_eq!(names.name("levels/bulge.bin"), "Bulge");
+    }
+
+    #[test]
+    /// Localize the mission of /tests/replays/client_1.wrpl with a made up
+    /// lang CSV
+    fn test_localizer_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let csv =
+            "\u{feff}\"<ID|readonly|noverify>\";\"<English>\";\"<German>\";\"<Comments>\"\r\n\
+                   \"missions/_Conq1\";\"[Conquest #1] %s\";\"[Eroberung #1] %s\";\"\"\r\n\
+                   \"missions/sinai_02/name\";\"Sinai\";\"Sinai\";\"\"\r\n\
+                   \"missions/quoted\";\"a \"\"b\"\"; c\";\"\";\"\"\r\n";
+
+        let mut english = localize::Localizer::new("English");
+        assert_eq!(english.load_csv(csv).unwrap(), 3);
+        assert_eq!(
+            header.mission_title(&english).as_deref(),
+            Some("[Conquest #1] Sinai")
+        );
+        assert_eq!(english.get("MISSIONS/QUOTED"), Some("a \"b\"; c"));
+
+        let mut german = localize::Localizer::new("german");
+        german.load_csv(csv).unwrap();
+        assert_eq!(
+            header.mission_title(&german).as_deref(),
+            Some("[Eroberung #1] Sinai")
+        );
+        assert!(localize::Localizer::new("French").load_csv(csv).is_err());
+        assert!(english.localize("missions/unknown").is_none());
+    }
+
+    #[test]
+    /// Battle classes of /tests/replays/client_1.wrpl and client_2.wrpl
+    fn test_battle_class() {
+        use header::{BattleClass, ObjectiveKind};
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let class = header::parse_header(&file).unwrap().parsed_battle_class();
+        assert_eq!(
+            class,
+            BattleClass {
+                air: true,
+                ground: true,
+                naval: false,
+                objective: ObjectiveKind::Conquest,
+                unknown: Vec::new(),
+            }
+        );
+
+        let file = read("tests/replays/client_2.wrpl").unwrap();
+        let class = header::parse_header(&file).unwrap().parsed_battle_class();
+        assert!(class.air && !class.ground);
+        assert_eq!(class.objective, ObjectiveKind::Domination);
+        assert_eq!(
+            BattleClass::parse("naval_Xyz").objective,
+            ObjectiveKind::Other("Xyz".to_string())
+        );
+        // unknown parts don't hide a known objective
+        let class = BattleClass::parse("ground_Conq_Xyz");
+        assert_eq!(class.objective, ObjectiveKind::Conquest);
+        assert_eq!(class.unknown, vec!["Xyz".to_string()]);
+    }
+
+    #[test]
+    /// Kill streaks are off in /tests/replays/client_1.wrpl
+    fn test_kill_streak() {
+        use header::{KillStreakConfig, KillStreakVehicles};
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        assert!(header::parse_header(&file).unwrap().kill_streak().is_none());
+
+        let config = KillStreakConfig::parse("killStreaksAircraftOrHelicopter_1").unwrap();
+        assert_eq!(config.vehicles, KillStreakVehicles::AircraftOrHelicopter);
+        assert_eq!(config.preset, Some(1));
+        let config = KillStreakConfig::parse("somethingNew").unwrap();
+        assert_eq!(
+            config.vehicles,
+            KillStreakVehicles::Other("somethingNew".to_string())
+        );
+        assert_eq!(config.preset, None);
+    }
+
+    #[test]
+    /// Keep the unknown header regions of /tests/replays/client_2.wrpl and
+    /// server_2.wrpl
+    fn test_header_unknown_regions() {
+        let options = header::HeaderOptions {
+            keep_unknown: true,
+            ..Default::default()
+        };
+        let client = read("tests/replays/client_2.wrpl").unwrap();
+        assert!(header::parse_header(&client).unwrap().unknown.is_none());
+        let client = header::parse_header_with_options(&client, &options).unwrap();
+        let client = client.unknown.unwrap();
+        let lengths: Vec<_> = client.regions().iter().map(|r| r.2.len()).collect();
+        assert_eq!(lengths, [35, 4, 4, 32, 48]);
+
+        // same session, so some of it is the same
+        let server = read("tests/replays/server_2.wrpl").unwrap();
+        let server = header::parse_header_with_options(&server, &options).unwrap();
+        let server = server.unknown.unwrap();
+        assert_eq!(client.after_difficulty, server.after_difficulty);
+        assert_ne!(client.after_session_id, server.after_session_id);
+    }
+
+    #[test]
+    /// Diff the headers of /tests/replays/client_1.wrpl and client_2.wrpl
+    fn test_diff_headers() {
+        let options = header::HeaderOptions {
+            keep_unknown: true,
+            mission_settings: true,
+        };
+        let parse = |path| {
+            let file = read(path).unwrap();
+            header::parse_header_with_options(&file, &options).unwrap()
+        };
+        let client_1 = parse("tests/replays/client_1.wrpl");
+        let client_2 = parse("tests/replays/client_2.wrpl");
+        assert!(diff::diff_headers(&client_1, &client_1).is_empty());
+
+        let diffs = diff::diff_headers(&client_1, &client_2);
+        let level = diffs.iter().find(|d| d.field == "level").unwrap();
+        assert_eq!(level.a, "levels/avg_egypt_sinai.bin");
+        assert!(diffs.iter().any(|d| d.field == "mission_settings.level"));
+        let region = diffs
+            .iter()
+            .find(|d| d.field == "after_difficulty")
+            .unwrap();
+        assert_eq!(region.a.len(), 70);
+        assert!(region
+            .offsets
+            .iter()
+            .all(|&o| (0x2B1..0x2B1 + 35).contains(&o)));
+    }
+
+    #[test]
+    /// Support levels of replay versions
+    fn test_compat() {
+        use compat::{Subsystem, SupportLevel};
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let compatibility = header::parse_header(&file).unwrap().compatibility();
+        assert_eq!(compatibility.level, SupportLevel::Full);
+        assert!(compatibility.expects(Subsystem::Results));
+        assert_eq!(compat::check_data(&file), Some(compatibility));
+
+        assert_eq!(compat::check(101_290).level, SupportLevel::Partial);
+        let newer = compat::check(101_400);
+        assert_eq!(newer.level, SupportLevel::Partial);
+        assert!(newer.expects(Subsystem::Chat) && !newer.expects(Subsystem::Results));
+        let garbage = compat::check(7);
+        assert_eq!(garbage.level, SupportLevel::Unknown);
+        assert!(garbage.working.is_empty());
+        assert!(compat::check_data(&file[..4]).is_none());
+    }
+
+    #[test]
+    /// Seek around in /tests/replays/client_1.wrpl
+    fn test_seek_client_1() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+        let (times, _) = replay.packet_times();
+
+        let mut cursor = replay.at(60_000);
+        let (time, _) = cursor.next().unwrap();
+        assert!(time >= 60_000);
+        let before = cursor.position() - 2;
+        assert!(times[before].relative_time_ms < 60_000);
+
+        // and back again
+        cursor.seek(0);
+        assert_eq!(cursor.position(), 0);
+        assert_eq!(cursor.count(), replay.packets.len());
+
+        let end = replay.duration().as_millis() as u64;
+        let mut cursor = replay.at(end + 1);
+        assert!(cursor.time_ms().is_none() && cursor.next().is_none());
+    }
+
+    #[test]
+    /// Play back a minute of /tests/replays/client_1.wrpl at 1000x
+    fn test_playback_client_1() {
+        use playback::{PlaybackItem, PlaybackOptions};
+        use std::ops::ControlFlow;
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_stream(&file, 2088, Compression::Zlib, None).unwrap();
+
+        let options = PlaybackOptions {
+            speed: 1000.0,
+            start_ms: 30_000,
+            end_ms: Some(90_000),
+        };
+        let started = std::time::Instant::now();
+        let mut last = 0;
+        let summary = playback::play(&replay, &options, |time, _| {
+            assert!((30_000..=90_000).contains(&time) && time >= last);
+            last = time;
+            ControlFlow::Continue(())
+        });
+        // a minute at 1000x takes at least 60ms
+        assert!(started.elapsed().as_millis() >= 55);
+        assert!(summary.packets > 0 && !summary.stopped);
+
+        // all of it, as fast as possible, until the first chat message
+        let options = PlaybackOptions {
+            speed: f64::INFINITY,
+            ..Default::default()
+        };
+        let mut first = None;
+        let summary = playback::play(&replay, &options, |_, item| match item {
+            PlaybackItem::Chat(chat) => {
+                first = Some(chat.message.clone());
+                ControlFlow::Break(())
+            }
+            PlaybackItem::Packet(_) => ControlFlow::Continue(()),
+        });
+        assert!(summary.stopped);
+        assert_eq!(summary.chat, 1);
+        assert_eq!(first.as_deref(), Some("TEST"));
+        let all = playback::play(&replay, &options, |_, _| ControlFlow::Continue(()));
+        assert_eq!(all.packets, replay.packets.len());
+        assert_eq!(all.chat, 19);
+    }
+
+    #[test]
+    /// Two subscribers to the parse of /tests/replays/client_1.wrpl
+    fn test_broadcast_client_1() {
+        use events::GameEvent;
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let mut broadcaster = events::Broadcaster::new();
+        let counters: Vec<_> = (0..2)
+            .map(|_| {
+                let events = broadcaster.subscribe();
+                std::thread::spawn(move || {
+                    let (mut packets, mut chat, mut results) = (0, 0, 0);
+                    for event in events {
+                        match &*event {
+                            GameEvent::Packet(_) => packets += 1,
+                            GameEvent::Chat(_) => chat += 1,
+                            GameEvent::Results(_) => results += 1,
+                            GameEvent::Header(_)
+                            | GameEvent::Decoded(_)
+                            | GameEvent::BattleStart { .. }
+                            | GameEvent::BattleEnd { .. } => {}
+                        }
+                    }
+                    (packets, chat, results)
+                })
+            })
+            .collect();
+        assert_eq!(broadcaster.subscribers(), 2);
+
+        let broadcaster = parser::parse_with(&file, broadcaster).unwrap();
+        drop(broadcaster);
+        for counter in counters {
+            let (packets, chat, results) = counter.join().unwrap();
+            assert_eq!(packets, 51952);
+            assert_eq!((chat, results), (19, 1));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    /// The tokio version of test_broadcast_client_1
+    fn test_tokio_broadcast_client_1() {
+        use events::GameEvent;
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let broadcaster = events::TokioBroadcaster::new(100_000);
+        let mut events = broadcaster.subscribe();
+        drop(parser::parse_with(&file, broadcaster).unwrap());
+        let mut chat = 0;
+        while let Ok(event) = events.try_recv() {
+            chat += matches!(*event, GameEvent::Chat(_)) as usize;
+        }
+        assert_eq!(chat, 19);
+    }
+
+    #[test]
+    /// Positions of made up tracks at some times
+    fn test_positions_at() {
+        use telemetry::{Lookup, TelemetrySample, Track, UnitRef, Vec3};
+        let track = |id, samples: &[(u32, f32)]| Track {
+            unit: UnitRef { id, name: None },
+            samples: samples
+                .iter()
+                .map(|&(time_ms, x)| TelemetrySample {
+                    time_ms,
+                    position: Vec3::new(x, 0.0, 0.0),
+                })
+                .collect(),
+        };
+        let tracks = vec![
+            track(1, &[(0, 0.0), (1000, 10.0), (2000, 30.0)]),
+            track(2, &[(1500, 5.0)]),
+        ];
+
+        let at = |time_ms, lookup| telemetry::positions_at(&tracks, time_ms, lookup);
+        let positions = at(1500, Lookup::Interpolate);
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].1, Vec3::new(20.0, 0.0, 0.0));
+        assert_eq!(
+            positions[1],
+            (tracks[1].unit.clone(), Vec3::new(5.0, 0.0, 0.0))
+        );
+
+        let positions = at(1200, Lookup::Nearest);
+        assert_eq!(
+            positions,
+            vec![(tracks[0].unit.clone(), Vec3::new(10.0, 0.0, 0.0))]
+        );
+        assert_eq!(at(250, Lookup::Interpolate)[0].1.x, 2.5);
+        assert!(at(2001, Lookup::Nearest).is_empty());
+    }
+
+    #[test]
+    /// Smooth a made up track with a glitch in it
+    fn test_trajectory() {
+        use telemetry::{TelemetrySample, Track, UnitRef, Vec3};
+        use trajectory::{Interpolation, Trajectory, TrajectoryOptions};
+        // 100 m/s along x, with a sample repeated and one way off
+        let mut samples: Vec<_> = (0..=5)
+            .map(|i| TelemetrySample {
+                time_ms: i * 1000,
+                position: Vec3::new(i as f32 * 100.0, 50.0, 0.0),
+            })
+            .collect();
+        samples.insert(3, samples[2]);
+        samples.insert(
+            5,
+            TelemetrySample {
+                time_ms: 3500,
+                position: Vec3::new(1e6, 50.0, 0.0),
+            },
+        );
+        let track = Track {
+            unit: UnitRef { id: 1, name: None },
+            samples,
+        };
+
+        let trajectory = Trajectory::new(&track, &TrajectoryOptions::default());
+        assert_eq!(trajectory.rejected, 2);
+        assert_eq!(trajectory.samples().len(), 6);
+        let close = |a: Vec3, b: Vec3| a.distance(b) < 0.01;
+        assert!(close(
+            trajectory.position_at(3500.0).unwrap(),
+            Vec3::new(350.0, 50.0, 0.0)
+        ));
+        assert!(close(
+            trajectory.velocity_at(2250.0).unwrap(),
+            Vec3::new(100.0, 0.0, 0.0)
+        ));
+        assert!(trajectory.position_at(5001.0).is_none());
+        assert_eq!(trajectory.resample(250).len(), 21);
+
+        // without rejection the glitch stays in
+        let options = TrajectoryOptions {
+            interpolation: Interpolation::Linear,
+            max_speed: None,
+        };
+        let raw = Trajectory::new(&track, &options);
+        assert_eq!(raw.rejected, 1);
+        assert!(raw.position_at(3500.0).unwrap().x > 1000.0);
+    }
+
+    #[test]
+    /// Flight metrics of a level turn
+    fn test_flight_metrics() {
+        use flight::{flight_metrics, FlightOptions};
+        use telemetry::{TelemetrySample, Track, UnitRef, Vec3};
+        use trajectory::{Trajectory, TrajectoryOptions};
+        // 100 m/s round a 500 m circle at 3000 m: 0.2 rad/s, 20 m/s² inwards
+        let samples = (0..=200)
+            .map(|i| {
+                let angle = i as f32 * 0.1 * 0.2;
+                TelemetrySample {
+                    time_ms: i * 100,
+                    position: Vec3::new(500.0 * angle.cos(), 3000.0, 500.0 * angle.sin()),
+                }
+            })
+            .collect();
+        let track = Track {
+            unit: UnitRef { id: 1, name: None },
+            samples,
+        };
+        let trajectory = Trajectory::new(&track, &TrajectoryOptions::default());
+        let metrics = flight_metrics(&trajectory, &FlightOptions::default());
+        assert_eq!(metrics.len(), 81);
+
+        let middle = metrics[40];
+        assert_eq!(middle.time_ms, 10_000);
+        assert!((middle.altitude - 3000.0).abs() < 0.1);
+        assert!((middle.tas - 100.0).abs() < 0.5);
+        // thinner air up there
+        assert!(middle.ias > 85.0 && middle.ias < 90.0);
+        assert!(middle.climb_rate.abs() < 0.1);
+        assert!((middle.turn_rate - 0.2f32.to_degrees()).abs() < 0.2);
+        let g = (1.0 + (20.0f32 / 9.80665).powi(2)).sqrt();
+        assert!((middle.g_load - g).abs() < 0.05);
+    }
+
+    #[test]
+    /// Tell client replays, server segments and streams apart
+    fn test_inspect() {
+        use format::ReplayKind;
+        let kind =
+            |name: &str| format::inspect(&read(format!("tests/replays/{}.wrpl", name)).unwrap());
+        assert_eq!(
+            kind("client_1"),
+            ReplayKind::Client {
+                stream_offset: 2088
+            }
+        );
+        assert_eq!(
+            kind("server_2"),
+            ReplayKind::ServerSegment {
+                stream_offset: 1226,
+                last: true
+            }
+        );
+        assert_eq!(
+            kind("server_3"),
+            ReplayKind::ServerSegment {
+                stream_offset: 1226,
+                last: false
+            }
+        );
+        assert_eq!(kind("client_2_decom"), ReplayKind::RawStream);
+        assert!(!ReplayKind::RawStream.is_compressed());
+
+        let client_1 = read("tests/replays/client_1.wrpl").unwrap();
+        assert_eq!(format::inspect(&client_1[2088..]), ReplayKind::ZlibStream);
+        assert_eq!(format::inspect(&client_1[8..]), ReplayKind::Unknown);
+    }
+
+    #[test]
+    /// Break chat and MPI packets down into fields
+    fn test_dissect() {
+        use parser::ReplayPacketType;
+        let chat = [0, 2, b'h', b'i', 3, b'g', b'g', b'!', 1, 0, 0xAA];
+        let fields = dissect::dissect_payload(ReplayPacketType::Chat, &chat);
+        let names: Vec<_> = fields.iter().map(|f| f.name).collect();
+        assert_eq!(
+            names,
+            [
+                "flags",
+                "sender_len",
+                "sender",
+                "message_len",
+                "message",
+                "channel_type",
+                "is_enemy",
+                "unknown"
+            ]
+        );
+        assert_eq!(fields[4].range, 5..8);
+        assert_eq!(fields[4].value, "gg!");
+        assert_eq!(fields[7].range, 10..11);
+
+        // cut short in the message
+        let fields = dissect::dissect_payload(ReplayPacketType::Chat, &chat[..6]);
+        assert_eq!(fields.last().unwrap().name, "unknown");
+        assert_eq!(fields.len(), 5);
+
+        let mpi = [0x00, 0x02, 0x58, 0x2d, 1, 2, 3];
+        let frame = parser::parse_mpi_packet(&mpi).unwrap();
+        assert_eq!((frame.object_id, frame.message_id), (2, 0x582d));
+        let fields = dissect::dissect_payload(ReplayPacketType::MPI, &mpi);
+        assert_eq!(fields[1].value, "0x582d");
+        assert_eq!(fields[2].range, 4..7);
+        assert!(dissect::dissect_payload(ReplayPacketType::ECS, &mpi).is_empty());
+    }
+
+    #[test]
+    /// Run registered decoders on /tests/replays/client_1.wrpl
+    fn test_packet_decoders() {
+        use decode::{DecoderKey, DecoderRegistry, PacketDecoder};
+
+        struct Length(DecoderKey);
+        impl PacketDecoder for Length {
+            fn key(&self) -> DecoderKey {
+                self.0
+            }
+            fn name(&self) -> &str {
+                "length"
+            }
+            fn decode(&self, payload: &[u8]) -> anyhow::Result<serde_json::Value> {
+                anyhow::ensure!(payload.len() > 16, "too short");
+                Ok(payload.len().into())
+            }
+        }
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let mut decoders = DecoderRegistry::new();
+        decoders.register(Length(DecoderKey::Packet(3)));
+        decoders.register(Length(DecoderKey::Mpi(0x582d)));
+        let options = parser::ParseOptions {
+            decoders,
+            ..Default::default()
+        };
+        let replay =
+            parser::process_replay_data_with_options(&file, 2088, Compression::Zlib, &options)
+                .unwrap();
+
+        let chat: Vec<_> = replay.decoded.iter().filter(|d| d.raw_type == 3).collect();
+        // the short ones failed
+        assert!(!chat.is_empty() && chat.len() < replay.chat_messages.len());
+        let mpi = replay.decoded.iter().find(|d| d.raw_type == 4).unwrap();
+        let packet = replay
+            .packets
+            .iter()
+            .find(|p| p.stream_offset == mpi.stream_offset)
+            .unwrap();
+        assert_eq!(&packet.payload[2..4], [0x58, 0x2d]);
+        assert_eq!(mpi.value, packet.payload.len());
+        assert_eq!(mpi.decoder, "length");
+    }
+
+    #[test]
+    /// Hand MPI messages of /tests/replays/client_1.wrpl to closures
+    fn test_mpi_handlers() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let (mut known, mut other) = (Vec::new(), 0);
+        let mut handlers = decode::MpiHandlers::new();
+        handlers.on(0x582d, |packet, frame| {
+            assert_eq!(frame.payload.len() + 4, packet.payload.len());
+            known.push(frame.object_id);
+        });
+        handlers.on_other(|_, frame| {
+            assert_ne!(frame.message_id, 0x582d);
+            other += 1;
+        });
+        parser::parse_with(&file, handlers).unwrap();
+        assert!(!known.is_empty());
+        assert!(known.iter().all(|&id| id == known[0]));
+        assert!(other > known.len());
+    }
+
+    #[test]
+    /// Keep the log of parsing /tests/replays/client_1.wrpl
+    fn test_capture_log() {
+        use logging::LogLevel;
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let options = parser::ParseOptions {
+            capture_log: true,
+            ..Default::default()
+        };
+        let replay = parser::process_replay_stream_with_options(
+            &file,
+            2088,
+            Compression::Zlib,
+            Some(&header),
+            &options,
+        )
+        .unwrap();
+        assert!(
+            replay
+                .log
+                .iter()
+                .any(|e| e.level == LogLevel::Info
+                    && e.message.starts_with("Processed 51952 packets"))
+        );
+        assert!(replay.log.iter().all(|e| e.target.starts_with("wrpl::")));
+
+        // not captured by default, or outside a parse
+        let replay = parser::process_replay_data(&file, 2088, Compression::Zlib).unwrap();
+        assert!(replay.log.is_empty());
+        let (_, log) = logging::capture(|| parser::parse_chat_packet(&[], 0));
+        assert_eq!(log[0].level, LogLevel::Warn);
+        assert!(logging::capture(|| ()).1.is_empty());
+    }
+
+    #[test]
+    /// Offsets as typed on the command line
+    fn test_parse_offset() {
+        use utils::{parse_file_offset, parse_offset, FileOffset};
+        assert_eq!(parse_offset("0x4C8"), Ok(0x4C8));
+        assert_eq!(parse_offset("1_226"), Ok(1226));
+        assert_eq!(parse_offset("4k"), Ok(4096));
+        assert_eq!(parse_offset("2M"), Ok(2 << 20));
+        assert_eq!(parse_offset("0x1_0K"), Ok(16 << 10));
+        assert!(parse_offset("4x").is_err());
+        assert!(parse_offset("-4").is_err());
+        assert!(parse_offset("0xffffffffffffffffk").is_err());
+
+        assert_eq!(parse_file_offset("-4k"), Ok(FileOffset::End(4096)));
+        assert_eq!(parse_file_offset("10"), Ok(FileOffset::Start(10)));
+        assert_eq!(FileOffset::End(4096).resolve(10_000), Some(5904));
+        assert_eq!(FileOffset::End(4096).resolve(100), None);
+        assert_eq!(FileOffset::End(0).resolve(100), Some(100));
+    }
+
+    #[test]
+    /// Parse /tests/replays/client_1_decom.wrpl recompressed as raw deflate and gzip
+    fn test_compression() {
+        use flate2::write::DeflateEncoder;
+        use flate2::GzBuilder;
+        use std::io::Write;
+
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let level = flate2::Compression::fast();
+        let mut deflate = DeflateEncoder::new(Vec::new(), level);
+        deflate.write_all(&decom).unwrap();
+        let deflate = deflate.finish().unwrap();
+        let mut gzip = GzBuilder::new()
+            .filename("client_1_decom.wrpl")
+            .comment("test")
+            .write(Vec::new(), level);
+        gzip.write_all(&decom).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let parse = |data: &[u8], compression| {
+            parser::process_replay_data(data, 0, compression)
+                .unwrap()
+                .packet_count
+        };
+        assert_eq!(parse(&deflate, Compression::RawDeflate), 51952);
+        assert_eq!(parse(&gzip, Compression::Gzip), 51952);
+        assert_eq!(parse(&gzip, Compression::Auto), 51952);
+        assert_eq!(parse(&decom, Compression::Auto), 51952);
+        let client_1 = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_data(&client_1, 2088, Compression::Auto).unwrap();
+        assert_eq!(replay.packet_count, 51952);
+        assert!(replay.complete);
+
+        // cut short, like a crashed client's
+        let truncated =
+            parser::process_replay_data(&gzip[..gzip.len() / 2], 0, Compression::Gzip).unwrap();
+        assert!(!truncated.complete);
+        assert!(parser::process_replay_data(&deflate, 0, Compression::Gzip).is_err());
+        assert_eq!(
+            Compression::detect(&[0x28, 0xB5, 0x2F, 0xFD, 0]),
+            Compression::Zstd
+        );
+    }
+
+    #[test]
+    /// Tell compressed and raw streams apart by their first bytes
+    fn test_detect_compression() {
+        use flate2::write::DeflateEncoder;
+        use std::io::Write;
+
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let mut deflate = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+        deflate.write_all(&decom).unwrap();
+        let deflate = deflate.finish().unwrap();
+        let client_1 = read("tests/replays/client_1.wrpl").unwrap();
+        let server_2 = read("tests/replays/server_2.wrpl").unwrap();
+
+        let sniff = |data: &[u8]| Compression::detect(&data[..data.len().min(parser::DETECT_LEN)]);
+        assert_eq!(sniff(&client_1[2088..]), Compression::Zlib);
+        assert_eq!(sniff(&server_2[1226..]), Compression::Zlib);
+        assert_eq!(sniff(&decom), Compression::None);
+        assert_eq!(sniff(&deflate), Compression::RawDeflate);
+        // just the first few bytes still work
+        assert_eq!(sniff(&decom[..20]), Compression::None);
+        assert_eq!(sniff(&client_1[2088..2100]), Compression::Zlib);
+
+        let replay = parser::process_replay_data(&deflate, 0, Compression::Auto).unwrap();
+        assert_eq!(replay.packet_count, 51952);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    /// Parse /tests/replays/client_1_decom.wrpl recompressed with zstd
+    fn test_zstd() {
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let compressed = zstd::encode_all(&decom[..], 1).unwrap();
+        for compression in [Compression::Zstd, Compression::Auto] {
+            let replay = parser::process_replay_data(&compressed, 0, compression).unwrap();
+            assert_eq!(replay.packet_count, 51952);
+            assert!(replay.complete);
+        }
+        let cut = &compressed[..compressed.len() / 2];
+        let truncated = parser::process_replay_data(cut, 0, Compression::Zstd).unwrap();
+        assert!(!truncated.complete);
+    }
+
+    #[test]
+    /// Decompress the stream in /tests/replays/client_1.wrpl to a writer
+    fn test_decompress_stream() {
+        let client_1 = read("tests/replays/client_1.wrpl").unwrap();
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let mut out = Vec::new();
+        let info = utils::decompress_stream(&client_1, 2088, &mut out).unwrap();
+        assert!(info.complete);
+        assert_eq!(out, decom);
+        assert_eq!(info.bytes_out, decom.len() as u64);
+        assert!(2088 + info.bytes_in <= client_1.len() as u64);
+
+        let mut out = Vec::new();
+        let cut = &client_1[..client_1.len() / 2];
+        let info = utils::decompress_stream(cut, 2088, &mut out).unwrap();
+        assert!(!info.complete);
+        assert_eq!(info.bytes_in, cut.len() as u64 - 2088);
+        assert!(decom.starts_with(&out));
+        assert!(utils::decompress_stream(&client_1, 0, &mut Vec::new()).is_err());
+        assert!(utils::decompress_stream(&client_1, 1 << 40, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    /// Extract some packets of /tests/replays/client_1.wrpl into a stream
+    fn test_extract_packets() {
+        use writer::PacketRange;
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_data(&file, 2088, Compression::Zlib).unwrap();
+
+        let stream = writer::extract_packets(&replay, PacketRange::Indices(700..800)).unwrap();
+        assert!(format::is_packet_stream(&stream));
+        let extract = parser::process_replay_data(&stream, 0, Compression::Auto).unwrap();
+        assert_eq!(extract.packets.len(), 101);
+        assert_eq!(extract.packets[0].payload, replay.packets[0].payload);
+        for (a, b) in extract.packets[1..].iter().zip(&replay.packets[700..800]) {
+            assert_eq!((a.raw_type, a.timestamp_ms), (b.raw_type, b.timestamp_ms));
+            assert_eq!(a.payload, b.payload);
+        }
+        assert_eq!(extract.chat_messages[0].message, "TEST");
+
+        let index = seek::TimeIndex::new(&replay.packets);
+        let (from, to) = (index.time_of(1000).unwrap(), index.time_of(2000).unwrap());
+        let stream = writer::extract_packets(&replay, PacketRange::Time(from..to)).unwrap();
+        let extract = parser::process_replay_data(&stream, 0, Compression::None).unwrap();
+        assert!(extract.packets.len() > 1 && extract.packets.len() <= 1001);
+
+        assert!(writer::extract_packets(&replay, PacketRange::Indices(60000..60001)).is_err());
+    }
+
+    #[test]
+    /// Parse the replays in /tests/replays through the prelude
+    fn test_prelude() {
+        use crate::prelude::*;
+
+        let client = Replay::open("tests/replays/client_1.wrpl").unwrap();
+        let header: &ReplayHeader = client.header.as_ref().unwrap();
+        assert_eq!(header.magic, format::MAGIC);
+        assert_eq!(client.parsed.packet_count, 51952);
+        let chat: &ChatInfo = &client.parsed.chat_messages[0];
+        assert_eq!(chat.message, "TEST");
+
+        let server = Replay::parse(&read("tests/replays/server_2.wrpl").unwrap()).unwrap();
+        assert!(server.header.is_some());
+        let stream = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let options = ParseOptions {
+            discard_payloads: true,
+            ..Default::default()
+        };
+        let raw: Result<Replay> = Replay::parse_with_options(&stream, &options);
+        let raw = raw.unwrap();
+        assert!(raw.header.is_none());
+        assert_eq!(raw.parsed.packet_count, 51952);
+        assert!(Replay::parse(&[0; 16]).is_err());
+        assert!(Replay::open("tests/replays/missing.wrpl").is_err());
+    }
+
+    #[test]
+    /// Collapse spammed chat, in /tests/replays/client_1.wrpl and made up
+    fn test_collapse_chat() {
+        let message = |time_s: u32, sender: &str, text: &str| {
+            let mut chat = parser::parse_chat_packet(&[0, 0, 0, 1], 0).unwrap();
+            chat.timestamp_ms = time_s * 1000 * clock::TICKS_PER_MS;
+            chat.sender = sender.to_string();
+            chat.message = text.to_string();
+            chat
+        };
+        let mut chat = vec![
+            message(0, "a", "Attack the D point!"),
+            message(1, "b", "Attack the D point!"),
+            message(2, "a", "Attack the D point!"),
+            message(3, "a", "gg"),
+            message(4, "a", "Attack the D point!"),
+            message(20, "a", "Attack the D point!"),
+        ];
+        transcript::collapse_repeats(&mut chat, 5000);
+        let summary: Vec<_> = chat
+            .iter()
+            .map(|c| (c.sender.as_str(), c.message.as_str(), c.repeats))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("a", "Attack the D point!", 2),
+                ("b", "Attack the D point!", 0),
+                ("a", "gg", 0),
+                ("a", "Attack the D point!", 0),
+            ]
+        );
+        assert!(!serde_json::to_string(&chat[1]).unwrap().contains("repeats"));
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let all = parser::process_replay_data(&file, 2088, Compression::Zlib).unwrap();
+        let options = parser::ParseOptions {
+            collapse_chat_ms: Some(10_000),
+            ..Default::default()
+        };
+        let collapsed =
+            parser::process_replay_data_with_options(&file, 2088, Compression::Zlib, &options)
+                .unwrap();
+        let total: u32 = collapsed.chat_messages.iter().map(|c| c.repeats + 1).sum();
+        assert_eq!(total as usize, all.chat_messages.len());
+    }
+
+    #[test]
+    /// Find the start and end of the battles in /tests/replays
+    fn test_lifecycle_events() {
+        use events::{GameEvent, Lifecycle};
+
+        let client = replay::Replay::open("tests/replays/client_1.wrpl").unwrap();
+        let lifecycle = client.parsed.lifecycle_events();
+        let kinds: Vec<_> = lifecycle.iter().map(|e| (e.kind, e.packet_index)).collect();
+        assert_eq!(
+            kinds,
+            [(Lifecycle::BattleStart, 647), (Lifecycle::BattleEnd, 51951)]
+        );
+        // about 20s of loading
+        assert_eq!(client.parsed.battle_start_ms(), Some(lifecycle[0].time_ms));
+        assert!((20_000..21_000).contains(&lifecycle[0].time_ms));
+        assert!(client.parsed.reached_end());
+
+        // not the last segment, so it never ends
+        let segment = replay::Replay::open("tests/replays/server_3.wrpl").unwrap();
+        assert!(segment.parsed.lifecycle_events().is_empty());
+        assert!(!segment.parsed.reached_end());
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let mut broadcaster = events::Broadcaster::new();
+        let events = broadcaster.subscribe();
+        drop(parser::parse_with(&file, broadcaster).unwrap());
+        let markers: Vec<_> = events
+            .iter()
+            .filter_map(|event| match *event {
+                GameEvent::BattleStart { timestamp_ms } => {
+                    Some((Lifecycle::BattleStart, timestamp_ms))
+                }
+                GameEvent::BattleEnd { timestamp_ms } => Some((Lifecycle::BattleEnd, timestamp_ms)),
+                _ => None,
+            })
+            .collect();
+        let packets = &client.parsed.packets;
+        assert_eq!(
+            markers,
+            [
+                (
+                    Lifecycle::BattleStart,
+                    clock::ticks_to_ms(packets[647].timestamp_ms)
+                ),
+                (
+                    Lifecycle::BattleEnd,
+                    clock::ticks_to_ms(packets[51951].timestamp_ms)
+                )
+            ]
+        );
+    }
+
+    #[test]
+    /// Follow /tests/replays/server_3.wrpl on to another segment
+    fn test_parse_segments() {
+        let server_3 = read("tests/replays/server_3.wrpl").unwrap();
+        let server_2 = read("tests/replays/server_2.wrpl").unwrap();
+        let options = parser::ParseOptions::default();
+
+        let mut asked = Vec::new();
+        let joined = replay::Replay::parse_segments(&server_3, &options, |number| {
+            asked.push(number);
+            Ok(Some(server_2.clone()))
+        })
+        .unwrap();
+        // server_2 is the last segment, so it stops there
+        assert_eq!(asked, [1]);
+        assert_eq!(joined.parsed.packets.len(), 71106 + 28090);
+        assert_eq!(joined.parsed.packet_count, 71106 + 28090);
+        assert_eq!(
+            joined.parsed.packets[71106].packet_type,
+            parser::ReplayPacketType::ReplayHeaderInfo
+        );
+        assert!(joined.parsed.reached_end());
+        assert!(joined.parsed.replay_results.is_some());
+        assert_eq!(
+            joined.header.unwrap().session_id,
+            header::parse_header(&server_3).unwrap().session_id
+        );
+
+        let alone = replay::Replay::parse_segments(&server_3, &options, |_| Ok(None)).unwrap();
+        assert_eq!(alone.parsed.packets.len(), 71106);
+        assert!(!alone.parsed.reached_end());
+        let failed = replay::Replay::parse_segments(&server_3, &options, |_| Ok(Some(vec![0; 8])));
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    /// Break the stream of /tests/replays/client_1.wrpl down by packet type
+    fn test_bytes_by_type() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_data(&file, 2088, Compression::Zlib).unwrap();
+        let by_type = &replay.bytes_by_type;
+        let bytes: u64 = by_type.values().map(|b| b.bytes).sum();
+        let packets: u64 = by_type.values().map(|b| b.packets).sum();
+        assert_eq!(bytes, replay.total_decompressed_bytes);
+        assert_eq!(packets, replay.packet_count);
+        assert_eq!(by_type[&3].packets, 19);
+        let shares: f64 = by_type
+            .values()
+            .map(|b| b.share(replay.total_decompressed_bytes))
+            .sum();
+        assert!((shares - 1.0).abs() < 1e-9);
+
+        // still counted when skipped
+        let options = parser::ParseOptions {
+            packet_types: vec![3],
+            ..Default::default()
+        };
+        let chat_only =
+            parser::process_replay_data_with_options(&file, 2088, Compression::Zlib, &options)
+                .unwrap();
+        assert_eq!(&chat_only.bytes_by_type, by_type);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let replay = parser::process_replay_data(&file, 2088, Compression::Zlib).unwrap();
+        let memory = replay.memory;
+        let payloads: usize = replay.packets.iter().map(|p| p.payload.len()).sum();
+        assert!(memory.payloads >= payloads);
+        assert!(memory.chat > 0);
+        assert!(memory.total() > memory.payloads);
+
+        let options = parser::ParseOptions {
+            discard_payloads: true,
+            ..Default::default()
+        };
+        let discarded =
+            parser::process_replay_data_with_options(&file, 2088, Compression::Zlib, &options)
+                .unwrap();
+        assert_eq!(discarded.memory.payloads, 0);
+
+        let options = parser::ParseOptions {
+            memory_limit: Some(memory.total() / 2),
+            ..Default::default()
+        };
+        assert!(
+            parser::process_replay_data_with_options(&file, 2088, Compression::Zlib, &options)
+                .is_err()
+        );
+        let options = parser::ParseOptions {
+            memory_limit: Some(memory.total()),
+            ..Default::default()
+        };
+        let limited =
+            parser::process_replay_data_with_options(&file, 2088, Compression::Zlib, &options)
+                .unwrap();
+        assert_eq!(limited.memory, memory);
+    }
+
+    #[test]
+    fn test_timings() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let replay =
+            parser::process_replay_stream(&file, 2088, Compression::Zlib, Some(&header)).unwrap();
+        let timings = replay.timings;
+        use std::time::Duration;
+        assert!(timings.decompression > Duration::ZERO);
+        assert!(timings.packet_loop > Duration::ZERO);
+        assert!(timings.chat > Duration::ZERO);
+        assert!(timings.results > Duration::ZERO);
+        assert_eq!(
+            timings.total(),
+            timings.decompression + timings.packet_loop + timings.chat + timings.results
+        );
+    }
+
+    #[test]
+    fn test_max_packet_size() {
+        let file = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let replay = parser::process_replay_data(&file, 0, Compression::None).unwrap();
+        assert!(replay.complete);
+
+        // the biggest packet in client_1 is 9594 bytes
+        let options = parser::ParseOptions {
+            max_packet_size: Some(9000),
+            ..Default::default()
+        };
+        let limited =
+            parser::process_replay_data_with_options(&file, 0, Compression::None, &options)
+                .unwrap();
+        assert!(!limited.complete);
+        assert!(limited.packet_count < replay.packet_count);
+        assert!(limited.packets.iter().all(|p| p.size <= 9000));
+
+        let strict = parser::ParseOptions {
+            strict: true,
+            ..options
+        };
+        let err = parser::process_replay_data_with_options(&file, 0, Compression::None, &strict)
+            .unwrap_err();
+        assert!(err.to_string().contains("more than the 9000 allowed"));
+    }
+
+    #[test]
+    fn test_zero_size_policy() {
+        use parser::ZeroSizePolicy;
+        let decom = read("tests/replays/client_1_decom.wrpl").unwrap();
+        let replay = parser::process_replay_data(&decom, 0, Compression::None).unwrap();
+        // five empty packets (one byte size prefixes) after the first one
+        let second = replay.packets[1].stream_offset as usize;
+        let mut data = decom[..second].to_vec();
+        data.extend([0x80; 5]);
+        data.extend(&decom[second..]);
+        let parse = |zero_size| {
+            let options = parser::ParseOptions {
+                zero_size,
+                ..Default::default()
+            };
+            parser::process_replay_data_with_options(&data, 0, Compression::None, &options)
+        };
+
+        let continued = parse(ZeroSizePolicy::Continue).unwrap();
+        assert_eq!(continued.packet_count, replay.packet_count + 5);
+        assert!(continued.complete);
+        assert!(parse(ZeroSizePolicy::AbortAfter(5)).is_ok());
+        assert!(parse(ZeroSizePolicy::AbortAfter(4)).is_err());
+        let ended = parse(ZeroSizePolicy::EndOfStream).unwrap();
+        assert_eq!(ended.packet_count, 1);
+        assert!(ended.complete);
+    }
+
+    #[test]
+    fn test_json_stream() {
+        use export::json::{self, JsonStreamOptions};
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let out = json::write_json(
+            &file,
+            Vec::new(),
+            &parser::ParseOptions::default(),
+            JsonStreamOptions::default(),
+        )
+        .unwrap();
+        let events: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        let count = |event: &str| events.iter().filter(|e| e["event"] == event).count();
+        assert_eq!(events[0]["event"], "header");
+        assert_eq!(count("packet"), 51952);
+        assert_eq!(count("chat"), 19);
+        assert_eq!(count("battle_start"), 1);
+        assert_eq!(count("battle_end"), 1);
+        assert_eq!(events.last().unwrap()["event"], "results");
+        let chat = events.iter().find(|e| e["event"] == "chat").unwrap();
+        assert_eq!(chat["message"], "TEST");
+        assert!(events[1].get("payload").is_none());
+        // in ms, like the results' time played is in s
+        let end = events.iter().find(|e| e["event"] == "battle_end").unwrap();
+        let time_played = events.last().unwrap()["time_played"].as_f64().unwrap();
+        let end_s = end["timestamp_ms"].as_f64().unwrap() / 1000.0;
+        assert!(
+            (end_s - time_played).abs() < 5.0,
+            "{} vs {}",
+            end_s,
+            time_played
+        );
+
+        let options = JsonStreamOptions {
+            skip_packets: true,
+            payloads: true,
+        };
+        let out =
+            json::write_json(&file, Vec::new(), &parser::ParseOptions::default(), options).unwrap();
+        let events: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(events.len(), 1 + 19 + 2 + 1);
+    }
+
+    #[test]
+    fn test_tail() {
+        use parser::{ChatInfo, PacketRef, PacketVisitor, ReplayResults};
+        use std::io::Write;
+        use std::ops::ControlFlow;
+        use tail::{TailOptions, Tailer};
+
+        #[derive(Default)]
+        struct Count {
+            packets: u64,
+            chat: Vec<String>,
+            results: bool,
+        }
+        impl PacketVisitor for Count {
+            fn packet(&mut self, _packet: PacketRef<'_>) {
+                self.packets += 1;
+            }
+            fn chat(&mut self, chat: &ChatInfo) {
+                self.chat.push(chat.message.clone());
+            }
+            fn results(&mut self, _results: &ReplayResults) {
+                self.results = true;
+            }
+        }
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let path = std::env::temp_dir().join(format!("wrpl_tail_{}.wrpl", std::process::id()));
+        // the game writes the replay a bit at a time, not even a whole
+        // header to begin with
+        std::fs::write(&path, &file[..100]).unwrap();
+        let mut tailer = Tailer::new(&path, TailOptions::default());
+        let mut count = Count::default();
+        assert_eq!(tailer.poll(&mut count).unwrap(), 0);
+        assert!(tailer.header().is_none());
+
+        let mut written = 100;
+        let count = tailer
+            .follow_with(count, |_| {
+                if written == file.len() {
+                    return ControlFlow::Break(());
+                }
+                let end = (written + 7919).min(file.len());
+                let mut out = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .unwrap();
+                out.write_all(&file[written..end]).unwrap();
+                written = end;
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(tailer.finished());
+        assert!(tailer.header().is_some());
+        assert_eq!(count.packets, 51952);
+        assert_eq!(tailer.packet_count(), 51952);
+        assert_eq!(count.chat.len(), 19);
+        assert_eq!(count.chat[0], "TEST");
+        assert!(count.results);
+    }
+
+    #[test]
+    fn test_tail_checkpoint() {
+        use parser::{PacketRef, PacketVisitor};
+        use tail::{ParseCheckpoint, TailOptions, Tailer};
+
+        #[derive(Default)]
+        struct Offsets(Vec<u64>);
+        impl PacketVisitor for Offsets {
+            fn packet(&mut self, packet: PacketRef<'_>) {
+                self.0.push(packet.stream_offset);
+            }
+        }
+
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let path = std::env::temp_dir().join(format!("wrpl_resume_{}.wrpl", std::process::id()));
+        std::fs::write(&path, &file[..file.len() / 2]).unwrap();
+        let mut tailer = Tailer::new(&path, TailOptions::default());
+        let mut before = Offsets::default();
+        tailer.poll(&mut before).unwrap();
+        let checkpoint = tailer.checkpoint().unwrap();
+        assert!(checkpoint.compressed);
+        assert_eq!(checkpoint.packet_count, before.0.len() as u64);
+        drop(tailer);
+
+        // carry on from a saved checkpoint, once the rest is written
+        let saved = serde_json::to_string(&checkpoint).unwrap();
+        let checkpoint: ParseCheckpoint = serde_json::from_str(&saved).unwrap();
+        std::fs::write(&path, &file).unwrap();
+        let mut tailer = Tailer::resume(&path, TailOptions::default(), &checkpoint).unwrap();
+        let mut after = Offsets::default();
+        tailer.poll(&mut after).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(tailer.finished());
+        assert_eq!(after.0[0], checkpoint.stream_offset);
+        assert!(before.0.last().unwrap() < &after.0[0]);
+        assert_eq!(before.0.len() + after.0.len(), 51952);
+        assert_eq!(tailer.packet_count(), 51952);
+    }
+
+    #[test]
+    /// Search the chat of the replays in /tests/replays
+    fn test_grep_chat() {
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let options = search::GrepOptions::default();
+        let found = search::grep_chat(&file, "point", &options).unwrap();
+        assert!(found.iter().any(|c| c.message == "Attack the D point!"));
+        assert!(search::grep_chat(&file, "test", &options)
+            .unwrap()
+            .is_empty());
+
+        let options = search::GrepOptions {
+            ignore_case: true,
+            match_sender: true,
+        };
+        let found = search::grep_chat(&file, "test", &options).unwrap();
+        assert_eq!(found[0].sender, "kiTmalZ");
+
+        let matches =
+            search::grep_dir(std::path::Path::new("tests/replays"), "TEST", &options).unwrap();
+        assert!(matches
+            .iter()
+            .any(|m| m.path.ends_with("client_1.wrpl") && m.session_id.is_some()));
+    }
+
+    #[test]
+    /// Uptiers in /tests/replays/client_1.wrpl, with made up BRs
+    fn test_battle_rating_client_1() {
+        use teams::{Tier, VehicleInfo, VehicleTable};
+        let file = read("tests/replays/client_1.wrpl").unwrap();
+        let header = header::parse_header(&file).unwrap();
+        let results = parser::parse_replay_results(&file, header.rez_offset as usize).unwrap();
+        let author = results
+            .players
+            .iter()
+            .find(|p| p.player_info.user_id == results.author_user_id)
+            .unwrap();
+
+        let mut table = VehicleTable::new();
+        assert!(results.battle_rating_analysis(&table).is_none());
+
+        // everyone at 6.7, except the author's vehicles at 5.7
+        for player in &results.players {
+            for name in &player.replay_data.lineup {
+                let br = if author.replay_data.lineup.contains(name) {
+                    5.7
+                } else {
+                    6.7
+                };
+                let mut info = teams::guess_vehicle(name);
+                info.battle_rating = Some(br);
+                table.insert(name.clone(), info);
+            }
+        }
+        let analysis = results.battle_rating_analysis(&table).unwrap();
+        assert_eq!(analysis.tier, Tier::Uptier);
+        assert_eq!(analysis.author_br, Some(5.7));
+        assert!(analysis.is_full_uptier());
+
+        // now the author brings a 7.7
+        table.insert(
+            author.replay_data.lineup[0].clone(),
+            VehicleInfo {
+                battle_rating: Some(7.7),
+                ..teams::guess_vehicle(&author.replay_data.lineup[0])
+            },
+        );
+        let analysis = results.battle_rating_analysis(&table).unwrap();
+        assert_eq!(analysis.tier, Tier::Downtier);
+        assert_eq!(analysis.uptier_by, 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "metadata")]
+    /// Check /tests/replays/client_2.wrpl against server_2.wrpl standing in
+    /// for the CDN's metadata.
+    fn test_metadata_client_2() {
+        let server = read("tests/replays/server_2.wrpl").unwrap();
+        let server_header = header::parse_header(&server).unwrap();
+        let server_results =
+            parser::parse_replay_results(&server, server_header.rez_offset as usize);
+        let mut metadata = metadata::SessionMetadata {
+            session_id: server_header.session_id,
+            header: server_header,
+            segments: 1,
+            results: server_results,
+        };
+        assert_eq!(metadata.mission(), "missions/bulge_bastogne_BfD_norespawn");
+
+        let client = read("tests/replays/client_2.wrpl").unwrap();
+        let header = header::parse_header(&client).unwrap();
+        let results = parser::parse_replay_results(&client, header.rez_offset as usize);
+        assert!(metadata.check(&header, results.as_ref()).is_empty());
+
+        metadata.header.level = "levels/avg_normandy.bin".to_string();
+        let mismatches = metadata.check(&header, results.as_ref());
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].field, "level");
+    }
+
+    #[test]
+    #[cfg(feature = "ffi")]
+    /// Parse /tests/replays/client_1.wrpl through the C ABI.
+    fn test_ffi_client_1() {
+        use std::ffi::CStr;
+
+        let file = std::fs::read("tests/replays/client_1.wrpl").unwrap();
+        unsafe {
+            let replay = ffi::wrpl_parse(file.as_ptr(), file.len());
+            assert!(!replay.is_null());
+            assert_eq!(ffi::wrpl_chat_count(replay), 19);
+
+            let sender = ffi::wrpl_chat_sender(replay, 0);
+            assert_eq!(CStr::from_ptr(sender).to_str().unwrap(), "kiTmalZ");
+            ffi::wrpl_string_free(sender);
+            assert!(ffi::wrpl_chat_sender(replay, 19).is_null());
+            let raw = replay::Replay::parse(&file).unwrap().parsed.chat_messages[0].timestamp_ms;
+            assert_eq!(
+                ffi::wrpl_chat_timestamp_ms(replay, 0),
+                clock::ticks_to_ms(raw)
+            );
+
+            let json = ffi::wrpl_to_json(replay);
+            let value: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(value["chat"][0]["message"], "TEST");
+            assert_eq!(value["chat"][0]["timestamp_ms"], clock::ticks_to_ms(raw));
+            ffi::wrpl_string_free(json);
+            ffi::wrpl_free(replay);
+
+            assert!(ffi::wrpl_parse(file.as_ptr(), 16).is_null());
+            assert!(!ffi::wrpl_last_error().is_null());
+        }
+    }
 }
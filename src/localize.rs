@@ -0,0 +1,176 @@
+//! Localized names from the game's `lang/*.csv` files, e.g. the mission
+//! title for the header's `loc_name`.
+//!
+//! The files are `;` separated with every field quoted. The first row names
+//! the columns: `"<ID|readonly|noverify>";"<English>";"<French>";...`.
+
+use crate::header::ReplayHeader;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Splits a lang CSV into rows of fields. Quotes are doubled inside quoted
+/// fields, which may span lines.
+fn parse_rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ';' if !quoted => row.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            '\r' if !quoted => {}
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Localized strings of one language.
+#[derive(Debug, Clone)]
+pub struct Localizer {
+    language: String,
+    /// By lowercased key, as the game doesn't keep to one case.
+    strings: HashMap<String, String>,
+}
+
+impl Localizer {
+    /// An empty localizer for `language`, as named in the header row of the
+    /// CSVs (e.g. `English`, `Russian`, `Chinese`).
+    pub fn new(language: impl Into<String>) -> Self {
+        Localizer {
+            language: language.into(),
+            strings: HashMap::new(),
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// Number of strings loaded.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Adds the strings of one lang CSV, replacing any with the same key.
+    /// Returns how many there were. Fails if the file has no column for
+    /// the language.
+    pub fn load_csv(&mut self, text: &str) -> Result<usize> {
+        let mut rows = parse_rows(text.strip_prefix('\u{feff}').unwrap_or(text)).into_iter();
+        let Some(columns) = rows.next() else {
+            return Ok(0);
+        };
+        let wanted = self.language.to_lowercase();
+        let Some(column) = columns
+            .iter()
+            .position(|name| name.trim_matches(['<', '>']).to_lowercase() == wanted)
+        else {
+            bail!("No column for language {:?}", self.language);
+        };
+
+        let mut loaded = 0;
+        for row in rows {
+            let (Some(key), Some(value)) = (row.first(), row.get(column)) else {
+                continue;
+            };
+            if key.is_empty() {
+                continue;
+            }
+            self.strings.insert(key.to_lowercase(), value.clone());
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Same as [`Localizer::load_csv`], for a file.
+    pub fn load_file(&mut self, path: &Path) -> Result<usize> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        self.load_csv(&text)
+            .with_context(|| format!("Failed to load {:?}", path))
+    }
+
+    /// Loads every `.csv` in `dir` (e.g. the game's `lang` folder). Files
+    /// that can't be loaded are skipped with a warning.
+    pub fn load_dir(&mut self, dir: &Path) -> Result<usize> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {:?}", dir))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+            .collect();
+        paths.sort();
+
+        let mut loaded = 0;
+        for path in paths {
+            match self.load_file(&path) {
+                Ok(n) => loaded += n,
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// The string for `key`, ignoring case.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.strings.get(&key.to_lowercase()).map(String::as_str)
+    }
+
+    /// Adds or replaces a string.
+    pub fn insert(&mut self, key: &str, value: impl Into<String>) {
+        self.strings.insert(key.to_lowercase(), value.into());
+    }
+
+    /// Localizes a `loc_name`. Besides plain keys these can be several
+    /// joined by `;` (`missions/_Conq1;sinai_02/name`), where the later
+    /// ones are relative to the first one's folder. The parts are put into
+    /// the first one's `%s` if it has one, or joined by spaces.
+    pub fn localize(&self, loc_name: &str) -> Option<String> {
+        if let Some(value) = self.get(loc_name) {
+            return Some(value.to_string());
+        }
+        let mut parts = loc_name.split(';');
+        let first = parts.next()?;
+        let folder = first.rsplit_once('/').map_or("", |(folder, _)| folder);
+        let mut text = self.get(first)?.to_string();
+        for part in parts {
+            let value = self
+                .get(&format!("{}/{}", folder, part))
+                .or_else(|| self.get(part))?;
+            if text.contains("%s") {
+                text = text.replacen("%s", value, 1);
+            } else {
+                text.push(' ');
+                text.push_str(value);
+            }
+        }
+        Some(text)
+    }
+}
+
+impl ReplayHeader {
+    /// The mission's title in `localizer`'s language, from `loc_name`.
+    pub fn mission_title(&self, localizer: &Localizer) -> Option<String> {
+        localizer.localize(&self.loc_name)
+    }
+}
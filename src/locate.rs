@@ -0,0 +1,202 @@
+//! Locating the game's `Replays` directory and scanning it for `.wrpl` files.
+
+use crate::format::{self, ReplayKind};
+use crate::header::{self, ReplayHeader, HEADER_SIZE, M_SET_OFFSET};
+use crate::utils::fnv1a;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Environment variable that overrides replay directory discovery.
+pub const REPLAYS_DIR_ENV: &str = "WT_REPLAYS_DIR";
+
+/// Steam app ID of War Thunder, used for Proton prefixes.
+const STEAM_APP_ID: &str = "236390";
+
+/// A `.wrpl` file found while scanning, with a quick summary of its header.
+#[derive(Debug, Clone)]
+pub struct ReplayFileSummary {
+    /// Path to the replay.
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Last modification time, if the platform provides it.
+    pub modified: Option<SystemTime>,
+    /// The parsed header, or `None` if it couldn't be parsed.
+    pub header: Option<ReplayHeader>,
+    /// For server replay segments, a hash of the whole file. All segments
+    /// of a battle share the session ID, so this is what tells them apart.
+    pub segment_hash: Option<u64>,
+}
+
+/// Returns every directory the game might keep replays in, most likely first.
+/// The directories are not checked for existence.
+pub fn candidate_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(dir) = env::var_os(REPLAYS_DIR_ENV) {
+        dirs.push(PathBuf::from(dir));
+    }
+
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+
+    // Gaijin launcher installs
+    if let Some(local) = env::var_os("LOCALAPPDATA") {
+        dirs.push(PathBuf::from(local).join("WarThunder"));
+    }
+    if let Some(home) = &home {
+        dirs.push(home.join(".local/share/WarThunder"));
+        dirs.push(home.join("Library/Application Support/WarThunder"));
+    }
+
+    // Steam installs, including any extra library folders
+    for steamapps in steamapps_dirs(home.as_deref()) {
+        dirs.push(steamapps.join("common/War Thunder"));
+        // Proton prefix, for those running the Windows build
+        dirs.push(steamapps.join(format!(
+            "compatdata/{}/pfx/drive_c/users/steamuser/AppData/Local/WarThunder",
+            STEAM_APP_ID
+        )));
+    }
+
+    dirs.into_iter()
+        .map(|dir| {
+            if dir.ends_with("Replays") {
+                dir
+            } else {
+                dir.join("Replays")
+            }
+        })
+        .collect()
+}
+
+/// Finds the first existing replay directory from [`candidate_dirs`].
+pub fn find_replays_dir() -> Option<PathBuf> {
+    candidate_dirs().into_iter().find(|dir| {
+        debug!("Checking for replays in {:?}", dir);
+        dir.is_dir()
+    })
+}
+
+/// Lists all `steamapps` directories, following `libraryfolders.vdf`.
+fn steamapps_dirs(home: Option<&Path>) -> Vec<PathBuf> {
+    let mut roots = vec![
+        PathBuf::from(r"C:\Program Files (x86)\Steam"),
+        PathBuf::from(r"C:\Program Files\Steam"),
+    ];
+    if let Some(home) = home {
+        roots.push(home.join(".local/share/Steam"));
+        roots.push(home.join(".steam/steam"));
+        roots.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+        roots.push(home.join("Library/Application Support/Steam"));
+    }
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for root in roots {
+        let steamapps = root.join("steamapps");
+        if !steamapps.is_dir() {
+            continue;
+        }
+        if let Ok(vdf) = fs::read_to_string(steamapps.join("libraryfolders.vdf")) {
+            for library in parse_library_folders(&vdf) {
+                let library_apps = library.join("steamapps");
+                if !dirs.contains(&library_apps) {
+                    dirs.push(library_apps);
+                }
+            }
+        }
+        if !dirs.contains(&steamapps) {
+            dirs.push(steamapps);
+        }
+    }
+    dirs
+}
+
+/// Extracts the `"path"` entries from Steam's `libraryfolders.vdf`.
+fn parse_library_folders(vdf: &str) -> Vec<PathBuf> {
+    vdf.lines()
+        .filter_map(|line| {
+            let mut parts = line.split('"').filter(|p| !p.trim().is_empty());
+            match (parts.next(), parts.next()) {
+                (Some("path"), Some(path)) => Some(PathBuf::from(path.replace("\\\\", "\\"))),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Reads and parses only the fixed-size header of a replay file.
+pub fn read_header_summary(path: &Path) -> Result<ReplayHeader> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open replay {:?}", path))?;
+    let mut buffer = vec![0u8; HEADER_SIZE];
+    file.read_exact(&mut buffer)
+        .with_context(|| format!("Replay {:?} is shorter than a header", path))?;
+    header::parse_header(&buffer)
+}
+
+/// See [`ReplayFileSummary::segment_hash`]. `None` for anything that isn't
+/// a server replay segment.
+pub fn segment_hash(path: &Path) -> Result<Option<u64>> {
+    let mut start = Vec::new();
+    File::open(path)
+        .with_context(|| format!("Failed to open replay {:?}", path))?
+        .take(M_SET_OFFSET as u64 + 2)
+        .read_to_end(&mut start)?;
+    if !matches!(format::inspect(&start), ReplayKind::ServerSegment { .. }) {
+        return Ok(None);
+    }
+    let data = fs::read(path).with_context(|| format!("Failed to read replay {:?}", path))?;
+    Ok(Some(fnv1a(&data)))
+}
+
+/// Lists all `.wrpl` files in `dir` (non-recursively), newest first.
+pub fn scan_replays(dir: &Path) -> Result<Vec<ReplayFileSummary>> {
+    let mut summaries = Vec::new();
+
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_wrpl = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wrpl"));
+        if !is_wrpl || !path.is_file() {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let header = match read_header_summary(&path) {
+            Ok(header) => Some(header),
+            Err(e) => {
+                warn!("Couldn't read header of {:?}: {}", path, e);
+                None
+            }
+        };
+
+        let segment_hash = segment_hash(&path).unwrap_or_else(|e| {
+            warn!("{:#}", e);
+            None
+        });
+
+        summaries.push(ReplayFileSummary {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            header,
+            segment_hash,
+        });
+    }
+
+    summaries.sort_by_key(|s| std::cmp::Reverse(s.modified));
+    Ok(summaries)
+}
+
+/// Returns the most recently modified replay in `dir`.
+pub fn latest_replay(dir: &Path) -> Result<Option<ReplayFileSummary>> {
+    Ok(scan_replays(dir)?.into_iter().next())
+}
@@ -0,0 +1,106 @@
+//! Keeping the messages logged during a parse, e.g. to show them to whoever
+//! uploaded the replay.
+//!
+//! The parser logs through the `log` crate as usual. With
+//! [`ParseOptions::capture_log`](crate::parser::ParseOptions::capture_log)
+//! its info, warning and error messages are also kept in
+//! [`ParsedReplay::log`](crate::parser::ParsedReplay::log). To catch what
+//! else logs (like [`crate::header::parse_header`]), wrap it in [`capture`].
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fmt;
+
+/// How bad a [`LogEntry`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+}
+
+/// A message logged during a parse.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    /// The module it came from, e.g. `wrpl::parser`.
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:?}] {}", self.level, self.message)
+    }
+}
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Vec<LogEntry>>> = const { RefCell::new(None) };
+}
+
+/// Stops capturing even if the closure panics.
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        CAPTURED.with(|c| c.borrow_mut().take());
+    }
+}
+
+/// Runs `f`, keeping what this crate logs on this thread meanwhile. Inside
+/// another `capture`, everything goes to the outer one and this returns
+/// nothing.
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<LogEntry>) {
+    if is_capturing() {
+        return (f(), Vec::new());
+    }
+    CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+    let guard = Guard;
+    let value = f();
+    let entries = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+    drop(guard);
+    (value, entries)
+}
+
+fn is_capturing() -> bool {
+    CAPTURED.with(|c| c.borrow().is_some())
+}
+
+#[doc(hidden)]
+pub fn record(level: LogLevel, target: &str, message: fmt::Arguments<'_>) {
+    CAPTURED.with(|c| {
+        if let Some(entries) = c.borrow_mut().as_mut() {
+            entries.push(LogEntry {
+                level,
+                target: target.to_string(),
+                message: message.to_string(),
+            });
+        }
+    });
+}
+
+// drop-in replacements for the `log` macros that also record
+
+macro_rules! log_error {
+    ($($arg:tt)+) => {{
+        $crate::logging::record($crate::logging::LogLevel::Error, module_path!(), format_args!($($arg)+));
+        ::log::error!($($arg)+);
+    }};
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => {{
+        $crate::logging::record($crate::logging::LogLevel::Warn, module_path!(), format_args!($($arg)+));
+        ::log::warn!($($arg)+);
+    }};
+}
+
+macro_rules! log_info {
+    ($($arg:tt)+) => {{
+        $crate::logging::record($crate::logging::LogLevel::Info, module_path!(), format_args!($($arg)+));
+        ::log::info!($($arg)+);
+    }};
+}
+
+pub(crate) use {log_error as error, log_info as info, log_warn as warn};
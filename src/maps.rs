@@ -0,0 +1,133 @@
+//! Display names of maps, from the header's level path
+//! (`levels/avg_egypt_sinai.bin` is "Sinai").
+
+use crate::header::ReplayHeader;
+use std::collections::HashMap;
+
+/// Display names of well known levels, by level name. Anything else gets a
+/// name made from its level name, see [`MapNames::name`].
+const BUILTIN_MAP_NAMES: [(&str, &str); 37] = [
+    ("avg_38_parallel", "38th Parallel"),
+    ("avg_abandoned_factory", "Abandoned Factory"),
+    ("avg_advance_to_the_rhine", "Advance to the Rhine"),
+    ("avg_american_valley", "American Desert"),
+    ("avg_aral_sea", "Aral Sea"),
+    ("avg_ardennes", "Ardennes"),
+    ("avg_ash_river", "Ash River"),
+    ("avg_berlin", "Berlin"),
+    ("avg_breslau", "Breslau"),
+    ("avg_carpathians", "Carpathians"),
+    ("avg_eastern_europe", "Eastern Europe"),
+    ("avg_egypt_sinai", "Sinai"),
+    ("avg_el_alamein", "El Alamein"),
+    ("avg_finland", "Finland"),
+    ("avg_fire_arc", "Fire Arc"),
+    ("avg_frozen_pass", "Frozen Pass"),
+    ("avg_fulda", "Fulda"),
+    ("avg_golan_heights", "Golan Heights"),
+    ("avg_hurtgen", "Hürtgen Forest"),
+    ("avg_iberian_castle", "Iberian Castle"),
+    ("avg_japan", "Japan"),
+    ("avg_karelia_forest_a", "Karelia"),
+    ("avg_korea_lake", "Korea"),
+    ("avg_kursk", "Kursk"),
+    ("avg_maginot", "Maginot Line"),
+    ("avg_mozdok", "Mozdok"),
+    ("avg_normandy", "Normandy"),
+    ("avg_poland", "Poland"),
+    ("avg_port_novorossiysk", "Port Novorossiysk"),
+    ("avg_red_desert", "Red Desert"),
+    ("avg_sector_montmedy", "Sector Montmedy"),
+    ("avg_stalingrad_factory", "Stalingrad"),
+    ("avg_sweden", "Sweden"),
+    ("avg_tunisia_desert", "Tunisia"),
+    ("avg_vietnam_hills", "Vietnam"),
+    ("avg_volokolamsk", "Volokolamsk"),
+    ("air_mysterious_valley", "Mysterious Valley"),
+];
+
+/// Level name prefixes that say what the map is for, not what it's called.
+const MODE_PREFIXES: [&str; 4] = ["avg_", "air_", "water_", "tank_"];
+
+/// `avg_egypt_sinai` from `levels/avg_egypt_sinai.bin` (or from itself).
+pub fn level_name(level: &str) -> &str {
+    let name = level.rsplit(['/', '\\']).next().unwrap_or(level);
+    name.strip_suffix(".bin").unwrap_or(name)
+}
+
+/// Display names by level name: the built-in ones, plus any added.
+#[derive(Debug, Clone)]
+pub struct MapNames {
+    names: HashMap<String, String>,
+}
+
+impl Default for MapNames {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl MapNames {
+    /// Just the built-in names.
+    pub fn builtin() -> Self {
+        MapNames {
+            names: BUILTIN_MAP_NAMES
+                .iter()
+                .map(|(level, name)| (level.to_string(), name.to_string()))
+                .collect(),
+        }
+    }
+
+    /// No names at all, every map gets a made up one.
+    pub fn empty() -> Self {
+        MapNames {
+            names: HashMap::new(),
+        }
+    }
+
+    /// Adds or replaces the name of `level` (a path or level name).
+    pub fn insert(&mut self, level: &str, name: impl Into<String>) {
+        self.names
+            .insert(level_name(level).to_string(), name.into());
+    }
+
+    /// The name of `level`, if it has one.
+    pub fn get(&self, level: &str) -> Option<&str> {
+        self.names.get(level_name(level)).map(String::as_str)
+    }
+
+    /// The name of `level`, or one made from its level name if it has none
+    /// (`air_mysterious_valley` is "Mysterious Valley").
+    pub fn name(&self, level: &str) -> String {
+        if let Some(name) = self.get(level) {
+            return name.to_string();
+        }
+        let mut name = level_name(level);
+        for prefix in MODE_PREFIXES {
+            name = name.strip_prefix(prefix).unwrap_or(name);
+        }
+        name.split('_')
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl ReplayHeader {
+    /// Display name of the map, from the built-in names.
+    pub fn map_name(&self) -> String {
+        MapNames::builtin().name(&self.level)
+    }
+
+    /// Display name of the map, from `names`.
+    pub fn map_name_with(&self, names: &MapNames) -> String {
+        names.name(&self.level)
+    }
+}
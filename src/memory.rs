@@ -0,0 +1,70 @@
+//! Rough accounting of the memory a parse keeps, for services that give
+//! each request a budget (see
+//! [`ParseOptions::memory_limit`](crate::parser::ParseOptions::memory_limit)).
+//!
+//! The numbers are estimates of the heap each kept item takes: the item
+//! itself, its strings and payload. Spare capacity in the lists isn't
+//! counted.
+
+use crate::decode::DecodedPacket;
+use crate::parser::{ChatInfo, PacketInfo};
+use serde::Serialize;
+use std::mem::size_of;
+
+/// Bytes kept in a [`ParsedReplay`](crate::parser::ParsedReplay), by what
+/// they're for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct MemoryUsage {
+    /// [`PacketInfo`]s without their payloads.
+    pub packets: usize,
+    pub payloads: usize,
+    pub chat: usize,
+    pub decoded: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.packets + self.payloads + self.chat + self.decoded
+    }
+
+    pub(crate) fn add_packet(&mut self, packet: &PacketInfo) {
+        self.packets += size_of::<PacketInfo>();
+        self.payloads += packet.payload.capacity();
+    }
+
+    pub(crate) fn add_chat(&mut self, chat: &ChatInfo) {
+        self.chat += size_of::<ChatInfo>() + chat.sender.capacity() + chat.message.capacity();
+    }
+
+    pub(crate) fn add_decoded(&mut self, decoded: &DecodedPacket) {
+        self.decoded +=
+            size_of::<DecodedPacket>() + decoded.decoder.capacity() + json_size(&decoded.value);
+    }
+
+    /// Adds up the usage of two parses, e.g. of segments that are joined.
+    pub(crate) fn add(&mut self, other: &MemoryUsage) {
+        self.packets += other.packets;
+        self.payloads += other.payloads;
+        self.chat += other.chat;
+        self.decoded += other.decoded;
+    }
+}
+
+/// Heap taken by a JSON value, not counting the value itself.
+fn json_size(value: &serde_json::Value) -> usize {
+    use serde_json::Value;
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+        Value::String(s) => s.capacity(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| size_of::<Value>() + json_size(item))
+            .sum(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, item)| {
+                size_of::<String>() + key.capacity() + size_of::<Value>() + json_size(item)
+            })
+            .sum(),
+    }
+}
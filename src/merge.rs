@@ -0,0 +1,128 @@
+//! Combining a client replay with the server replay of the same session.
+//!
+//! Server replays (see [`crate::download`]) record every unit, but miss
+//! what only the client saw: chat, and the results blob. [`merge`] takes the
+//! server's packets and adds the client-only ones, shifted onto the server's
+//! clock, and keeps the client's chat and results.
+
+use crate::clock::TICKS_PER_MS;
+use crate::diff::{self, DiffOptions};
+use crate::parser::{PacketInfo, ParsedReplay};
+
+/// What [`merge`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Packets taken from the server segments.
+    pub server_packets: usize,
+    /// Client packets that weren't in the server replay and were added.
+    pub client_only_packets: usize,
+    /// Milliseconds added to client times to line them up with the server's.
+    pub offset_ms: i64,
+}
+
+fn shift_ticks(timestamp: u32, offset_ms: i64) -> u32 {
+    let shifted = timestamp as i64 + offset_ms * TICKS_PER_MS as i64;
+    shifted.clamp(0, u32::MAX as i64) as u32
+}
+
+/// Merges two packet lists by timestamp, keeping the order within each
+/// (so jitter in either isn't "fixed" by sorting).
+fn interleave(server: Vec<PacketInfo>, client: Vec<PacketInfo>) -> Vec<PacketInfo> {
+    let mut out = Vec::with_capacity(server.len() + client.len());
+    let mut client = client.into_iter().peekable();
+    for packet in server {
+        while let Some(c) = client.next_if(|c| c.timestamp_ms < packet.timestamp_ms) {
+            out.push(c);
+        }
+        out.push(packet);
+    }
+    out.extend(client);
+    out
+}
+
+/// Joins the segments of a replay (in order) into one, as if it had been a
+/// single file. Packet offsets stay relative to their own segment's stream.
+pub fn join_segments(segments: Vec<ParsedReplay>) -> ParsedReplay {
+    let mut joined = ParsedReplay {
+        complete: !segments.is_empty(),
+        ..Default::default()
+    };
+    for segment in segments {
+        joined.complete &= segment.complete;
+        joined.total_decompressed_bytes += segment.total_decompressed_bytes;
+        for (raw_type, bytes) in segment.bytes_by_type {
+            let joined_bytes = joined.bytes_by_type.entry(raw_type).or_default();
+            joined_bytes.packets += bytes.packets;
+            joined_bytes.bytes += bytes.bytes;
+        }
+        joined.trailing_bytes = segment.trailing_bytes;
+        joined.packets.extend(segment.packets);
+        joined.chat_messages.extend(segment.chat_messages);
+        joined.decoded.extend(segment.decoded);
+        joined.memory.add(&segment.memory);
+        joined.timings.add(&segment.timings);
+        joined.log.extend(segment.log);
+        if joined.replay_results.is_none() {
+            joined.replay_results = segment.replay_results;
+        }
+    }
+    joined.packet_count = joined.packets.len() as u64;
+    joined
+}
+
+/// Merges a client replay with the server segments of the same session
+/// (in order). Timestamps in the result are on the server's clock.
+pub fn merge(
+    client: ParsedReplay,
+    server_segments: Vec<ParsedReplay>,
+) -> (ParsedReplay, MergeReport) {
+    // decoded client packets aren't carried over
+    let mut merged = join_segments(server_segments);
+    let server_chat = std::mem::take(&mut merged.chat_messages);
+    let server_packets = merged.packets.len();
+
+    let diff = diff::diff_packets(&client.packets, &merged.packets, &DiffOptions::default());
+    let offset_ms = diff.offset_ms;
+
+    let mut client_only = vec![false; client.packets.len()];
+    for &i in &diff.only_in_a {
+        client_only[i] = true;
+    }
+    let added: Vec<PacketInfo> = client
+        .packets
+        .into_iter()
+        .zip(client_only)
+        .filter(|(_, only)| *only)
+        .map(|(mut packet, _)| {
+            packet.timestamp_ms = shift_ticks(packet.timestamp_ms, offset_ms);
+            packet
+        })
+        .collect();
+    merged.packets = interleave(std::mem::take(&mut merged.packets), added);
+    merged.packet_count = merged.packets.len() as u64;
+
+    // the client's chat is complete, the server's is at best partial
+    merged.chat_messages = if client.chat_messages.is_empty() {
+        server_chat
+    } else {
+        client
+            .chat_messages
+            .into_iter()
+            .map(|mut chat| {
+                chat.timestamp_ms = shift_ticks(chat.timestamp_ms, offset_ms);
+                chat
+            })
+            .collect()
+    };
+    if client.replay_results.is_some() {
+        merged.replay_results = client.replay_results;
+    }
+    merged.resolve_chat_senders();
+
+    let report = MergeReport {
+        server_packets,
+        client_only_packets: diff.only_in_a.len(),
+        offset_ms,
+    };
+    (merged, report)
+}
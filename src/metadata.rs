@@ -0,0 +1,224 @@
+//! Server-side metadata of a session, read from Gaijin's replay CDN.
+//!
+//! There's no documented metadata API, so this reads what the CDN already
+//! serves: the header of the first segment (mission, level, start time) and
+//! the results at the end of the last one (time played and, when present,
+//! the player list). Only the bytes needed are requested, using `Range`.
+//!
+//! [`SessionMetadata::check`] compares it with a local parse of the same
+//! session.
+
+use crate::download::{self, DownloadOptions};
+use crate::header::{self, ReplayHeader, SessionId, HEADER_SIZE};
+use crate::parser::{self, PlayerData, ReplayResults};
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::fmt;
+
+/// How far (in seconds) local and server `time_played` may be apart.
+/// The server keeps recording for a few seconds after the client stops.
+pub const TIME_PLAYED_TOLERANCE: f64 = 10.0;
+
+/// What the server knows about a session.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionMetadata {
+    pub session_id: SessionId,
+    /// Header of the first segment.
+    pub header: ReplayHeader,
+    /// Number of segments on the CDN.
+    pub segments: u32,
+    /// Results of the last segment, if it has any.
+    pub results: Option<ReplayResults>,
+}
+
+impl SessionMetadata {
+    /// The mission's localisation key, e.g. `missions/bulge_bastogne_BfD_norespawn`.
+    pub fn mission(&self) -> &str {
+        &self.header.loc_name
+    }
+
+    /// Unix time the first segment started at.
+    pub fn start_time(&self) -> u32 {
+        self.header.start_time
+    }
+
+    /// Players in the server results (often empty, the server doesn't
+    /// always write them).
+    pub fn players(&self) -> &[PlayerData] {
+        self.results.as_ref().map_or(&[], |r| &r.players)
+    }
+
+    /// Compares a local parse of the same session with the server's data.
+    /// Returns every field that disagrees.
+    pub fn check(
+        &self,
+        header: &ReplayHeader,
+        results: Option<&ReplayResults>,
+    ) -> Vec<MetadataMismatch> {
+        let mut mismatches = Vec::new();
+        let mut compare = |field: &'static str, local: String, server: String| {
+            if local != server {
+                mismatches.push(MetadataMismatch {
+                    field,
+                    local,
+                    server,
+                });
+            }
+        };
+        let server = &self.header;
+        compare(
+            "session_id",
+            header.session_id.to_string(),
+            self.session_id.to_string(),
+        );
+        compare(
+            "version",
+            header.version.to_string(),
+            server.version.to_string(),
+        );
+        compare("level", header.level.clone(), server.level.clone());
+        compare(
+            "battle_type",
+            header.battle_type.clone(),
+            server.battle_type.clone(),
+        );
+        compare("loc_name", header.loc_name.clone(), server.loc_name.clone());
+
+        if let (Some(local), Some(server)) = (results, &self.results) {
+            if (local.time_played - server.time_played).abs() > TIME_PLAYED_TOLERANCE {
+                compare(
+                    "time_played",
+                    format!("{:.1}", local.time_played),
+                    format!("{:.1}", server.time_played),
+                );
+            }
+        }
+        mismatches
+    }
+}
+
+/// A field where a local replay and the server metadata disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataMismatch {
+    pub field: &'static str,
+    pub local: String,
+    pub server: String,
+}
+
+impl fmt::Display for MetadataMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: local {:?}, server {:?}",
+            self.field, self.local, self.server
+        )
+    }
+}
+
+/// Fetches bytes `start..` (or `start..end`) of a file on the CDN.
+/// Returns `None` if it doesn't exist.
+async fn fetch_range(
+    client: &reqwest::Client,
+    url: &str,
+    start: u64,
+    end: Option<u64>,
+) -> Result<Option<Vec<u8>>> {
+    let range = match end {
+        Some(end) => format!("bytes={}-{}", start, end - 1),
+        None => format!("bytes={}-", start),
+    };
+    let response = client
+        .get(url)
+        .header(RANGE, range)
+        .send()
+        .await
+        .with_context(|| format!("Request for {} failed", url))?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        StatusCode::PARTIAL_CONTENT => Ok(Some(response.bytes().await?.to_vec())),
+        // the range was ignored, cut it out ourselves
+        StatusCode::OK => {
+            let bytes = response.bytes().await?;
+            let start = (start as usize).min(bytes.len());
+            let end = end.map_or(bytes.len(), |e| (e as usize).min(bytes.len()));
+            Ok(Some(bytes[start..end].to_vec()))
+        }
+        status => bail!("Error fetching {}: {}", url, status),
+    }
+}
+
+async fn fetch_header(client: &reqwest::Client, url: &str) -> Result<Option<ReplayHeader>> {
+    match fetch_range(client, url, 0, Some(HEADER_SIZE as u64)).await? {
+        Some(data) => Ok(Some(
+            header::parse_header(&data).with_context(|| format!("Bad header in {}", url))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Fetches the metadata of the session `id` (hex).
+pub async fn fetch_metadata(id: &str, options: &DownloadOptions) -> Result<SessionMetadata> {
+    let client = options.build_client()?;
+    fetch_metadata_with_client(&client, id, options).await
+}
+
+/// Same as [`fetch_metadata`], but reuses an existing client.
+pub async fn fetch_metadata_with_client(
+    client: &reqwest::Client,
+    id: &str,
+    options: &DownloadOptions,
+) -> Result<SessionMetadata> {
+    let session_id: SessionId = id
+        .parse()
+        .with_context(|| format!("Invalid session ID {:?}", id))?;
+
+    let first_url = download::segment_url(&options.base_url, id, 0);
+    let Some(first) = fetch_header(client, &first_url).await? else {
+        bail!("Session {} not found", session_id);
+    };
+
+    // count the segments without downloading them
+    let mut segments = 1;
+    loop {
+        let url = download::segment_url(&options.base_url, id, segments);
+        let response = client
+            .head(&url)
+            .send()
+            .await
+            .with_context(|| format!("Request for {} failed", url))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            break;
+        }
+        if !response.status().is_success() {
+            bail!("Error checking {}: {}", url, response.status());
+        }
+        segments += 1;
+    }
+    info!("Session {} has {} segment(s)", session_id, segments);
+
+    let last_url = download::segment_url(&options.base_url, id, segments - 1);
+    let last = if segments == 1 {
+        Some(first.clone())
+    } else {
+        fetch_header(client, &last_url).await?
+    };
+    let results = match last {
+        Some(last) if last.rez_offset > 0 => {
+            debug!("Fetching results at {} of {}", last.rez_offset, last_url);
+            fetch_range(client, &last_url, last.rez_offset as u64, None)
+                .await?
+                .and_then(|data| parser::parse_replay_results(&data, 0))
+        }
+        _ => None,
+    };
+
+    Ok(SessionMetadata {
+        session_id,
+        header: first,
+        segments,
+        results,
+    })
+}
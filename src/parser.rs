@@ -1,150 +1,109 @@
-use crate::header::ReplayHeader;
+use crate::cache::ResultsCache;
+use crate::clock::{self, ClockStats, DurationReport, PacketTime};
+use crate::decode::{DecodedPacket, DecoderRegistry};
+use crate::format;
+use crate::header::{self, ReplayHeader};
+use crate::logging::{self, error, info, warn, LogEntry};
+use crate::memory::MemoryUsage;
+use crate::raw::{read_packet_header_from_stream, read_variable_length_size};
+use crate::stats::{self, PacketStats, Timings, TypeBytes};
+use crate::transcript;
 use crate::utils::hex;
 use anyhow::{bail, Context, Result};
-use flate2::read::ZlibDecoder;
-use log::{debug, error, info, warn};
+use flate2::{Decompress, FlushDecompress, Status};
+use log::debug;
+use memchr::memmem;
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, Cursor, Read};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use wt_blk::blk;
 use wt_blk::blk::file::FileType;
 use wt_blk::blk::name_map::NameMap;
 
-/// Reads a variable-length size prefix from the stream.
-pub fn read_variable_length_size<R: Read>(stream: &mut R) -> Result<Option<(u32, usize)>> {
-    let mut buf = [0u8; 1];
-
-    // read the first byte
-    match stream.read(&mut buf)? {
-        0 => return Ok(None), // clean EOF
-        1 => {}
-        _ => bail!("Unexpected read count when reading first byte of size prefix"),
-    }
-    let first_byte = buf[0];
-    let mut prefix_bytes_read: usize = 1;
-    let payload_size: i64;
-
-    if (first_byte & 0x80) != 0 {
-        // High bit SET (1xxxxxxx)
-        if (first_byte & 0x40) == 0 {
-            // 10xxxxxx -> 1 byte total
-            payload_size = (first_byte & 0x7F) as i64;
-        } else {
-            // 11xxxxxx -> Invalid case
-            bail!(
-                "Invalid first size prefix byte encountered: {:#02x}",
-                first_byte
-            );
-        }
-    } else {
-        // High bit CLEAR (0xxxxxxx)
-        if (first_byte & 0x40) != 0 {
-            // 01xxxxxx -> 2 bytes total
-            let mut b1_buf = [0u8; 1];
-            stream
-                .read_exact(&mut b1_buf)
-                .context("Failed to read 2nd byte of 2-byte size prefix")?;
-            prefix_bytes_read += 1;
-            payload_size = (((first_byte as i64) << 8) | (b1_buf[0] as i64)) ^ 0x4000;
-        } else if (first_byte & 0x20) != 0 {
-            // 001xxxxx -> 3 bytes total
-            let mut b1_b2_buf = [0u8; 2];
-            stream
-                .read_exact(&mut b1_b2_buf)
-                .context("Failed to read bytes 2-3 of 3-byte size prefix")?;
-            prefix_bytes_read += 2;
-            payload_size = (((first_byte as i64) << 16)
-                | ((b1_b2_buf[0] as i64) << 8)
-                | (b1_b2_buf[1] as i64))
-                ^ 0x200000;
-        } else if (first_byte & 0x10) != 0 {
-            // 0001xxxx -> 4 bytes total
-            let mut b1_b3_buf = [0u8; 3];
-            stream
-                .read_exact(&mut b1_b3_buf)
-                .context("Failed to read bytes 2-4 of 4-byte size prefix")?;
-            prefix_bytes_read += 3;
-            payload_size = (((first_byte as i64) << 24)
-                | ((b1_b3_buf[0] as i64) << 16)
-                | ((b1_b3_buf[1] as i64) << 8)
-                | (b1_b3_buf[2] as i64))
-                ^ 0x10000000;
-        } else {
-            // 0000xxxx -> 5 bytes total
-            let mut b1_b4_buf = [0u8; 4];
-            stream
-                .read_exact(&mut b1_b4_buf)
-                .context("Failed to read bytes 2-5 of 5-byte size prefix")?;
-            prefix_bytes_read += 4;
-            // little Endian u32 - use stdlib method
-            payload_size = u32::from_le_bytes(b1_b4_buf) as i64;
-        }
-    }
-
-    if payload_size < 0 {
-        warn!(
-            "Calculated negative payload size ({}). This bodes ill.",
-            payload_size
-        );
-    }
-
-    let final_size = payload_size.try_into().with_context(|| {
-        format!(
-            "Payload size {} cannot fit into u32 (prefix starts with {:#02x})",
-            payload_size, first_byte
-        )
-    })?;
+/// How the packet stream is parsed.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Fail (with the stream offset) on anything unusual instead of working
+    /// around it: unknown packet types, zero-size packets, and packets or
+    /// zlib streams cut short. For format-regression tests.
+    pub strict: bool,
+    /// Where to look up (and store) decoded results, instead of decoding
+    /// them every time.
+    pub results_cache: Option<ResultsCache>,
+    /// Raw packet type IDs to decode and keep. Empty means all of them.
+    /// Other packets are skipped over without copying their payloads.
+    pub packet_types: Vec<u8>,
+    /// Don't keep payloads in [`ParsedReplay::packets`], only their size.
+    /// Chat is still decoded.
+    pub discard_payloads: bool,
+    /// Extra decoders to run on the packets, see [`crate::decode`].
+    pub decoders: DecoderRegistry,
+    /// Keep what's logged while parsing in [`ParsedReplay::log`]. If the
+    /// parse fails it's lost, use [`logging::capture`] to keep it then too.
+    pub capture_log: bool,
+    /// Collapse chat messages repeated within this many ms into one, see
+    /// [`transcript::collapse_repeats`].
+    pub collapse_chat_ms: Option<u32>,
+    /// Fail the parse once what it keeps (see [`ParsedReplay::memory`])
+    /// goes over this many bytes. What was kept is freed right away, but
+    /// the stream is still read to the end.
+    pub memory_limit: Option<usize>,
+    /// The largest payload a size prefix may claim, `None` for
+    /// [`DEFAULT_MAX_PACKET_SIZE`]. A bigger one means the stream is
+    /// corrupt (or misaligned), so parsing stops there with a warning, or
+    /// fails when [`ParseOptions::strict`].
+    pub max_packet_size: Option<u32>,
+    /// What to do about packets with an empty payload. Ignored when
+    /// [`ParseOptions::strict`], which fails on the first one.
+    pub zero_size: ZeroSizePolicy,
+}
 
-    Ok(Some((final_size, prefix_bytes_read)))
+/// See [`ParseOptions::zero_size`]. Real replays don't have zero-size
+/// packets, but a run of zeroes (padding, or a corrupt stream) reads as
+/// one every five bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZeroSizePolicy {
+    /// Log a warning for each and carry on.
+    #[default]
+    Continue,
+    /// Carry on for this many, then fail.
+    AbortAfter(u32),
+    /// Stop at the first one, as if the stream ended there.
+    EndOfStream,
 }
 
-/// Reads packet type and timestamp from the start of a DECOMPRESSED stream/buffer.
-/// Returns `Ok(Some((packet_type, timestamp_ms, bytes_read)))` or `Ok(None)` on EOF.
-pub fn read_packet_header_from_stream<R: Read>(
-    stream: &mut R,
-    last_timestamp_ms: u32,
-) -> Result<Option<(u8, u32, usize)>> {
-    let mut first_byte_buf = [0u8; 1];
+/// Default for
+/// [`ParseOptions::max_packet_size`](field@ParseOptions::max_packet_size).
+/// Real packets are at most a few KiB.
+pub const DEFAULT_MAX_PACKET_SIZE: u32 = 1024 * 1024;
 
-    match stream.read(&mut first_byte_buf)? {
-        0 => return Ok(None), // clean EOF
-        1 => {}
-        _ => bail!("Unexpected read count reading first byte of packet header"),
+impl ParseOptions {
+    pub(crate) fn wants(&self, raw_type: u8) -> bool {
+        self.packet_types.is_empty() || self.packet_types.contains(&raw_type)
     }
-    let first_byte = first_byte_buf[0];
-    let mut bytes_read_for_header = 1;
-    let mut timestamp_ms = last_timestamp_ms;
-    let packet_type_val: u8;
 
-    if (first_byte & 0x10) != 0 {
-        // timestamp didn't change
-        packet_type_val = first_byte ^ 0x10;
-    } else {
-        packet_type_val = first_byte;
-        let mut ts_bytes = [0u8; 4];
-        match stream.read_exact(&mut ts_bytes) {
-            Ok(_) => {
-                timestamp_ms = u32::from_le_bytes(ts_bytes);
-                bytes_read_for_header += 4;
-            }
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
-                warn!("Unexpected EOF reading timestamp after type byte {:#02x}. Using last known timestamp.", packet_type_val);
-                return Ok(Some((packet_type_val, timestamp_ms, bytes_read_for_header)));
-            }
-            Err(e) => {
-                return Err(e).context("Failed to read timestamp bytes");
-            }
-        }
+    pub(crate) fn max_packet_size(&self) -> u32 {
+        self.max_packet_size.unwrap_or(DEFAULT_MAX_PACKET_SIZE)
     }
-
-    Ok(Some((packet_type_val, timestamp_ms, bytes_read_for_header)))
 }
 
 /// Process replay data (potentially compressed) from a byte slice.
 pub fn process_replay_data(
     data: &[u8],
     start_offset: u64,
-    skip_zlib: bool,
+    compression: Compression,
+) -> Result<ParsedReplay> {
+    process_replay_data_with_options(data, start_offset, compression, &ParseOptions::default())
+}
+
+/// Same as [`process_replay_data`], with [`ParseOptions`].
+pub fn process_replay_data_with_options(
+    data: &[u8],
+    start_offset: u64,
+    compression: Compression,
+    options: &ParseOptions,
 ) -> Result<ParsedReplay> {
     // Validate start_offset before slicing
     if start_offset > data.len() as u64 {
@@ -154,10 +113,90 @@ pub fn process_replay_data(
             data.len()
         );
     }
-    let input_data = &data[start_offset as usize..];
+    process_replay_reader_with_options(Cursor::new(data), start_offset, compression, options)
+}
+
+/// Process replay data (potentially compressed) from any seekable reader,
+/// e.g. a `File`, without loading the whole replay into memory.
+pub fn process_replay_reader<R: Read + Seek>(
+    input: R,
+    start_offset: u64,
+    compression: Compression,
+) -> Result<ParsedReplay> {
+    process_replay_reader_with_options(input, start_offset, compression, &ParseOptions::default())
+}
+
+/// Same as [`process_replay_reader`], with [`ParseOptions`].
+pub fn process_replay_reader_with_options<R: Read + Seek>(
+    input: R,
+    start_offset: u64,
+    compression: Compression,
+    options: &ParseOptions,
+) -> Result<ParsedReplay> {
+    with_log(options, || {
+        let mut collect = Collect {
+            discard_payloads: options.discard_payloads,
+            memory_limit: options.memory_limit,
+            ..Default::default()
+        };
+        let mut stats = visit_packets(input, start_offset, compression, options, &mut collect)?;
+        if collect.over_limit {
+            bail!(
+                "Parse kept more than its memory limit of {} bytes",
+                options.memory_limit.unwrap_or_default()
+            );
+        }
+        stats.packets = collect.packets;
+        stats.chat_messages = collect.chat_messages;
+        stats.decoded = collect.decoded;
+        stats.memory = collect.memory;
+        if let Some(window_ms) = options.collapse_chat_ms {
+            transcript::collapse_repeats(&mut stats.chat_messages, window_ms);
+            stats.memory.chat = 0;
+            for chat in &stats.chat_messages {
+                stats.memory.add_chat(chat);
+            }
+        }
+        Ok(stats)
+    })
+}
+
+/// Runs a parse, capturing its log into [`ParsedReplay::log`] if the
+/// options say so.
+fn with_log(
+    options: &ParseOptions,
+    parse: impl FnOnce() -> Result<ParsedReplay>,
+) -> Result<ParsedReplay> {
+    if !options.capture_log {
+        return parse();
+    }
+    let (result, log) = logging::capture(parse);
+    result.map(|mut stats| {
+        // nested parses leave theirs to the outermost
+        stats.log.extend(log);
+        stats
+    })
+}
 
-    let mut reader = std::io::BufReader::new(create_reader(input_data, skip_zlib)?);
-    if !skip_zlib {
+/// The packet loop. Fills in everything but the packet and chat lists,
+/// which go to the visitor instead.
+fn visit_packets<R: Read + Seek, V: PacketVisitor + ?Sized>(
+    mut input: R,
+    start_offset: u64,
+    compression: Compression,
+    options: &ParseOptions,
+    visitor: &mut V,
+) -> Result<ParsedReplay> {
+    input
+        .seek(SeekFrom::Start(start_offset))
+        .with_context(|| format!("Failed to seek to stream offset {:#0x}", start_offset))?;
+
+    let compression = resolve_compression(&mut input, compression)?;
+    let mut reader = Counted::new(BufReader::new(Timed::new(create_reader(
+        input,
+        compression,
+    )?)));
+    if compression != Compression::None {
         let peeked = reader.fill_buf().unwrap_or(&[]);
         if peeked.len() >= 3 {
             // second bytes seems to be E<anything> (E2, E6 i've seen)
@@ -170,85 +209,148 @@ pub fn process_replay_data(
     }
 
     let mut stats = ParsedReplay::default();
-    let last_timestamp_ms = 0;
+    let mut last_timestamp_ms = 0;
+    // one buffer for all packets, the visitor only gets to borrow it
+    let mut packet_data_with_header = Vec::new();
+    let mut chat_time = Duration::ZERO;
+    let mut zero_size_packets = 0;
+    let loop_start = Instant::now();
 
     loop {
         debug!(
             "Processing Packet {} (Decompressed bytes read so far: {}) ---",
             stats.packet_count, stats.total_decompressed_bytes
         );
-
-        let (decompressed_payload_size, prefix_bytes_read) =
-            match read_variable_length_size(&mut reader) {
-                Ok(Some((size, bytes_read))) => (size, bytes_read),
-                Ok(None) => {
-                    debug!("EOF reached while reading packet size prefix. End of stream.");
-                    break;
-                }
-                Err(e) => {
-                    if let Some(io_err) = e.downcast_ref::<io::Error>() {
-                        if io_err.kind() == io::ErrorKind::UnexpectedEof {
-                            warn!("Incomplete packet size prefix at end of stream: {}", e);
-                            break; // treat as EOF
-                        }
-                    }
-                    error!("Error reading packet size prefix: {:?}", e);
-                    bail!("Failed to read or parse packet size prefix");
-                }
-            };
+        let packet_offset = stats.total_decompressed_bytes;
+
+        let decompressed_payload_size = match read_variable_length_size(&mut reader) {
+            Ok(Some((size, _))) => size,
+            Ok(None) => {
+                debug!("EOF reached while reading packet size prefix. End of stream.");
+                stats.complete = true;
+                break;
+            }
+            // the stream ended (or stopped decompressing) mid-prefix
+            Err(e) if !options.strict && e.downcast_ref::<io::Error>().is_some() => {
+                warn!("Incomplete packet size prefix at end of stream: {:#}", e);
+                break;
+            }
+            Err(e) => {
+                error!("Error reading packet size prefix: {:?}", e);
+                return Err(e.context(format!(
+                    "Failed to read or parse packet size prefix at stream offset {:#x}",
+                    packet_offset
+                )));
+            }
+        };
 
         debug!(
             "Read size prefix ({} decomp. bytes): Expected payload size = {} bytes",
-            prefix_bytes_read, decompressed_payload_size
+            reader.count - packet_offset,
+            decompressed_payload_size
         );
-        stats.total_decompressed_bytes += prefix_bytes_read as u64;
 
-        if decompressed_payload_size == 0 {
-            warn!("Encountered zero-size packet payload. Continuing.");
+        if decompressed_payload_size > options.max_packet_size() {
+            if options.strict {
+                bail!(
+                    "Packet at stream offset {:#x} claims {} bytes, more than the {} allowed",
+                    packet_offset,
+                    decompressed_payload_size,
+                    options.max_packet_size()
+                );
+            }
+            warn!(
+                "Packet {} at stream offset {:#x} claims {} bytes (max {}), stream is probably corrupt. Stopping.",
+                stats.packet_count,
+                packet_offset,
+                decompressed_payload_size,
+                options.max_packet_size()
+            );
+            break;
         }
 
-        let mut packet_data_with_header = vec![0u8; decompressed_payload_size as usize];
-        let total_bytes_read_for_payload;
-
-        match reader.read_exact(&mut packet_data_with_header) {
-            Ok(_) => {
-                total_bytes_read_for_payload = decompressed_payload_size as usize;
+        if decompressed_payload_size == 0 {
+            if options.strict {
+                bail!("Zero-size packet at stream offset {:#x}", packet_offset);
             }
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
-                warn!(
-                    "Incomplete packet payload read. Expected {}, stream ended early.",
-                    decompressed_payload_size
-                );
-                packet_data_with_header.fill(0);
-                let mut temp_reader = (&mut reader).take(decompressed_payload_size as u64);
-                match temp_reader.read_to_end(&mut packet_data_with_header) {
-                    Ok(bytes_actually_read) => {
-                        total_bytes_read_for_payload = bytes_actually_read;
-                        packet_data_with_header.truncate(total_bytes_read_for_payload);
-                        warn!(
-                            "Read {} bytes of partial payload.",
-                            total_bytes_read_for_payload
-                        );
-                    }
-                    Err(read_err) => {
-                        error!("Error attempting to read partial payload: {:?}", read_err);
-                        bail!("Failed to read partial packet payload after EOF detected");
-                    }
+            zero_size_packets += 1;
+            match options.zero_size {
+                ZeroSizePolicy::Continue => {
+                    warn!("Encountered zero-size packet payload. Continuing.")
+                }
+                ZeroSizePolicy::AbortAfter(max) if zero_size_packets > max => bail!(
+                    "More than {} zero-size packets, the last at stream offset {:#x}",
+                    max,
+                    packet_offset
+                ),
+                ZeroSizePolicy::AbortAfter(_) => {
+                    warn!("Encountered zero-size packet payload. Continuing.")
                 }
-                if total_bytes_read_for_payload == 0 {
-                    info!("No payload data read after size prefix indicated > 0. Stopping.");
+                ZeroSizePolicy::EndOfStream => {
+                    info!(
+                        "Zero-size packet at stream offset {:#x}, taking it as the end of the stream.",
+                        packet_offset
+                    );
+                    stats.total_decompressed_bytes = reader.count;
+                    stats.complete = true;
                     break;
                 }
             }
-            Err(e) => {
-                error!("I/O error reading packet payload: {:?}", e);
-                bail!("Failed to read packet payload");
+        }
+
+        packet_data_with_header.clear();
+        // type byte and timestamp first, to see if the packet is wanted
+        let mut read = (&mut reader)
+            .take(decompressed_payload_size.min(5) as u64)
+            .read_to_end(&mut packet_data_with_header)
+            .map(|_| ());
+        let rest = decompressed_payload_size as u64 - packet_data_with_header.len() as u64;
+        let skip = match packet_data_with_header.first() {
+            Some(&first) => !options.wants(first & !0x10),
+            None => false,
+        };
+        let mut skipped = 0;
+        if read.is_ok() && skip {
+            read = io::copy(&mut (&mut reader).take(rest), &mut io::sink()).map(|n| skipped = n);
+        } else if read.is_ok() {
+            read = (&mut reader)
+                .take(rest)
+                .read_to_end(&mut packet_data_with_header)
+                .map(|_| ());
+        }
+        let got = packet_data_with_header.len() as u64 + skipped;
+        if let Err(e) = read {
+            if options.strict {
+                return Err(e).context(format!(
+                    "Stream stopped {} bytes into the {} byte packet at stream offset {:#x}",
+                    got, decompressed_payload_size, packet_offset
+                ));
             }
+            warn!(
+                "Stream stopped {} bytes into a {} byte packet: {}",
+                got, decompressed_payload_size, e
+            );
+            break;
+        }
+        if got < decompressed_payload_size as u64 {
+            if options.strict {
+                bail!(
+                    "Packet at stream offset {:#x} is cut off after {} of {} bytes",
+                    packet_offset,
+                    got,
+                    decompressed_payload_size
+                );
+            }
+            warn!(
+                "Incomplete packet payload read. Expected {}, stream ended after {}.",
+                decompressed_payload_size, got
+            );
+            break;
         }
 
-        stats.total_decompressed_bytes += total_bytes_read_for_payload as u64;
+        stats.total_decompressed_bytes = reader.count;
 
-        if total_bytes_read_for_payload > 0 {
+        if decompressed_payload_size > 0 {
             let mut payload_cursor = Cursor::new(&packet_data_with_header);
 
             match read_packet_header_from_stream(&mut payload_cursor, last_timestamp_ms) {
@@ -258,62 +360,107 @@ pub fn process_replay_data(
                         header_bytes_read, packet_type_val, timestamp_ms
                     );
 
+                    // the 0x10 bit clear means a 4 byte timestamp follows the type
+                    if options.strict
+                        && packet_data_with_header[0] & 0x10 == 0
+                        && header_bytes_read < 5
+                    {
+                        bail!(
+                            "Packet at stream offset {:#x} is too short for its timestamp",
+                            packet_offset
+                        );
+                    }
+                    if options.strict
+                        && ReplayPacketType::from(packet_type_val) == ReplayPacketType::Unknown
+                    {
+                        bail!(
+                            "Unknown packet type {} at stream offset {:#x}",
+                            packet_type_val,
+                            packet_offset
+                        );
+                    }
+                    last_timestamp_ms = timestamp_ms;
+                    stats
+                        .bytes_by_type
+                        .entry(packet_type_val)
+                        .or_default()
+                        .add(reader.count - packet_offset);
+                    if skip {
+                        stats.packet_count += 1;
+                        continue;
+                    }
                     let header_len = header_bytes_read;
                     let payload_content = &packet_data_with_header[header_len..];
 
-                    stats.packets.push(PacketInfo {
-                        packet_type: match packet_type_val {
-                            0 => ReplayPacketType::EndMarker,
-                            1 => ReplayPacketType::StartMarker,
-                            2 => ReplayPacketType::AircraftSmall,
-                            3 => ReplayPacketType::Chat,
-                            4 => ReplayPacketType::MPI,
-                            5 => ReplayPacketType::NextSegment,
-                            6 => ReplayPacketType::ECS,
-                            7 => ReplayPacketType::Snapshot,
-                            8 => ReplayPacketType::ReplayHeaderInfo,
-                            _ => ReplayPacketType::Unknown,
-                        },
+                    let packet = PacketRef {
+                        packet_type: ReplayPacketType::from(packet_type_val),
+                        raw_type: packet_type_val,
                         timestamp_ms,
-                        payload: payload_content.to_vec(),
-                    });
+                        stream_offset: packet_offset,
+                        payload: payload_content,
+                    };
+                    visitor.packet(packet);
+                    if !options.decoders.is_empty() {
+                        if let Some(decoded) = options.decoders.decode(&packet) {
+                            visitor.decoded(&decoded);
+                        }
+                    }
 
                     if packet_type_val == 3 {
-                        if let Some(chat_info) = parse_chat_packet(payload_content, timestamp_ms) {
-                            stats.chat_messages.push(chat_info);
+                        let chat_start = Instant::now();
+                        let chat = parse_chat_packet(payload_content, timestamp_ms);
+                        chat_time += chat_start.elapsed();
+                        if let Some(chat_info) = chat {
+                            visitor.chat(&chat_info);
                         }
                     }
-                }
-                Ok(None) => {
-                    warn!("Unexpected EOF reading packet header from payload buffer. Skipping payload.");
-                    if total_bytes_read_for_payload < decompressed_payload_size as usize {
-                        info!("Partial payload likely caused header read failure. Stopping.");
+                    if visitor.stop() {
+                        debug!(
+                            "The visitor asked to stop at stream offset {:#x}",
+                            packet_offset
+                        );
                         break;
                     }
                 }
+                Ok(None) => unreachable!("payload is not empty"),
                 Err(e) => {
                     error!("Error reading packet header from payload data: {:?}", e);
-                    if total_bytes_read_for_payload < decompressed_payload_size as usize {
-                        info!("Partial payload likely caused header read failure. Stopping.");
-                        break;
-                    }
-                    bail!("Failed to parse packet header from payload");
+                    return Err(e.context(format!(
+                        "Failed to parse packet header at stream offset {:#x}",
+                        packet_offset
+                    )));
                 }
             }
-        } else if decompressed_payload_size > 0 {
-            info!("No payload data could be read even after partial attempt. Stopping.");
-            break;
         }
 
         stats.packet_count += 1;
     }
 
+    let decompression = reader.inner.get_ref().spent;
+    stats.timings = Timings {
+        decompression,
+        packet_loop: loop_start
+            .elapsed()
+            .saturating_sub(decompression + chat_time),
+        chat: chat_time,
+        ..Default::default()
+    };
+
+    // whatever was read past the last whole packet
+    stats.trailing_bytes = reader.count - stats.total_decompressed_bytes;
+    if !stats.complete {
+        warn!(
+            "Replay is truncated: {} trailing bytes after packet {} couldn't be parsed",
+            stats.trailing_bytes, stats.packet_count
+        );
+    }
+
     info!(
         "Processed {} packets ({} bytes)",
         stats.packet_count, stats.total_decompressed_bytes
     );
 
-    if skip_zlib {
+    if compression == Compression::None {
         stats.final_offset = start_offset + stats.total_decompressed_bytes;
         info!(
             "Final position in input (uncompressed): {:#0x}",
@@ -331,56 +478,616 @@ pub fn process_replay_data(
     Ok(stats)
 }
 
-/// Creates the appropriate reader (direct or zlib) based on the flag.
-fn create_reader<'a>(input_data: &'a [u8], skip_zlib: bool) -> Result<Box<dyn Read + 'a>> {
-    let cursor = Cursor::new(input_data);
-    let reader: Box<dyn Read + 'a> = if skip_zlib {
-        info!("Processing stream directly (zlib decoding skipped).");
-        Box::new(cursor)
-    } else {
-        info!("Processing stream with zlib decoder.");
-        Box::new(ZlibDecoder::new(cursor))
+/// The start of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// How the packet stream is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Not compressed, e.g. a stream dumped by `decom_from_addr`.
+    None,
+    /// zlib, as in replay files.
+    #[default]
+    Zlib,
+    /// DEFLATE without the zlib wrapper.
+    RawDeflate,
+    Gzip,
+    /// Needs the `zstd` feature.
+    Zstd,
+    /// Worked out from the first bytes of the stream, see
+    /// [`Compression::detect`].
+    Auto,
+}
+
+/// How much of a stream [`Compression::detect`] looks at.
+pub const DETECT_LEN: usize = 64 * 1024;
+
+impl Compression {
+    /// Guesses the compression of a stream starting with `bytes` (ideally
+    /// [`DETECT_LEN`] of them): gzip and zstd by their magic, zlib and raw
+    /// deflate if they inflate to something that looks like packets (see
+    /// [`format::is_packet_stream_prefix`]), uncompressed if the bytes
+    /// themselves look like packets. Failing all that, a zlib header still
+    /// means zlib, and anything else is taken to be uncompressed.
+    pub fn detect(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(&[0x1F, 0x8B]) {
+            return Compression::Gzip;
+        }
+        if bytes.starts_with(&ZSTD_MAGIC) {
+            return Compression::Zstd;
+        }
+        let inflates_to_packets = |zlib_header| {
+            inflate_prefix(bytes, zlib_header)
+                .is_some_and(|out| format::is_packet_stream_prefix(&out))
+        };
+        if format::is_zlib_header(bytes) && inflates_to_packets(true) {
+            Compression::Zlib
+        } else if format::is_packet_stream_prefix(bytes) {
+            Compression::None
+        } else if inflates_to_packets(false) {
+            Compression::RawDeflate
+        } else if format::is_zlib_header(bytes) {
+            Compression::Zlib
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Up to [`DETECT_LEN`] bytes inflated from the start of `bytes`, `None` if
+/// it isn't valid deflate.
+fn inflate_prefix(bytes: &[u8], zlib_header: bool) -> Option<Vec<u8>> {
+    let mut out = vec![0; DETECT_LEN];
+    let mut inflater = Decompress::new(zlib_header);
+    inflater
+        .decompress(bytes, &mut out, FlushDecompress::None)
+        .ok()?;
+    out.truncate(inflater.total_out() as usize);
+    Some(out)
+}
+
+/// Works out what [`Compression::Auto`] means for the stream at the
+/// current position of `input`, leaving it there.
+fn resolve_compression<R: Read + Seek>(
+    input: &mut R,
+    compression: Compression,
+) -> Result<Compression> {
+    if compression != Compression::Auto {
+        return Ok(compression);
+    }
+    let start = input.stream_position()?;
+    let mut peeked = Vec::with_capacity(DETECT_LEN);
+    (&mut *input)
+        .take(DETECT_LEN as u64)
+        .read_to_end(&mut peeked)?;
+    input.seek(SeekFrom::Start(start))?;
+    let detected = Compression::detect(&peeked);
+    info!("Detected {:?} compression", detected);
+    Ok(detected)
+}
+
+/// Creates the appropriate reader (direct or decompressing) for the
+/// compression, which can't be `Auto`.
+fn create_reader<'a, R: Read + 'a>(
+    input: R,
+    compression: Compression,
+) -> Result<Box<dyn Read + 'a>> {
+    let mut input = BufReader::new(input);
+    let reader: Box<dyn Read + 'a> = match compression {
+        Compression::None => {
+            info!("Processing stream directly (zlib decoding skipped).");
+            Box::new(input)
+        }
+        Compression::Zlib => {
+            info!("Processing stream with zlib decoder.");
+            Box::new(DeflateStreamReader::new(input, true))
+        }
+        Compression::RawDeflate => {
+            info!("Processing stream with raw deflate decoder.");
+            Box::new(DeflateStreamReader::new(input, false))
+        }
+        Compression::Gzip => {
+            info!("Processing stream with gzip decoder.");
+            skip_gzip_header(&mut input)?;
+            Box::new(DeflateStreamReader::new(input, false))
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            info!("Processing stream with zstd decoder.");
+            Box::new(zstd::stream::read::Decoder::with_buffer(input)?)
+        }
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => bail!("Reading zstd streams needs the zstd feature"),
+        Compression::Auto => bail!("Compression::Auto must be resolved before reading"),
     };
     Ok(reader)
 }
 
+/// Reads past a gzip header (RFC 1952), leaving the deflate data. The
+/// trailer after it is never read, so isn't checked.
+fn skip_gzip_header<R: BufRead>(input: &mut R) -> Result<()> {
+    let mut fixed = [0u8; 10];
+    input
+        .read_exact(&mut fixed)
+        .context("Stream is too short for a gzip header")?;
+    if fixed[..3] != [0x1F, 0x8B, 8] {
+        bail!(
+            "Not a gzip stream (starts with {})",
+            hex::encode(&fixed[..3])
+        );
+    }
+    let flags = fixed[3];
+    if flags & 0x04 != 0 {
+        // FEXTRA
+        let mut len = [0u8; 2];
+        input.read_exact(&mut len)?;
+        io::copy(
+            &mut input.take(u16::from_le_bytes(len) as u64),
+            &mut io::sink(),
+        )?;
+    }
+    for flag in [0x08, 0x10] {
+        // FNAME and FCOMMENT, both zero-terminated
+        if flags & flag != 0 {
+            input.read_until(0, &mut Vec::new())?;
+        }
+    }
+    if flags & 0x02 != 0 {
+        // FHCRC
+        input.read_exact(&mut [0u8; 2])?;
+    }
+    Ok(())
+}
+
+/// A deflate decoder that, unlike flate2's, fails with `UnexpectedEof` when
+/// the input runs out before the end of the stream, so a truncated replay
+/// can be told apart from a complete one.
+struct DeflateStreamReader<R> {
+    inner: R,
+    decompress: Decompress,
+    finished: bool,
+}
+
+impl<R: BufRead> DeflateStreamReader<R> {
+    /// `zlib_header` is false for raw deflate.
+    fn new(inner: R, zlib_header: bool) -> Self {
+        DeflateStreamReader {
+            inner,
+            decompress: Decompress::new(zlib_header),
+            finished: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for DeflateStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let input = self.inner.fill_buf()?;
+            let eof = input.is_empty();
+            let (in_before, out_before) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress(input, buf, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let consumed = (self.decompress.total_in() - in_before) as usize;
+            let produced = (self.decompress.total_out() - out_before) as usize;
+            self.inner.consume(consumed);
+
+            if status == Status::StreamEnd {
+                self.finished = true;
+                return Ok(produced);
+            }
+            if produced > 0 {
+                return Ok(produced);
+            }
+            if eof {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "compressed stream is cut off",
+                ));
+            }
+        }
+    }
+}
+
+/// Counts the bytes consumed from a reader.
+struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> Counted<R> {
+    fn new(inner: R) -> Self {
+        Counted { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Adds up the time spent reading from a (decompressing) reader.
+struct Timed<R> {
+    inner: R,
+    spent: Duration,
+}
+
+impl<R> Timed<R> {
+    fn new(inner: R) -> Self {
+        Timed {
+            inner,
+            spent: Duration::ZERO,
+        }
+    }
+}
+
+impl<R: Read> Read for Timed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let read = self.inner.read(buf);
+        self.spent += start.elapsed();
+        read
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
 /// Processes the replay stream provided as a byte slice.
 pub fn process_replay_stream(
     replay_data: &[u8],
     start_offset: u64,
-    skip_zlib: bool,
+    compression: Compression,
     header: Option<&ReplayHeader>,
+) -> Result<ParsedReplay> {
+    process_replay_stream_with_options(
+        replay_data,
+        start_offset,
+        compression,
+        header,
+        &ParseOptions::default(),
+    )
+}
+
+/// Same as [`process_replay_stream`], with [`ParseOptions`].
+pub fn process_replay_stream_with_options(
+    replay_data: &[u8],
+    start_offset: u64,
+    compression: Compression,
+    header: Option<&ReplayHeader>,
+    options: &ParseOptions,
+) -> Result<ParsedReplay> {
+    with_log(options, || {
+        process_replay_stream_inner(replay_data, start_offset, compression, header, options)
+    })
+}
+
+fn process_replay_stream_inner(
+    replay_data: &[u8],
+    start_offset: u64,
+    compression: Compression,
+    header: Option<&ReplayHeader>,
+    options: &ParseOptions,
 ) -> Result<ParsedReplay> {
     if start_offset > 0 {
         info!(
             "Seeking to stream offset {:#0x} ({}) in input data.",
             start_offset, start_offset
         );
-        if skip_zlib {
+        if compression == Compression::None {
             info!("Will read raw packet data from this offset.");
         }
     } else {
         info!("Starting processing from beginning of input data (offset 0).");
     }
 
-    let mut stats = process_replay_data(replay_data, start_offset, skip_zlib)?;
+    let mut stats =
+        process_replay_data_with_options(replay_data, start_offset, compression, options)?;
 
     if let Some(header) = header {
-        if header.rez_offset > 0 && header.rez_offset < replay_data.len() as u32 {
-            info!(
-                "Attempting to parse end-of-replay results at offset {}",
-                header.rez_offset
+        let results_start = Instant::now();
+        stats.replay_results = results_for(replay_data, header, options);
+        stats.timings.results = results_start.elapsed();
+        stats.resolve_chat_senders();
+    }
+
+    Ok(stats)
+}
+
+/// The results of a whole replay file, at `rez_offset` or, if that's 0 (as
+/// in some server replays), wherever they can be found (see
+/// [`find_replay_results`]).
+pub fn results_for(
+    replay_data: &[u8],
+    header: &ReplayHeader,
+    options: &ParseOptions,
+) -> Option<ReplayResults> {
+    results_in_tail(replay_data, 0, header, options)
+}
+
+/// Same as [`results_for`], given only the end of the file, from
+/// `tail_start` on.
+fn results_in_tail(
+    tail: &[u8],
+    tail_start: usize,
+    header: &ReplayHeader,
+    options: &ParseOptions,
+) -> Option<ReplayResults> {
+    let rez_offset = header.rez_offset as usize;
+    if rez_offset > 0 && (tail_start..tail_start + tail.len()).contains(&rez_offset) {
+        info!(
+            "Attempting to parse end-of-replay results at offset {}",
+            header.rez_offset
+        );
+        let blob = &tail[rez_offset - tail_start..];
+        let results = match &options.results_cache {
+            Some(cache) => cache.parse_results_blob(blob, header),
+            None => parse_replay_results(blob, 0),
+        };
+
+        if results.is_some() {
+            info!("Successfully parsed end-of-replay results");
+        } else {
+            warn!("Failed to parse end-of-replay results (compression not yet implemented)");
+        }
+        results
+    } else if let Some((offset, results)) = find_replay_results(tail) {
+        info!(
+            "No rez_offset in header, but found results at {:#x}",
+            tail_start + offset
+        );
+        Some(results)
+    } else {
+        warn!("No valid rez_offset found in header, skipping result parsing");
+        None
+    }
+}
+
+/// Callbacks for [`parse_with`]. Every method does nothing by default, so
+/// only implement what you need.
+pub trait PacketVisitor {
+    /// The replay header, before any packets.
+    fn header(&mut self, _header: &ReplayHeader) {}
+    /// Every packet, in stream order. The payload is only borrowed, copy
+    /// what you want to keep.
+    fn packet(&mut self, _packet: PacketRef<'_>) {}
+    /// Every chat message, right after its packet.
+    fn chat(&mut self, _chat: &ChatInfo) {}
+    /// Packets decoded by [`ParseOptions::decoders`], right after their
+    /// packet.
+    fn decoded(&mut self, _decoded: &DecodedPacket) {}
+    /// The end-of-replay results, after all packets.
+    fn results(&mut self, _results: &ReplayResults) {}
+    /// Checked after every packet (and its chat and decoded packets), the
+    /// packet loop ends early once it's true.
+    fn stop(&self) -> bool {
+        false
+    }
+}
+
+impl<V: PacketVisitor + ?Sized> PacketVisitor for &mut V {
+    fn header(&mut self, header: &ReplayHeader) {
+        (**self).header(header)
+    }
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        (**self).packet(packet)
+    }
+    fn chat(&mut self, chat: &ChatInfo) {
+        (**self).chat(chat)
+    }
+    fn decoded(&mut self, decoded: &DecodedPacket) {
+        (**self).decoded(decoded)
+    }
+    fn results(&mut self, results: &ReplayResults) {
+        (**self).results(results)
+    }
+    fn stop(&self) -> bool {
+        (**self).stop()
+    }
+}
+
+/// A packet as passed to [`PacketVisitor::packet`]: a [`PacketInfo`] that
+/// borrows its payload.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketRef<'a> {
+    pub packet_type: ReplayPacketType,
+    pub raw_type: u8,
+    /// Raw timestamp, in 1/256 ms ticks (see [`crate::clock`]).
+    pub timestamp_ms: u32,
+    /// Offset of the packet's size prefix in the decompressed stream.
+    pub stream_offset: u64,
+    pub payload: &'a [u8],
+}
+
+impl PacketRef<'_> {
+    pub fn to_owned(&self) -> PacketInfo {
+        PacketInfo {
+            packet_type: self.packet_type,
+            raw_type: self.raw_type,
+            timestamp_ms: self.timestamp_ms,
+            stream_offset: self.stream_offset,
+            size: self.payload.len() as u32,
+            payload: self.payload.to_vec(),
+        }
+    }
+}
+
+/// What [`process_replay_reader`] collects into a [`ParsedReplay`].
+#[derive(Default)]
+struct Collect {
+    discard_payloads: bool,
+    memory_limit: Option<usize>,
+    memory: MemoryUsage,
+    /// Went over the memory limit, so stopped keeping anything.
+    over_limit: bool,
+    packets: Vec<PacketInfo>,
+    chat_messages: Vec<ChatInfo>,
+    decoded: Vec<DecodedPacket>,
+}
+
+impl Collect {
+    /// Checks the memory limit after keeping something, dropping it all
+    /// (and stopping the parse, see [`PacketVisitor::stop`]) if it's gone
+    /// over.
+    fn check_limit(&mut self) {
+        if self
+            .memory_limit
+            .is_some_and(|limit| self.memory.total() > limit)
+        {
+            warn!(
+                "Parse went over its memory limit with {} bytes kept",
+                self.memory.total()
             );
-            stats.replay_results = parse_replay_results(replay_data, header.rez_offset as usize);
+            self.over_limit = true;
+            self.packets = Vec::new();
+            self.chat_messages = Vec::new();
+            self.decoded = Vec::new();
+        }
+    }
+}
 
-            if stats.replay_results.is_some() {
-                info!("Successfully parsed end-of-replay results");
+impl PacketVisitor for Collect {
+    fn packet(&mut self, packet: PacketRef<'_>) {
+        if self.over_limit {
+            return;
+        }
+        let mut owned = PacketRef {
+            payload: if self.discard_payloads {
+                &[]
             } else {
-                warn!("Failed to parse end-of-replay results (compression not yet implemented)");
-            }
-        } else {
-            warn!("No valid rez_offset found in header, skipping result parsing");
+                packet.payload
+            },
+            ..packet
+        }
+        .to_owned();
+        owned.size = packet.payload.len() as u32;
+        self.memory.add_packet(&owned);
+        self.packets.push(owned);
+        self.check_limit();
+    }
+    fn chat(&mut self, chat: &ChatInfo) {
+        if self.over_limit {
+            return;
+        }
+        self.memory.add_chat(chat);
+        self.chat_messages.push(chat.clone());
+        self.check_limit();
+    }
+    fn decoded(&mut self, decoded: &DecodedPacket) {
+        if self.over_limit {
+            return;
         }
+        self.memory.add_decoded(decoded);
+        self.decoded.push(decoded.clone());
+        self.check_limit();
+    }
+    fn stop(&self) -> bool {
+        self.over_limit
+    }
+}
+
+/// Parses a replay file (or a bare packet stream), handing everything to
+/// `visitor` instead of collecting it. Returns the visitor.
+pub fn parse_with<V: PacketVisitor>(data: &[u8], visitor: V) -> Result<V> {
+    parse_with_options(data, visitor, &ParseOptions::default())
+}
+
+/// Same as [`parse_with`], with [`ParseOptions`].
+pub fn parse_with_options<V: PacketVisitor>(
+    data: &[u8],
+    mut visitor: V,
+    options: &ParseOptions,
+) -> Result<V> {
+    let kind = format::detect_format(data);
+    let header = if kind.has_header() {
+        let header = header::parse_header(data)?;
+        visitor.header(&header);
+        Some(header)
+    } else {
+        None
+    };
+    // anything unrecognised is taken to be an already decompressed stream
+    let (start, compression) = match kind.stream_offset() {
+        Some(start) => (start as u64, Compression::Zlib),
+        None if header.is_none() => (0, Compression::None),
+        None => bail!("No packet stream after the replay header"),
+    };
+    visit_packets(Cursor::new(data), start, compression, options, &mut visitor)?;
+    if let Some(results) = header.and_then(|h| results_for(data, &h, options)) {
+        visitor.results(&results);
+    }
+    Ok(visitor)
+}
+
+/// How much of the end of the input [`process_replay_stream_reader`] reads
+/// to look for results when the header has no `rez_offset`.
+const RESULTS_SEARCH_LEN: u64 = 1024 * 1024;
+
+/// Like [`process_replay_stream`], but over a seekable reader. Only the
+/// results blob (or, without `rez_offset`, the end of the input) is read
+/// into memory.
+pub fn process_replay_stream_reader<R: Read + Seek>(
+    input: R,
+    start_offset: u64,
+    compression: Compression,
+    header: Option<&ReplayHeader>,
+) -> Result<ParsedReplay> {
+    process_replay_stream_reader_with_options(
+        input,
+        start_offset,
+        compression,
+        header,
+        &ParseOptions::default(),
+    )
+}
+
+/// Same as [`process_replay_stream_reader`], with [`ParseOptions`].
+pub fn process_replay_stream_reader_with_options<R: Read + Seek>(
+    mut input: R,
+    start_offset: u64,
+    compression: Compression,
+    header: Option<&ReplayHeader>,
+    options: &ParseOptions,
+) -> Result<ParsedReplay> {
+    let mut stats =
+        process_replay_reader_with_options(&mut input, start_offset, compression, options)?;
+
+    if let Some(header) = header {
+        let results_start = Instant::now();
+        let len = input
+            .seek(SeekFrom::End(0))
+            .context("Failed to seek to the end of the input")?;
+        let rez_offset = header.rez_offset as u64;
+        let tail_start = if rez_offset > 0 && rez_offset < len {
+            rez_offset
+        } else {
+            len.saturating_sub(RESULTS_SEARCH_LEN)
+        };
+        input
+            .seek(SeekFrom::Start(tail_start))
+            .context("Failed to seek to the replay results")?;
+        let mut tail = Vec::new();
+        input
+            .read_to_end(&mut tail)
+            .context("Failed to read replay results")?;
+        stats.replay_results = results_in_tail(&tail, tail_start as usize, header, options);
+        stats.timings.results = results_start.elapsed();
+        stats.resolve_chat_senders();
     }
 
     Ok(stats)
@@ -388,25 +1095,45 @@ pub fn process_replay_stream(
 
 /// The result of a parsed replay.
 #[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct ParsedReplay {
     /// Total number of packets processed.
     pub packet_count: u64,
     /// Total bytes read *after* decompression (if any).
     /// If zlib is skipped, this is raw bytes read.
     pub total_decompressed_bytes: u64,
+    /// [`ParsedReplay::total_decompressed_bytes`] by raw packet type,
+    /// including the packets [`ParseOptions::packet_types`] skipped.
+    pub bytes_by_type: BTreeMap<u8, TypeBytes>,
     pub final_offset: u64,
     /// List of packets.
     pub packets: Vec<PacketInfo>,
     /// List of chat messages.
     pub chat_messages: Vec<ChatInfo>,
+    /// What [`ParseOptions::decoders`] decoded, in stream order.
+    pub decoded: Vec<DecodedPacket>,
+    /// Roughly how much memory the packets, chat and decoded packets take.
+    pub memory: MemoryUsage,
     /// End-of-replay results data (if available).
     pub replay_results: Option<ReplayResults>,
+    /// Whether the stream ended cleanly after a whole packet. False for
+    /// replays of crashed clients, which stop mid-packet (or mid-zlib).
+    pub complete: bool,
+    /// Bytes at the end of the stream that didn't make up a whole packet.
+    pub trailing_bytes: u64,
+    /// Info, warnings and errors logged while parsing, with
+    /// [`ParseOptions::capture_log`].
+    pub log: Vec<LogEntry>,
+    /// How long the parse spent decompressing, looping over packets,
+    /// decoding chat and reading the results.
+    pub timings: Timings,
 }
 
 /// Complete replay results containing battle outcome and player statistics.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct ReplayResults {
-    /// Battle result ("won", "lost", "left").
+    /// Battle result for the author, see [`ReplayResults::outcome_label`].
     pub status: String,
     /// Time played in seconds.
     pub time_played: f64,
@@ -418,7 +1145,65 @@ pub struct ReplayResults {
     pub players: Vec<PlayerData>,
 }
 
+impl ParsedReplay {
+    /// Fills in [`ChatInfo::player_ref`] from the results, for senders that
+    /// are in them. Done by the parse functions once results are read.
+    /// Returns how many messages were resolved.
+    pub fn resolve_chat_senders(&mut self) -> usize {
+        let Some(results) = &self.replay_results else {
+            return 0;
+        };
+        let mut resolved = 0;
+        for chat in &mut self.chat_messages {
+            chat.player_ref = results.player_ref(&chat.sender);
+            resolved += chat.player_ref.is_some() as usize;
+        }
+        resolved
+    }
+
+    /// Corrected, monotonic times for every packet, see [`crate::clock`].
+    pub fn packet_times(&self) -> (Vec<PacketTime>, ClockStats) {
+        clock::packet_times(&self.packets)
+    }
+
+    /// Per-type packet counts and sizes, and the time span they cover.
+    pub fn stats(&self) -> PacketStats {
+        stats::packet_stats(&self.packets)
+    }
+
+    /// How long the replay lasts, by the corrected time of its last packet.
+    pub fn duration(&self) -> Duration {
+        let (times, _) = self.packet_times();
+        Duration::from_millis(times.last().map_or(0, |t| t.relative_time_ms))
+    }
+
+    /// Compares the duration with the results (if parsed) and the header's
+    /// time limit, flagging e.g. truncated replays.
+    pub fn check_duration(&self, header: Option<&ReplayHeader>) -> DurationReport {
+        clock::check_duration(&self.packets, self.replay_results.as_ref(), header)
+    }
+}
+
+impl ReplayResults {
+    /// Human-readable outcome of the battle for the replay author.
+    pub fn outcome_label(&self) -> &'static str {
+        outcome_label_for(&self.status)
+    }
+}
+
+/// [`ReplayResults::outcome_label`] for a bare `status` string.
+pub fn outcome_label_for(status: &str) -> &'static str {
+    match status {
+        "fail" => "Victory",
+        "success" => "Defeat",
+        // not sure what this one means
+        "left" => "Draw",
+        _ => "Unknown",
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct PlayerData {
     pub player_info: PlayerInfo,
     pub replay_data: PlayerReplayData,
@@ -426,6 +1211,7 @@ pub struct PlayerData {
 
 /// Player profile information.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct PlayerInfo {
     /// Unique user ID.
     pub user_id: String,
@@ -440,6 +1226,7 @@ pub struct PlayerInfo {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct PlayerReplayData {
     pub user_id: String,
     pub squad: i32,
@@ -463,7 +1250,7 @@ pub struct PlayerReplayData {
     pub lineup: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
 pub enum ReplayPacketType {
     /// End of replay marker.
     EndMarker = 0,
@@ -487,16 +1274,43 @@ pub enum ReplayPacketType {
     Unknown = 255,
 }
 
-#[derive(Debug)]
+impl From<u8> for ReplayPacketType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ReplayPacketType::EndMarker,
+            1 => ReplayPacketType::StartMarker,
+            2 => ReplayPacketType::AircraftSmall,
+            3 => ReplayPacketType::Chat,
+            4 => ReplayPacketType::MPI,
+            5 => ReplayPacketType::NextSegment,
+            6 => ReplayPacketType::ECS,
+            7 => ReplayPacketType::Snapshot,
+            8 => ReplayPacketType::ReplayHeaderInfo,
+            _ => ReplayPacketType::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct PacketInfo {
     pub packet_type: ReplayPacketType,
+    /// The type byte as stored (without the 0x10 flag), so unknown
+    /// types can still be told apart.
+    pub raw_type: u8,
+    /// Raw timestamp, in 1/256 ms ticks (see [`crate::clock`]).
     pub timestamp_ms: u32,
+    /// Offset of the packet's size prefix in the decompressed stream.
+    pub stream_offset: u64,
+    /// Payload size, without the type and timestamp. Still set when the
+    /// payload itself was dropped (see [`ParseOptions::discard_payloads`]).
+    pub size: u32,
     pub payload: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 #[allow(dead_code)]
+#[non_exhaustive]
 pub struct ChatInfo {
     /// Timestamp in milliseconds
     pub timestamp_ms: u32,
@@ -504,10 +1318,88 @@ pub struct ChatInfo {
     pub sender: String,
     /// The message content
     pub message: String,
-    /// The type of channel. Believe it's for all, team, squad etc.
+    /// The type of channel, see [`ChatInfo::channel`].
     pub channel_type: Option<u8>,
     /// Whether the sender is an enemy
     pub is_enemy: Option<u8>,
+    /// The sender in the results, see [`ParsedReplay::resolve_chat_senders`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_ref: Option<PlayerRef>,
+    /// How many more times it was sent right after, if repeats were
+    /// collapsed (see [`ParseOptions::collapse_chat_ms`]).
+    #[serde(skip_serializing_if = "transcript::is_unrepeated")]
+    pub repeats: u32,
+}
+
+/// Which chat a message was sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ChatChannel {
+    /// Seen as `channel_type` 0.
+    Team,
+    /// Seen as `channel_type` 1, e.g. when talking to the enemy team.
+    All,
+    /// Assumed to be `channel_type` 2, not seen in a replay yet.
+    Squad,
+    /// Any other `channel_type`.
+    Other(u8),
+    /// No `channel_type` in the packet.
+    Unknown,
+}
+
+impl ChatInfo {
+    pub fn channel(&self) -> ChatChannel {
+        match self.channel_type {
+            Some(0) => ChatChannel::Team,
+            Some(1) => ChatChannel::All,
+            Some(2) => ChatChannel::Squad,
+            Some(other) => ChatChannel::Other(other),
+            None => ChatChannel::Unknown,
+        }
+    }
+
+    /// The sender's team, if they're in the results.
+    pub fn team(&self) -> Option<i32> {
+        self.player_ref.as_ref().map(|p| p.team)
+    }
+
+    /// The sender's squad ID, if they're in the results.
+    pub fn squad(&self) -> Option<i32> {
+        self.player_ref.as_ref().map(|p| p.squad)
+    }
+}
+
+/// Who sent a chat message, from the results.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlayerRef {
+    pub user_id: String,
+    /// Empty if not in a squadron.
+    pub squadron_tag: String,
+    pub team: i32,
+    /// The squad ID in the results, see [`crate::teams`].
+    pub squad: i32,
+}
+
+impl ReplayResults {
+    /// The player named `name`, as chat senders are. Falls back to ignoring
+    /// case if no name matches exactly.
+    pub fn player_ref(&self, name: &str) -> Option<PlayerRef> {
+        let player = self
+            .players
+            .iter()
+            .find(|p| p.player_info.username == name)
+            .or_else(|| {
+                let name = name.to_lowercase();
+                self.players
+                    .iter()
+                    .find(|p| p.player_info.username.to_lowercase() == name)
+            })?;
+        Some(PlayerRef {
+            user_id: player.player_info.user_id.clone(),
+            squadron_tag: player.player_info.squadron_tag.clone(),
+            team: player.replay_data.team,
+            squad: player.replay_data.squad,
+        })
+    }
 }
 
 /// Parses the payload of a chat packet.
@@ -580,6 +1472,8 @@ pub fn parse_chat_packet(payload: &[u8], timestamp_ms: u32) -> Option<ChatInfo>
             message,
             channel_type,
             is_enemy,
+            player_ref: None,
+            repeats: 0,
         })
     })() {
         Ok(chat_info) => Some(chat_info),
@@ -594,6 +1488,28 @@ pub fn parse_chat_packet(payload: &[u8], timestamp_ms: u32) -> Option<ChatInfo>
     }
 }
 
+/// An MPI packet split into its parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpiFrame<'a> {
+    /// The object (unit, player...) the message is for.
+    pub object_id: u16,
+    pub message_id: u16,
+    /// The message itself, not decoded.
+    pub payload: &'a [u8],
+}
+
+/// Splits the payload of an MPI packet into object ID, message ID and the
+/// message. The IDs look big-endian going by their values (object IDs are
+/// small). `None` if it's too short.
+pub fn parse_mpi_packet(payload: &[u8]) -> Option<MpiFrame<'_>> {
+    let (ids, payload) = payload.split_first_chunk::<4>()?;
+    Some(MpiFrame {
+        object_id: u16::from_be_bytes([ids[0], ids[1]]),
+        message_id: u16::from_be_bytes([ids[2], ids[3]]),
+        payload,
+    })
+}
+
 /// Parses end-of-replay results from offset.
 pub fn parse_replay_results(data: &[u8], rez_offset: usize) -> Option<ReplayResults> {
     if rez_offset >= data.len() {
@@ -630,7 +1546,67 @@ pub fn parse_replay_results(data: &[u8], rez_offset: usize) -> Option<ReplayResu
     }
 }
 
-fn decompress_blk(compressed_data: &[u8]) -> Result<String> {
+/// What the names section of a results BLK starts with.
+const RESULTS_SIGNATURE: &[u8] = b"status\0timePlayed\0";
+
+fn read_uleb128(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Walks back from the first name of a FAT BLK to its start:
+/// the file type byte, then the name count and names size as LEB128.
+fn fat_blk_start(data: &[u8], names_start: usize) -> Option<usize> {
+    (names_start.saturating_sub(11)..names_start.saturating_sub(2))
+        .rev()
+        .find(|&start| {
+            let mut pos = start + 1;
+            data[start] == 0x01
+                && read_uleb128(data, &mut pos).is_some()
+                && read_uleb128(data, &mut pos).is_some()
+                && pos == names_start
+        })
+}
+
+/// Looks for the results BLK in replays whose `rez_offset` is 0, like
+/// server replays (only the last segment of a session has results).
+/// Returns where they start, and the parsed results.
+pub fn find_replay_results(data: &[u8]) -> Option<(usize, ReplayResults)> {
+    // they come last, so search backwards, and don't try forever
+    for names_start in memmem::rfind_iter(data, RESULTS_SIGNATURE).take(4) {
+        let Some(offset) = fat_blk_start(data, names_start) else {
+            continue;
+        };
+        let results =
+            decompress_blk(&data[offset..]).and_then(|json| parse_replay_results_json(&json));
+        match results {
+            Ok(results) => return Some((offset, results)),
+            Err(e) => debug!("No results at {:#x}: {:#}", offset, e),
+        }
+    }
+    None
+}
+
+/// Finds the results of a session split into segments (in order).
+/// Returns the index of the segment they were in, and the results.
+pub fn find_segment_results(segments: &[&[u8]]) -> Option<(usize, ReplayResults)> {
+    segments
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(i, data)| Some((i, find_replay_results(data)?.1)))
+}
+
+/// Decodes a BLK to JSON with `wt_blk`.
+pub(crate) fn decompress_blk(compressed_data: &[u8]) -> Result<String> {
     if compressed_data.is_empty() {
         bail!("No data provided for decompress_blk");
     }
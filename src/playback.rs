@@ -0,0 +1,112 @@
+//! Playing a parsed replay back in real time, for live overlays and
+//! commentary tools.
+//!
+//! Packets (and the chat messages they carry) are handed to a callback at
+//! their corrected times (see [`crate::clock`]), optionally sped up or
+//! slowed down.
+
+use crate::parser::{ChatInfo, PacketInfo, ParsedReplay, ReplayPacketType};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How to play a replay back.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackOptions {
+    /// 2.0 plays twice as fast. Infinity doesn't wait at all.
+    pub speed: f64,
+    /// Corrected time to start at, earlier packets are skipped.
+    pub start_ms: u64,
+    /// Corrected time to stop at, if not the end.
+    pub end_ms: Option<u64>,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        PlaybackOptions {
+            speed: 1.0,
+            start_ms: 0,
+            end_ms: None,
+        }
+    }
+}
+
+/// Something that happened, as passed to the callback of [`play`].
+#[derive(Debug, Clone, Copy)]
+pub enum PlaybackItem<'a> {
+    Packet(&'a PacketInfo),
+    /// Comes right after its chat packet.
+    Chat(&'a ChatInfo),
+}
+
+/// What [`play`] went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlaybackSummary {
+    pub packets: usize,
+    pub chat: usize,
+    /// Whether the callback stopped it early.
+    pub stopped: bool,
+}
+
+/// The chat message of each chat packet, by packet index. Packets whose
+/// chat didn't parse have none.
+fn chat_by_packet(replay: &ParsedReplay) -> HashMap<usize, &ChatInfo> {
+    let mut chat = replay.chat_messages.iter().peekable();
+    let mut by_packet = HashMap::new();
+    for (i, packet) in replay.packets.iter().enumerate() {
+        if packet.packet_type != ReplayPacketType::Chat {
+            continue;
+        }
+        if let Some(message) = chat.next_if(|c| c.timestamp_ms == packet.timestamp_ms) {
+            by_packet.insert(i, message);
+        }
+    }
+    by_packet
+}
+
+/// Plays `replay` back, calling `callback` with each item and its corrected
+/// time as it comes due. Blocks until the end (or until the callback
+/// breaks).
+pub fn play<F>(replay: &ParsedReplay, options: &PlaybackOptions, mut callback: F) -> PlaybackSummary
+where
+    F: FnMut(u64, PlaybackItem<'_>) -> ControlFlow<()>,
+{
+    let mut summary = PlaybackSummary::default();
+    let chat = chat_by_packet(replay);
+    let started = Instant::now();
+
+    let mut cursor = replay.at(options.start_ms);
+    loop {
+        let index = cursor.position();
+        let Some((time, packet)) = cursor.next() else {
+            break;
+        };
+        if options.end_ms.is_some_and(|end| time > end) {
+            break;
+        }
+        // wait against the start, so sleeps that run long don't add up
+        if options.speed.is_finite() && options.speed > 0.0 {
+            let due = Duration::from_secs_f64(
+                time.saturating_sub(options.start_ms) as f64 / 1000.0 / options.speed,
+            );
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        summary.packets += 1;
+        if callback(time, PlaybackItem::Packet(packet)).is_break() {
+            summary.stopped = true;
+            return summary;
+        }
+        if let Some(message) = chat.get(&index) {
+            summary.chat += 1;
+            if callback(time, PlaybackItem::Chat(message)).is_break() {
+                summary.stopped = true;
+                return summary;
+            }
+        }
+    }
+    summary
+}
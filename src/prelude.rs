@@ -0,0 +1,19 @@
+//! The types most uses of this crate need, for a single glob import:
+//!
+//! ```no_run
+//! use wrpl::prelude::*;
+//!
+//! let replay = Replay::open("my_replay.wrpl")?;
+//! for chat in &replay.parsed.chat_messages {
+//!     println!("{}: {}", chat.sender, chat.message);
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! `Result` is anyhow's, which still takes an error type as a second
+//! parameter, so it doesn't get in the way of other results.
+
+pub use crate::header::ReplayHeader;
+pub use crate::parser::{ChatInfo, ParseOptions, ParsedReplay, ReplayResults};
+pub use crate::replay::Replay;
+pub use anyhow::{Error, Result};
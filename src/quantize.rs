@@ -0,0 +1,180 @@
+//! Decoding the quantized positions and rotations used in net packets.
+//!
+//! A quantized float is an unsigned integer of `bits` bits spread evenly over
+//! `[min, max]`, so both ends are exactly representable. Positions pack three
+//! of those into one integer, `x` in the lowest bits. Rotations use the
+//! "smallest three" scheme: the largest component is dropped (and made
+//! positive by negating the quaternion), its index stored in 2 bits.
+//!
+//! **Experimental**, and hidden from the docs for now: none of this has
+//! been checked against positions decoded from a real replay yet. The layout
+//! (axis order, ranges, the rounding) is how these schemes usually work, not
+//! something read out of the game, and may change once it is. The tests
+//! only show that [`quantize`] and [`dequantize`] agree with each other.
+
+use crate::telemetry::{Quat, Vec3};
+use anyhow::{bail, Result};
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Most bits a quantized float can have.
+pub const MAX_FLOAT_BITS: u32 = 32;
+
+/// Most bits per component of a packed quaternion, so that it and its 2-bit
+/// index fit in a u64.
+pub const MAX_QUATERNION_BITS: u32 = 20;
+
+fn check_float_bits(bits: u32) {
+    assert!(
+        (1..=MAX_FLOAT_BITS).contains(&bits),
+        "Quantized floats must use 1-{} bits, got {}",
+        MAX_FLOAT_BITS,
+        bits
+    );
+}
+
+fn check_quaternion_bits(bits: u32) {
+    assert!(
+        (1..=MAX_QUATERNION_BITS).contains(&bits),
+        "Quaternion components must use 1-{} bits, got {}",
+        MAX_QUATERNION_BITS,
+        bits
+    );
+}
+
+/// Maps a `bits`-bit integer onto `[min, max]`.
+///
+/// Panics unless `bits` is 1-[`MAX_FLOAT_BITS`].
+pub fn dequantize(value: u64, bits: u32, min: f32, max: f32) -> f32 {
+    check_float_bits(bits);
+    let steps = ((1u64 << bits) - 1) as f64;
+    let t = (value & ((1u64 << bits) - 1)) as f64 / steps;
+    (min as f64 + t * (max as f64 - min as f64)) as f32
+}
+
+/// Maps `value` in `[min, max]` to the nearest `bits`-bit integer (clamped).
+///
+/// Panics unless `bits` is 1-[`MAX_FLOAT_BITS`].
+pub fn quantize(value: f32, bits: u32, min: f32, max: f32) -> u64 {
+    check_float_bits(bits);
+    let steps = ((1u64 << bits) - 1) as f64;
+    let t = ((value as f64 - min as f64) / (max as f64 - min as f64)).clamp(0.0, 1.0);
+    (t * steps).round() as u64
+}
+
+/// How a position is packed: bits per axis and the box it covers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionQuantization {
+    /// Bits used for x, y and z.
+    pub bits: [u32; 3],
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl PositionQuantization {
+    /// Checks that each axis has 1-[`MAX_FLOAT_BITS`] bits, and that they
+    /// fit in a u64 together.
+    pub fn new(bits: [u32; 3], min: Vec3, max: Vec3) -> Result<Self> {
+        let quantization = PositionQuantization { bits, min, max };
+        quantization.check()?;
+        Ok(quantization)
+    }
+
+    /// What [`PositionQuantization::new`] checks, for ones built by hand.
+    pub fn check(&self) -> Result<()> {
+        if self.bits.iter().any(|&b| b == 0 || b > MAX_FLOAT_BITS) || self.total_bits() > 64 {
+            bail!("Unsupported position quantization {:?}", self.bits);
+        }
+        Ok(())
+    }
+
+    /// Total number of bits of one packed position.
+    pub fn total_bits(&self) -> u32 {
+        self.bits.iter().sum()
+    }
+
+    /// Decodes a packed position (`x` in the lowest bits).
+    ///
+    /// Panics if [`PositionQuantization::check`] fails.
+    pub fn decode(&self, packed: u64) -> Vec3 {
+        if let Err(e) = self.check() {
+            panic!("{}", e);
+        }
+        let [bx, by, bz] = self.bits;
+        Vec3::new(
+            dequantize(packed, bx, self.min.x, self.max.x),
+            dequantize(packed >> bx, by, self.min.y, self.max.y),
+            dequantize(packed >> (bx + by), bz, self.min.z, self.max.z),
+        )
+    }
+
+    /// Packs a position, the inverse of [`decode`](Self::decode).
+    ///
+    /// Panics if [`PositionQuantization::check`] fails.
+    pub fn encode(&self, position: Vec3) -> u64 {
+        if let Err(e) = self.check() {
+            panic!("{}", e);
+        }
+        let [bx, by, bz] = self.bits;
+        quantize(position.x, bx, self.min.x, self.max.x)
+            | quantize(position.y, by, self.min.y, self.max.y) << bx
+            | quantize(position.z, bz, self.min.z, self.max.z) << (bx + by)
+    }
+}
+
+/// Rebuilds a quaternion from the index of its dropped (largest) component
+/// and the other three, in x, y, z, w order.
+pub fn quat_from_smallest_three(largest: usize, rest: [f32; 3]) -> Quat {
+    let largest = largest & 3;
+    let mut components = [0f32; 4];
+    let mut rest = rest.into_iter();
+    let mut sum_sq = 0f32;
+    for (i, component) in components.iter_mut().enumerate() {
+        if i != largest {
+            *component = rest.next().unwrap_or_default();
+            sum_sq += *component * *component;
+        }
+    }
+    components[largest] = (1.0 - sum_sq).max(0.0).sqrt();
+    Quat::from_array(components)
+}
+
+/// Decodes a packed "smallest three" quaternion: the 2-bit index in the top
+/// bits, then the three components with `bits` bits each, first one highest.
+///
+/// Panics unless `bits` is 1-[`MAX_QUATERNION_BITS`].
+pub fn decode_quaternion(packed: u64, bits: u32) -> Quat {
+    check_quaternion_bits(bits);
+    let largest = (packed >> (3 * bits)) as usize & 3;
+    let component = |i: u32| {
+        dequantize(
+            packed >> (bits * (2 - i)),
+            bits,
+            -FRAC_1_SQRT_2,
+            FRAC_1_SQRT_2,
+        )
+    };
+    quat_from_smallest_three(largest, [component(0), component(1), component(2)])
+}
+
+/// Packs a unit quaternion, the inverse of [`decode_quaternion`].
+///
+/// Panics unless `bits` is 1-[`MAX_QUATERNION_BITS`].
+pub fn encode_quaternion(quat: Quat, bits: u32) -> u64 {
+    check_quaternion_bits(bits);
+    let mut components = quat.to_array();
+    let largest = (0..4)
+        .max_by(|&a, &b| components[a].abs().total_cmp(&components[b].abs()))
+        .unwrap_or(3);
+    // q and -q are the same rotation, keep the dropped component positive
+    if components[largest] < 0.0 {
+        components.iter_mut().for_each(|c| *c = -*c);
+    }
+
+    let mut packed = largest as u64;
+    for (i, &component) in components.iter().enumerate() {
+        if i != largest {
+            packed = packed << bits | quantize(component, bits, -FRAC_1_SQRT_2, FRAC_1_SQRT_2);
+        }
+    }
+    packed
+}
@@ -0,0 +1,181 @@
+//! The low-level encoding of the packet stream: size prefixes and packet
+//! headers.
+//!
+//! These follow the format as it's currently understood, so unlike
+//! [`crate::prelude`] they can change whenever something new is found out
+//! about it. Use them for tools that work on the bytes themselves.
+
+use crate::logging::warn;
+use anyhow::{bail, Context, Result};
+use smallvec::{smallvec, SmallVec};
+use std::io::{self, Read};
+
+/// Reads a variable-length size prefix from the stream.
+pub fn read_variable_length_size<R: Read>(stream: &mut R) -> Result<Option<(u32, usize)>> {
+    let mut buf = [0u8; 1];
+
+    // read the first byte
+    match stream.read(&mut buf)? {
+        0 => return Ok(None), // clean EOF
+        1 => {}
+        _ => bail!("Unexpected read count when reading first byte of size prefix"),
+    }
+    let first_byte = buf[0];
+    let mut prefix_bytes_read: usize = 1;
+    let payload_size: i64;
+
+    if (first_byte & 0x80) != 0 {
+        // High bit SET (1xxxxxxx)
+        if (first_byte & 0x40) == 0 {
+            // 10xxxxxx -> 1 byte total
+            payload_size = (first_byte & 0x7F) as i64;
+        } else {
+            // 11xxxxxx -> Invalid case
+            bail!(
+                "Invalid first size prefix byte encountered: {:#02x}",
+                first_byte
+            );
+        }
+    } else {
+        // High bit CLEAR (0xxxxxxx)
+        if (first_byte & 0x40) != 0 {
+            // 01xxxxxx -> 2 bytes total
+            let mut b1_buf = [0u8; 1];
+            stream
+                .read_exact(&mut b1_buf)
+                .context("Failed to read 2nd byte of 2-byte size prefix")?;
+            prefix_bytes_read += 1;
+            payload_size = (((first_byte as i64) << 8) | (b1_buf[0] as i64)) ^ 0x4000;
+        } else if (first_byte & 0x20) != 0 {
+            // 001xxxxx -> 3 bytes total
+            let mut b1_b2_buf = [0u8; 2];
+            stream
+                .read_exact(&mut b1_b2_buf)
+                .context("Failed to read bytes 2-3 of 3-byte size prefix")?;
+            prefix_bytes_read += 2;
+            payload_size = (((first_byte as i64) << 16)
+                | ((b1_b2_buf[0] as i64) << 8)
+                | (b1_b2_buf[1] as i64))
+                ^ 0x200000;
+        } else if (first_byte & 0x10) != 0 {
+            // 0001xxxx -> 4 bytes total
+            let mut b1_b3_buf = [0u8; 3];
+            stream
+                .read_exact(&mut b1_b3_buf)
+                .context("Failed to read bytes 2-4 of 4-byte size prefix")?;
+            prefix_bytes_read += 3;
+            payload_size = (((first_byte as i64) << 24)
+                | ((b1_b3_buf[0] as i64) << 16)
+                | ((b1_b3_buf[1] as i64) << 8)
+                | (b1_b3_buf[2] as i64))
+                ^ 0x10000000;
+        } else {
+            // 0000xxxx -> 5 bytes total
+            let mut b1_b4_buf = [0u8; 4];
+            stream
+                .read_exact(&mut b1_b4_buf)
+                .context("Failed to read bytes 2-5 of 5-byte size prefix")?;
+            prefix_bytes_read += 4;
+            // little Endian u32 - use stdlib method
+            payload_size = u32::from_le_bytes(b1_b4_buf) as i64;
+        }
+    }
+
+    if payload_size < 0 {
+        warn!(
+            "Calculated negative payload size ({}). This bodes ill.",
+            payload_size
+        );
+    }
+
+    let final_size = payload_size.try_into().with_context(|| {
+        format!(
+            "Payload size {} cannot fit into u32 (prefix starts with {:#02x})",
+            payload_size, first_byte
+        )
+    })?;
+
+    Ok(Some((final_size, prefix_bytes_read)))
+}
+
+/// Encodes a payload size as a variable-length size prefix, the counterpart
+/// of [`read_variable_length_size`]. Always picks the shortest form.
+pub fn write_variable_length_size(size: u32) -> SmallVec<[u8; 5]> {
+    if size < 0x40 {
+        // 10xxxxxx
+        smallvec![0x80 | size as u8]
+    } else if size < 0x4000 {
+        // 01xxxxxx xxxxxxxx
+        SmallVec::from_slice(&(size | 0x4000).to_be_bytes()[2..])
+    } else if size < 0x20_0000 {
+        // 001xxxxx + 2 bytes
+        SmallVec::from_slice(&(size | 0x20_0000).to_be_bytes()[1..])
+    } else if size < 0x1000_0000 {
+        // 0001xxxx + 3 bytes
+        SmallVec::from_slice(&(size | 0x1000_0000).to_be_bytes())
+    } else {
+        // 00000000 + little endian u32
+        let mut out = smallvec![0u8];
+        out.extend_from_slice(&size.to_le_bytes());
+        out
+    }
+}
+
+/// Encodes a packet's type byte and timestamp, the counterpart of
+/// [`read_packet_header_from_stream`]. If the timestamp didn't change since
+/// the last packet, it's left out and the type gets the 0x10 flag instead.
+pub fn write_packet_header(
+    packet_type: u8,
+    timestamp_ms: u32,
+    last_timestamp_ms: u32,
+) -> SmallVec<[u8; 5]> {
+    if timestamp_ms == last_timestamp_ms {
+        smallvec![packet_type | 0x10]
+    } else {
+        let mut out = smallvec![packet_type];
+        out.extend_from_slice(&timestamp_ms.to_le_bytes());
+        out
+    }
+}
+
+/// Reads packet type and timestamp from the start of a DECOMPRESSED stream/buffer.
+/// Returns `Ok(Some((packet_type, timestamp_ms, bytes_read)))` or `Ok(None)` on EOF.
+pub fn read_packet_header_from_stream<R: Read>(
+    stream: &mut R,
+    last_timestamp_ms: u32,
+) -> Result<Option<(u8, u32, usize)>> {
+    let mut first_byte_buf = [0u8; 1];
+
+    match stream.read(&mut first_byte_buf)? {
+        0 => return Ok(None), // clean EOF
+        1 => {}
+        _ => bail!("Unexpected read count reading first byte of packet header"),
+    }
+    let first_byte = first_byte_buf[0];
+    let mut bytes_read_for_header = 1;
+    let mut timestamp_ms = last_timestamp_ms;
+    let packet_type_val: u8;
+
+    if (first_byte & 0x10) != 0 {
+        // timestamp didn't change
+        packet_type_val = first_byte ^ 0x10;
+    } else {
+        packet_type_val = first_byte;
+        let mut ts_bytes = [0u8; 4];
+        match stream.read_exact(&mut ts_bytes) {
+            Ok(_) => {
+                timestamp_ms = u32::from_le_bytes(ts_bytes);
+                bytes_read_for_header += 4;
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("Unexpected EOF reading timestamp after type byte {:#02x}. Using last known timestamp.", packet_type_val);
+                return Ok(Some((packet_type_val, timestamp_ms, bytes_read_for_header)));
+            }
+            Err(e) => {
+                return Err(e).context("Failed to read timestamp bytes");
+            }
+        }
+    }
+
+    Ok(Some((packet_type_val, timestamp_ms, bytes_read_for_header)))
+}
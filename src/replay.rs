@@ -0,0 +1,102 @@
+//! A whole replay file, header and packets, parsed in one go.
+//!
+//! [`Replay::open`] (or [`Replay::parse`]) works out what kind of file it is (see
+//! [`format::inspect`]), so callers don't have to find the packet stream
+//! or pick a compression themselves.
+//!
+//! Replays split over several files (server replays, see
+//! [`crate::download`]) are followed from one to the next with
+//! [`Replay::parse_segments`].
+
+use crate::format;
+use crate::header::{self, ReplayHeader};
+use crate::merge;
+use crate::parser::{self, Compression, ParseOptions, ParsedReplay, ReplayPacketType};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// A parsed replay file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Replay {
+    /// `None` for files that are just a packet stream.
+    pub header: Option<ReplayHeader>,
+    pub parsed: ParsedReplay,
+}
+
+impl Replay {
+    /// Reads and parses a replay file, see [`Replay::parse`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Replay> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        Replay::parse(&data).with_context(|| format!("Failed to parse {:?}", path))
+    }
+
+    /// Parses a replay file (client, server segment or bare stream) with
+    /// the default [`ParseOptions`].
+    pub fn parse(data: &[u8]) -> Result<Replay> {
+        Replay::parse_with_options(data, &ParseOptions::default())
+    }
+
+    pub fn parse_with_options(data: &[u8], options: &ParseOptions) -> Result<Replay> {
+        let kind = format::inspect(data);
+        let header = if kind.has_header() {
+            Some(header::parse_header(data)?)
+        } else {
+            None
+        };
+        let offset = kind
+            .stream_offset()
+            .with_context(|| format!("Couldn't find the packet stream ({:?})", kind))?;
+        let parsed = parser::process_replay_stream_with_options(
+            data,
+            offset as u64,
+            Compression::Auto,
+            header.as_ref(),
+            options,
+        )?;
+        Ok(Replay { header, parsed })
+    }
+
+    /// Parses a replay split over several files. Whenever a segment ends
+    /// with a NextSegment packet, `next_segment` is asked for the next one
+    /// by number (1 for the second file); `None` stops there. The segments
+    /// are joined into one replay (see [`merge::join_segments`]) with the
+    /// first one's header. When filtering by
+    /// [`ParseOptions::packet_types`], keep type 5 for this to work.
+    pub fn parse_segments<F>(
+        first: &[u8],
+        options: &ParseOptions,
+        mut next_segment: F,
+    ) -> Result<Replay>
+    where
+        F: FnMut(usize) -> Result<Option<Vec<u8>>>,
+    {
+        let Replay { header, parsed } = Replay::parse_with_options(first, options)?;
+        let mut continues = ends_in_next_segment(&parsed);
+        let mut segments = vec![parsed];
+        while continues {
+            let number = segments.len();
+            let Some(data) = next_segment(number)? else {
+                break;
+            };
+            let segment = Replay::parse_with_options(&data, options)
+                .with_context(|| format!("Failed to parse segment {}", number))?;
+            continues = ends_in_next_segment(&segment.parsed);
+            segments.push(segment.parsed);
+        }
+        Ok(Replay {
+            header,
+            parsed: merge::join_segments(segments),
+        })
+    }
+}
+
+/// Whether the last packet says the replay goes on in another file.
+fn ends_in_next_segment(parsed: &ParsedReplay) -> bool {
+    parsed
+        .packets
+        .last()
+        .is_some_and(|p| p.packet_type == ReplayPacketType::NextSegment)
+}
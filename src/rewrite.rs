@@ -0,0 +1,113 @@
+//! Helpers for tools that rewrite a client replay's packet stream.
+//!
+//! A client `.wrpl` is laid out as:
+//! `[header + mission settings][zlib packet stream][tail (results)]`.
+//! The header's `rez_offset` points into the tail, so it has to be shifted
+//! whenever the compressed stream changes size.
+
+use crate::header::{self, HEADER_SIZE, REZ_OFFSET_POS};
+use crate::utils;
+use anyhow::{bail, Context, Result};
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use log::{debug, warn};
+use std::io::{Read, Write};
+
+/// Where the sections of a client replay are.
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayLayout {
+    /// Offset of the zlib stream.
+    pub stream_start: usize,
+    /// Offset right after the end of the zlib stream.
+    pub stream_end: usize,
+    /// Offset of the results blob, 0 if there are none.
+    pub rez_offset: usize,
+}
+
+/// A replay split into its sections, with the packet stream decompressed.
+#[derive(Debug, Clone)]
+pub struct SplitReplay<'a> {
+    pub layout: ReplayLayout,
+    /// Everything before the packet stream (header, mission settings).
+    pub prefix: &'a [u8],
+    /// The decompressed packet stream.
+    pub stream: Vec<u8>,
+    /// Everything after the packet stream (results).
+    pub tail: &'a [u8],
+}
+
+/// Finds the packet stream of a client replay and decompresses it.
+pub fn split_replay(data: &[u8]) -> Result<SplitReplay<'_>> {
+    let header = header::parse_header(data).context("Failed to parse replay header")?;
+
+    let stream_start = utils::find_zlib_header(data, HEADER_SIZE)
+        .context("Couldn't find the zlib stream, is this a client replay?")?;
+
+    let mut decoder = ZlibDecoder::new(&data[stream_start..]);
+    let mut stream = Vec::new();
+    decoder
+        .read_to_end(&mut stream)
+        .context("Failed to decompress packet stream")?;
+    let stream_end = stream_start + decoder.total_in() as usize;
+    debug!(
+        "Packet stream spans {:#x}..{:#x} ({} bytes decompressed)",
+        stream_start,
+        stream_end,
+        stream.len()
+    );
+
+    let rez_offset = header.rez_offset as usize;
+    if rez_offset > 0 && rez_offset < stream_end {
+        bail!(
+            "rez_offset {:#x} points inside the packet stream (ends at {:#x})",
+            rez_offset,
+            stream_end
+        );
+    }
+
+    Ok(SplitReplay {
+        layout: ReplayLayout {
+            stream_start,
+            stream_end,
+            rez_offset,
+        },
+        prefix: &data[..stream_start],
+        stream,
+        tail: &data[stream_end..],
+    })
+}
+
+/// Compresses `stream` with the given zlib level.
+pub fn compress_stream(stream: &[u8], level: Compression) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+    encoder.write_all(stream)?;
+    Ok(encoder.finish()?)
+}
+
+/// Reassembles a replay from its prefix, a new (raw) stream section and tail,
+/// fixing up the header's `rez_offset`.
+pub fn assemble(
+    prefix: &[u8],
+    stream_section: &[u8],
+    tail: &[u8],
+    layout: &ReplayLayout,
+) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(prefix.len() + stream_section.len() + tail.len());
+    out.extend_from_slice(prefix);
+    out.extend_from_slice(stream_section);
+    out.extend_from_slice(tail);
+
+    if layout.rez_offset > 0 {
+        let new_rez_offset =
+            layout.rez_offset - layout.stream_end + prefix.len() + stream_section.len();
+        let new_rez_offset: u32 = new_rez_offset
+            .try_into()
+            .context("Rewritten replay is too large for a u32 rez_offset")?;
+        out[REZ_OFFSET_POS..REZ_OFFSET_POS + 4].copy_from_slice(&new_rez_offset.to_le_bytes());
+    } else {
+        warn!("Replay has no rez_offset, results (if any) are copied as-is");
+    }
+
+    Ok(out)
+}
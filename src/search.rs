@@ -0,0 +1,135 @@
+//! Searching chat across many replays, e.g. for moderators collecting
+//! evidence of chat abuse.
+//!
+//! Only chat packets are decoded (see [`ParseOptions::packet_types`]), so a
+//! folder of replays goes by quickly.
+
+use crate::header::{ReplayHeader, SessionId};
+use crate::locate;
+use crate::parser::{self, ChatInfo, PacketVisitor, ParseOptions, ReplayPacketType};
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How to match chat messages.
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+    /// Match regardless of case.
+    pub ignore_case: bool,
+    /// Also match the pattern against the sender's name.
+    pub match_sender: bool,
+}
+
+/// A chat message that matched, and where it came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMatch {
+    pub path: PathBuf,
+    /// From the header, if the replay has one.
+    pub session_id: Option<SessionId>,
+    pub chat: ChatInfo,
+}
+
+impl ChatMatch {
+    /// Milliseconds since the start of the replay.
+    pub fn time_ms(&self) -> u32 {
+        crate::clock::ticks_to_ms(self.chat.timestamp_ms)
+    }
+}
+
+struct Matcher {
+    pattern: String,
+    options: GrepOptions,
+}
+
+impl Matcher {
+    fn new(pattern: &str, options: &GrepOptions) -> Self {
+        let pattern = if options.ignore_case {
+            pattern.to_lowercase()
+        } else {
+            pattern.to_string()
+        };
+        Matcher {
+            pattern,
+            options: options.clone(),
+        }
+    }
+
+    fn is_match(&self, chat: &ChatInfo) -> bool {
+        let contains = |text: &str| {
+            if self.options.ignore_case {
+                text.to_lowercase().contains(&self.pattern)
+            } else {
+                text.contains(&self.pattern)
+            }
+        };
+        contains(&chat.message) || (self.options.match_sender && contains(&chat.sender))
+    }
+}
+
+struct Grep {
+    matcher: Matcher,
+    session_id: Option<SessionId>,
+    matches: Vec<ChatInfo>,
+}
+
+impl PacketVisitor for Grep {
+    fn header(&mut self, header: &ReplayHeader) {
+        self.session_id = Some(header.session_id);
+    }
+
+    fn chat(&mut self, chat: &ChatInfo) {
+        if self.matcher.is_match(chat) {
+            self.matches.push(chat.clone());
+        }
+    }
+}
+
+fn grep_data(data: &[u8], matcher: Matcher) -> Result<(Option<SessionId>, Vec<ChatInfo>)> {
+    let options = ParseOptions {
+        packet_types: vec![ReplayPacketType::Chat as u8],
+        ..Default::default()
+    };
+    let grep = Grep {
+        matcher,
+        session_id: None,
+        matches: Vec::new(),
+    };
+    let grep = parser::parse_with_options(data, grep, &options)?;
+    Ok((grep.session_id, grep.matches))
+}
+
+/// Chat messages of one replay (any format [`parser::parse_with`] takes)
+/// that contain `pattern`.
+pub fn grep_chat(data: &[u8], pattern: &str, options: &GrepOptions) -> Result<Vec<ChatInfo>> {
+    Ok(grep_data(data, Matcher::new(pattern, options))?.1)
+}
+
+/// Chat messages containing `pattern` in every replay in `dir` (not
+/// recursively), by file name and then time. Unreadable replays are
+/// skipped with a warning.
+pub fn grep_dir(dir: &Path, pattern: &str, options: &GrepOptions) -> Result<Vec<ChatMatch>> {
+    let mut summaries = locate::scan_replays(dir)?;
+    summaries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut matches = Vec::new();
+    for summary in summaries {
+        let found = fs::read(&summary.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| grep_data(&data, Matcher::new(pattern, options)));
+        let (session_id, chats) = match found {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Couldn't search {:?}: {:#}", summary.path, e);
+                continue;
+            }
+        };
+        matches.extend(chats.into_iter().map(|chat| ChatMatch {
+            path: summary.path.clone(),
+            session_id,
+            chat,
+        }));
+    }
+    Ok(matches)
+}
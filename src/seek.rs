@@ -0,0 +1,110 @@
+//! Jumping to a point in time in a parsed replay, e.g. for scrubbing
+//! through a battle in a viewer.
+//!
+//! Times are the corrected ones from [`crate::clock`], which never go
+//! backwards, so finding a time is a binary search.
+
+use crate::clock;
+use crate::parser::{PacketInfo, ParsedReplay};
+
+/// The corrected time of every packet, for finding packets by time.
+#[derive(Debug, Clone, Default)]
+pub struct TimeIndex {
+    times: Vec<u64>,
+}
+
+impl TimeIndex {
+    pub fn new(packets: &[PacketInfo]) -> Self {
+        let (times, _) = clock::packet_times(packets);
+        TimeIndex {
+            times: times.iter().map(|t| t.relative_time_ms).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Index of the first packet at or after `time_ms`, or the number of
+    /// packets if there's none.
+    pub fn index_at(&self, time_ms: u64) -> usize {
+        self.times.partition_point(|&t| t < time_ms)
+    }
+
+    /// Corrected time of packet `index`.
+    pub fn time_of(&self, index: usize) -> Option<u64> {
+        self.times.get(index).copied()
+    }
+}
+
+/// A position in a replay's packets that can jump to a time and iterate
+/// forward from there, yielding packets with their corrected times.
+#[derive(Debug, Clone)]
+pub struct ReplayCursor<'a> {
+    packets: &'a [PacketInfo],
+    index: TimeIndex,
+    position: usize,
+}
+
+impl<'a> ReplayCursor<'a> {
+    /// A cursor at the start of `replay`.
+    pub fn new(replay: &'a ParsedReplay) -> Self {
+        ReplayCursor {
+            packets: &replay.packets,
+            index: TimeIndex::new(&replay.packets),
+            position: 0,
+        }
+    }
+
+    /// Moves to the first packet at or after `time_ms` (which can be
+    /// before the current position).
+    pub fn seek(&mut self, time_ms: u64) {
+        self.position = self.index.index_at(time_ms);
+    }
+
+    /// Index of the next packet.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Corrected time of the next packet, `None` at the end.
+    pub fn time_ms(&self) -> Option<u64> {
+        self.index.time_of(self.position)
+    }
+
+    /// The next packet, without moving.
+    pub fn peek(&self) -> Option<&'a PacketInfo> {
+        self.packets.get(self.position)
+    }
+
+    pub fn time_index(&self) -> &TimeIndex {
+        &self.index
+    }
+}
+
+impl<'a> Iterator for ReplayCursor<'a> {
+    /// Corrected time and packet.
+    type Item = (u64, &'a PacketInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let packet = self.packets.get(self.position)?;
+        let time = self.index.time_of(self.position)?;
+        self.position += 1;
+        Some((time, packet))
+    }
+}
+
+impl ParsedReplay {
+    /// A cursor at the first packet at or after `time_ms` (corrected time).
+    /// Each call builds the time index, so keep the cursor and
+    /// [`ReplayCursor::seek`] it to scrub around.
+    pub fn at(&self, time_ms: u64) -> ReplayCursor<'_> {
+        let mut cursor = ReplayCursor::new(self);
+        cursor.seek(time_ms);
+        cursor
+    }
+}
@@ -0,0 +1,133 @@
+//! Summary statistics over a replay's packets.
+
+use crate::clock;
+use crate::parser::{PacketInfo, ReplayPacketType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Counts and payload sizes of a set of packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct SizeStats {
+    pub count: u64,
+    /// Sum of payload sizes (packet headers not included).
+    pub total_bytes: u64,
+    pub min_size: usize,
+    pub max_size: usize,
+}
+
+impl SizeStats {
+    fn add(&mut self, size: usize) {
+        if self.count == 0 {
+            self.min_size = size;
+            self.max_size = size;
+        } else {
+            self.min_size = self.min_size.min(size);
+            self.max_size = self.max_size.max(size);
+        }
+        self.count += 1;
+        self.total_bytes += size as u64;
+    }
+
+    /// Mean payload size, 0 if there are no packets.
+    pub fn mean_size(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_bytes as f64 / self.count as f64
+        }
+    }
+}
+
+/// How much of the decompressed stream packets of a type take up, see
+/// [`ParsedReplay::bytes_by_type`](crate::parser::ParsedReplay::bytes_by_type).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct TypeBytes {
+    pub packets: u64,
+    /// Whole packets: size prefix, type, timestamp and payload.
+    pub bytes: u64,
+}
+
+impl TypeBytes {
+    pub(crate) fn add(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+
+    /// The fraction of `total` bytes these are, e.g. of
+    /// [`ParsedReplay::total_decompressed_bytes`](crate::parser::ParsedReplay::total_decompressed_bytes).
+    pub fn share(&self, total: u64) -> f64 {
+        if total == 0 {
+            0.0
+        } else {
+            self.bytes as f64 / total as f64
+        }
+    }
+}
+
+/// Wall-clock time spent in each part of a parse, see
+/// [`ParsedReplay::timings`](crate::parser::ParsedReplay::timings). The
+/// parts don't overlap, so they add up to (about) the whole parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Timings {
+    /// Reading and decompressing the packet stream.
+    pub decompression: Duration,
+    /// Everything else done per packet: headers, filtering, decoders and
+    /// whatever the visitor does with them.
+    pub packet_loop: Duration,
+    /// Decoding chat packets.
+    pub chat: Duration,
+    /// Reading the end-of-replay results.
+    pub results: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.decompression + self.packet_loop + self.chat + self.results
+    }
+
+    /// Adds up the timings of two parses, e.g. of segments that are joined.
+    pub(crate) fn add(&mut self, other: &Timings) {
+        self.decompression += other.decompression;
+        self.packet_loop += other.packet_loop;
+        self.chat += other.chat;
+        self.results += other.results;
+    }
+}
+
+/// Statistics over all packets of a replay, see [`crate::parser::ParsedReplay::stats`].
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct PacketStats {
+    /// All packets together.
+    pub total: SizeStats,
+    pub by_type: BTreeMap<ReplayPacketType, SizeStats>,
+    /// Corrected time of the first packet, in ms.
+    pub first_time_ms: u64,
+    /// Corrected time of the last packet, in ms.
+    pub last_time_ms: u64,
+}
+
+impl PacketStats {
+    /// Time between the first and last packet, in ms.
+    pub fn span_ms(&self) -> u64 {
+        self.last_time_ms - self.first_time_ms
+    }
+}
+
+/// Computes [`PacketStats`] over `packets`.
+pub fn packet_stats(packets: &[PacketInfo]) -> PacketStats {
+    let mut stats = PacketStats::default();
+    for packet in packets {
+        stats.total.add(packet.size as usize);
+        stats
+            .by_type
+            .entry(packet.packet_type)
+            .or_default()
+            .add(packet.size as usize);
+    }
+
+    let (times, _) = clock::packet_times(packets);
+    stats.first_time_ms = times.first().map_or(0, |t| t.relative_time_ms);
+    stats.last_time_ms = times.last().map_or(0, |t| t.relative_time_ms);
+    stats
+}
@@ -0,0 +1,458 @@
+//! Following a replay while the game is still writing it, e.g. for an
+//! overlay that shows the battle as it happens.
+//!
+//! The client writes the header first, then appends to the zlib stream as
+//! the battle goes on. A [`Tailer`] reads whatever has been added since it
+//! last looked, decompresses it and hands every whole packet to a
+//! [`PacketVisitor`]. A packet that's only partly written is kept until the
+//! rest of it turns up, so nothing is skipped or seen twice.
+//!
+//! ```no_run
+//! use wrpl::parser::{ChatInfo, PacketVisitor};
+//! use wrpl::tail::{TailOptions, Tailer};
+//!
+//! struct PrintChat;
+//! impl PacketVisitor for PrintChat {
+//!     fn chat(&mut self, chat: &ChatInfo) {
+//!         println!("{}: {}", chat.sender, chat.message);
+//!     }
+//! }
+//!
+//! Tailer::new("my_replay.wrpl", TailOptions::default()).follow(PrintChat)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Where a tailer has got to can be saved as a [`ParseCheckpoint`] and
+//! picked up again later, even by another process, with
+//! [`Tailer::resume`].
+
+use crate::format::{self, ReplayKind};
+use crate::header::{self, ReplayHeader};
+use crate::logging::{info, warn};
+use crate::parser::{self, PacketRef, PacketVisitor, ParseOptions, ReplayPacketType};
+use crate::raw::{read_packet_header_from_stream, read_variable_length_size};
+use anyhow::{bail, Context, Result};
+use flate2::{Decompress, FlushDecompress, Status};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How [`Tailer::follow`] waits for more of the replay.
+#[derive(Debug, Clone)]
+pub struct TailOptions {
+    /// How often to look at the file when nothing new was found.
+    pub poll_interval: Duration,
+    /// Give up after the file hasn't grown for this long, e.g. because the
+    /// game crashed. `None` waits for the end of the battle however long it
+    /// takes.
+    pub idle_timeout: Option<Duration>,
+    /// [`ParseOptions::packet_types`], [`ParseOptions::decoders`] and
+    /// [`ParseOptions::max_packet_size`](field@ParseOptions::max_packet_size)
+    /// are used, the rest is ignored.
+    pub parse: ParseOptions,
+}
+
+impl Default for TailOptions {
+    fn default() -> Self {
+        TailOptions {
+            poll_interval: Duration::from_millis(500),
+            idle_timeout: Some(Duration::from_secs(60)),
+            parse: ParseOptions::default(),
+        }
+    }
+}
+
+/// Where the packet stream is, once the file has enough of it to tell.
+#[derive(Debug)]
+struct Stream {
+    /// File offset the stream starts at.
+    start: u64,
+    /// `None` for an uncompressed stream.
+    decompress: Option<Decompress>,
+    /// File offset up to which the stream has been read.
+    read_to: u64,
+}
+
+/// How far a [`Tailer`] has got, to carry on from later with
+/// [`Tailer::resume`]. Serializable, so it can be stored or sent to another
+/// process.
+///
+/// The decompressor's state can't be saved, so resuming a compressed stream
+/// decompresses it again up to the checkpoint (but doesn't parse it).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseCheckpoint {
+    /// File offset of the packet stream.
+    pub stream_start: u64,
+    pub compressed: bool,
+    /// Offset of the next packet in the decompressed stream.
+    pub stream_offset: u64,
+    /// Timestamp of the last packet, which the next one's may be relative
+    /// to.
+    pub last_timestamp_ms: u32,
+    pub packet_count: u64,
+    pub finished: bool,
+}
+
+/// Parses a replay file as it grows, see the [module docs](self).
+#[derive(Debug)]
+pub struct Tailer {
+    path: PathBuf,
+    options: TailOptions,
+    header: Option<ReplayHeader>,
+    stream: Option<Stream>,
+    /// Decompressed bytes that aren't a whole packet yet.
+    pending: Vec<u8>,
+    /// Offset of `pending` in the decompressed stream.
+    stream_offset: u64,
+    last_timestamp_ms: u32,
+    packet_count: u64,
+    finished: bool,
+}
+
+impl Tailer {
+    pub fn new(path: impl AsRef<Path>, options: TailOptions) -> Self {
+        Tailer {
+            path: path.as_ref().to_path_buf(),
+            options,
+            header: None,
+            stream: None,
+            pending: Vec::new(),
+            stream_offset: 0,
+            last_timestamp_ms: 0,
+            packet_count: 0,
+            finished: false,
+        }
+    }
+
+    /// The header, once the file has one. Fields the game fills in at the
+    /// end (like `rez_offset`) are as they were when it was first read.
+    pub fn header(&self) -> Option<&ReplayHeader> {
+        self.header.as_ref()
+    }
+
+    /// Packets handed to the visitor so far (including skipped ones).
+    pub fn packet_count(&self) -> u64 {
+        self.packet_count
+    }
+
+    /// Whether the battle is over: the end marker packet or the end of the
+    /// zlib stream has been read. Nothing more will be found after that.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Where it's got to, or `None` if the packet stream hasn't been found
+    /// yet (in which case there's nothing to resume, start a new tailer).
+    /// Packets that were only partly written are left out, they're read
+    /// again on resuming.
+    pub fn checkpoint(&self) -> Option<ParseCheckpoint> {
+        let stream = self.stream.as_ref()?;
+        Some(ParseCheckpoint {
+            stream_start: stream.start,
+            compressed: stream.decompress.is_some(),
+            stream_offset: self.stream_offset,
+            last_timestamp_ms: self.last_timestamp_ms,
+            packet_count: self.packet_count,
+            finished: self.finished,
+        })
+    }
+
+    /// A tailer that carries on from `checkpoint`: the next poll hands over
+    /// the packet after the last one handed over before. The header is read
+    /// again but not handed to the visitor.
+    pub fn resume(
+        path: impl AsRef<Path>,
+        options: TailOptions,
+        checkpoint: &ParseCheckpoint,
+    ) -> Result<Tailer> {
+        let mut tailer = Tailer::new(path, options);
+        let data = std::fs::read(&tailer.path)
+            .with_context(|| format!("Failed to read {:?}", tailer.path))?;
+        if format::inspect(&data).has_header() {
+            tailer.header = Some(header::parse_header(&data)?);
+        }
+        let stream_data = data
+            .get(checkpoint.stream_start as usize..)
+            .context("Checkpoint's stream starts past the end of the file")?;
+        let stream = if checkpoint.compressed {
+            let mut decompress = Decompress::new(true);
+            let consumed = skip_inflated(&mut decompress, stream_data, checkpoint.stream_offset)?;
+            Stream {
+                start: checkpoint.stream_start,
+                decompress: Some(decompress),
+                read_to: checkpoint.stream_start + consumed as u64,
+            }
+        } else {
+            if checkpoint.stream_offset > stream_data.len() as u64 {
+                bail!("Checkpoint is past the end of the file");
+            }
+            Stream {
+                start: checkpoint.stream_start,
+                decompress: None,
+                read_to: checkpoint.stream_start + checkpoint.stream_offset,
+            }
+        };
+        info!(
+            "Resuming at stream offset {:#x} after {} packets",
+            checkpoint.stream_offset, checkpoint.packet_count
+        );
+        tailer.stream = Some(stream);
+        tailer.stream_offset = checkpoint.stream_offset;
+        tailer.last_timestamp_ms = checkpoint.last_timestamp_ms;
+        tailer.packet_count = checkpoint.packet_count;
+        tailer.finished = checkpoint.finished;
+        Ok(tailer)
+    }
+
+    /// Reads whatever was added to the file since the last poll and hands
+    /// the whole packets in it to `visitor` (and the header, the first time
+    /// there is one). Returns how many packets there were.
+    pub fn poll<V: PacketVisitor + ?Sized>(&mut self, visitor: &mut V) -> Result<u64> {
+        if self.finished {
+            return Ok(0);
+        }
+        let mut file =
+            File::open(&self.path).with_context(|| format!("Failed to open {:?}", self.path))?;
+        if self.stream.is_none() && !self.find_stream(&mut file, visitor)? {
+            return Ok(0);
+        }
+        let stream = self.stream.as_mut().expect("stream was found");
+
+        file.seek(SeekFrom::Start(stream.read_to))?;
+        let mut added = Vec::new();
+        file.read_to_end(&mut added)
+            .with_context(|| format!("Failed to read {:?}", self.path))?;
+        stream.read_to += added.len() as u64;
+        let stream_ended = match &mut stream.decompress {
+            Some(decompress) => inflate(decompress, &added, &mut self.pending)?,
+            None => {
+                self.pending.extend_from_slice(&added);
+                false
+            }
+        };
+        let packets = self.parse_pending(visitor)?;
+        self.finished |= stream_ended;
+        Ok(packets)
+    }
+
+    /// Looks for the packet stream in the file, which may not even have a
+    /// whole header yet. Returns whether it was found.
+    fn find_stream<V: PacketVisitor + ?Sized>(
+        &mut self,
+        file: &mut File,
+        visitor: &mut V,
+    ) -> Result<bool> {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {:?}", self.path))?;
+        let kind = format::inspect(&data);
+        let (offset, compressed) = match kind {
+            ReplayKind::Client { stream_offset }
+            | ReplayKind::ServerSegment { stream_offset, .. } => (stream_offset, true),
+            ReplayKind::ZlibStream => (0, true),
+            ReplayKind::RawStream => (0, false),
+            // not enough written to tell yet
+            ReplayKind::HeaderOnly | ReplayKind::Unknown => return Ok(false),
+        };
+        if kind.has_header() {
+            let header = header::parse_header(&data)?;
+            visitor.header(&header);
+            self.header = Some(header);
+        }
+        info!("Found the packet stream at {:#x} ({:?})", offset, kind);
+        self.stream = Some(Stream {
+            start: offset as u64,
+            decompress: compressed.then(|| Decompress::new(true)),
+            read_to: offset as u64,
+        });
+        Ok(true)
+    }
+
+    /// Hands the whole packets in `pending` to the visitor, keeping the
+    /// rest for next time.
+    fn parse_pending<V: PacketVisitor + ?Sized>(&mut self, visitor: &mut V) -> Result<u64> {
+        let options = &self.options.parse;
+        let mut pos = 0;
+        let mut packets = 0;
+        while !self.finished {
+            let packet_offset = self.stream_offset + pos as u64;
+            let mut cursor = Cursor::new(&self.pending[pos..]);
+            let size = match read_variable_length_size(&mut cursor) {
+                Ok(Some((size, _))) => size,
+                Ok(None) => break,
+                // the rest of the prefix isn't written yet
+                Err(e) if e.downcast_ref::<io::Error>().is_some() => break,
+                Err(e) => {
+                    return Err(e.context(format!(
+                        "Bad packet size prefix at stream offset {:#x}",
+                        packet_offset
+                    )))
+                }
+            };
+            if size > options.max_packet_size() {
+                bail!(
+                    "Packet at stream offset {:#x} claims {} bytes, the stream is probably corrupt",
+                    packet_offset,
+                    size
+                );
+            }
+            let start = pos + cursor.position() as usize;
+            let end = start + size as usize;
+            if end > self.pending.len() {
+                break;
+            }
+            pos = end;
+            packets += 1;
+            self.packet_count += 1;
+            if size == 0 {
+                warn!("Zero-size packet at stream offset {:#x}", packet_offset);
+                continue;
+            }
+
+            let data = &self.pending[start..end];
+            let (raw_type, timestamp_ms, header_len) =
+                read_packet_header_from_stream(&mut Cursor::new(data), self.last_timestamp_ms)?
+                    .expect("packet is not empty");
+            self.last_timestamp_ms = timestamp_ms;
+            if !options.wants(raw_type) {
+                continue;
+            }
+            let packet = PacketRef {
+                packet_type: ReplayPacketType::from(raw_type),
+                raw_type,
+                timestamp_ms,
+                stream_offset: packet_offset,
+                payload: &data[header_len..],
+            };
+            visitor.packet(packet);
+            if !options.decoders.is_empty() {
+                if let Some(decoded) = options.decoders.decode(&packet) {
+                    visitor.decoded(&decoded);
+                }
+            }
+            if raw_type == 3 {
+                if let Some(chat) = parser::parse_chat_packet(packet.payload, timestamp_ms) {
+                    visitor.chat(&chat);
+                }
+            }
+            if packet.packet_type == ReplayPacketType::EndMarker {
+                self.finished = true;
+            }
+        }
+        self.pending.drain(..pos);
+        self.stream_offset += pos as u64;
+        Ok(packets)
+    }
+
+    /// Reads the end-of-replay results, if the game has written them. Worth
+    /// trying once [`Tailer::finished`].
+    pub fn results<V: PacketVisitor + ?Sized>(&self, visitor: &mut V) -> Result<bool> {
+        let data =
+            std::fs::read(&self.path).with_context(|| format!("Failed to read {:?}", self.path))?;
+        if !format::inspect(&data).has_header() {
+            return Ok(false);
+        }
+        // read again for the rez_offset written at the end
+        let header = header::parse_header(&data)?;
+        match parser::results_for(&data, &header, &self.options.parse) {
+            Some(results) => {
+                visitor.results(&results);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Polls until the battle is over, waiting [`TailOptions::poll_interval`]
+    /// whenever nothing new was found, or until the file stops growing for
+    /// [`TailOptions::idle_timeout`]. Then hands over the results if the game
+    /// wrote them. Returns the visitor.
+    pub fn follow<V: PacketVisitor>(&mut self, visitor: V) -> Result<V> {
+        let poll_interval = self.options.poll_interval;
+        let idle_timeout = self.options.idle_timeout;
+        let mut last_growth = Instant::now();
+        self.follow_with(visitor, |found| {
+            if found > 0 {
+                last_growth = Instant::now();
+            } else if idle_timeout.is_some_and(|timeout| last_growth.elapsed() >= timeout) {
+                warn!("Replay hasn't grown for {:?}, giving up", idle_timeout);
+                return ControlFlow::Break(());
+            }
+            thread::sleep(poll_interval);
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// [`Tailer::follow`], with `wait` deciding how to wait between polls
+    /// (e.g. for an inotify event) and when to give up. It's given how many
+    /// packets the last poll found.
+    pub fn follow_with<V, W>(&mut self, mut visitor: V, mut wait: W) -> Result<V>
+    where
+        V: PacketVisitor,
+        W: FnMut(u64) -> ControlFlow<()>,
+    {
+        loop {
+            let found = self.poll(&mut visitor)?;
+            if self.finished {
+                break;
+            }
+            if wait(found).is_break() {
+                return Ok(visitor);
+            }
+        }
+        // the results may take a moment after the last packet
+        let mut found_results = self.results(&mut visitor)?;
+        while !found_results && wait(0).is_continue() {
+            found_results = self.results(&mut visitor)?;
+        }
+        Ok(visitor)
+    }
+}
+
+/// Decompresses the first `skip` bytes of the stream in `input`, throwing
+/// them away. Returns how much of `input` that took.
+fn skip_inflated(decompress: &mut Decompress, input: &[u8], mut skip: u64) -> Result<usize> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut pos = 0;
+    while skip > 0 {
+        let want = skip.min(buf.len() as u64) as usize;
+        let (in_before, out_before) = (decompress.total_in(), decompress.total_out());
+        let status = decompress
+            .decompress(&input[pos..], &mut buf[..want], FlushDecompress::None)
+            .context("Failed to decompress the packet stream")?;
+        let consumed = (decompress.total_in() - in_before) as usize;
+        let produced = decompress.total_out() - out_before;
+        pos += consumed;
+        skip -= produced;
+        if skip > 0 && (status == Status::StreamEnd || (consumed == 0 && produced == 0)) {
+            bail!("Checkpoint is past the end of the stream");
+        }
+    }
+    Ok(pos)
+}
+
+/// Decompresses `input` onto the end of `out`. Returns whether the zlib
+/// stream ended.
+fn inflate(decompress: &mut Decompress, mut input: &[u8], out: &mut Vec<u8>) -> Result<bool> {
+    loop {
+        out.reserve(64 * 1024);
+        let (in_before, out_before) = (decompress.total_in(), decompress.total_out());
+        let status = decompress
+            .decompress_vec(input, out, FlushDecompress::None)
+            .context("Failed to decompress the packet stream")?;
+        let consumed = (decompress.total_in() - in_before) as usize;
+        let produced = decompress.total_out() - out_before;
+        input = &input[consumed..];
+        if status == Status::StreamEnd {
+            return Ok(true);
+        }
+        // done once the output isn't what's holding it back
+        if (input.is_empty() && out.len() < out.capacity()) || (consumed == 0 && produced == 0) {
+            return Ok(false);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Squads and teams, from the player list in the results.
+//!
+//! Results only name vehicles (e.g. `germ_pzkpfw_VI_ausf_e_tiger`). What
+//! kind of vehicle each one is comes from a [`VehicleTable`] if given, or is
+//! guessed from the name otherwise.
+
+use crate::parser::{PlayerData, ReplayResults};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Squad IDs from here up are squads the players formed themselves. Below
+/// are `team * 1000 + n` groups the game puts solo players in. (All replays
+/// seen so far have `auto_squad` false, so it can't be used for this.)
+pub const PLAYER_SQUAD_ID: i32 = 0x1000;
+
+/// Players of the same team with the same squad ID.
+#[derive(Debug, Clone, Serialize)]
+pub struct Squad<'a> {
+    pub team: i32,
+    pub id: i32,
+    /// Put together by the game rather than the players, see
+    /// [`PLAYER_SQUAD_ID`].
+    pub auto: bool,
+    pub members: Vec<&'a PlayerData>,
+    /// Air, ground and naval kills, summed over the members.
+    pub kills: i32,
+    pub deaths: i32,
+    pub assists: i32,
+    pub score: i32,
+}
+
+impl Squad<'_> {
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+}
+
+impl ReplayResults {
+    /// The squads of the battle, by team and squad ID. Groups the game
+    /// made up are left out unless `include_auto` is set.
+    pub fn squads(&self, include_auto: bool) -> Vec<Squad<'_>> {
+        let mut squads: BTreeMap<(i32, i32), Squad<'_>> = BTreeMap::new();
+        for player in &self.players {
+            let data = &player.replay_data;
+            let auto = data.auto_squad || data.squad < PLAYER_SQUAD_ID;
+            if auto && !include_auto {
+                continue;
+            }
+            let squad = squads
+                .entry((data.team, data.squad))
+                .or_insert_with(|| Squad {
+                    team: data.team,
+                    id: data.squad,
+                    auto,
+                    members: Vec::new(),
+                    kills: 0,
+                    deaths: 0,
+                    assists: 0,
+                    score: 0,
+                });
+            squad.members.push(player);
+            squad.kills += data.kills + data.ground_kills + data.naval_kills;
+            squad.deaths += data.deaths;
+            squad.assists += data.assists;
+            squad.score += data.score;
+        }
+        squads.into_values().collect()
+    }
+}
+
+/// What kind of vehicle something is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum VehicleClass {
+    Tank,
+    /// Anti-air ground vehicles.
+    Spaa,
+    /// Planes, which in ground battles means CAS.
+    Aircraft,
+    Helicopter,
+    /// Never guessed, only from a [`VehicleTable`].
+    Ship,
+}
+
+/// What's known about a vehicle, for a [`VehicleTable`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct VehicleInfo {
+    pub class: VehicleClass,
+    /// E.g. `germ`, as in the name prefixes of ground vehicles.
+    pub nation: Option<String>,
+    /// Battle rating in the battle's mode, e.g. 6.7.
+    pub battle_rating: Option<f32>,
+}
+
+/// Vehicle info by name, e.g. loaded from a datamine. Vehicles that aren't
+/// in it get [`guess_vehicle`].
+pub type VehicleTable = HashMap<String, VehicleInfo>;
+
+/// Name prefixes of ground vehicles. Aircraft names don't have one.
+const NATION_PREFIXES: [&str; 10] = [
+    "us", "germ", "ussr", "uk", "jp", "cn", "it", "fr", "sw", "il",
+];
+
+/// Bits of SPAA names.
+const SPAA_HINTS: [&str; 16] = [
+    "flak",
+    "_aa",
+    "zsu",
+    "zsd",
+    "btr_zd",
+    "gepard",
+    "skink",
+    "wirbelwind",
+    "ostwind",
+    "m16_",
+    "m42",
+    "m163",
+    "m247",
+    "tunguska",
+    "pantsir",
+    "lvkv",
+];
+
+/// Name prefixes of helicopters.
+const HELICOPTER_PREFIXES: [&str; 14] = [
+    "ah_", "uh_", "oh_", "mh_", "wah_", "mi_", "ka_", "tiger_", "bo_105", "sa_34", "alouette",
+    "lynx", "a_129", "z_",
+];
+
+/// Guesses class and nation from a vehicle's name alone. Ships and the
+/// nation of aircraft can't be told from the name.
+pub fn guess_vehicle(name: &str) -> VehicleInfo {
+    let nation = name
+        .split_once('_')
+        .map(|(prefix, _)| prefix)
+        .filter(|prefix| NATION_PREFIXES.contains(prefix));
+    let class = match nation {
+        Some(_) if SPAA_HINTS.iter().any(|h| name.contains(h)) => VehicleClass::Spaa,
+        Some(_) => VehicleClass::Tank,
+        None if HELICOPTER_PREFIXES.iter().any(|p| name.starts_with(p)) => VehicleClass::Helicopter,
+        None => VehicleClass::Aircraft,
+    };
+    VehicleInfo {
+        class,
+        nation: nation.map(str::to_string),
+        battle_rating: None,
+    }
+}
+
+/// The vehicles one team brought.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TeamComposition {
+    pub team: i32,
+    pub players: usize,
+    /// Vehicles in all the lineups together.
+    pub vehicles: usize,
+    pub by_class: BTreeMap<VehicleClass, usize>,
+    /// Vehicles whose nation is known, by nation.
+    pub by_nation: BTreeMap<String, usize>,
+}
+
+impl ReplayResults {
+    /// What each team's lineups are made of, by team number.
+    pub fn team_composition(&self, vehicles: &VehicleTable) -> Vec<TeamComposition> {
+        let mut teams: BTreeMap<i32, TeamComposition> = BTreeMap::new();
+        for player in &self.players {
+            let team = player.replay_data.team;
+            let composition = teams.entry(team).or_insert_with(|| TeamComposition {
+                team,
+                players: 0,
+                vehicles: 0,
+                by_class: BTreeMap::new(),
+                by_nation: BTreeMap::new(),
+            });
+            composition.players += 1;
+            for name in &player.replay_data.lineup {
+                let info = match vehicles.get(name) {
+                    Some(info) => info.clone(),
+                    None => guess_vehicle(name),
+                };
+                composition.vehicles += 1;
+                *composition.by_class.entry(info.class).or_default() += 1;
+                if let Some(nation) = info.nation {
+                    *composition.by_nation.entry(nation).or_default() += 1;
+                }
+            }
+        }
+        teams.into_values().collect()
+    }
+}
+
+/// How far apart battle ratings in one battle may be.
+pub const MAX_BR_SPREAD: f32 = 1.0;
+
+/// Where the replay's author was relative to the rest of the battle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Tier {
+    /// Below the top BR of the battle.
+    Uptier,
+    /// At the top BR, with lower BRs in the battle.
+    Downtier,
+    /// Everyone at the same BR.
+    Even,
+    /// The author's BR isn't known.
+    Unknown,
+}
+
+/// Battle ratings of a battle, see [`ReplayResults::battle_rating_analysis`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BattleRatingAnalysis {
+    /// Highest BR of any player, which is what matchmaking was done for.
+    pub top: f32,
+    /// Lowest BR of any player.
+    pub bottom: f32,
+    /// Players whose BR is known.
+    pub players_rated: usize,
+    pub author_br: Option<f32>,
+    pub tier: Tier,
+    /// How far the author was below the top (0 if at the top).
+    pub uptier_by: f32,
+}
+
+impl BattleRatingAnalysis {
+    pub fn spread(&self) -> f32 {
+        self.top - self.bottom
+    }
+
+    /// Whether the author was a full [`MAX_BR_SPREAD`] below the top.
+    pub fn is_full_uptier(&self) -> bool {
+        self.uptier_by >= MAX_BR_SPREAD - 0.05
+    }
+}
+
+/// A player's BR: the highest of the vehicles in their lineup.
+fn player_br(player: &PlayerData, vehicles: &VehicleTable) -> Option<f32> {
+    player
+        .replay_data
+        .lineup
+        .iter()
+        .filter_map(|name| vehicles.get(name)?.battle_rating)
+        .reduce(f32::max)
+}
+
+impl ReplayResults {
+    /// Battle ratings of the battle and whether the author was uptiered,
+    /// using the BRs in `vehicles`. `None` if no player's BR is known.
+    pub fn battle_rating_analysis(&self, vehicles: &VehicleTable) -> Option<BattleRatingAnalysis> {
+        let brs: Vec<f32> = self
+            .players
+            .iter()
+            .filter_map(|p| player_br(p, vehicles))
+            .collect();
+        let top = brs.iter().copied().reduce(f32::max)?;
+        let bottom = brs.iter().copied().reduce(f32::min)?;
+        let author_br = self
+            .players
+            .iter()
+            .find(|p| p.player_info.user_id == self.author_user_id)
+            .and_then(|p| player_br(p, vehicles));
+
+        // BRs are multiples of 0.3 or 0.4, anything closer is the same
+        let same = |a: f32, b: f32| (a - b).abs() < 0.05;
+        let tier = match author_br {
+            None => Tier::Unknown,
+            Some(br) if !same(br, top) => Tier::Uptier,
+            Some(_) if same(bottom, top) => Tier::Even,
+            Some(_) => Tier::Downtier,
+        };
+        Some(BattleRatingAnalysis {
+            top,
+            bottom,
+            players_rated: brs.len(),
+            author_br,
+            tier,
+            uptier_by: author_br.map_or(0.0, |br| (top - br).max(0.0)),
+        })
+    }
+}
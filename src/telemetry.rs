@@ -0,0 +1,155 @@
+//! Types for unit movement data.
+//!
+//! Nothing in the packet stream is decoded into these yet (the movement packets
+//! are still being reverse-engineered), but exporters and analysis code are
+//! written against them so they work as soon as a decoder exists.
+
+use serde::{Deserialize, Serialize};
+
+/// A position in map-local world coordinates (metres). `y` is up.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Vec3 { x, y, z }
+    }
+
+    /// The point `t` of the way from `self` to `other`.
+    pub fn lerp(self, other: Vec3, t: f32) -> Vec3 {
+        self + (other - self) * t
+    }
+
+    pub fn length(self) -> f32 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    pub fn distance(self, other: Vec3) -> f32 {
+        (other - self).length()
+    }
+}
+
+impl std::ops::Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl std::ops::Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl std::ops::Mul<f32> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, factor: f32) -> Vec3 {
+        Vec3::new(self.x * factor, self.y * factor, self.z * factor)
+    }
+}
+
+/// A rotation as a unit quaternion.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat::new(0.0, 0.0, 0.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Quat { x, y, z, w }
+    }
+
+    /// Returns `[x, y, z, w]`.
+    pub fn to_array(self) -> [f32; 4] {
+        [self.x, self.y, self.z, self.w]
+    }
+
+    pub fn from_array([x, y, z, w]: [f32; 4]) -> Self {
+        Quat { x, y, z, w }
+    }
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Quat::IDENTITY
+    }
+}
+
+/// Identifies a unit (vehicle) within a replay.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct UnitRef {
+    /// The unit's ID in the replay.
+    pub id: u32,
+    /// Nickname of the controlling player, if known.
+    pub name: Option<String>,
+}
+
+/// A single position sample of a unit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TelemetrySample {
+    /// Timestamp in milliseconds.
+    pub time_ms: u32,
+    pub position: Vec3,
+}
+
+/// All position samples of one unit, sorted by time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub unit: UnitRef,
+    pub samples: Vec<TelemetrySample>,
+}
+
+/// How [`Track::position_at`] picks a position between samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lookup {
+    /// The closest sample in time.
+    Nearest,
+    /// Linearly between the samples either side.
+    #[default]
+    Interpolate,
+}
+
+impl Track {
+    /// Where the unit was at `time_ms`. `None` before its first sample and
+    /// after its last, as it presumably wasn't around then.
+    pub fn position_at(&self, time_ms: u32, lookup: Lookup) -> Option<Vec3> {
+        let first = self.samples.first()?;
+        let last = self.samples.last()?;
+        if time_ms < first.time_ms || time_ms > last.time_ms {
+            return None;
+        }
+        // first sample at or after the time, there is one as it's in range
+        let after = self.samples.partition_point(|s| s.time_ms < time_ms);
+        let next = &self.samples[after];
+        if next.time_ms == time_ms || after == 0 {
+            return Some(next.position);
+        }
+        let prev = &self.samples[after - 1];
+        let span = (next.time_ms - prev.time_ms) as f32;
+        let t = (time_ms - prev.time_ms) as f32 / span;
+        Some(match lookup {
+            Lookup::Nearest if t < 0.5 => prev.position,
+            Lookup::Nearest => next.position,
+            Lookup::Interpolate => prev.position.lerp(next.position, t),
+        })
+    }
+}
+
+/// Where every unit around at `time_ms` was, e.g. for drawing a minimap.
+pub fn positions_at(tracks: &[Track], time_ms: u32, lookup: Lookup) -> Vec<(UnitRef, Vec3)> {
+    tracks
+        .iter()
+        .filter_map(|track| Some((track.unit.clone(), track.position_at(time_ms, lookup)?)))
+        .collect()
+}
@@ -0,0 +1,192 @@
+//! Smooth per-unit paths from the raw [`Track`] samples, which are sparse
+//! and jittery.
+//!
+//! A [`Trajectory`] drops samples that can't be right (see
+//! [`TrajectoryOptions::max_speed`]) and then interpolates between the rest,
+//! so it can be sampled at any time or resampled at a fixed rate.
+
+use crate::telemetry::{TelemetrySample, Track, UnitRef, Vec3};
+
+/// How positions between samples are worked out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Straight lines between samples.
+    Linear,
+    /// Cubic Hermite (Catmull-Rom) curves, which keep the velocity smooth.
+    #[default]
+    Hermite,
+}
+
+/// How a [`Trajectory`] is built.
+#[derive(Debug, Clone, Copy)]
+pub struct TrajectoryOptions {
+    pub interpolation: Interpolation,
+    /// A sample that's further than this (in m/s) from both of its
+    /// neighbours is a glitch and is dropped. `None` keeps everything.
+    pub max_speed: Option<f32>,
+}
+
+impl Default for TrajectoryOptions {
+    fn default() -> Self {
+        TrajectoryOptions {
+            interpolation: Interpolation::Hermite,
+            // well past anything in the game, jets included
+            max_speed: Some(1500.0),
+        }
+    }
+}
+
+/// A unit's path, cleaned up and interpolated.
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    pub unit: UnitRef,
+    samples: Vec<TelemetrySample>,
+    interpolation: Interpolation,
+    /// Samples dropped as glitches (or as repeats of a time).
+    pub rejected: usize,
+}
+
+/// Speed in m/s going from `a` to `b`.
+fn speed(a: &TelemetrySample, b: &TelemetrySample) -> f32 {
+    let dt = b.time_ms.abs_diff(a.time_ms).max(1) as f32 / 1000.0;
+    a.position.distance(b.position) / dt
+}
+
+impl Trajectory {
+    pub fn new(track: &Track, options: &TrajectoryOptions) -> Self {
+        let raw = &track.samples;
+        let mut samples: Vec<TelemetrySample> = Vec::with_capacity(raw.len());
+        let mut rejected = 0;
+        for (i, sample) in raw.iter().enumerate() {
+            let Some(prev) = samples.last() else {
+                samples.push(*sample);
+                continue;
+            };
+            if sample.time_ms <= prev.time_ms {
+                rejected += 1;
+                continue;
+            }
+            // a spike: way off from the sample before and the one after
+            let spike = match (options.max_speed, raw.get(i + 1)) {
+                (Some(max), Some(next)) => speed(prev, sample) > max && speed(sample, next) > max,
+                _ => false,
+            };
+            if spike {
+                rejected += 1;
+            } else {
+                samples.push(*sample);
+            }
+        }
+        Trajectory {
+            unit: track.unit.clone(),
+            samples,
+            interpolation: options.interpolation,
+            rejected,
+        }
+    }
+
+    /// The samples that were kept.
+    pub fn samples(&self) -> &[TelemetrySample] {
+        &self.samples
+    }
+
+    pub fn start_ms(&self) -> Option<u32> {
+        self.samples.first().map(|s| s.time_ms)
+    }
+
+    pub fn end_ms(&self) -> Option<u32> {
+        self.samples.last().map(|s| s.time_ms)
+    }
+
+    /// Tangent at sample `i`, in metres per ms.
+    fn tangent(&self, i: usize) -> Vec3 {
+        let before = &self.samples[i.saturating_sub(1)];
+        let after = &self.samples[(i + 1).min(self.samples.len() - 1)];
+        let dt = after.time_ms.saturating_sub(before.time_ms);
+        if dt == 0 {
+            return Vec3::default();
+        }
+        (after.position - before.position) * (1.0 / dt as f32)
+    }
+
+    /// The segment `time_ms` is in: the index of the sample starting it and
+    /// how far along it is (0 to 1). `None` outside the trajectory.
+    fn segment(&self, time_ms: f64) -> Option<(usize, f32)> {
+        let start = self.start_ms()? as f64;
+        let end = self.end_ms()? as f64;
+        if time_ms < start || time_ms > end {
+            return None;
+        }
+        let after = self
+            .samples
+            .partition_point(|s| (s.time_ms as f64) < time_ms);
+        if after == 0 || self.samples.len() == 1 {
+            return Some((0, 0.0));
+        }
+        let (a, b) = (&self.samples[after - 1], &self.samples[after]);
+        let t = (time_ms - a.time_ms as f64) / (b.time_ms - a.time_ms) as f64;
+        Some((after - 1, t as f32))
+    }
+
+    /// Where the unit was at `time_ms`, `None` outside the trajectory.
+    pub fn position_at(&self, time_ms: f64) -> Option<Vec3> {
+        let (i, t) = self.segment(time_ms)?;
+        let a = &self.samples[i];
+        let Some(b) = self.samples.get(i + 1) else {
+            return Some(a.position);
+        };
+        Some(match self.interpolation {
+            Interpolation::Linear => a.position.lerp(b.position, t),
+            Interpolation::Hermite => {
+                let h = (b.time_ms - a.time_ms) as f32;
+                let (t2, t3) = (t * t, t * t * t);
+                a.position * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + self.tangent(i) * ((t3 - 2.0 * t2 + t) * h)
+                    + b.position * (-2.0 * t3 + 3.0 * t2)
+                    + self.tangent(i + 1) * ((t3 - t2) * h)
+            }
+        })
+    }
+
+    /// Velocity in m/s at `time_ms`, `None` outside the trajectory (or if
+    /// it's a single sample).
+    pub fn velocity_at(&self, time_ms: f64) -> Option<Vec3> {
+        let (i, t) = self.segment(time_ms)?;
+        let (a, b) = (&self.samples[i], self.samples.get(i + 1)?);
+        let h = (b.time_ms - a.time_ms) as f32;
+        let per_ms = match self.interpolation {
+            Interpolation::Linear => (b.position - a.position) * (1.0 / h),
+            Interpolation::Hermite => {
+                let t2 = t * t;
+                (a.position * (6.0 * t2 - 6.0 * t) + b.position * (-6.0 * t2 + 6.0 * t)) * (1.0 / h)
+                    + self.tangent(i) * (3.0 * t2 - 4.0 * t + 1.0)
+                    + self.tangent(i + 1) * (3.0 * t2 - 2.0 * t)
+            }
+        };
+        Some(per_ms * 1000.0)
+    }
+
+    /// Positions every `interval_ms` from the start to the end.
+    pub fn resample(&self, interval_ms: u32) -> Vec<TelemetrySample> {
+        let (Some(start), Some(end)) = (self.start_ms(), self.end_ms()) else {
+            return Vec::new();
+        };
+        (start..=end)
+            .step_by(interval_ms.max(1) as usize)
+            .filter_map(|time_ms| {
+                Some(TelemetrySample {
+                    time_ms,
+                    position: self.position_at(time_ms as f64)?,
+                })
+            })
+            .collect()
+    }
+
+    /// [`Trajectory::resample`] as a [`Track`], e.g. for the exporters.
+    pub fn to_track(&self, interval_ms: u32) -> Track {
+        Track {
+            unit: self.unit.clone(),
+            samples: self.resample(interval_ms),
+        }
+    }
+}
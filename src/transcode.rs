@@ -0,0 +1,61 @@
+//! Re-emitting a client replay's packet stream with a different compression.
+//!
+//! Useful for smaller archives (higher zlib level) or for easier inspection
+//! (uncompressed stream, parseable with `--compression none --offset`).
+
+use crate::rewrite;
+use anyhow::{bail, Context, Result};
+use flate2::Compression;
+use log::info;
+
+/// How the packet stream should be stored in the transcoded replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamEncoding {
+    /// zlib with the given level (0-9).
+    Zlib { level: u32 },
+    /// The raw, decompressed packet stream.
+    Uncompressed,
+}
+
+/// The result of [`transcode`].
+#[derive(Debug, Clone)]
+pub struct TranscodedReplay {
+    /// The rewritten replay file.
+    pub data: Vec<u8>,
+    /// Offset of the packet stream within `data`.
+    pub stream_offset: u64,
+    /// Length of the (possibly compressed) packet stream within `data`.
+    pub stream_len: u64,
+}
+
+/// Decompresses a client replay's packet stream and re-emits it with `encoding`.
+/// The header, mission settings and results are copied unchanged
+/// (apart from `rez_offset`, which is shifted to match).
+pub fn transcode(data: &[u8], encoding: StreamEncoding) -> Result<TranscodedReplay> {
+    let split = rewrite::split_replay(data)?;
+
+    let stream_section = match encoding {
+        StreamEncoding::Zlib { level } => {
+            if level > 9 {
+                bail!("Invalid zlib level {}, expected 0-9", level);
+            }
+            rewrite::compress_stream(&split.stream, Compression::new(level))
+                .context("Failed to recompress packet stream")?
+        }
+        StreamEncoding::Uncompressed => split.stream,
+    };
+
+    info!(
+        "Packet stream: {} -> {} bytes",
+        split.layout.stream_end - split.layout.stream_start,
+        stream_section.len()
+    );
+
+    let out = rewrite::assemble(split.prefix, &stream_section, split.tail, &split.layout)?;
+
+    Ok(TranscodedReplay {
+        data: out,
+        stream_offset: split.prefix.len() as u64,
+        stream_len: stream_section.len() as u64,
+    })
+}
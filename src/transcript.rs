@@ -0,0 +1,103 @@
+//! Chat split up into transcripts by channel, so all-chat, team chat and
+//! squad chat can be rendered apart.
+//!
+//! [`collapse_repeats`] tidies up spammed quick commands first.
+
+use crate::clock;
+use crate::parser::{ChatChannel, ChatInfo, ParsedReplay};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The chat of a replay by channel, each in time order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Transcripts<'a> {
+    pub all: Vec<&'a ChatInfo>,
+    /// Team chat of the author's team, or of senders whose team isn't
+    /// known.
+    pub team: Vec<&'a ChatInfo>,
+    /// Team chat of the other team, which only server replays have.
+    pub enemy_team: Vec<&'a ChatInfo>,
+    pub squad: Vec<&'a ChatInfo>,
+    /// Messages of channels that aren't known.
+    pub other: Vec<&'a ChatInfo>,
+}
+
+impl Transcripts<'_> {
+    /// The transcripts with their names, e.g. for file names.
+    pub fn named(&self) -> [(&'static str, &[&ChatInfo]); 5] {
+        [
+            ("all", &self.all),
+            ("team", &self.team),
+            ("enemy_team", &self.enemy_team),
+            ("squad", &self.squad),
+            ("other", &self.other),
+        ]
+    }
+}
+
+/// Splits `chat` by channel. Team chat is split by the sender's team (see
+/// [`ChatInfo::player_ref`]) if `author_team` is given.
+pub fn transcripts(chat: &[ChatInfo], author_team: Option<i32>) -> Transcripts<'_> {
+    let mut transcripts = Transcripts::default();
+    for message in chat {
+        let transcript = match message.channel() {
+            ChatChannel::All => &mut transcripts.all,
+            ChatChannel::Squad => &mut transcripts.squad,
+            ChatChannel::Team => match (author_team, message.team()) {
+                (Some(author), Some(team)) if author != team => &mut transcripts.enemy_team,
+                _ => &mut transcripts.team,
+            },
+            ChatChannel::Other(_) | ChatChannel::Unknown => &mut transcripts.other,
+        };
+        transcript.push(message);
+    }
+    transcripts
+}
+
+impl ParsedReplay {
+    /// The chat by channel, with team chat split by the author's team if
+    /// there are results.
+    pub fn transcripts(&self) -> Transcripts<'_> {
+        let author_team = self.replay_results.as_ref().and_then(|results| {
+            results
+                .players
+                .iter()
+                .find(|p| p.player_info.user_id == results.author_user_id)
+                .map(|p| p.replay_data.team)
+        });
+        transcripts(&self.chat_messages, author_team)
+    }
+}
+
+/// Collapses a message the sender sent again (same text and channel) within
+/// `window_ms` of the last time into the first one, counting it in
+/// [`ChatInfo::repeats`]. Messages in between are kept, so spam from several
+/// players at once collapses too.
+pub fn collapse_repeats(chat: &mut Vec<ChatInfo>, window_ms: u32) {
+    // where each message was last sent: its entry, and when
+    let mut last_sent: HashMap<(String, String, Option<u8>), (usize, u32)> = HashMap::new();
+    let mut kept: Vec<ChatInfo> = Vec::with_capacity(chat.len());
+    for message in chat.drain(..) {
+        let key = (
+            message.sender.clone(),
+            message.message.clone(),
+            message.channel_type,
+        );
+        let time_ms = clock::ticks_to_ms(message.timestamp_ms);
+        match last_sent.get_mut(&key) {
+            Some((index, last_ms)) if time_ms.saturating_sub(*last_ms) <= window_ms => {
+                kept[*index].repeats += 1;
+                *last_ms = time_ms;
+            }
+            _ => {
+                last_sent.insert(key, (kept.len(), time_ms));
+                kept.push(message);
+            }
+        }
+    }
+    *chat = kept;
+}
+
+pub(crate) fn is_unrepeated(repeats: &u32) -> bool {
+    *repeats == 0
+}
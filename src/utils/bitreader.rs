@@ -0,0 +1,164 @@
+//! Reading bit-packed data, as used by MPI and ECS payloads.
+//!
+//! Dagor's net code is derived from RakNet, whose `BitStream` fills each
+//! byte starting at the most significant bit. [`BitReader`] does the same:
+//! the first bit read is bit 7 of the first byte.
+//!
+//! That bit order is an assumption carried over from RakNet, it hasn't been
+//! verified against a payload decoded from a real replay yet. The quantized
+//! readers are hidden from the docs for the same reason, see
+//! [`crate::quantize`].
+
+use crate::quantize::{self, PositionQuantization};
+use crate::telemetry::{Quat, Vec3};
+use anyhow::{bail, Result};
+
+/// Reads values bit by bit from a byte slice.
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits from the start of `data`.
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    /// Current position in bits.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Number of bits left to read.
+    pub fn remaining_bits(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    /// Skips `count` bits.
+    pub fn skip_bits(&mut self, count: usize) -> Result<()> {
+        self.ensure(count)?;
+        self.pos += count;
+        Ok(())
+    }
+
+    /// Skips to the start of the next byte (no-op if already aligned).
+    pub fn align_to_byte(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    fn ensure(&self, count: usize) -> Result<()> {
+        if count > self.remaining_bits() {
+            bail!(
+                "Tried to read {} bits at bit {}, only {} left",
+                count,
+                self.pos,
+                self.remaining_bits()
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads a single bit.
+    pub fn read_bit(&mut self) -> Result<bool> {
+        self.ensure(1)?;
+        let byte = self.data[self.pos / 8];
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit == 1)
+    }
+
+    /// Reads up to 64 bits, first bit read ending up most significant.
+    pub fn read_bits(&mut self, count: u32) -> Result<u64> {
+        if count > 64 {
+            bail!("Can't read {} bits at once, at most 64", count);
+        }
+        self.ensure(count as usize)?;
+
+        let mut value = 0u64;
+        let mut left = count;
+        while left > 0 {
+            let bit_in_byte = (self.pos % 8) as u32;
+            let available = 8 - bit_in_byte;
+            let take = available.min(left);
+            let byte = self.data[self.pos / 8] as u64;
+            // the `take` bits right after bit_in_byte
+            let chunk = (byte >> (available - take)) & ((1 << take) - 1);
+            value = (value << take) | chunk;
+            self.pos += take as usize;
+            left -= take;
+        }
+        Ok(value)
+    }
+
+    /// Reads `count` whole bytes (need not be aligned).
+    pub fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>> {
+        self.ensure(count * 8)?;
+        if self.pos.is_multiple_of(8) {
+            let start = self.pos / 8;
+            self.pos += count * 8;
+            return Ok(self.data[start..start + count].to_vec());
+        }
+        (0..count).map(|_| Ok(self.read_bits(8)? as u8)).collect()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bits(8)? as u8)
+    }
+
+    /// Reads a little-endian u16, as written by `BitStream::Write`.
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads a little-endian u32, as written by `BitStream::Write`.
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a little-endian f32.
+    pub fn read_f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    /// Reads a float quantized to `bits` bits over `[min, max]`.
+    #[doc(hidden)]
+    pub fn read_quantized_float(&mut self, bits: u32, min: f32, max: f32) -> Result<f32> {
+        if bits == 0 || bits > quantize::MAX_FLOAT_BITS {
+            bail!(
+                "Quantized floats must use 1-{} bits, got {}",
+                quantize::MAX_FLOAT_BITS,
+                bits
+            );
+        }
+        Ok(quantize::dequantize(self.read_bits(bits)?, bits, min, max))
+    }
+
+    /// Reads a packed position, see [`PositionQuantization`].
+    #[doc(hidden)]
+    pub fn read_quantized_position(&mut self, quantization: &PositionQuantization) -> Result<Vec3> {
+        quantization.check()?;
+        Ok(quantization.decode(self.read_bits(quantization.total_bits())?))
+    }
+
+    /// Reads a "smallest three" compressed quaternion: a 2-bit index of the
+    /// largest component, then the other three with `bits` bits each over
+    /// `[-1/sqrt(2), 1/sqrt(2)]`.
+    #[doc(hidden)]
+    pub fn read_compressed_quaternion(&mut self, bits: u32) -> Result<Quat> {
+        if bits == 0 || bits > quantize::MAX_QUATERNION_BITS {
+            bail!(
+                "Quaternion components must use 1-{} bits, got {}",
+                quantize::MAX_QUATERNION_BITS,
+                bits
+            );
+        }
+        Ok(quantize::decode_quaternion(
+            self.read_bits(2 + 3 * bits)?,
+            bits,
+        ))
+    }
+}
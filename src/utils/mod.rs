@@ -1,11 +1,15 @@
 use anyhow::{Context, Result};
+use flate2::{Decompress, FlushDecompress, Status};
 
-use log::{debug, info, warn};
+use crate::logging::{info, warn};
+use log::debug;
 use memchr::memmem;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
+pub mod bitreader;
+
 // FIXME: we do NOT need to search for a zlib header manually
 // but i can't be bothered changing right now
 
@@ -20,13 +24,56 @@ const ZLIB_HEADERS: [&[u8]; 3] = [
 /// Max length of headers in ZLIB_HEADERS
 const MAX_ZLIB_HEADER_LEN: usize = 2;
 
-/// Helper for parsing user input
+/// Helper for parsing user input: decimal or hex (`0x...`), with `_`
+/// separators and an optional `k`, `M` or `G` suffix (powers of 1024),
+/// e.g. `0x4C8`, `1_226` or `4k`.
 pub fn parse_offset(s: &str) -> Result<u64, String> {
-    // FIXME: use lower
-    if let Some(hex_val) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
-        u64::from_str_radix(hex_val, 16).map_err(|e| e.to_string())
+    let s = s.trim().replace('_', "");
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s.as_str(), 1),
+    };
+    let value = if let Some(hex_val) = number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex_val, 16)
     } else {
-        s.parse::<u64>().map_err(|e| e.to_string())
+        number.parse::<u64>()
+    }
+    .map_err(|e| format!("{:?}: {}", s, e))?;
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{:?} is too large", s))
+}
+
+/// An offset into a file, which can count back from its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOffset {
+    Start(u64),
+    /// This many bytes before the end.
+    End(u64),
+}
+
+impl FileOffset {
+    /// The offset from the start of a file of `len` bytes, `None` if it's
+    /// before the start.
+    pub fn resolve(self, len: u64) -> Option<u64> {
+        match self {
+            FileOffset::Start(offset) => Some(offset),
+            FileOffset::End(back) => len.checked_sub(back),
+        }
+    }
+}
+
+/// Like [`parse_offset`], but a leading `-` counts from the end of the
+/// file, e.g. `-4k` is 4 KiB before the end.
+pub fn parse_file_offset(s: &str) -> Result<FileOffset, String> {
+    match s.trim().strip_prefix('-') {
+        Some(back) => parse_offset(back).map(FileOffset::End),
+        None => parse_offset(s).map(FileOffset::Start),
     }
 }
 
@@ -43,16 +90,20 @@ pub mod hex {
     }
 }
 
+/// FNV-1a, a hash that (unlike `DefaultHasher`) is the same on every build.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 /// Searches for common zlib headers in a byte slice.
 /// Returns the offset of the first header found, or None if not found.
 pub fn find_zlib_header(data: &[u8], search_start_offset: usize) -> Option<usize> {
     let search_data = &data[search_start_offset..];
 
     // Build finders for efficiency
-    let finders: Vec<_> = ZLIB_HEADERS
-        .iter()
-        .map(|h| memmem::Finder::new(h))
-        .collect();
+    let finders: Vec<_> = ZLIB_HEADERS.iter().map(memmem::Finder::new).collect();
 
     // Search for headers in the data
     let mut first_found_offset = None;
@@ -95,7 +146,17 @@ pub fn find_zlib_header_offset(
 ) -> Result<Option<u64>> {
     let file = File::open(file_path)
         .with_context(|| format!("Failed to open file for searching: {:?}", file_path))?;
-    let mut reader = BufReader::with_capacity(ZLIB_SEARCH_CHUNK_SIZE, file);
+    find_zlib_header_in_reader(file, search_start_offset, search_end_offset)
+}
+
+/// Searches for common zlib headers in any seekable reader within a range.
+/// Returns the offset of the first header found, or None if not found.
+pub fn find_zlib_header_in_reader<R: Read + Seek>(
+    input: R,
+    search_start_offset: u64,
+    search_end_offset: Option<u64>,
+) -> Result<Option<u64>> {
+    let mut reader = BufReader::with_capacity(ZLIB_SEARCH_CHUNK_SIZE, input);
 
     reader
         .seek(SeekFrom::Start(search_start_offset))
@@ -168,3 +229,64 @@ pub fn find_zlib_header_offset(
     warn!("Are you sure this is a complete full client-side replay?");
     Ok(None)
 }
+
+/// What [`decompress_stream`] got through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecompressInfo {
+    /// Compressed bytes read from the offset, so the stream ended at
+    /// `offset + bytes_in`.
+    pub bytes_in: u64,
+    /// Decompressed bytes written.
+    pub bytes_out: u64,
+    /// Whether the zlib stream ended properly, rather than the data running
+    /// out first (e.g. a crashed client's replay).
+    pub complete: bool,
+}
+
+/// Decompresses the zlib stream at `offset` in `data` into `out`, like
+/// `decom_from_addr` does. A truncated stream writes what's there and isn't
+/// an error; check [`DecompressInfo::complete`].
+pub fn decompress_stream<W: Write>(
+    data: &[u8],
+    offset: u64,
+    out: &mut W,
+) -> Result<DecompressInfo> {
+    let input = usize::try_from(offset)
+        .ok()
+        .and_then(|offset| data.get(offset..))
+        .with_context(|| format!("Offset {:#x} is past the end of the data", offset))?;
+    let mut inflater = Decompress::new(true);
+    let mut buffer = vec![0; ZLIB_SEARCH_CHUNK_SIZE];
+    let complete = loop {
+        let consumed = inflater.total_in() as usize;
+        let before = inflater.total_out();
+        let status = inflater
+            .decompress(&input[consumed..], &mut buffer, FlushDecompress::None)
+            .with_context(|| {
+                format!(
+                    "Bad zlib data {:#x} bytes into the stream",
+                    inflater.total_in()
+                )
+            })?;
+        let written = (inflater.total_out() - before) as usize;
+        out.write_all(&buffer[..written])?;
+        match status {
+            Status::StreamEnd => break true,
+            // no progress and nothing more to read: it's been cut off
+            _ if written == 0 && inflater.total_in() as usize == consumed => break false,
+            _ => {}
+        }
+    };
+    if !complete {
+        warn!(
+            "zlib stream at {:#x} is truncated after {} bytes",
+            offset,
+            inflater.total_in()
+        );
+    }
+    Ok(DecompressInfo {
+        bytes_in: inflater.total_in(),
+        bytes_out: inflater.total_out(),
+        complete,
+    })
+}
@@ -0,0 +1,298 @@
+//! Checking a replay file for corruption, e.g. before archiving it.
+//!
+//! [`validate`] runs every check it can and reports each one, rather than
+//! stopping at the first problem like the parser does. Checks that depend on
+//! an earlier one that failed are skipped.
+
+use crate::format::{self, FileKind};
+use crate::header::{self, ReplayHeader};
+use crate::parser;
+use crate::raw;
+use flate2::{Decompress, FlushDecompress, Status};
+use serde::Serialize;
+use std::fmt;
+use std::io::Cursor;
+
+/// How a single check went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// One check of an [`IntegrityReport`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    /// What was found, or why it failed or was skipped.
+    pub detail: String,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.outcome {
+            Outcome::Passed => "PASS",
+            Outcome::Failed => "FAIL",
+            Outcome::Skipped => "SKIP",
+        };
+        write!(f, "[{}] {}: {}", label, self.name, self.detail)
+    }
+}
+
+/// The result of [`validate`].
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub checks: Vec<Check>,
+}
+
+impl IntegrityReport {
+    /// Whether no check failed (skipped ones don't count).
+    pub fn is_ok(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// The checks that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| c.outcome == Outcome::Failed)
+    }
+
+    /// Looks up a check by name.
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|c| c.name == name)
+    }
+
+    fn push(&mut self, name: &'static str, result: Result<String, String>) {
+        let (outcome, detail) = match result {
+            Ok(detail) => (Outcome::Passed, detail),
+            Err(detail) => (Outcome::Failed, detail),
+        };
+        self.checks.push(Check {
+            name,
+            outcome,
+            detail,
+        });
+    }
+
+    fn skip(&mut self, name: &'static str, reason: &str) {
+        self.checks.push(Check {
+            name,
+            outcome: Outcome::Skipped,
+            detail: reason.to_string(),
+        });
+    }
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fields that are never empty or zero in a real replay.
+fn check_header_fields(header: &ReplayHeader) -> Result<String, String> {
+    let mut problems = Vec::new();
+    if !header.level.starts_with("levels/") {
+        problems.push(format!("level {:?} isn't under levels/", header.level));
+    }
+    if header.battle_type.is_empty() {
+        problems.push("battle_type is empty".to_string());
+    }
+    if header.session_id.0 == 0 {
+        problems.push("session_id is 0".to_string());
+    }
+    if header.start_time == 0 {
+        problems.push("start_time is 0".to_string());
+    }
+    if header.time_limit == 0 {
+        problems.push("time_limit is 0".to_string());
+    }
+    if problems.is_empty() {
+        Ok(format!(
+            "{} on {}, session {}",
+            header.battle_type, header.level, header.session_id
+        ))
+    } else {
+        Err(problems.join(", "))
+    }
+}
+
+/// Decompresses a zlib stream, returning how many bytes it took up.
+/// Unlike the parser's reader, a stream cut short is an error.
+fn inflate(data: &[u8]) -> Result<(usize, Vec<u8>), String> {
+    let mut decompress = Decompress::new(true);
+    let mut out = Vec::with_capacity(data.len() * 2);
+    loop {
+        if out.len() == out.capacity() {
+            out.reserve(out.len());
+        }
+        let consumed = decompress.total_in() as usize;
+        let status = decompress
+            .decompress_vec(&data[consumed..], &mut out, FlushDecompress::None)
+            .map_err(|e| format!("is corrupt after {} bytes: {}", consumed, e))?;
+        match status {
+            Status::StreamEnd => return Ok((decompress.total_in() as usize, out)),
+            // out of input with room to spare, so the end is missing
+            _ if decompress.total_in() as usize == data.len() && out.len() < out.capacity() => {
+                return Err(format!("is cut off after {} bytes", data.len()))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Walks the size prefixes of a decompressed packet stream, checking that
+/// every packet fits and is long enough for its own packet header.
+fn check_packets(stream: &[u8]) -> Result<String, String> {
+    let mut cursor = Cursor::new(stream);
+    let mut packets = 0u64;
+    let mut empty = 0u64;
+    loop {
+        let offset = cursor.position();
+        let size = match raw::read_variable_length_size(&mut cursor) {
+            Ok(Some((size, _))) => size as u64,
+            Ok(None) => break,
+            Err(e) => {
+                return Err(format!(
+                    "bad size prefix of packet {} at {:#x}: {:#}",
+                    packets, offset, e
+                ))
+            }
+        };
+        let start = cursor.position();
+        let left = stream.len() as u64 - start;
+        if size > left {
+            return Err(format!(
+                "packet {} at {:#x} claims {} bytes, only {} left",
+                packets, offset, size, left
+            ));
+        }
+        if size == 0 {
+            empty += 1;
+        } else {
+            let first = stream[start as usize];
+            // type byte, plus a timestamp unless the 0x10 bit says it's unchanged
+            let header_len = if first & 0x10 != 0 { 1 } else { 5 };
+            if size < header_len {
+                return Err(format!(
+                    "packet {} at {:#x} is {} bytes, shorter than its header",
+                    packets, offset, size
+                ));
+            }
+        }
+        cursor.set_position(start + size);
+        packets += 1;
+    }
+    if packets == 0 {
+        return Err("stream has no packets".to_string());
+    }
+    Ok(format!("{} packets, {} empty", packets, empty))
+}
+
+/// Checks `data` (a whole replay file): magic, header fields, the zlib
+/// stream, the packets in it, and the results at `rez_offset`.
+pub fn validate(data: &[u8]) -> IntegrityReport {
+    let mut report = IntegrityReport { checks: Vec::new() };
+
+    let magic = format::check_magic(data);
+    let magic_ok = magic.is_ok();
+    report.push(
+        "magic",
+        magic
+            .map(|_| {
+                format!(
+                    "version {}",
+                    u32::from_le_bytes(data[4..8].try_into().unwrap())
+                )
+            })
+            .map_err(|e| format!("{:#}", e)),
+    );
+    if !magic_ok {
+        for name in ["header", "stream", "packets", "results"] {
+            report.skip(name, "not a replay");
+        }
+        return report;
+    }
+
+    let header = header::parse_header(data).map_err(|e| format!("{:#}", e));
+    report.push(
+        "header",
+        header
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(check_header_fields),
+    );
+
+    // decompress the stream, noting where it ends
+    let kind = format::detect_format(data);
+    let stream = match kind.stream_offset() {
+        Some(start) => inflate(&data[start..])
+            .map(|(len, stream)| (start, start + len, stream))
+            .map_err(|e| format!("zlib stream at {:#x} {}", start, e)),
+        None => Err("couldn't find the packet stream".to_string()),
+    };
+    report.push(
+        "stream",
+        stream
+            .as_ref()
+            .map(|(start, end, stream)| {
+                let kind = match kind {
+                    FileKind::ServerReplay { .. } => "server",
+                    _ => "client",
+                };
+                format!(
+                    "{} stream {:#x}..{:#x}, {} bytes decompressed",
+                    kind,
+                    start,
+                    end,
+                    stream.len()
+                )
+            })
+            .map_err(Clone::clone),
+    );
+
+    match &stream {
+        Ok((_, _, stream)) => report.push("packets", check_packets(stream)),
+        Err(_) => report.skip("packets", "no packet stream"),
+    }
+
+    let rez_offset = header.as_ref().map_or(0, |h| h.rez_offset as usize);
+    let stream_end = stream.as_ref().map_or(0, |(_, end, _)| *end);
+    let results = if rez_offset == 0 {
+        match parser::find_replay_results(data) {
+            // as in server replays
+            Some((offset, _)) => Ok(format!(
+                "rez_offset is 0, but found results at {:#x}",
+                offset
+            )),
+            None => Ok("no results (rez_offset is 0)".to_string()),
+        }
+    } else if rez_offset >= data.len() {
+        Err(format!(
+            "rez_offset {:#x} is past the end of the file ({:#x})",
+            rez_offset,
+            data.len()
+        ))
+    } else if rez_offset < stream_end {
+        Err(format!(
+            "rez_offset {:#x} points inside the packet stream (ends at {:#x})",
+            rez_offset, stream_end
+        ))
+    } else {
+        match parser::parse_replay_results(data, rez_offset) {
+            Some(results) => Ok(format!(
+                "{} players, {:.1}s played",
+                results.players.len(),
+                results.time_played
+            )),
+            None => Err(format!("results at {:#x} don't parse", rez_offset)),
+        }
+    };
+    report.push("results", results);
+
+    report
+}
@@ -0,0 +1,239 @@
+//! Writing `.wrpl` files from a header and a list of packets.
+//!
+//! This is the counterpart of [`crate::header::parse_header`] and
+//! [`crate::parser::process_replay_data`].
+//!
+//! What's written parses back the same with this crate, but hasn't been
+//! tried in the game. The header only comes out as it was read if its
+//! unknown regions were kept (see [`write_header`]); otherwise they're
+//! zeroed, which the game may not accept.
+
+use crate::header::{ReplayHeader, HEADER_SIZE, REZ_OFFSET_POS};
+use crate::parser::{PacketInfo, ParsedReplay, ReplayPacketType};
+use crate::raw;
+use crate::rewrite;
+use crate::seek::TimeIndex;
+use crate::transcode::StreamEncoding;
+use anyhow::{bail, Context, Result};
+use flate2::Compression;
+use log::warn;
+use std::ops::Range;
+
+fn write_string(out: &mut Vec<u8>, value: &str, max_len: usize, field: &str) -> Result<()> {
+    let bytes = value.as_bytes();
+    if bytes.len() > max_len {
+        bail!(
+            "Header field {} is {} bytes long, at most {} fit",
+            field,
+            bytes.len(),
+            max_len
+        );
+    }
+    out.extend_from_slice(bytes);
+    out.resize(out.len() + max_len - bytes.len(), 0);
+    Ok(())
+}
+
+/// Writes an unknown region back as it was read, or zeroes if it wasn't kept.
+fn write_region(out: &mut Vec<u8>, region: Option<&[u8]>, len: usize, name: &str) -> Result<()> {
+    match region {
+        Some(bytes) if bytes.len() != len => bail!(
+            "Unknown header region {} is {} bytes long, should be {}",
+            name,
+            bytes.len(),
+            len
+        ),
+        Some(bytes) => out.extend_from_slice(bytes),
+        None => out.resize(out.len() + len, 0),
+    }
+    Ok(())
+}
+
+/// Serializes a replay header. The unknown regions are written back from
+/// [`ReplayHeader::unknown`] if they were kept (see
+/// [`HeaderOptions::keep_unknown`](crate::header::HeaderOptions::keep_unknown)),
+/// as zeroes otherwise.
+pub fn write_header(header: &ReplayHeader) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(HEADER_SIZE);
+    let unknown = header.unknown.as_ref();
+
+    out.extend_from_slice(&header.magic.to_le_bytes());
+    out.extend_from_slice(&header.version.to_le_bytes());
+    write_string(&mut out, &header.level, 128, "level")?;
+    write_string(&mut out, &header.level_settings, 260, "level_settings")?;
+    write_string(&mut out, &header.battle_type, 128, "battle_type")?;
+    write_string(&mut out, &header.environment, 128, "environment")?;
+    write_string(&mut out, &header.visibility, 32, "visibility")?;
+    out.extend_from_slice(&header.rez_offset.to_le_bytes());
+    out.push((header.difficulty.unknown_nibble << 4) | (header.difficulty.difficulty_value & 0x0F));
+    write_region(
+        &mut out,
+        unknown.map(|u| u.after_difficulty.as_slice()),
+        35,
+        "after_difficulty",
+    )?;
+    out.extend_from_slice(&header.session_type.to_le_bytes());
+    write_region(
+        &mut out,
+        unknown.map(|u| u.after_session_type.as_slice()),
+        4,
+        "after_session_type",
+    )?;
+    out.extend_from_slice(&header.session_id.0.to_le_bytes());
+    write_region(
+        &mut out,
+        unknown.map(|u| u.after_session_id.as_slice()),
+        4,
+        "after_session_id",
+    )?;
+    out.extend_from_slice(&header.m_set_size.to_le_bytes());
+    write_region(
+        &mut out,
+        unknown.map(|u| u.after_m_set_size.as_slice()),
+        32,
+        "after_m_set_size",
+    )?;
+    write_string(&mut out, &header.loc_name, 128, "loc_name")?;
+    out.extend_from_slice(&header.start_time.to_le_bytes());
+    out.extend_from_slice(&header.time_limit.to_le_bytes());
+    out.extend_from_slice(&header.score_limit.to_le_bytes());
+    write_region(
+        &mut out,
+        unknown.map(|u| u.after_score_limit.as_slice()),
+        48,
+        "after_score_limit",
+    )?;
+    write_string(&mut out, &header.battle_class, 128, "battle_class")?;
+    write_string(
+        &mut out,
+        &header.battle_kill_streak,
+        128,
+        "battle_kill_streak",
+    )?;
+
+    debug_assert_eq!(out.len(), HEADER_SIZE);
+    Ok(out)
+}
+
+/// Serializes packets into an uncompressed packet stream.
+pub fn write_packet_stream(packets: &[PacketInfo]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut last_timestamp_ms = 0;
+
+    for (i, packet) in packets.iter().enumerate() {
+        let packet_type = match packet.packet_type {
+            ReplayPacketType::Unknown => packet.raw_type,
+            known => known as u8,
+        };
+        if packet_type & 0x10 != 0 {
+            bail!(
+                "Packet {} has type {:#x}, which collides with the timestamp flag",
+                i,
+                packet_type
+            );
+        }
+        let header = raw::write_packet_header(packet_type, packet.timestamp_ms, last_timestamp_ms);
+        last_timestamp_ms = packet.timestamp_ms;
+
+        let size: u32 = (header.len() + packet.payload.len())
+            .try_into()
+            .with_context(|| format!("Packet {} is too large", i))?;
+        out.extend_from_slice(&raw::write_variable_length_size(size));
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&packet.payload);
+    }
+
+    Ok(out)
+}
+
+/// Which packets [`extract_packets`] copies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketRange {
+    /// By index in [`ParsedReplay::packets`].
+    Indices(Range<usize>),
+    /// By corrected time in ms (see [`TimeIndex`]).
+    Time(Range<u64>),
+}
+
+/// Writes some of `replay`'s packets as an uncompressed packet stream, to
+/// share a tricky section without the whole replay. The header info packet
+/// is always written first, so the result parses like any other stream.
+///
+/// Needs the payloads, so fails on a replay parsed with
+/// [`ParseOptions::discard_payloads`](crate::parser::ParseOptions::discard_payloads).
+pub fn extract_packets(replay: &ParsedReplay, range: PacketRange) -> Result<Vec<u8>> {
+    let packets = &replay.packets;
+    let indices = match range {
+        PacketRange::Indices(indices) => indices,
+        PacketRange::Time(times) => {
+            let index = TimeIndex::new(packets);
+            index.index_at(times.start)..index.index_at(times.end)
+        }
+    };
+    let indices = indices.start..indices.end.min(packets.len());
+    if indices.is_empty() {
+        bail!("No packets in {:?} ({} packets)", indices, packets.len());
+    }
+
+    let mut selected = Vec::with_capacity(indices.len() + 1);
+    let header_info = packets
+        .iter()
+        .position(|p| p.packet_type == ReplayPacketType::ReplayHeaderInfo);
+    match header_info {
+        Some(i) if i < indices.start => selected.push(&packets[i]),
+        Some(_) => {}
+        None => warn!("Replay has no header info packet, the extract won't either"),
+    }
+    selected.extend(&packets[indices]);
+    if let Some(p) = selected.iter().find(|p| p.payload.len() != p.size as usize) {
+        bail!(
+            "The packet at stream offset {:#x} has no payload, parse without discard_payloads",
+            p.stream_offset
+        );
+    }
+
+    let selected: Vec<PacketInfo> = selected.into_iter().cloned().collect();
+    write_packet_stream(&selected)
+}
+
+/// Writes a complete replay.
+///
+/// `mission_settings` is copied verbatim between the header and the packet
+/// stream, and `results` (a BLK blob) after it. The header's `rez_offset`
+/// is set to wherever the results end up (0 without results).
+pub fn write_replay(
+    header: &ReplayHeader,
+    mission_settings: &[u8],
+    packets: &[PacketInfo],
+    results: Option<&[u8]>,
+    encoding: StreamEncoding,
+) -> Result<Vec<u8>> {
+    let stream = write_packet_stream(packets)?;
+    let stream_section = match encoding {
+        StreamEncoding::Zlib { level } => {
+            if level > 9 {
+                bail!("Invalid zlib level {}, expected 0-9", level);
+            }
+            rewrite::compress_stream(&stream, Compression::new(level))?
+        }
+        StreamEncoding::Uncompressed => stream,
+    };
+
+    let mut out = write_header(header)?;
+    out.extend_from_slice(mission_settings);
+    out.extend_from_slice(&stream_section);
+
+    let rez_offset: u32 = match results {
+        Some(results) => {
+            let offset = out.len();
+            out.extend_from_slice(results);
+            offset
+                .try_into()
+                .context("Replay is too large for a u32 rez_offset")?
+        }
+        None => 0,
+    };
+    out[REZ_OFFSET_POS..REZ_OFFSET_POS + 4].copy_from_slice(&rez_offset.to_le_bytes());
+
+    Ok(out)
+}